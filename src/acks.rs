@@ -0,0 +1,146 @@
+use crate::{ErrorCodes, VerbosityLevel, error_handler, print_to_terminal};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+#[derive(Serialize, Deserialize)]
+pub struct Acknowledgment {
+    pub note: String,
+    pub acknowledged_at: String,
+}
+
+/// Endpoints someone has already looked at, with the reason they gave, so
+/// later scans can report them apart from new findings.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Acknowledgments {
+    endpoints: BTreeMap<SocketAddr, Acknowledgment>,
+}
+
+fn acknowledgments_path() -> Option<PathBuf> {
+    Some(
+        dirs::data_dir()?
+            .join("connection-tester")
+            .join("acknowledgments.toml"),
+    )
+}
+
+fn parse_endpoint(endpoint: &str) -> SocketAddr {
+    match endpoint.parse() {
+        Ok(endpoint_result) => endpoint_result,
+        Err(_) => error_handler(ErrorCodes::INVALID_VARIABLE, line!(), Some("endpoint")),
+    }
+}
+
+impl Acknowledgments {
+    /// Reads the saved acknowledgments. Having none saved yet is not an error.
+    pub fn load() -> Acknowledgments {
+        let Some(path) = acknowledgments_path() else {
+            return Acknowledgments::default();
+        };
+        let Ok(contents) = fs::read_to_string(&path) else {
+            return Acknowledgments::default();
+        };
+
+        match toml::from_str(&contents) {
+            Ok(acknowledgments_result) => acknowledgments_result,
+            Err(_) => error_handler(
+                ErrorCodes::ACKNOWLEDGMENTS_UNAVAILABLE,
+                line!(),
+                Some(&path.display().to_string()),
+            ),
+        }
+    }
+
+    pub fn note_for(&self, endpoint: SocketAddr) -> Option<String> {
+        self.endpoints
+            .get(&endpoint)
+            .map(|acknowledgment| acknowledgment.note.clone())
+    }
+
+    fn save(&self) {
+        let Some(path) = acknowledgments_path() else {
+            error_handler(ErrorCodes::CONFIG_DIRECTORY_UNAVAILABLE, line!(), None);
+        };
+        let path_name = path.display().to_string();
+        let contents = match toml::to_string_pretty(self) {
+            Ok(contents_result) => contents_result,
+            Err(_) => error_handler(
+                ErrorCodes::ACKNOWLEDGMENTS_UNAVAILABLE,
+                line!(),
+                Some(&path_name),
+            ),
+        };
+
+        let written = match path.parent() {
+            Some(parent) => {
+                fs::create_dir_all(parent).is_ok() && fs::write(&path, contents).is_ok()
+            }
+            None => false,
+        };
+        if !written {
+            error_handler(
+                ErrorCodes::ACKNOWLEDGMENTS_UNAVAILABLE,
+                line!(),
+                Some(&path_name),
+            );
+        }
+    }
+}
+
+pub fn acknowledge(endpoint: &str, note: &str) {
+    let endpoint = parse_endpoint(endpoint);
+    let mut acknowledgments = Acknowledgments::load();
+    acknowledgments.endpoints.insert(
+        endpoint,
+        Acknowledgment {
+            note: note.to_string(),
+            acknowledged_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        },
+    );
+    acknowledgments.save();
+
+    print_to_terminal(
+        format!("Acknowledged {}: {}", endpoint, note),
+        VerbosityLevel::INFO,
+    );
+}
+
+pub fn unacknowledge(endpoint: &str) {
+    let endpoint = parse_endpoint(endpoint);
+    let mut acknowledgments = Acknowledgments::load();
+    if acknowledgments.endpoints.remove(&endpoint).is_none() {
+        print_to_terminal(
+            format!("{} was not acknowledged", endpoint),
+            VerbosityLevel::WARN,
+        );
+        return;
+    }
+    acknowledgments.save();
+
+    print_to_terminal(
+        format!("Removed the acknowledgment for {}", endpoint),
+        VerbosityLevel::INFO,
+    );
+}
+
+pub fn list_acknowledgments() {
+    let acknowledgments = Acknowledgments::load();
+    if acknowledgments.endpoints.is_empty() {
+        print_to_terminal(
+            String::from("No acknowledged endpoints"),
+            VerbosityLevel::INFO,
+        );
+        return;
+    }
+
+    for (endpoint, acknowledgment) in &acknowledgments.endpoints {
+        println!(
+            "{} - {} ({})",
+            endpoint, acknowledgment.note, acknowledgment.acknowledged_at
+        );
+    }
+}
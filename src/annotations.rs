@@ -0,0 +1,186 @@
+use crate::{ErrorCodes, VerbosityLevel, error_handler, print_to_terminal};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// What an inventory such as a CMDB says about a machine.
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq)]
+pub struct Annotation {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl Annotation {
+    fn from_fields(mut field: impl FnMut(&str) -> Option<String>) -> Annotation {
+        let mut non_empty = |name: &str| field(name).filter(|value| !value.trim().is_empty());
+        Annotation {
+            owner: non_empty("owner"),
+            hostname: non_empty("hostname"),
+            description: non_empty("description"),
+        }
+    }
+
+    /// The annotation for a result line, e.g. `owner: web-team, web01`.
+    pub fn describe(&self) -> String {
+        let mut parts: Vec<String> = Vec::new();
+        if let Some(owner) = &self.owner {
+            parts.push(format!("owner: {}", owner));
+        }
+        parts.extend(self.hostname.clone());
+        parts.extend(self.description.clone());
+        parts.join(", ")
+    }
+}
+
+/// Annotations per address, read from the `--annotate` file.
+///
+/// A CSV file needs a header row naming an `ip` column and any of `owner`,
+/// `hostname` and `description`, in any order. A JSON file is either a list
+/// of objects with those keys, or an object keyed by address.
+#[derive(Default)]
+pub struct Annotations(HashMap<IpAddr, Annotation>);
+
+impl Annotations {
+    pub fn load(path: Option<&Path>) -> Annotations {
+        let Some(path) = path else {
+            return Annotations::default();
+        };
+        let path_name = path.display().to_string();
+        let contents = match fs::read_to_string(path) {
+            Ok(contents_result) => contents_result,
+            Err(_) => error_handler(
+                ErrorCodes::ANNOTATION_FILE_INVALID,
+                line!(),
+                Some(&path_name),
+            ),
+        };
+
+        let is_json = path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+            || contents.trim_start().starts_with(['[', '{']);
+        let entries = if is_json {
+            parse_json(&contents)
+        } else {
+            parse_csv(&contents)
+        };
+        let annotations = match entries {
+            Ok(entries) => Annotations(entries.into_iter().collect()),
+            Err(location) => error_handler(
+                ErrorCodes::ANNOTATION_FILE_INVALID,
+                line!(),
+                Some(&format!("{}{}", path_name, location)),
+            ),
+        };
+
+        print_to_terminal(
+            format!("Loaded annotations for {} hosts", annotations.0.len()),
+            VerbosityLevel::DEBUG,
+        );
+        annotations
+    }
+
+    pub fn annotation_for(&self, ip: IpAddr) -> Option<Annotation> {
+        self.0.get(&ip).cloned()
+    }
+}
+
+/// Splits a CSV line into fields, with double quotes around fields that
+/// contain commas and doubled quotes inside them.
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+        .into_iter()
+        .map(|field| field.trim().to_string())
+        .collect()
+}
+
+/// The entries of a CSV file, or where it went wrong, e.g. `:3`.
+fn parse_csv(contents: &str) -> Result<Vec<(IpAddr, Annotation)>, String> {
+    let mut lines = contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'));
+    let Some((_, header)) = lines.next() else {
+        return Ok(Vec::new());
+    };
+    let columns: Vec<String> = csv_fields(header)
+        .into_iter()
+        .map(|column| column.to_lowercase())
+        .collect();
+    let Some(ip_column) = columns.iter().position(|column| column == "ip") else {
+        return Err(String::from(":1"));
+    };
+
+    let mut entries = Vec::new();
+    for (line_index, line) in lines {
+        let fields = csv_fields(line);
+        let Some(ip) = fields
+            .get(ip_column)
+            .and_then(|ip| ip.parse::<IpAddr>().ok())
+        else {
+            return Err(format!(":{}", line_index + 1));
+        };
+        let annotation = Annotation::from_fields(|name| {
+            let column = columns.iter().position(|column| column == name)?;
+            fields.get(column).cloned()
+        });
+        entries.push((ip, annotation));
+    }
+    Ok(entries)
+}
+
+/// The entries of a JSON file, or where it went wrong, e.g. ` entry 3`.
+fn parse_json(contents: &str) -> Result<Vec<(IpAddr, Annotation)>, String> {
+    let text = |object: &serde_json::Map<String, Value>, name: &str| {
+        object.get(name).and_then(Value::as_str).map(String::from)
+    };
+    match serde_json::from_str::<Value>(contents) {
+        Ok(Value::Array(objects)) => objects
+            .iter()
+            .enumerate()
+            .map(|(index, object)| {
+                let entry = object.as_object().and_then(|object| {
+                    let ip = text(object, "ip")?.parse().ok()?;
+                    Some((ip, Annotation::from_fields(|name| text(object, name))))
+                });
+                entry.ok_or_else(|| format!(" entry {}", index + 1))
+            })
+            .collect(),
+        Ok(Value::Object(hosts)) => hosts
+            .iter()
+            .map(|(ip, object)| {
+                let entry = object.as_object().and_then(|object| {
+                    Some((
+                        ip.parse().ok()?,
+                        Annotation::from_fields(|name| text(object, name)),
+                    ))
+                });
+                entry.ok_or_else(|| format!(" entry {}", ip))
+            })
+            .collect(),
+        _ => Err(String::new()),
+    }
+}
@@ -0,0 +1,256 @@
+use crate::{ErrorCodes, VerbosityLevel, error_handler, print_to_terminal};
+use std::collections::HashSet;
+use std::ffi::CStr;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::{Arc, Mutex};
+use tokio::io::unix::AsyncFd;
+use tokio::time::{Duration, sleep};
+
+/// How long to wait for replies after each round of requests.
+const REPLY_WAIT: Duration = Duration::from_secs(1);
+/// Rounds of requests, the later ones only to hosts that haven't replied.
+const ROUNDS: usize = 2;
+const ETH_P_ARP: u16 = 0x0806;
+const ETH_P_IP: u16 = 0x0800;
+const REQUEST: u16 = 1;
+const REPLY: u16 = 2;
+const BROADCAST: [u8; 6] = [0xff; 6];
+
+/// An IPv4 address on an Ethernet interface, which ARP can reach every
+/// address of the subnet from.
+struct Interface {
+    index: i32,
+    mac: [u8; 6],
+    address: Ipv4Addr,
+    netmask: u32,
+}
+
+impl Interface {
+    fn on_link(&self, ip: Ipv4Addr) -> bool {
+        u32::from(ip) & self.netmask == u32::from(self.address) & self.netmask && ip != self.address
+    }
+
+    /// The interfaces that are up, have a hardware address and aren't
+    /// loopback.
+    fn list() -> Vec<Interface> {
+        let mut first: *mut libc::ifaddrs = std::ptr::null_mut();
+        if unsafe { libc::getifaddrs(&mut first) } != 0 {
+            return Vec::new();
+        }
+        let mut addresses = Vec::new();
+        let mut hardware = Vec::new();
+        let mut current = first;
+        while let Some(entry) = unsafe { current.as_ref() } {
+            current = entry.ifa_next;
+            let up = entry.ifa_flags & libc::IFF_UP as u32 != 0;
+            let loopback = entry.ifa_flags & libc::IFF_LOOPBACK as u32 != 0;
+            if !up || loopback || entry.ifa_addr.is_null() {
+                continue;
+            }
+            let name = unsafe { CStr::from_ptr(entry.ifa_name) }.to_owned();
+            match unsafe { (*entry.ifa_addr).sa_family } as i32 {
+                libc::AF_INET if !entry.ifa_netmask.is_null() => {
+                    let address = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in) };
+                    let netmask = unsafe { &*(entry.ifa_netmask as *const libc::sockaddr_in) };
+                    addresses.push((
+                        name,
+                        Ipv4Addr::from(address.sin_addr.s_addr.to_ne_bytes()),
+                        u32::from_be(netmask.sin_addr.s_addr),
+                    ));
+                }
+                libc::AF_PACKET => {
+                    let link = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_ll) };
+                    if link.sll_halen == 6 {
+                        let mut mac = [0u8; 6];
+                        mac.copy_from_slice(&link.sll_addr[..6]);
+                        hardware.push((name, link.sll_ifindex, mac));
+                    }
+                }
+                _ => {}
+            }
+        }
+        unsafe { libc::freeifaddrs(first) };
+
+        addresses
+            .into_iter()
+            .filter_map(|(name, address, netmask)| {
+                let (_, index, mac) = hardware.iter().find(|(link, _, _)| *link == name)?;
+                Some(Interface {
+                    index: *index,
+                    mac: *mac,
+                    address,
+                    netmask,
+                })
+            })
+            .collect()
+    }
+}
+
+fn link_address(index: i32, destination: [u8; 6]) -> libc::sockaddr_ll {
+    let mut address: libc::sockaddr_ll = unsafe { mem::zeroed() };
+    address.sll_family = libc::AF_PACKET as u16;
+    address.sll_protocol = ETH_P_ARP.to_be();
+    address.sll_ifindex = index;
+    address.sll_halen = 6;
+    address.sll_addr[..6].copy_from_slice(&destination);
+    address
+}
+
+fn arp_request(interface: &Interface, target: Ipv4Addr) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(28);
+    packet.extend_from_slice(&1u16.to_be_bytes());
+    packet.extend_from_slice(&ETH_P_IP.to_be_bytes());
+    packet.extend_from_slice(&[6, 4]);
+    packet.extend_from_slice(&REQUEST.to_be_bytes());
+    packet.extend_from_slice(&interface.mac);
+    packet.extend_from_slice(&interface.address.octets());
+    packet.extend_from_slice(&[0; 6]);
+    packet.extend_from_slice(&target.octets());
+    packet
+}
+
+/// A packet socket for ARP on every interface. It's opened before
+/// privileges are dropped, since packet sockets need root or
+/// `CAP_NET_RAW`.
+pub struct ArpScanner {
+    fd: Arc<AsyncFd<OwnedFd>>,
+}
+
+impl ArpScanner {
+    pub fn open() -> ArpScanner {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_PACKET,
+                libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                ETH_P_ARP.to_be() as i32,
+            )
+        };
+        if fd < 0 {
+            error_handler(ErrorCodes::ARP_UNAVAILABLE, line!(), None);
+        }
+        match AsyncFd::new(unsafe { OwnedFd::from_raw_fd(fd) }) {
+            Ok(fd) => ArpScanner { fd: Arc::new(fd) },
+            Err(_) => error_handler(ErrorCodes::ARP_UNAVAILABLE, line!(), None),
+        }
+    }
+
+    async fn send(&self, interface: &Interface, target: Ipv4Addr) -> io::Result<()> {
+        let packet = arp_request(interface, target);
+        let address = link_address(interface.index, BROADCAST);
+        loop {
+            let mut guard = self.fd.writable().await?;
+            let sent = guard.try_io(|fd| {
+                let sent = unsafe {
+                    libc::sendto(
+                        fd.as_raw_fd(),
+                        packet.as_ptr() as *const libc::c_void,
+                        packet.len(),
+                        0,
+                        &address as *const libc::sockaddr_ll as *const libc::sockaddr,
+                        mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+                    )
+                };
+                if sent < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(())
+                }
+            });
+            match sent {
+                Ok(result) => return result,
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    /// Waits for the next ARP reply and returns the address it's for.
+    async fn receive(fd: &AsyncFd<OwnedFd>) -> io::Result<Ipv4Addr> {
+        let mut buffer = [0u8; 64];
+        loop {
+            let mut guard = fd.readable().await?;
+            let received = guard.try_io(|fd| {
+                let read = unsafe {
+                    libc::recv(
+                        fd.as_raw_fd(),
+                        buffer.as_mut_ptr() as *mut libc::c_void,
+                        buffer.len(),
+                        0,
+                    )
+                };
+                if read < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(read as usize)
+                }
+            });
+            let read = match received {
+                Ok(result) => result?,
+                Err(_would_block) => continue,
+            };
+            if read >= 28 && u16::from_be_bytes([buffer[6], buffer[7]]) == REPLY {
+                return Ok(Ipv4Addr::new(
+                    buffer[14], buffer[15], buffer[16], buffer[17],
+                ));
+            }
+        }
+    }
+
+    /// Asks for the hardware address of every address on a directly
+    /// attached subnet. Returns the addresses that were on one, and of
+    /// those the ones that answered. Hosts that drop ICMP and TCP still
+    /// answer ARP, so this finds them where a ping sweep can't.
+    pub async fn sweep(&self, addresses: &[IpAddr]) -> (HashSet<IpAddr>, HashSet<IpAddr>) {
+        let interfaces = Interface::list();
+        let on_link: Vec<(Ipv4Addr, &Interface)> = addresses
+            .iter()
+            .filter_map(|ip| match ip {
+                IpAddr::V4(ipv4) => interfaces
+                    .iter()
+                    .find(|interface| interface.on_link(*ipv4))
+                    .map(|interface| (*ipv4, interface)),
+                IpAddr::V6(_) => None,
+            })
+            .collect();
+
+        let alive: Arc<Mutex<HashSet<Ipv4Addr>>> = Arc::default();
+        let listener = {
+            let fd = self.fd.clone();
+            let alive = alive.clone();
+            tokio::spawn(async move {
+                while let Ok(ip) = ArpScanner::receive(&fd).await {
+                    alive.lock().unwrap().insert(ip);
+                }
+            })
+        };
+        for _round in 0..ROUNDS {
+            if on_link.is_empty() || alive.lock().unwrap().len() >= on_link.len() {
+                break;
+            }
+            for (ip, interface) in &on_link {
+                if alive.lock().unwrap().contains(ip) {
+                    continue;
+                }
+                if let Err(e) = self.send(interface, *ip).await {
+                    print_to_terminal(
+                        format!("Could not send an ARP request for {}: {}", ip, e),
+                        VerbosityLevel::DEBUG,
+                    );
+                }
+            }
+            sleep(REPLY_WAIT).await;
+        }
+        listener.abort();
+
+        let alive = alive.lock().unwrap();
+        let replied = on_link
+            .iter()
+            .filter(|(ip, _)| alive.contains(ip))
+            .map(|(ip, _)| IpAddr::V4(*ip))
+            .collect();
+        let on_link = on_link.iter().map(|(ip, _)| IpAddr::V4(*ip)).collect();
+        (on_link, replied)
+    }
+}
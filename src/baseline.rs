@@ -0,0 +1,229 @@
+use crate::cli::{BaselineCheckArgs, ScanArgs};
+use crate::diff::{self, DiffLine};
+use crate::logging;
+use crate::output::{ResultRecord, SavedReport};
+use crate::{
+    ErrorCodes, ScanResult, ScanSettings, VerbosityLevel, build_scan_targets, check_target,
+    error_handler, print_to_terminal,
+};
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::task::JoinSet;
+
+/// How an endpoint's state differs from the approved one.
+pub enum Deviation {
+    Changed {
+        endpoint: SocketAddr,
+        from: String,
+        to: String,
+    },
+    /// In the baseline but not in the newer results
+    Missing { endpoint: SocketAddr, from: String },
+    /// In the newer results but not in the baseline
+    New { endpoint: SocketAddr, to: String },
+}
+
+impl Deviation {
+    pub fn diff_line(&self) -> DiffLine {
+        match self {
+            Deviation::Changed { endpoint, from, to } => {
+                DiffLine::new(endpoint.to_string(), from.clone(), to.clone())
+            }
+            Deviation::Missing { endpoint, from } => DiffLine::new(
+                endpoint.to_string(),
+                from.clone(),
+                String::from(diff::NOT_SCANNED),
+            ),
+            Deviation::New { endpoint, to } => DiffLine::new(
+                endpoint.to_string(),
+                String::from(diff::NOT_SCANNED),
+                to.clone(),
+            ),
+        }
+    }
+}
+
+fn baseline_path() -> PathBuf {
+    match dirs::data_dir() {
+        Some(data_dir) => data_dir.join("connection-tester").join("baseline.json"),
+        None => error_handler(ErrorCodes::CONFIG_DIRECTORY_UNAVAILABLE, line!(), None),
+    }
+}
+
+fn read_results(path: &Path) -> SavedReport {
+    match SavedReport::read(path) {
+        Some(report_result) => report_result,
+        None => error_handler(
+            ErrorCodes::RESULTS_FILE_INVALID,
+            line!(),
+            Some(&path.display().to_string()),
+        ),
+    }
+}
+
+/// Keys saved results by endpoint, skipping any record that doesn't name a
+/// valid address.
+pub fn statuses_by_endpoint(records: &[ResultRecord]) -> BTreeMap<SocketAddr, String> {
+    records
+        .iter()
+        .filter_map(|record| {
+            let ip: IpAddr = record.target.parse().ok()?;
+            Some((SocketAddr::new(ip, record.port), record.status.clone()))
+        })
+        .collect()
+}
+
+/// Lists every endpoint whose state in `current` differs from `approved`.
+pub fn compare(
+    approved: &BTreeMap<SocketAddr, String>,
+    current: &BTreeMap<SocketAddr, String>,
+) -> Vec<Deviation> {
+    let mut deviations: Vec<Deviation> = Vec::new();
+    for (endpoint, from) in approved {
+        match current.get(endpoint) {
+            Some(to) if to != from => deviations.push(Deviation::Changed {
+                endpoint: *endpoint,
+                from: from.clone(),
+                to: to.clone(),
+            }),
+            Some(_) => {}
+            None => deviations.push(Deviation::Missing {
+                endpoint: *endpoint,
+                from: from.clone(),
+            }),
+        }
+    }
+    for (endpoint, to) in current {
+        if !approved.contains_key(endpoint) {
+            deviations.push(Deviation::New {
+                endpoint: *endpoint,
+                to: to.clone(),
+            });
+        }
+    }
+    deviations.sort_by_key(|deviation| match deviation {
+        Deviation::Changed { endpoint, .. }
+        | Deviation::Missing { endpoint, .. }
+        | Deviation::New { endpoint, .. } => *endpoint,
+    });
+    deviations
+}
+
+pub fn set_baseline(results_path: &Path) {
+    let report = read_results(results_path);
+    let path = baseline_path();
+    let path_name = path.display().to_string();
+
+    // The file is kept as-is, so it stays readable by anything else that
+    // understands the JSON output.
+    let written = match path.parent() {
+        Some(parent) => fs::create_dir_all(parent).is_ok() && fs::copy(results_path, &path).is_ok(),
+        None => false,
+    };
+    if !written {
+        error_handler(ErrorCodes::OUTPUT_WRITE_FAILURE, line!(), Some(&path_name));
+    }
+
+    print_to_terminal(
+        format!(
+            "Baseline set from {} ({} endpoints)",
+            results_path.display(),
+            report.results.len()
+        ),
+        VerbosityLevel::INFO,
+    );
+}
+
+/// Compares a fresh scan of the baseline's targets, or an existing results
+/// file, with the baseline and reports only what has changed.
+pub async fn check_baseline(check_args: BaselineCheckArgs) {
+    let path = baseline_path();
+    if !path.exists() {
+        error_handler(ErrorCodes::BASELINE_NOT_SET, line!(), None);
+    }
+    let baseline = read_results(&path);
+    let approved = statuses_by_endpoint(&baseline.results);
+
+    let current = match &check_args.results {
+        Some(results_path) => statuses_by_endpoint(&read_results(results_path).results),
+        None => {
+            let mut scan = ScanArgs {
+                targets: baseline.scan.targets.clone(),
+                ports: Some(baseline.scan.ports.clone()),
+                config: check_args.config.clone(),
+                ..Default::default()
+            };
+            let endpoints = build_scan_targets(&mut scan).await;
+            scan_statuses(endpoints, ScanSettings::from_args(&scan)).await
+        }
+    };
+
+    let deviations = compare(&approved, &current);
+    let lines: Vec<DiffLine> = deviations.iter().map(Deviation::diff_line).collect();
+    let color = check_args.out.is_none() && logging::use_color(false);
+    let summary = if deviations.is_empty() {
+        format!(
+            "Result: matches the baseline ({} endpoints)",
+            approved.len()
+        )
+    } else {
+        format!("Result: {} deviations from the baseline", deviations.len())
+    };
+    let report = diff::render(&lines, color) + &summary + "\n";
+
+    match &check_args.out {
+        Some(out_path) => {
+            if fs::write(out_path, &report).is_err() {
+                error_handler(
+                    ErrorCodes::OUTPUT_WRITE_FAILURE,
+                    line!(),
+                    Some(&out_path.display().to_string()),
+                );
+            }
+        }
+        None => print!("{}", report),
+    }
+
+    if !deviations.is_empty() {
+        error_handler(
+            ErrorCodes::BASELINE_DEVIATIONS_FOUND,
+            line!(),
+            Some(&deviations.len().to_string()),
+        );
+    }
+}
+
+async fn scan_statuses(
+    endpoints: Vec<SocketAddr>,
+    settings: Arc<ScanSettings>,
+) -> BTreeMap<SocketAddr, String> {
+    let mut set: JoinSet<ScanResult> = JoinSet::new();
+    let mut statuses: BTreeMap<SocketAddr, String> = BTreeMap::new();
+
+    for endpoint in endpoints {
+        set.spawn(check_target(endpoint, None, settings.clone()));
+    }
+
+    print_to_terminal(String::from("Waiting for results"), VerbosityLevel::INFO);
+
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok(scan_result) => {
+                statuses.insert(
+                    scan_result.ip,
+                    scan_result.status.to_string().to_lowercase(),
+                );
+            }
+            Err(e) => {
+                print_to_terminal(
+                    format!("An error has occured: {}", e),
+                    VerbosityLevel::ERROR,
+                );
+            }
+        }
+    }
+    statuses
+}
@@ -0,0 +1,353 @@
+use crate::cli::ScanArgs;
+use crate::output::{self, OutputFormat, ScanMetadata};
+use crate::{ErrorCodes, ScanResult, VerbosityLevel, error_handler, logging, print_to_terminal};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Longest entry name a plain ustar header holds.
+const MAX_NAME_BYTES: usize = 99;
+/// How far back, and how many candidates deep, matches are looked for.
+const WINDOW: usize = 32 * 1024;
+const MAX_CHAIN: usize = 64;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Writes deflate's bits, least significant first.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    current: u32,
+    filled: u32,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, count: u32) {
+        self.current |= value << self.filled;
+        self.filled += count;
+        while self.filled >= 8 {
+            self.bytes.push(self.current as u8);
+            self.current >>= 8;
+            self.filled -= 8;
+        }
+    }
+
+    /// Huffman codes go out most significant bit first.
+    fn code(&mut self, code: u32, length: u32) {
+        let reversed = code.reverse_bits() >> (32 - length);
+        self.bits(reversed, length);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.filled > 0 {
+            self.bytes.push(self.current as u8);
+        }
+        self.bytes
+    }
+
+    /// A literal byte or length symbol in the fixed Huffman code.
+    fn symbol(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn reference(&mut self, length: usize, distance: usize) {
+        let index = LENGTH_BASE
+            .iter()
+            .rposition(|base| *base as usize <= length)
+            .unwrap_or_default();
+        self.symbol(257 + index as u16);
+        self.bits(
+            (length - LENGTH_BASE[index] as usize) as u32,
+            LENGTH_EXTRA[index] as u32,
+        );
+        let index = DISTANCE_BASE
+            .iter()
+            .rposition(|base| *base as usize <= distance)
+            .unwrap_or_default();
+        self.code(index as u32, 5);
+        self.bits(
+            (distance - DISTANCE_BASE[index] as usize) as u32,
+            DISTANCE_EXTRA[index] as u32,
+        );
+    }
+}
+
+fn hash(data: &[u8], position: usize) -> usize {
+    let key = (data[position] as usize) << 16
+        | (data[position + 1] as usize) << 8
+        | data[position + 2] as usize;
+    (key.wrapping_mul(2654435761) >> 17) & (WINDOW - 1)
+}
+
+/// Remembers that the three bytes at `position` were seen there, ahead of
+/// the earlier places they were seen.
+fn remember(
+    data: &[u8],
+    position: usize,
+    head: &mut [Option<usize>],
+    earlier: &mut [Option<usize>],
+) {
+    if position + MIN_MATCH <= data.len() {
+        let slot = hash(data, position);
+        earlier[position] = head[slot];
+        head[slot] = Some(position);
+    }
+}
+
+/// The longest repeat of what starts at `position`, as its length and how
+/// far back it starts.
+fn longest_match(
+    data: &[u8],
+    position: usize,
+    head: &[Option<usize>],
+    earlier: &[Option<usize>],
+) -> (usize, usize) {
+    let mut best = (0, 0);
+    if position + MIN_MATCH > data.len() {
+        return best;
+    }
+    let mut candidate = head[hash(data, position)];
+    let mut tried = 0;
+    while let Some(start) = candidate
+        && position - start <= WINDOW
+        && tried < MAX_CHAIN
+    {
+        let length = data[start..]
+            .iter()
+            .zip(&data[position..])
+            .take(MAX_MATCH)
+            .take_while(|(a, b)| a == b)
+            .count();
+        if length > best.0 {
+            best = (length, position - start);
+        }
+        tried += 1;
+        candidate = earlier[start];
+    }
+    best
+}
+
+/// Compresses `data` into a single deflate block with the fixed Huffman
+/// code, finding repeats greedily. Reports and logs repeat themselves
+/// enough that this gets most of what a full encoder would.
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    // Final block, fixed Huffman codes
+    writer.bits(1, 1);
+    writer.bits(1, 2);
+    let mut head: Vec<Option<usize>> = vec![None; WINDOW];
+    let mut earlier: Vec<Option<usize>> = vec![None; data.len()];
+
+    let mut position = 0;
+    while position < data.len() {
+        let (length, distance) = longest_match(data, position, &head, &earlier);
+        if length >= MIN_MATCH {
+            writer.reference(length, distance);
+            for skipped in position..position + length {
+                remember(data, skipped, &mut head, &mut earlier);
+            }
+            position += length;
+        } else {
+            writer.symbol(data[position] as u16);
+            remember(data, position, &mut head, &mut earlier);
+            position += 1;
+        }
+    }
+    writer.symbol(256);
+    writer.finish()
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn gzip(data: &[u8], mtime: u32) -> Vec<u8> {
+    let mut gzipped = vec![0x1f, 0x8b, 8, 0];
+    gzipped.extend_from_slice(&mtime.to_le_bytes());
+    // No extra flags, unknown OS
+    gzipped.extend_from_slice(&[0, 0xff]);
+    gzipped.extend_from_slice(&deflate(data));
+    gzipped.extend_from_slice(&crc32(data).to_le_bytes());
+    gzipped.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    gzipped
+}
+
+fn octal(field: &mut [u8], value: u64) {
+    let width = field.len() - 1;
+    let digits = format!("{:0width$o}", value, width = width);
+    field[..width].copy_from_slice(digits.as_bytes());
+}
+
+/// Appends a regular file to a ustar archive.
+fn tar_entry(archive: &mut Vec<u8>, name: &str, contents: &[u8], mtime: u64) {
+    let mut header = [0u8; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    octal(&mut header[100..108], 0o644);
+    octal(&mut header[108..116], 0);
+    octal(&mut header[116..124], 0);
+    octal(&mut header[124..136], contents.len() as u64);
+    octal(&mut header[136..148], mtime);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    // The checksum counts its own field as spaces
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|byte| *byte as u32).sum();
+    header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+    archive.extend_from_slice(&header);
+    archive.extend_from_slice(contents);
+    archive.resize(archive.len().next_multiple_of(512), 0);
+}
+
+/// The archive's top directory, named after the bundle, e.g. `audit` for
+/// `audit.tar.gz`.
+fn directory_name(path: &Path) -> String {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let directory = file_name
+        .strip_suffix(".tar.gz")
+        .or_else(|| file_name.strip_suffix(".tgz"))
+        .unwrap_or(&file_name);
+    if directory.is_empty() {
+        String::from("scan")
+    } else {
+        directory.to_string()
+    }
+}
+
+/// What goes in a bundle, as archive entry names relative to its directory
+/// and their contents.
+struct Contents {
+    entries: Vec<(String, Vec<u8>)>,
+    names: HashSet<String>,
+}
+
+impl Contents {
+    fn add(&mut self, name: &str, contents: Vec<u8>) {
+        let mut unique = name.to_string();
+        let mut copy = 2;
+        while self.names.contains(&unique) {
+            unique = format!("{}-{}", copy, name);
+            copy += 1;
+        }
+        self.names.insert(unique.clone());
+        self.entries.push((unique, contents));
+    }
+
+    /// A file the run wrote or read, under its own name. Files that can't
+    /// be read are left out with a warning rather than losing the bundle.
+    fn add_file(&mut self, path: &Path) {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| String::from("file"));
+        match fs::read(path) {
+            Ok(contents) => self.add(&name, contents),
+            Err(e) => print_to_terminal(
+                format!("Left {} out of the bundle: {}", path.display(), e),
+                VerbosityLevel::WARN,
+            ),
+        }
+    }
+}
+
+/// Packs everything about the run into one `.tar.gz` for `--bundle`: the
+/// results as JSON, every `--output-file` and `--report` written, the scan's
+/// settings and config file, and this run's log.
+pub fn write_bundle(
+    path: &Path,
+    scan: &ScanArgs,
+    metadata: &ScanMetadata,
+    results: &[ScanResult],
+    reports: &[PathBuf],
+) {
+    let mut contents = Contents {
+        entries: Vec::new(),
+        names: HashSet::new(),
+    };
+    contents.add(
+        "results.json",
+        output::render(OutputFormat::Json, metadata, results).into_bytes(),
+    );
+    for report in scan.output_file.iter().chain(reports) {
+        contents.add_file(report);
+    }
+    contents.add(
+        "scan.toml",
+        toml::to_string_pretty(scan)
+            .unwrap_or_default()
+            .into_bytes(),
+    );
+    if let Some(config) = &scan.config {
+        contents.add_file(config);
+    }
+    contents.add("scan.log", logging::captured().into_bytes());
+
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default();
+    let directory = directory_name(path);
+    let mut archive = Vec::new();
+    for (name, entry) in &contents.entries {
+        let mut name = format!("{}/{}", directory, name);
+        while name.len() > MAX_NAME_BYTES {
+            name.pop();
+        }
+        tar_entry(&mut archive, &name, entry, mtime);
+    }
+    // Two empty blocks end the archive
+    archive.resize(archive.len() + 1024, 0);
+
+    if fs::write(path, gzip(&archive, mtime as u32)).is_err() {
+        error_handler(
+            ErrorCodes::OUTPUT_WRITE_FAILURE,
+            line!(),
+            Some(&path.display().to_string()),
+        );
+    }
+    print_to_terminal(
+        format!(
+            "Bundle of {} files written to {}",
+            contents.entries.len(),
+            path.display()
+        ),
+        VerbosityLevel::INFO,
+    );
+}
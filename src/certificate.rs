@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+/// The parts of a server certificate worth showing to someone checking
+/// what a TLS endpoint serves.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CertificateSummary {
+    /// The subject's common name, or the whole subject if it has none
+    pub subject: String,
+    /// DNS names and addresses from the subject alternative names
+    pub names: Vec<String>,
+    pub issuer: String,
+    #[serde(with = "humantime_serde")]
+    pub not_after: SystemTime,
+    /// Whole days from when the certificate was fetched until it expires,
+    /// negative once it has
+    #[serde(default)]
+    pub days_left: i64,
+}
+
+impl CertificateSummary {
+    pub fn parse(der: &[u8]) -> Option<CertificateSummary> {
+        let (_, certificate) = X509Certificate::from_der(der).ok()?;
+
+        let subject = certificate
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|common_name| common_name.as_str().ok())
+            .map(String::from)
+            .unwrap_or_else(|| certificate.subject().to_string());
+        let issuer = certificate
+            .issuer()
+            .iter_common_name()
+            .next()
+            .and_then(|common_name| common_name.as_str().ok())
+            .map(String::from)
+            .unwrap_or_else(|| certificate.issuer().to_string());
+
+        let mut names: Vec<String> = Vec::new();
+        if let Ok(Some(alternative_names)) = certificate.subject_alternative_name() {
+            for name in &alternative_names.value.general_names {
+                match name {
+                    GeneralName::DNSName(dns_name) => names.push(dns_name.to_string()),
+                    GeneralName::IPAddress(octets) => {
+                        if let Ok(v4) = <[u8; 4]>::try_from(*octets) {
+                            names.push(IpAddr::from(v4).to_string());
+                        } else if let Ok(v6) = <[u8; 16]>::try_from(*octets) {
+                            names.push(IpAddr::from(v6).to_string());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let expiry_seconds = certificate.validity().not_after.timestamp();
+        let not_after = UNIX_EPOCH + Duration::from_secs(expiry_seconds.max(0) as u64);
+        let now_seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        let days_left = (expiry_seconds - now_seconds).div_euclid(86400);
+
+        Some(CertificateSummary {
+            subject,
+            names,
+            issuer,
+            not_after,
+            days_left,
+        })
+    }
+
+    /// The certificate for a result line, e.g. `www.example.com (www.example.com,
+    /// example.com) from R3, expires in 41 days`.
+    pub fn describe(&self) -> String {
+        let names = if self.names.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", self.names.join(", "))
+        };
+        let expiry = if self.days_left < 0 {
+            format!("expired {} days ago", -self.days_left)
+        } else {
+            format!("expires in {} days", self.days_left)
+        };
+        format!("{}{} from {}, {}", self.subject, names, self.issuer, expiry)
+    }
+
+    /// Whether a client connecting as `name` would accept this certificate,
+    /// going by its alternative names (or its subject, when it has none).
+    /// Wildcards only cover a single label, as browsers treat them.
+    pub fn covers(&self, name: &str) -> bool {
+        let name = name.trim_end_matches(".").to_lowercase();
+        let candidates: Vec<&String> = if self.names.is_empty() {
+            vec![&self.subject]
+        } else {
+            self.names.iter().collect()
+        };
+
+        candidates.iter().any(|candidate| {
+            let candidate = candidate.trim_end_matches(".").to_lowercase();
+            match candidate.strip_prefix("*.") {
+                Some(parent) => name
+                    .split_once(".")
+                    .is_some_and(|(label, rest)| !label.is_empty() && rest == parent),
+                None => candidate == name,
+            }
+        })
+    }
+}
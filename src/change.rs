@@ -0,0 +1,240 @@
+use crate::cli::{ChangePhase, ScanArgs, VerifyChangeArgs};
+use crate::{
+    ConnectionStatus, ErrorCodes, ScanResult, ScanSettings, VerbosityLevel, build_scan_targets,
+    check_target, error_handler, print_to_terminal,
+};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+
+/// A firewall change described as the port states expected on either side
+/// of it.
+#[derive(Deserialize)]
+struct ChangeRequest {
+    #[serde(default)]
+    name: Option<String>,
+    checks: Vec<ChangeCheck>,
+}
+
+#[derive(Deserialize)]
+struct ChangeCheck {
+    target: String,
+    ports: PortSpec,
+    #[serde(default)]
+    before: Option<ExpectedState>,
+    #[serde(default)]
+    after: Option<ExpectedState>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PortSpec {
+    Single(u16),
+    List(String),
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum ExpectedState {
+    Open,
+    /// Anything other than open
+    Closed,
+    Refused,
+    Timeout,
+    Unreachable,
+}
+
+impl ExpectedState {
+    fn matches(&self, status: ConnectionStatus) -> bool {
+        match self {
+            ExpectedState::Open => status == ConnectionStatus::Open,
+            ExpectedState::Closed => status != ConnectionStatus::Open,
+            ExpectedState::Refused => status == ConnectionStatus::Refused,
+            ExpectedState::Timeout => status == ConnectionStatus::Timeout,
+            ExpectedState::Unreachable => status == ConnectionStatus::Unreachable,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            ExpectedState::Open => "open",
+            ExpectedState::Closed => "closed",
+            ExpectedState::Refused => "refused",
+            ExpectedState::Timeout => "timeout",
+            ExpectedState::Unreachable => "unreachable",
+        }
+    }
+}
+
+pub async fn verify_change(verify_args: VerifyChangeArgs) {
+    let path_name = verify_args.change_file.display().to_string();
+    let contents = match fs::read_to_string(&verify_args.change_file) {
+        Ok(contents_result) => contents_result,
+        Err(_) => error_handler(ErrorCodes::CHANGE_FILE_INVALID, line!(), Some(&path_name)),
+    };
+    let change: ChangeRequest = match serde_yaml::from_str(&contents) {
+        Ok(change_result) => change_result,
+        Err(_) => error_handler(ErrorCodes::CHANGE_FILE_INVALID, line!(), Some(&path_name)),
+    };
+    let change_name = change.name.clone().unwrap_or(path_name);
+
+    let mut check_endpoints: Vec<Vec<SocketAddr>> = Vec::new();
+    let mut all_ports: Vec<String> = Vec::new();
+    for check in &change.checks {
+        let ports = match &check.ports {
+            PortSpec::Single(port) => port.to_string(),
+            PortSpec::List(port_list) => port_list.clone(),
+        };
+        all_ports.push(ports.clone());
+        check_endpoints.push(
+            build_scan_targets(&mut ScanArgs {
+                targets: vec![check.target.clone()],
+                ports: Some(ports),
+                ..Default::default()
+            })
+            .await,
+        );
+    }
+
+    // Every check is probed under the same limits a scan of them would be
+    let settings = ScanSettings::from_args(&ScanArgs {
+        targets: change
+            .checks
+            .iter()
+            .map(|check| check.target.clone())
+            .collect(),
+        ports: Some(all_ports.join(",")),
+        ..Default::default()
+    });
+
+    let mut report: Vec<String> = vec![format!("Change verification: {}", change_name)];
+    let mut failures: usize = 0;
+
+    if verify_args.phase != ChangePhase::Post {
+        let results = scan_endpoints(&check_endpoints, &settings).await;
+        for (check, endpoints) in change.checks.iter().zip(&check_endpoints) {
+            failures += evaluate(&mut report, "before", check.before, endpoints, &results);
+        }
+    }
+
+    if verify_args.phase == ChangePhase::Both {
+        println!("Apply the change, then press Enter to run the post-change scan");
+        let mut confirmation = String::new();
+        if io::stdin().read_line(&mut confirmation).is_err() {
+            error_handler(ErrorCodes::INVALID_INPUT, line!(), None);
+        }
+    }
+
+    if verify_args.phase != ChangePhase::Pre {
+        let results = scan_endpoints(&check_endpoints, &settings).await;
+        for (check, endpoints) in change.checks.iter().zip(&check_endpoints) {
+            failures += evaluate(&mut report, "after", check.after, endpoints, &results);
+        }
+    }
+
+    report.push(if failures == 0 {
+        String::from("Result: PASS")
+    } else {
+        format!("Result: FAIL ({} mismatched)", failures)
+    });
+    let report = report.join("\n") + "\n";
+
+    match &verify_args.out {
+        Some(path) => {
+            if fs::write(path, &report).is_err() {
+                error_handler(
+                    ErrorCodes::OUTPUT_WRITE_FAILURE,
+                    line!(),
+                    Some(&path.display().to_string()),
+                );
+            }
+        }
+        None => print!("{}", report),
+    }
+
+    if failures > 0 {
+        error_handler(
+            ErrorCodes::CHANGE_VERIFICATION_FAILED,
+            line!(),
+            Some(&change_name),
+        );
+    }
+    print_to_terminal(
+        format!("Change {} verified", change_name),
+        VerbosityLevel::INFO,
+    );
+}
+
+async fn scan_endpoints(
+    check_endpoints: &[Vec<SocketAddr>],
+    settings: &Arc<ScanSettings>,
+) -> HashMap<SocketAddr, ConnectionStatus> {
+    let mut set: JoinSet<ScanResult> = JoinSet::new();
+    let mut results: HashMap<SocketAddr, ConnectionStatus> = HashMap::new();
+    let mut spawned: HashSet<SocketAddr> = HashSet::new();
+
+    for endpoint in check_endpoints.iter().flatten() {
+        if spawned.insert(*endpoint) {
+            set.spawn(check_target(*endpoint, None, settings.clone()));
+        }
+    }
+
+    print_to_terminal(String::from("Waiting for results"), VerbosityLevel::INFO);
+
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok(scan_result) => {
+                results.insert(scan_result.ip, scan_result.status);
+            }
+            Err(e) => {
+                print_to_terminal(
+                    format!("An error has occured: {}", e),
+                    VerbosityLevel::ERROR,
+                );
+            }
+        }
+    }
+    results
+}
+
+/// Adds a report line for every endpoint in a check and returns how many of
+/// them didn't match the expected state.
+fn evaluate(
+    report: &mut Vec<String>,
+    phase: &str,
+    expected: Option<ExpectedState>,
+    endpoints: &[SocketAddr],
+    results: &HashMap<SocketAddr, ConnectionStatus>,
+) -> usize {
+    let Some(expected) = expected else {
+        return 0;
+    };
+
+    let mut failures: usize = 0;
+    for endpoint in endpoints {
+        let Some(status) = results.get(endpoint).copied() else {
+            failures += 1;
+            report.push(format!("[FAIL] {:<6} {} no result", phase, endpoint));
+            continue;
+        };
+        let verdict = if expected.matches(status) {
+            "PASS"
+        } else {
+            failures += 1;
+            "FAIL"
+        };
+        report.push(format!(
+            "[{}] {:<6} {} expected {}, found {}",
+            verdict,
+            phase,
+            endpoint,
+            expected.label(),
+            status
+        ));
+    }
+    failures
+}
@@ -0,0 +1,105 @@
+use crate::cli::ScanArgs;
+use crate::clock::RunClock;
+use crate::output::{self, OutputFormat, ScanMetadata};
+use crate::{
+    ErrorCodes, ScanResult, VerbosityLevel, error_handler, parse_reports, print_to_terminal,
+};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::signal;
+use tokio::signal::unix::{SignalKind, signal as unix_signal};
+
+/// How many results, and how long, a file output goes without being
+/// rewritten when `--flush-every` and `--flush-interval` aren't given.
+const DEFAULT_EVERY: usize = 100;
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Keeps the whole-document outputs of a scan, `--output-file` in JSON,
+/// CSV, HTML or grepable form and every `--report`, up to date while the
+/// scan runs, so a scan that dies part way leaves the results it had
+/// rather than nothing. NDJSON needs none of this, being flushed with every
+/// result. Each write replaces the file whole, so it's never left half
+/// written.
+pub struct Checkpoints {
+    sinks: Vec<(OutputFormat, PathBuf)>,
+    every: usize,
+    pending: usize,
+}
+
+impl Checkpoints {
+    pub fn new(scan: &ScanArgs) -> Checkpoints {
+        let mut sinks = Vec::new();
+        if let Some(path) = &scan.output_file
+            && !scan.output.is_streaming()
+        {
+            sinks.push((scan.output, path.clone()));
+        }
+        sinks.extend(parse_reports(&scan.reports));
+        Checkpoints {
+            sinks,
+            every: scan.flush_every.unwrap_or(DEFAULT_EVERY).max(1),
+            pending: 0,
+        }
+    }
+
+    /// How often the outputs are rewritten while results keep coming in.
+    pub fn interval(scan: &ScanArgs) -> Duration {
+        scan.flush_interval.unwrap_or(DEFAULT_INTERVAL)
+    }
+
+    /// Counts a new result, rewriting the outputs once `--flush-every` of
+    /// them have come in since the last time.
+    pub fn result_added(&mut self, scan: &ScanArgs, clock: &RunClock, results: &[ScanResult]) {
+        self.pending += 1;
+        if self.pending >= self.every {
+            self.write(scan, clock, results);
+        }
+    }
+
+    /// Rewrites the outputs with the results so far, marked partial, if
+    /// any came in since the last time.
+    pub fn write(&mut self, scan: &ScanArgs, clock: &RunClock, results: &[ScanResult]) {
+        if self.pending == 0 || self.sinks.is_empty() {
+            return;
+        }
+        self.pending = 0;
+        let mut metadata = ScanMetadata::new(scan, clock);
+        metadata.partial = true;
+        for (format, path) in &self.sinks {
+            output::write_results(*format, Some(path), &metadata, results);
+        }
+        print_to_terminal(
+            format!(
+                "Wrote the {} results so far to {} files",
+                results.len(),
+                self.sinks.len()
+            ),
+            VerbosityLevel::DEBUG,
+        );
+    }
+}
+
+/// Resolves on Ctrl-C or SIGTERM.
+pub async fn interrupted() {
+    let terminate = async {
+        match unix_signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        _ = signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+}
+
+/// Exits on Ctrl-C or SIGTERM from now on, for the parts of a scan that
+/// come after the results are in.
+pub fn exit_on_interrupt() {
+    tokio::spawn(async {
+        interrupted().await;
+        error_handler(ErrorCodes::SCAN_INTERRUPTED, line!(), None);
+    });
+}
@@ -0,0 +1,804 @@
+use crate::grouping::GroupBy;
+use crate::knock::KnockSequence;
+use crate::logging::ColorChoice;
+use crate::output::OutputFormat;
+use crate::proxy::HttpProxy;
+use crate::rate::Rate;
+use crate::syslog::Facility;
+use crate::timeouts::TimeoutOverride;
+use crate::units;
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Checks which TCP ports are reachable across a network"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Check just this HOST:PORT and exit 0 if it's open, 1 if it's closed or 2 if nothing
+    /// answered
+    #[arg(value_name = "ENDPOINT", conflicts_with_all = ["targets", "ports"])]
+    pub endpoint: Option<String>,
+
+    /// Also append every message, timestamped and at every verbosity level, to this file
+    #[arg(long, global = true, value_name = "FILE")]
+    pub log_file: Option<PathBuf>,
+
+    /// Also send results and errors to the local syslog daemon, under this facility
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        value_name = "FACILITY",
+        num_args = 0..=1,
+        default_missing_value = "user"
+    )]
+    pub syslog: Option<Facility>,
+
+    /// When to color terminal output. "auto" colors a stream only when it's a terminal and
+    /// NO_COLOR isn't set
+    #[arg(long, global = true, value_enum, value_name = "WHEN", default_value_t)]
+    pub color: ColorChoice,
+
+    /// Same as --color never
+    #[arg(long, global = true, conflicts_with = "color")]
+    pub no_color: bool,
+
+    /// Threads to run probes on [default: one per CPU core]
+    #[arg(
+        long,
+        global = true,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with = "current_thread"
+    )]
+    pub worker_threads: Option<u32>,
+
+    /// Run everything on a single thread, which on small containers does better than a thread
+    /// per core since the scanner spends nearly all its time waiting on the network
+    #[arg(long, global = true)]
+    pub current_thread: bool,
+
+    #[command(flatten)]
+    pub scan: ScanArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Save the given scan flags under a name so they can be rerun later
+    SaveProfile {
+        name: String,
+
+        #[command(flatten)]
+        scan: ScanArgs,
+    },
+    /// List the saved profiles
+    ListProfiles,
+    /// List the built-in presets for --preset
+    ListPresets,
+    /// Run a saved profile
+    Run { name: String },
+    /// Test every destination from every source address and report the results as a grid
+    Matrix(MatrixArgs),
+    /// Check a firewall change against the port states it is expected to produce
+    VerifyChange(VerifyChangeArgs),
+    /// Handshake with one TLS endpoint once per SNI name and report the certificate each gets
+    SniMatrix(SniMatrixArgs),
+    /// Request every endpoint once per Host header and report the status codes as a grid
+    HostMatrix(HostMatrixArgs),
+    /// Mark an endpoint's current state as known, so scans report it apart from new findings
+    Ack {
+        /// Endpoint to acknowledge, as IP:PORT
+        endpoint: String,
+        /// Why it's acceptable or who is handling it, e.g. a ticket number
+        note: String,
+    },
+    /// Remove an endpoint's acknowledgment
+    Unack { endpoint: String },
+    /// List the acknowledged endpoints and their notes
+    ListAcks,
+    /// Approve a set of results and check later scans against it
+    #[command(subcommand)]
+    Baseline(BaselineCommand),
+    /// List past results recorded with --db, newest first
+    History(HistoryArgs),
+    /// Move the scans recorded with --db in and out of the database, to back it up or migrate
+    /// it to another host
+    #[command(subcommand)]
+    Db(DbCommand),
+    /// Probe again only the inconclusive endpoints of a results file and write it back out
+    /// with their new results merged in
+    Rescan(RescanArgs),
+    /// Replay a results file through the configured outputs, webhook, trace export and
+    /// --fail-if without touching the network, to test what consumes them
+    Simulate(SimulateArgs),
+}
+
+#[derive(Args)]
+pub struct RescanArgs {
+    /// Results file written by --output json
+    pub results: PathBuf,
+
+    /// Statuses to probe again, e.g. timeout,unreachable
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "STATUSES",
+        default_value = "timeout,unreachable"
+    )]
+    pub statuses: Vec<String>,
+
+    /// TOML config file with detailed settings for the rescan
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// File to write the updated results to instead of stdout
+    #[arg(short, long)]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct SimulateArgs {
+    /// Results file written by --output json to replay
+    #[arg(long = "from", value_name = "FILE")]
+    pub results: PathBuf,
+
+    /// How much faster than the original scan to replay the results, e.g. 10x
+    #[arg(long, value_name = "FACTOR", default_value = "1x", value_parser = parse_speed)]
+    pub speed: f64,
+
+    /// Where and how to send the replayed results, as for a scan
+    #[command(flatten)]
+    pub scan: ScanArgs,
+}
+
+fn parse_speed(speed: &str) -> Result<f64, String> {
+    match speed.strip_suffix("x").unwrap_or(speed).parse::<f64>() {
+        Ok(factor) if factor > 0.0 && factor.is_finite() => Ok(factor),
+        _ => Err(String::from("expected a positive factor like 10x")),
+    }
+}
+
+#[derive(Args)]
+pub struct HistoryArgs {
+    /// SQLite database the scans were recorded in
+    #[arg(long, value_name = "PATH")]
+    pub db: PathBuf,
+
+    /// Only show results for this address
+    #[arg(long)]
+    pub host: Option<String>,
+
+    /// Only show results for this port
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// Only show results from scans whose --label contains TEXT
+    #[arg(long, value_name = "TEXT")]
+    pub label: Option<String>,
+
+    /// Only show results from scans run by this --operator
+    #[arg(long, value_name = "NAME")]
+    pub operator: Option<String>,
+
+    /// Most results to show
+    #[arg(long, default_value_t = 50)]
+    pub limit: u32,
+}
+
+#[derive(Subcommand)]
+pub enum DbCommand {
+    /// Write every recorded scan to a JSON lines dump, one scan and its results per line
+    Export(DumpArgs),
+    /// Add the scans of a dump written by db export, skipping any already recorded
+    Import(DumpArgs),
+}
+
+#[derive(Args)]
+pub struct DumpArgs {
+    /// The dump file
+    pub dump: PathBuf,
+
+    /// SQLite database the scans are recorded in
+    #[arg(long, value_name = "PATH")]
+    pub db: PathBuf,
+}
+
+#[derive(Subcommand)]
+pub enum BaselineCommand {
+    /// Approve a results file written by --output json as the baseline
+    Set { results: PathBuf },
+    /// Rescan the baseline's targets and report only the endpoints that differ from it
+    Check(BaselineCheckArgs),
+}
+
+#[derive(Args)]
+pub struct BaselineCheckArgs {
+    /// Compare this results file instead of scanning again
+    #[arg(long, value_name = "FILE")]
+    pub results: Option<PathBuf>,
+
+    /// TOML config file with detailed settings for the rescan
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// File to write the deviations to instead of stdout
+    #[arg(short, long)]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct HostMatrixArgs {
+    /// Host header to send. Can be given multiple times
+    #[arg(long = "host", value_name = "HOST", required = true)]
+    pub hosts: Vec<String>,
+
+    /// Path to request
+    #[arg(long, default_value = "/")]
+    pub path: String,
+
+    /// Make the requests over TLS, sending each host as the SNI name
+    #[arg(long)]
+    pub tls: bool,
+
+    #[arg(short, long, value_enum, default_value_t = MatrixFormat::Csv)]
+    pub format: MatrixFormat,
+
+    /// File to write the matrix to instead of stdout
+    #[arg(short, long)]
+    pub out: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub scan: ScanArgs,
+}
+
+#[derive(Args)]
+pub struct SniMatrixArgs {
+    /// TLS endpoint to test, as HOST:PORT
+    pub endpoint: String,
+
+    /// Server name to send. Can be given multiple times. A handshake without SNI is always included
+    #[arg(long = "sni", value_name = "NAME", required = true)]
+    pub names: Vec<String>,
+
+    #[arg(short, long, value_enum, default_value_t = SniFormat::Text)]
+    pub format: SniFormat,
+
+    /// File to write the matrix to instead of stdout
+    #[arg(short, long)]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum SniFormat {
+    Text,
+    Csv,
+}
+
+#[derive(Args)]
+pub struct VerifyChangeArgs {
+    /// YAML file listing the targets, ports, and their expected states before and after the change
+    pub change_file: PathBuf,
+
+    /// Which side of the change to verify. "both" pauses between the scans while the change is applied
+    #[arg(long, value_enum, default_value_t = ChangePhase::Both)]
+    pub phase: ChangePhase,
+
+    /// File to write the verification report to instead of stdout
+    #[arg(short, long)]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq)]
+pub enum ChangePhase {
+    Both,
+    Pre,
+    Post,
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailPolicy {
+    /// Fail when no endpoint is open, e.g. a service that should be reachable
+    NoneOpen,
+    /// Fail when any endpoint is open, e.g. ports that should be firewalled
+    AnyOpen,
+}
+
+impl FailPolicy {
+    pub fn fails(self, open: usize) -> bool {
+        match self {
+            FailPolicy::NoneOpen => open == 0,
+            FailPolicy::AnyOpen => open > 0,
+        }
+    }
+}
+
+/// How `--scan-type` probes each port.
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScanType {
+    /// A full TCP connection, as without --scan-type
+    Connect,
+    /// A bare SYN, as --syn sends
+    Syn,
+    /// A bare FIN
+    Fin,
+    /// A segment with no flags set
+    Null,
+    /// FIN, PSH and URG together, lit up like a Christmas tree
+    Xmas,
+}
+
+#[derive(Args)]
+pub struct MatrixArgs {
+    /// Local address to connect from. Can be given multiple times
+    #[arg(short, long = "source", value_name = "SOURCE", required = true)]
+    pub sources: Vec<IpAddr>,
+
+    #[arg(short, long, value_enum, default_value_t = MatrixFormat::Csv)]
+    pub format: MatrixFormat,
+
+    /// File to write the matrix to instead of stdout
+    #[arg(short, long)]
+    pub out: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub scan: ScanArgs,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum MatrixFormat {
+    Csv,
+    Html,
+}
+
+/// Everything needed to describe a scan. Anything left unset is asked for
+/// interactively when the scan starts.
+#[derive(Args, Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+#[command(group(ArgGroup::new("discovery").multiple(true)))]
+pub struct ScanArgs {
+    /// Network to scan, as an address, CIDR (e.g. 10.0.0.0/24) or hostname. Brace patterns
+    /// like web{01..20}.example.com are expanded. Targets can also be read from file:PATH, - (stdin),
+    /// consul:SERVICE, docker:[NAME], k8s:[NAMESPACE/]SERVICE or aws:[tag:KEY=VALUE]. Can be given
+    /// multiple times
+    #[arg(short, long = "target", value_name = "TARGET")]
+    pub targets: Vec<String>,
+
+    /// Ports to scan, e.g. 22,80,8000-8100. Services can be given by name (ssh, https, postgres,
+    /// ...) and common sets of ports as web, mail, database, remote or printer. Add =http, =tls,
+    /// =ssh, =dns, =smtp, =imap, =pop3, =grpc, =ntp or =snmp to tell probes what a nonstandard
+    /// port speaks, e.g. 8443=tls
+    #[arg(short, long)]
+    pub ports: Option<String>,
+
+    /// Ports to leave out of --ports, in the same form, e.g. 8080 or mail
+    #[arg(long, value_name = "PORTS")]
+    pub exclude_ports: Option<String>,
+
+    /// Print the ports the scan would cover once --preset, --ports and --exclude-ports are
+    /// resolved, then exit without scanning
+    #[arg(long)]
+    pub print_ports: bool,
+
+    /// Start from a built-in scan: web-audit, lan-inventory, mail-exposure or printer-sweep.
+    /// Flags given alongside it override the preset's. See list-presets
+    #[arg(long, value_name = "NAME")]
+    pub preset: Option<String>,
+
+    /// Scan UDP ports instead of TCP. Ports that answer are open, ports that send back an ICMP
+    /// port unreachable are refused, and silent ports are open|filtered
+    #[arg(long)]
+    pub udp: bool,
+
+    /// Scan with bare SYN packets instead of full connections. A SYN/ACK is open and an RST
+    /// refused, and the handshake is never completed, which is faster on wide sweeps and
+    /// gentler on fragile services. The OS family of each host that answers is guessed from
+    /// the reply's TTL and window. Needs root or CAP_NET_RAW, and per-port criteria from the
+    /// config aren't checked since no connection is made
+    #[arg(long, conflicts_with = "udp")]
+    pub syn: bool,
+
+    /// How to probe each port. fin, null and xmas send segments a closed port must answer
+    /// with an RST and an open one ignores, so silence shows as open|filtered: they tell closed
+    /// from filtered where firewalls only block SYNs. Hosts that reset them all, as Windows
+    /// does, look entirely closed. Everything but connect needs root or CAP_NET_RAW
+    #[arg(long, value_name = "TYPE", value_enum, conflicts_with_all = ["udp", "syn"])]
+    pub scan_type: Option<ScanType>,
+
+    /// Make every connection through an HTTP proxy with CONNECT, given as
+    /// http://[USER:PASSWORD@]HOST[:PORT] with port 3128 by default, for networks whose only
+    /// way out is a proxy. What the proxy can't reach shows as refused, timed out or
+    /// unreachable by how it answers. Probes after the scan still connect directly
+    #[arg(
+        long,
+        value_name = "URL",
+        value_parser = HttpProxy::parse,
+        conflicts_with_all = ["udp", "syn", "scan_type", "interfaces"]
+    )]
+    pub proxy: Option<HttpProxy>,
+
+    /// Knock on each host with this sequence of ports before scanning it, e.g. 7000,8000,9000,
+    /// so services a knock daemon like knockd guards can still be checked. Add :udp to ports
+    /// knocked on with a UDP datagram instead of a SYN
+    #[arg(long, value_name = "PORTS", value_parser = KnockSequence::parse)]
+    pub knock: Option<KnockSequence>,
+
+    /// Send from this interface's address, after checking that every target is routed out of
+    /// it. Can be given multiple times to scan through each, e.g. one per VRF, with results
+    /// and summaries reported per interface
+    #[arg(long = "interface", value_name = "NAME", conflicts_with_all = ["watch", "tui"])]
+    pub interfaces: Vec<String>,
+
+    /// Once the scan completes, identify the service and version behind each open port from
+    /// its banner or its answer to a probe, reported in a service field. More probes can be
+    /// added as [[fingerprints]] in the config
+    #[arg(long)]
+    pub detect_services: bool,
+
+    /// Once the scan completes, try a TLS handshake with each open port and tag it open (tls)
+    /// or open (plaintext), e.g. to check an encryption policy across a subnet
+    #[arg(long)]
+    pub detect_tls: bool,
+
+    /// Check TLS as --detect-tls does and warn about certificates in a chain that expire within
+    /// DAYS, exiting with an error if any do
+    #[arg(long, value_name = "DAYS")]
+    pub cert_warn_days: Option<u64>,
+
+    /// Check TLS as --detect-tls does, then try TLS 1.0 to 1.3 against each TLS port and report
+    /// the versions it accepts, warning about TLS 1.0 and 1.1
+    #[arg(long)]
+    pub tls_audit: bool,
+
+    /// With --tls-audit, also list the cipher suites each version accepts, in the server's
+    /// order of preference, warning about weak ones such as RC4, 3DES and export suites
+    #[arg(long, requires = "tls_audit")]
+    pub tls_audit_ciphers: bool,
+
+    /// Once the scan completes, request a page from each open port that looks like a web
+    /// server and report its status code, Server header, redirect target and page title
+    #[arg(long)]
+    pub http_probe: bool,
+
+    /// Once the scan completes, read the identification string of each open SSH port, port 22
+    /// or any flagged =ssh, and report the server software and version, e.g. for patch audits
+    #[arg(long)]
+    pub ssh_probe: bool,
+
+    /// With --ssh-probe, also list the key exchange and host key algorithms each server
+    /// offers, warning about weak ones such as diffie-hellman-group1-sha1 and ssh-dss
+    #[arg(long, requires = "ssh_probe")]
+    pub ssh_algorithms: bool,
+
+    /// Once the scan completes, query each DNS port, port 53 or any flagged =dns, over UDP and
+    /// TCP for version.bind in the CHAOS class, reporting whether it answers and whether it
+    /// offers recursion
+    #[arg(long)]
+    pub dns_probe: bool,
+
+    /// With --dns-probe, ask for the A records of NAME instead of version.bind
+    #[arg(long, value_name = "NAME", requires = "dns_probe")]
+    pub dns_query: Option<String>,
+
+    /// Once the scan completes, ask each NTP port, 123 included, for the time and report its
+    /// stratum and how far its clock is from this machine's, warning about servers that are
+    /// unsynchronized or more than a second off. Needs --udp, as NTP is only served over UDP
+    #[arg(long)]
+    pub ntp_probe: bool,
+
+    /// Once the scan completes, ask each SNMP port, 161 included, for its sysDescr with each
+    /// --snmp-community in turn and warn about agents that answer one. Needs --udp, as SNMP is
+    /// only served over UDP
+    #[arg(long)]
+    pub snmp_probe: bool,
+
+    /// A community string --snmp-probe tries instead of public. Can be given multiple times
+    #[arg(
+        long = "snmp-community",
+        value_name = "COMMUNITY",
+        requires = "snmp_probe"
+    )]
+    pub snmp_communities: Vec<String>,
+
+    /// Once the scan completes, greet each SMTP, IMAP and POP3 port, look for STARTTLS among
+    /// its capabilities and upgrade where it's offered, warning about servers that don't
+    /// offer it or fail the upgrade
+    #[arg(long)]
+    pub starttls_probe: bool,
+
+    /// Once the scan completes, try a QUIC handshake over UDP offering HTTP/3 against each TLS
+    /// port, 443 or any flagged =tls, and report whether the server speaks QUIC and serves
+    /// HTTP/3, which TCP scanning alone misses
+    #[arg(long)]
+    pub quic_probe: bool,
+
+    /// The path --http-probe requests instead of /
+    #[arg(long, value_name = "PATH", requires = "http_probe")]
+    pub http_path: Option<String>,
+
+    /// Once the scan completes, ask each open port that looks like a web server to upgrade to
+    /// a WebSocket and report whether it switches, e.g. to check proxies pass upgrades through
+    #[arg(long)]
+    pub websocket_probe: bool,
+
+    /// The path --websocket-probe asks to upgrade instead of /
+    #[arg(long, value_name = "PATH", requires = "websocket_probe")]
+    pub websocket_path: Option<String>,
+
+    /// Once the scan completes, run the standard grpc.health.v1 health check against each open
+    /// port hinted as gRPC, 50051 included, and warn about any not SERVING. The check is made
+    /// over TLS where --detect-tls found it
+    #[arg(long)]
+    pub grpc_probe: bool,
+
+    /// The service --grpc-probe asks about instead of the server as a whole
+    #[arg(long, value_name = "NAME", requires = "grpc_probe")]
+    pub grpc_service: Option<String>,
+
+    /// Once the scan completes, trace the path to each port that timed out or was unreachable
+    /// with SYNs of increasing TTL, and report the last hop that answered, to show where
+    /// connectivity breaks
+    #[arg(long, conflicts_with = "udp")]
+    pub traceroute: bool,
+
+    /// Once the scan completes, find the largest packet that reaches each host with echo
+    /// requests that mustn't be fragmented, narrowing down from the local MTU, and warn about
+    /// paths below --pmtu-min, where an MTU black hole makes larger transfers time out. Needs
+    /// root or CAP_NET_RAW unless ping sockets are allowed
+    #[arg(long)]
+    pub pmtu: bool,
+
+    /// The path MTU in bytes below which --pmtu warns, 1400 by default
+    #[arg(long, value_name = "BYTES", requires = "pmtu")]
+    pub pmtu_min: Option<u16>,
+
+    /// Once the scan completes, connect over IPv4 and IPv6 at the same moment to every
+    /// hostname and port that resolved to both, and report how each went and which family
+    /// was faster, warning where only one connects, as a broken IPv6 route does
+    #[arg(long, conflicts_with_all = ["udp", "proxy"])]
+    pub happy_eyeballs: bool,
+
+    /// Ping every address first and only scan the ones that reply, which saves probing every
+    /// port of empty addresses. Needs root or CAP_NET_RAW unless ping sockets are allowed
+    #[arg(long, group = "discovery")]
+    pub ping: bool,
+
+    /// Find live hosts on directly attached subnets with ARP first, which even hosts that
+    /// drop ICMP and TCP answer, and only scan the ones that reply. Addresses elsewhere are
+    /// left to --ping, or scanned as usual without it. Needs root or CAP_NET_RAW
+    #[arg(long, group = "discovery")]
+    pub arp: bool,
+
+    /// With --ping or --arp, still scan the addresses that don't reply, e.g. where ICMP is
+    /// filtered, and only report which ones did
+    #[arg(long, requires = "discovery")]
+    pub assume_alive: bool,
+
+    /// Only print open results as they arrive. Every state is still counted in the summary
+    #[arg(long)]
+    pub open: bool,
+
+    /// Only print open results, the final summary and errors
+    #[arg(short, long)]
+    pub quiet: bool,
+
+    /// Print one line per host, listing all of its ports, once every port on it has answered
+    #[arg(long)]
+    pub by_host: bool,
+
+    /// Organize reports and output files by group: label (the first label), label:KEY
+    /// (labels like KEY:VALUE), subnet, subnet/PREFIX or service
+    #[arg(long, value_name = "GROUPING", value_parser = GroupBy::parse)]
+    pub group_by: Option<GroupBy>,
+
+    /// Hold results until the scan completes and print them sorted by address then port, so
+    /// repeated runs can be diffed
+    #[arg(long)]
+    pub sorted: bool,
+
+    /// Show a progress bar with the scan rate and time remaining on stderr
+    #[arg(long)]
+    pub progress: bool,
+
+    /// Show a live dashboard of hosts, results and throughput instead of printing lines as they
+    /// arrive. Press p to pause, f to filter and q to quit
+    #[arg(long)]
+    pub tui: bool,
+
+    /// Draw a histogram of open connection latencies under the summary
+    #[arg(long)]
+    pub latency_histogram: bool,
+
+    /// Format to write the scan results in once the scan completes
+    #[arg(long, value_enum, default_value_t)]
+    pub output: OutputFormat,
+
+    /// File to write the scan results to instead of stdout
+    #[arg(long, value_name = "PATH")]
+    pub output_file: Option<PathBuf>,
+
+    /// Also write the results to PATH in FORMAT (e.g. --report html audit.html). Can be given
+    /// multiple times
+    #[arg(long = "report", num_args = 2, value_names = ["FORMAT", "PATH"])]
+    pub reports: Vec<String>,
+
+    /// Rewrite the --output-file and --report files with the results so far after every N
+    /// results, so a scan that dies part way leaves them complete up to that point
+    /// [default: 100]
+    #[arg(long, value_name = "N")]
+    pub flush_every: Option<usize>,
+
+    /// Also rewrite them on this interval while the scan runs (e.g. 30s) [default: 5s]
+    #[arg(long, value_name = "INTERVAL", value_parser = units::parse_duration)]
+    #[serde(with = "units::optional_duration")]
+    pub flush_interval: Option<Duration>,
+
+    /// Once the scan completes, pack the results, every report written, the scan's settings
+    /// and config file and the run's log into one .tar.gz at PATH, e.g. to attach to a ticket
+    #[arg(long, value_name = "PATH", conflicts_with = "watch")]
+    pub bundle: Option<PathBuf>,
+
+    /// Exit with an error once the scan completes if its results match POLICY, so CI jobs and
+    /// cron scripts can branch on the exit code
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub fail_if: Option<FailPolicy>,
+
+    /// When the scan ends, write one JSON line to stderr with the counts per state, how long
+    /// it took and how it exited, whatever the output format, so wrappers don't have to parse
+    /// the main output
+    #[arg(long)]
+    pub summary_json: bool,
+
+    /// Also record the scan and its results in this SQLite database, for the history subcommand
+    #[arg(long, value_name = "PATH")]
+    pub db: Option<PathBuf>,
+
+    /// What the scan was run for, e.g. "pre-change baseline". Kept with the scan in --db and
+    /// in exports
+    #[arg(long = "label", value_name = "TEXT")]
+    pub run_label: Option<String>,
+
+    /// Who ran the scan. Kept with the scan in --db and in exports
+    #[arg(long, value_name = "NAME")]
+    pub operator: Option<String>,
+
+    /// Export a trace of the scan, with a span per connection, to this OTLP/HTTP collector,
+    /// e.g. http://localhost:4318
+    #[arg(long, value_name = "URL")]
+    pub otlp_endpoint: Option<String>,
+
+    /// POST the results as JSON to this URL once the scan completes, to trigger follow-up
+    /// jobs
+    #[arg(long, value_name = "URL")]
+    pub webhook: Option<String>,
+
+    /// TOML config file with probe chains and other detailed settings
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// File of "CIDR label" lines used to annotate results, e.g. known scanners or VPN pools.
+    /// Can be given multiple times
+    #[arg(long = "labels-file", value_name = "FILE")]
+    pub labels: Vec<PathBuf>,
+
+    /// Attach who owns each host, its inventory hostname and a description to its results and
+    /// reports, from a CSV file with ip, owner, hostname and description columns or the same as
+    /// JSON, e.g. a CMDB export
+    #[arg(long, value_name = "FILE")]
+    pub annotate: Option<PathBuf>,
+
+    /// Only scan addresses carrying this label. Can be given multiple times
+    #[arg(long = "only-label", value_name = "LABEL")]
+    pub only_labels: Vec<String>,
+
+    /// Don't scan addresses carrying this label. Can be given multiple times
+    #[arg(long = "skip-label", value_name = "LABEL")]
+    pub skip_labels: Vec<String>,
+
+    /// Look up the PTR name of every host before scanning and show it alongside the address in
+    /// results, so opens in a wide range are recognizable at a glance
+    #[arg(long)]
+    pub reverse_dns: bool,
+
+    /// Resolve every hostname fresh instead of reusing addresses saved by earlier runs
+    #[arg(long)]
+    pub no_dns_cache: bool,
+
+    /// Don't point out what changed since the last scan of the same targets and ports, or save
+    /// this scan's results for the next one
+    #[arg(long)]
+    pub no_compare: bool,
+
+    /// Keep probing every endpoint on this interval (e.g. 30s, 5m) until interrupted
+    #[arg(long, value_name = "INTERVAL", value_parser = units::parse_duration)]
+    #[serde(with = "units::optional_duration")]
+    pub watch: Option<Duration>,
+
+    /// How often --watch looks hostnames up again, so endpoints follow DNS changes [default: 5m]
+    #[arg(long, value_name = "INTERVAL", value_parser = units::parse_duration)]
+    #[serde(with = "units::optional_duration")]
+    pub reresolve: Option<Duration>,
+
+    /// With --watch, rewrite this file with the time on every heartbeat, so outside monitoring
+    /// can tell the watch has died when it goes stale
+    #[arg(long, value_name = "PATH")]
+    pub heartbeat_file: Option<PathBuf>,
+
+    /// With --watch, send a GET to this http:// or https:// URL on every heartbeat
+    #[arg(long, value_name = "URL")]
+    pub heartbeat_url: Option<String>,
+
+    /// When started as root, switch to this user before any host is probed. Defaults to the
+    /// user who ran sudo
+    #[arg(long, value_name = "USER")]
+    pub run_as: Option<String>,
+
+    /// With --watch, serve Prometheus metrics on http://ADDRESS/metrics, e.g. 127.0.0.1:9102
+    #[arg(long, value_name = "ADDRESS")]
+    pub metrics_listen: Option<SocketAddr>,
+
+    /// How often --watch sends a heartbeat [default: 1m]
+    #[arg(long, value_name = "INTERVAL", value_parser = units::parse_duration)]
+    #[serde(with = "units::optional_duration")]
+    pub heartbeat_interval: Option<Duration>,
+
+    /// Stop probing once the scan has run this long (e.g. 90s, 2h) and report what came in, or
+    /// with --watch stop watching
+    #[arg(long, value_name = "DURATION", value_parser = units::parse_duration)]
+    #[serde(with = "units::optional_duration")]
+    pub max_runtime: Option<Duration>,
+
+    /// Most of a banner or response any one probe reads (e.g. 512, 4kb, 1mb), overriding
+    /// max_probe_bytes in the config [default: 64kb]
+    #[arg(long, value_name = "SIZE", value_parser = units::parse_size)]
+    pub max_banner: Option<usize>,
+
+    /// How long each probe waits for an endpoint to answer (e.g. 300ms on a LAN, 10s over
+    /// satellite) before calling it a timeout [default: 3s]
+    #[arg(long, value_name = "DURATION", value_parser = units::parse_duration)]
+    #[serde(with = "units::optional_duration")]
+    pub timeout: Option<Duration>,
+
+    /// A different timeout for one port or network, e.g. 443=5s or 10.0.0.0/24=1s, for
+    /// services like databases and VPN concentrators that are slow to accept. Where several
+    /// apply to an endpoint the longest is used. Can be given multiple times
+    #[arg(
+        long = "timeout-for",
+        value_name = "PORT|CIDR=DURATION",
+        value_parser = TimeoutOverride::parse
+    )]
+    pub timeout_overrides: Vec<TimeoutOverride>,
+
+    /// Probe endpoints that time out or are unreachable up to this many more times, waiting
+    /// longer before each retry, so a lossy link's dropped packets aren't taken for closed
+    /// ports. Results record how many attempts they took
+    #[arg(long, value_name = "N")]
+    pub retries: Option<u32>,
+
+    /// Start at most this many connection attempts a second, or a minute or hour as in 200/s,
+    /// 30/m or 500/h, so scans stay under IDS thresholds and don't overwhelm small embedded
+    /// devices
+    #[arg(long, value_name = "RATE", value_parser = Rate::parse)]
+    pub rate: Option<Rate>,
+
+    /// Most endpoints probed at once, so wide scans don't run out of sockets or file
+    /// descriptors [default: 512, or 4096 with --adaptive]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub concurrency: Option<u32>,
+
+    /// Find how many endpoints to probe at once as the scan goes: start low, keep raising it
+    /// while probes answer promptly, and halve it when timeouts or running out of sockets
+    /// show up, never going above --concurrency. Filtered ports time out too, so scans of
+    /// mostly filtered ports settle lower
+    #[arg(long)]
+    pub adaptive: bool,
+}
@@ -0,0 +1,75 @@
+use crate::{VerbosityLevel, print_to_terminal};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Changes to the wall clock smaller than this are left to NTP slewing and
+/// timer jitter.
+const ADJUSTMENT_THRESHOLD: Duration = Duration::from_secs(1);
+
+static PROCESS_START: OnceLock<Instant> = OnceLock::new();
+
+/// Time since the process started on the monotonic clock, which orders
+/// results correctly however the wall clock is adjusted.
+pub fn monotonic() -> Duration {
+    PROCESS_START.get_or_init(Instant::now).elapsed()
+}
+
+/// Reads the wall clock and the monotonic clock side by side over a run.
+/// Durations are measured on the monotonic one, and the wall clock moving
+/// away from it is reported as an adjustment.
+pub struct RunClock {
+    pub started_at: SystemTime,
+    started: Instant,
+    /// The readings the next check is measured against
+    last: (Instant, SystemTime),
+    /// How far the wall clock has been set forward (or back, if negative)
+    /// over the run, in seconds
+    adjustment: f64,
+}
+
+impl RunClock {
+    pub fn start() -> RunClock {
+        let started = Instant::now();
+        let started_at = SystemTime::now();
+        RunClock {
+            started_at,
+            started,
+            last: (started, started_at),
+            adjustment: 0.0,
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Warns if the wall clock has jumped since the last check.
+    pub fn check(&mut self) {
+        let now = (Instant::now(), SystemTime::now());
+        let monotonic = now.0.duration_since(self.last.0).as_secs_f64();
+        let wall = match now.1.duration_since(self.last.1) {
+            Ok(forward) => forward.as_secs_f64(),
+            Err(backward) => -backward.duration().as_secs_f64(),
+        };
+        self.last = now;
+
+        let jump = wall - monotonic;
+        if jump.abs() < ADJUSTMENT_THRESHOLD.as_secs_f64() {
+            return;
+        }
+        self.adjustment += jump;
+        print_to_terminal(
+            format!(
+                "The system clock was set {} by {:.1}s mid-run. Timestamps after this are on the new time, durations are unaffected",
+                if jump > 0.0 { "forward" } else { "back" },
+                jump.abs()
+            ),
+            VerbosityLevel::WARN,
+        );
+    }
+
+    /// The total adjustment in milliseconds, if there was one.
+    pub fn adjustment_ms(&self) -> Option<f64> {
+        (self.adjustment != 0.0).then_some(self.adjustment * 1000.0)
+    }
+}
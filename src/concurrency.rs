@@ -0,0 +1,161 @@
+use crate::ConnectionStatus;
+use std::sync::Mutex;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::time::{Duration, Instant};
+
+/// Where `--adaptive` starts, before it has seen how the network copes.
+const INITIAL_LIMIT: usize = 32;
+/// The fewest probes `--adaptive` backs off to.
+const MIN_LIMIT: usize = 4;
+
+/// The additive-increase, multiplicative-decrease state behind
+/// `--adaptive`, kept like TCP's congestion window: the limit doubles every
+/// round while probes answer promptly, then grows by one a round once it
+/// has had to back off, and halves when timeouts or resource errors show up.
+struct Aimd {
+    limit: f64,
+    /// Permits the semaphore has been given, `limit` rounded down
+    granted: usize,
+    /// Permits to take back from probes as they finish, after a decrease
+    debt: usize,
+    /// Still doubling, until the first decrease
+    slow_start: bool,
+    /// Decreases closer together than this are the same congestion event
+    cooldown: Duration,
+    last_decrease: Option<Instant>,
+}
+
+/// How many endpoints are probed at once: a fixed `--concurrency`, or with
+/// `--adaptive` as many as the network turns out to take, up to it.
+pub struct Concurrency {
+    slots: Semaphore,
+    /// The most probes that may ever run at once
+    ceiling: usize,
+    adaptive: Option<Mutex<Aimd>>,
+}
+
+/// A probe's place among the ones in flight, held until it's done.
+pub struct Slot<'a> {
+    permit: Option<SemaphorePermit<'a>>,
+    concurrency: &'a Concurrency,
+}
+
+impl Concurrency {
+    pub fn fixed(limit: usize) -> Concurrency {
+        Concurrency {
+            slots: Semaphore::new(limit.max(1)),
+            ceiling: limit.max(1),
+            adaptive: None,
+        }
+    }
+
+    /// Starts low and adapts up to `ceiling`, treating everything within
+    /// `cooldown` of a back-off, usually the connect timeout, as part of it.
+    pub fn adaptive(ceiling: usize, cooldown: Duration) -> Concurrency {
+        let ceiling = ceiling.max(1);
+        let initial = INITIAL_LIMIT.min(ceiling);
+        Concurrency {
+            slots: Semaphore::new(initial),
+            ceiling,
+            adaptive: Some(Mutex::new(Aimd {
+                limit: initial as f64,
+                granted: initial,
+                debt: 0,
+                slow_start: true,
+                cooldown,
+                last_decrease: None,
+            })),
+        }
+    }
+
+    pub async fn acquire(&self) -> Slot<'_> {
+        Slot {
+            permit: self.slots.acquire().await.ok(),
+            concurrency: self,
+        }
+    }
+
+    /// The most probes that may ever run at once.
+    pub fn ceiling(&self) -> usize {
+        self.ceiling
+    }
+
+    /// Where `--adaptive` has settled, if it's in use.
+    pub fn adaptive_limit(&self) -> Option<usize> {
+        let aimd = self.adaptive.as_ref()?.lock().ok()?;
+        Some(aimd.granted)
+    }
+}
+
+/// What probes get without `--concurrency` or `--adaptive`, so that every
+/// scan, whatever started it, stays within the open file limit.
+impl Default for Concurrency {
+    fn default() -> Concurrency {
+        Concurrency::fixed(crate::DEFAULT_CONCURRENCY)
+    }
+}
+
+impl Slot<'_> {
+    /// Feeds a probe's outcome to `--adaptive`: an answer well within the
+    /// timeout lets more run at once, while a timeout or running out of
+    /// sockets or file descriptors halves how many do.
+    pub fn record(&self, status: ConnectionStatus, prompt: bool, resource_exhausted: bool) {
+        let Some(adaptive) = &self.concurrency.adaptive else {
+            return;
+        };
+        let Ok(mut aimd) = adaptive.lock() else {
+            return;
+        };
+        if resource_exhausted || status == ConnectionStatus::Timeout {
+            let now = Instant::now();
+            if aimd
+                .last_decrease
+                .is_some_and(|last| now.duration_since(last) < aimd.cooldown)
+            {
+                return;
+            }
+            aimd.last_decrease = Some(now);
+            aimd.slow_start = false;
+            aimd.limit = (aimd.limit / 2.0).max(MIN_LIMIT as f64);
+            let limit = aimd.limit as usize;
+            if limit < aimd.granted {
+                let mut debt = aimd.debt + aimd.granted - limit;
+                debt -= self.concurrency.slots.forget_permits(debt);
+                aimd.debt = debt;
+                aimd.granted = limit;
+            }
+        } else if prompt {
+            let increase = if aimd.slow_start {
+                1.0
+            } else {
+                1.0 / aimd.limit
+            };
+            aimd.limit = (aimd.limit + increase).min(self.concurrency.ceiling as f64);
+            let limit = aimd.limit as usize;
+            if limit > aimd.granted {
+                let mut added = limit - aimd.granted;
+                // Permits still owed from a decrease are cancelled first
+                let repaid = added.min(aimd.debt);
+                aimd.debt -= repaid;
+                added -= repaid;
+                self.concurrency.slots.add_permits(added);
+                aimd.granted = limit;
+            }
+        }
+    }
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        let Some(adaptive) = &self.concurrency.adaptive else {
+            return;
+        };
+        if let Ok(mut aimd) = adaptive.lock()
+            && aimd.debt > 0
+            && let Some(permit) = self.permit.take()
+        {
+            permit.forget();
+            aimd.debt -= 1;
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use crate::fingerprint::ServiceProbe;
+use crate::pools::PoolsConfig;
+use crate::probes::{ProbeChain, ProbeStep};
+use crate::tls::ClientIdentity;
+use crate::units;
+use crate::{ErrorCodes, VerbosityLevel, error_handler, print_to_terminal};
+use regex::{Regex, bytes};
+use serde::Deserialize;
+use serde::de::{self, Deserializer};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Settings that are too detailed to pass as flags, read from the TOML file
+/// given with `--config`.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Probes run in order against hosts where the chain's port is open
+    pub chains: Vec<ProbeChain>,
+    /// Stricter definitions of "open" for particular ports
+    pub criteria: Vec<OpenCriterion>,
+    /// Concurrency and rate limits per address family and protocol
+    pub pools: PoolsConfig,
+    /// Guards against services that send too much or never stop
+    pub limits: Limits,
+    /// Service detection probes, tried before the built-in ones
+    pub fingerprints: Vec<ServiceProbe>,
+}
+
+/// Caps on what a probe may cost, so a tarpit or a giant banner can't blow
+/// up the scan's memory or runtime.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Most bytes any one probe reads from a host, e.g. 65536 or "64kb"
+    #[serde(deserialize_with = "units::deserialize_size")]
+    pub max_probe_bytes: usize,
+    /// Longest any one probe may run, however its own steps are timed
+    #[serde(deserialize_with = "units::deserialize_duration")]
+    pub max_probe_time: Duration,
+    /// Probe chains run against hosts at once
+    pub max_enrichment_probes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_probe_bytes: 64 * 1024,
+            max_probe_time: Duration::from_secs(30),
+            max_enrichment_probes: 64,
+        }
+    }
+}
+
+/// A port only counts as open once the service sends something matching
+/// `expect` within `timeout`, rather than as soon as the connection is
+/// accepted. This catches firewalls that complete handshakes on behalf of
+/// hosts that aren't there. Services that wait to be spoken to first are
+/// sent `send`, or the bytes `send_hex` spells out for binary protocols.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OpenCriterion {
+    pub port: u16,
+    pub send: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_hex")]
+    pub send_hex: Option<Vec<u8>>,
+    /// Matched against the raw reply, so `(?-u:\x00)` finds a zero byte
+    #[serde(deserialize_with = "deserialize_bytes_regex")]
+    pub expect: bytes::Regex,
+    #[serde(
+        default = "default_criterion_timeout",
+        deserialize_with = "units::deserialize_duration"
+    )]
+    pub timeout: Duration,
+}
+
+fn default_criterion_timeout() -> Duration {
+    Duration::from_secs(2)
+}
+
+pub fn deserialize_regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+    let pattern = String::deserialize(deserializer)?;
+    Regex::new(&pattern).map_err(de::Error::custom)
+}
+
+impl OpenCriterion {
+    /// What to send once the connection opens, if anything.
+    pub fn payload(&self) -> Option<&[u8]> {
+        match (&self.send, &self.send_hex) {
+            (Some(send), _) => Some(send.as_bytes()),
+            (None, Some(send_hex)) => Some(send_hex),
+            (None, None) => None,
+        }
+    }
+}
+
+fn deserialize_bytes_regex<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<bytes::Regex, D::Error> {
+    let pattern = String::deserialize(deserializer)?;
+    bytes::Regex::new(&pattern).map_err(de::Error::custom)
+}
+
+/// Reads bytes written as hex digits, e.g. "00 0a ff", ignoring whitespace.
+fn deserialize_hex<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<u8>>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    let digits: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err(de::Error::custom("hex payloads need two digits per byte"));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair: String = pair.iter().collect();
+            u8::from_str_radix(&pair, 16)
+                .map_err(|_| de::Error::custom(format!("{:?} is not a hex byte", pair)))
+        })
+        .collect::<Result<Vec<u8>, D::Error>>()
+        .map(Some)
+}
+
+impl Config {
+    pub fn criterion_for(&self, port: u16) -> Option<&OpenCriterion> {
+        self.criteria
+            .iter()
+            .find(|criterion| criterion.port == port)
+    }
+
+    pub fn load(path: Option<&Path>) -> Config {
+        let Some(path) = path else {
+            return Config::default();
+        };
+        let path_name = path.display().to_string();
+
+        let contents = match fs::read_to_string(path) {
+            Ok(contents_result) => contents_result,
+            Err(_) => error_handler(ErrorCodes::CONFIG_FILE_INVALID, line!(), Some(&path_name)),
+        };
+        let config: Config = match toml::from_str(&contents) {
+            Ok(config_result) => config_result,
+            Err(e) => {
+                print_to_terminal(format!("{}", e), VerbosityLevel::ERROR);
+                error_handler(ErrorCodes::CONFIG_FILE_INVALID, line!(), Some(&path_name))
+            }
+        };
+        if let Err(problem) = config.check_criteria() {
+            print_to_terminal(problem, VerbosityLevel::ERROR);
+            error_handler(ErrorCodes::CONFIG_FILE_INVALID, line!(), Some(&path_name));
+        }
+        if let Err(problem) = config.check_client_certificates() {
+            print_to_terminal(problem, VerbosityLevel::ERROR);
+            error_handler(ErrorCodes::CONFIG_FILE_INVALID, line!(), Some(&path_name));
+        }
+
+        print_to_terminal(
+            format!("Loaded config: {}", path_name),
+            VerbosityLevel::DEBUG,
+        );
+        config
+    }
+
+    /// Makes sure no criterion says what to send twice over.
+    fn check_criteria(&self) -> Result<(), String> {
+        match self
+            .criteria
+            .iter()
+            .find(|criterion| criterion.send.is_some() && criterion.send_hex.is_some())
+        {
+            Some(criterion) => Err(format!(
+                "the criterion for port {} has both send and send_hex, give only one",
+                criterion.port
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Makes sure every TLS step's client certificate and key come as a pair
+    /// and can be read, so a typo doesn't surface as an unhealthy host.
+    fn check_client_certificates(&self) -> Result<(), String> {
+        for chain in &self.chains {
+            for step in &chain.steps {
+                match step {
+                    ProbeStep::Tls {
+                        client_cert: Some(client_cert),
+                        client_key: Some(client_key),
+                    } => {
+                        if let Err(e) = ClientIdentity::load(client_cert, client_key) {
+                            return Err(format!(
+                                "client certificate {} could not be loaded: {}",
+                                client_cert.display(),
+                                e
+                            ));
+                        }
+                    }
+                    ProbeStep::Tls {
+                        client_cert: Some(_),
+                        client_key: None,
+                    }
+                    | ProbeStep::Tls {
+                        client_cert: None,
+                        client_key: Some(_),
+                    } => {
+                        return Err(String::from(
+                            "tls steps need both client_cert and client_key, or neither",
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
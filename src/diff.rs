@@ -0,0 +1,178 @@
+use colored::{Color, Colorize};
+use std::collections::{HashMap, VecDeque};
+
+/// Stands in for a state on the side of a diff that didn't scan the endpoint.
+pub const NOT_SCANNED: &str = "not scanned";
+
+/// What kind of change a diff line shows, which decides its marker and color.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChangeKind {
+    NewlyOpen,
+    NewlyClosed,
+    /// Changed state again soon after its last change, in watch mode
+    Flapping,
+    Changed,
+}
+
+impl ChangeKind {
+    pub fn classify(from: &str, to: &str) -> ChangeKind {
+        match (
+            from.eq_ignore_ascii_case("open"),
+            to.eq_ignore_ascii_case("open"),
+        ) {
+            (false, true) => ChangeKind::NewlyOpen,
+            (true, false) if to != NOT_SCANNED => ChangeKind::NewlyClosed,
+            _ => ChangeKind::Changed,
+        }
+    }
+
+    fn marker(self) -> char {
+        match self {
+            ChangeKind::NewlyOpen => '+',
+            ChangeKind::NewlyClosed => '-',
+            ChangeKind::Flapping => '~',
+            ChangeKind::Changed => ' ',
+        }
+    }
+
+    pub fn color(self) -> Option<Color> {
+        match self {
+            ChangeKind::NewlyOpen => Some(Color::Green),
+            ChangeKind::NewlyClosed => Some(Color::Red),
+            ChangeKind::Flapping => Some(Color::Yellow),
+            ChangeKind::Changed => None,
+        }
+    }
+
+    /// The value of the `change` field a diff line is logged with, which the
+    /// terminal colors it by.
+    pub fn field(self) -> &'static str {
+        match self {
+            ChangeKind::NewlyOpen => "newly_open",
+            ChangeKind::NewlyClosed => "newly_closed",
+            ChangeKind::Flapping => "flapping",
+            ChangeKind::Changed => "changed",
+        }
+    }
+
+    pub fn from_field(field: &str) -> Option<ChangeKind> {
+        [
+            ChangeKind::NewlyOpen,
+            ChangeKind::NewlyClosed,
+            ChangeKind::Flapping,
+            ChangeKind::Changed,
+        ]
+        .into_iter()
+        .find(|kind| kind.field() == field)
+    }
+}
+
+/// One endpoint's change of state.
+pub struct DiffLine {
+    pub kind: ChangeKind,
+    pub endpoint: String,
+    pub from: String,
+    pub to: String,
+}
+
+impl DiffLine {
+    pub fn new(endpoint: String, from: String, to: String) -> DiffLine {
+        DiffLine {
+            kind: ChangeKind::classify(&from, &to),
+            endpoint,
+            from,
+            to,
+        }
+    }
+
+    /// The line with its endpoint padded to `width`, so the states line up.
+    pub fn render(&self, width: usize, color: bool) -> String {
+        let line = format!(
+            "{} {:<width$}  {} -> {}",
+            self.kind.marker(),
+            self.endpoint,
+            self.from,
+            self.to,
+            width = width
+        );
+        match self.kind.color() {
+            Some(kind_color) if color => line.color(kind_color).to_string(),
+            _ => line,
+        }
+    }
+
+    /// Logs the line uncolored, for the terminal to color by its `change`
+    /// field. Closes and flapping are warnings.
+    pub fn emit(&self, width: usize) {
+        let line = self.render(width, false);
+        match self.kind {
+            ChangeKind::NewlyClosed | ChangeKind::Flapping => {
+                tracing::warn!(change = self.kind.field(), "{}", line)
+            }
+            _ => tracing::info!(change = self.kind.field(), "{}", line),
+        }
+    }
+}
+
+/// The longest endpoint, which the lines are aligned on.
+pub fn width(lines: &[DiffLine]) -> usize {
+    lines
+        .iter()
+        .map(|line| line.endpoint.len())
+        .max()
+        .unwrap_or_default()
+}
+
+/// Renders every line aligned on the longest endpoint.
+pub fn render(lines: &[DiffLine], color: bool) -> String {
+    let width = width(lines);
+    lines
+        .iter()
+        .map(|line| line.render(width, color) + "\n")
+        .collect()
+}
+
+/// Follows each endpoint's state across the probes of a watch and logs a
+/// diff line whenever it changes. An endpoint that changes again within
+/// `FLAP_WINDOW` probes of its last change is shown as flapping.
+#[derive(Default)]
+pub struct ChangeTracker {
+    /// Each endpoint's last state, and whether each of its recent probes
+    /// changed it
+    endpoints: HashMap<String, (String, VecDeque<bool>)>,
+    /// Grows with the longest endpoint seen, so lines stay aligned
+    width: usize,
+}
+
+const FLAP_WINDOW: usize = 5;
+
+impl ChangeTracker {
+    pub fn observe(&mut self, endpoint: String, status: String) {
+        self.width = self.width.max(endpoint.len());
+        let Some((previous, recent)) = self.endpoints.get_mut(&endpoint) else {
+            self.endpoints.insert(endpoint, (status, VecDeque::new()));
+            return;
+        };
+
+        let changed = *previous != status;
+        recent.push_back(changed);
+        if recent.len() > FLAP_WINDOW {
+            recent.pop_front();
+        }
+        if !changed {
+            return;
+        }
+
+        let mut line = DiffLine::new(endpoint, previous.clone(), status.clone());
+        if recent.iter().filter(|changed| **changed).count() > 1 {
+            line.kind = ChangeKind::Flapping;
+        }
+        *previous = status;
+        line.emit(self.width);
+    }
+
+    /// Stops following an endpoint that's no longer watched.
+    pub fn forget(&mut self, endpoint: &str) {
+        self.endpoints.remove(endpoint);
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{VerbosityLevel, print_to_terminal, usage};
+use hickory_resolver::TokioResolver;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::lookup_host;
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    addresses: Vec<IpAddr>,
+    /// Unix time in seconds after which the records' TTL has run out
+    expires_at: u64,
+}
+
+/// Resolved hostnames kept on disk between runs, so scheduled scans of long
+/// hostname lists only go back to DNS once a record's TTL has expired.
+#[derive(Serialize, Deserialize, Default)]
+pub struct DnsCache {
+    entries: HashMap<String, CacheEntry>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl DnsCache {
+    /// Reads the cache from disk. A disabled cache starts empty and is never
+    /// written back.
+    pub fn load(enabled: bool) -> DnsCache {
+        let path = match dirs::cache_dir() {
+            Some(cache_dir) if enabled => {
+                cache_dir.join("connection-tester").join("dns-cache.json")
+            }
+            _ => return DnsCache::default(),
+        };
+
+        let mut cache: DnsCache = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        cache.path = Some(path);
+        cache
+    }
+
+    pub fn get(&self, hostname: &str) -> Option<Vec<IpAddr>> {
+        let entry = self.entries.get(hostname)?;
+        if entry.expires_at <= unix_seconds(SystemTime::now()) {
+            return None;
+        }
+        Some(entry.addresses.clone())
+    }
+
+    pub fn insert(&mut self, hostname: String, addresses: Vec<IpAddr>, expires_at: SystemTime) {
+        self.entries.insert(
+            hostname,
+            CacheEntry {
+                addresses,
+                expires_at: unix_seconds(expires_at),
+            },
+        );
+    }
+
+    pub fn save(&mut self) {
+        let Some(path) = self.path.clone() else {
+            return;
+        };
+
+        let now = unix_seconds(SystemTime::now());
+        self.entries.retain(|_, entry| entry.expires_at > now);
+
+        let saved = match (path.parent(), serde_json::to_string(self)) {
+            (Some(parent), Ok(contents)) => {
+                fs::create_dir_all(parent).is_ok() && fs::write(&path, contents).is_ok()
+            }
+            _ => false,
+        };
+        if !saved {
+            print_to_terminal(
+                format!("Could not save the DNS cache to {}", path.display()),
+                VerbosityLevel::WARN,
+            );
+        }
+    }
+}
+
+pub fn build_resolver() -> Option<TokioResolver> {
+    match TokioResolver::builder_tokio().and_then(|builder| builder.build()) {
+        Ok(resolver) => Some(resolver),
+        Err(e) => {
+            print_to_terminal(
+                format!(
+                    "Could not read the system DNS configuration ({}), hostnames won't be cached",
+                    e
+                ),
+                VerbosityLevel::WARN,
+            );
+            None
+        }
+    }
+}
+
+/// Looks up a hostname, returning its addresses and when they stop being
+/// valid. Without a resolver, the system lookup is used and nothing is
+/// cacheable.
+pub async fn resolve(
+    resolver: Option<TokioResolver>,
+    hostname: &str,
+) -> (Vec<IpAddr>, Option<SystemTime>) {
+    usage::dns_query();
+    match resolver {
+        Some(resolver) => match resolver.lookup_ip(hostname).await {
+            Ok(lookup) => {
+                let ttl: Duration = lookup
+                    .valid_until()
+                    .saturating_duration_since(Instant::now());
+                (lookup.iter().collect(), Some(SystemTime::now() + ttl))
+            }
+            Err(_) => (Vec::new(), None),
+        },
+        None => match lookup_host((hostname, 0)).await {
+            Ok(socket_addresses) => (socket_addresses.map(|address| address.ip()).collect(), None),
+            Err(_) => (Vec::new(), None),
+        },
+    }
+}
@@ -0,0 +1,348 @@
+use crate::hints::ServiceProtocol;
+use crate::{
+    ConnectionStatus, ScanResult, ScanSettings, VerbosityLevel, print_to_terminal, sandbox, usage,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, timeout};
+
+/// How long the server gets to answer each query.
+const QUERY_WAIT: Duration = Duration::from_secs(3);
+/// Answers over UDP fit a datagram; over TCP they can be up to 64K.
+const MAX_UDP_BYTES: usize = 4096;
+const TYPE_A: u16 = 1;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const CLASS_CH: u16 = 3;
+
+/// The question `--dns-probe` asks: `version.bind` in the CHAOS class,
+/// which BIND, Unbound, Knot and others answer with their version, or the
+/// A records of a `--dns-query` name.
+#[derive(Clone, Debug)]
+pub struct DnsQuestion {
+    name: String,
+    kind: u16,
+    class: u16,
+}
+
+impl DnsQuestion {
+    pub fn new(name: Option<&str>) -> DnsQuestion {
+        match name {
+            Some(name) => DnsQuestion {
+                name: name.trim_end_matches('.').to_string(),
+                kind: TYPE_A,
+                class: CLASS_IN,
+            },
+            None => DnsQuestion {
+                name: String::from("version.bind"),
+                kind: TYPE_TXT,
+                class: CLASS_CH,
+            },
+        }
+    }
+
+    /// The question as written in zone files, e.g. `version.bind CH TXT`.
+    fn describe(&self) -> String {
+        let class = if self.class == CLASS_CH { "CH" } else { "IN" };
+        let kind = if self.kind == TYPE_TXT { "TXT" } else { "A" };
+        format!("{} {} {}", self.name, class, kind)
+    }
+
+    /// A query message with recursion desired, so the reply says whether
+    /// the server would recurse.
+    fn message(&self, id: u16) -> Vec<u8> {
+        let mut message = id.to_be_bytes().to_vec();
+        // Recursion desired, one question
+        message.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+        for label in self.name.split('.').filter(|label| !label.is_empty()) {
+            let label = &label.as_bytes()[..label.len().min(63)];
+            message.push(label.len() as u8);
+            message.extend_from_slice(label);
+        }
+        message.push(0);
+        message.extend_from_slice(&self.kind.to_be_bytes());
+        message.extend_from_slice(&self.class.to_be_bytes());
+        message
+    }
+}
+
+/// How a DNS server answered `--dns-probe`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DnsSummary {
+    /// The question asked, e.g. `version.bind CH TXT`
+    pub query: String,
+    /// Whether the server answered over UDP and over TCP
+    pub udp: bool,
+    pub tcp: bool,
+    /// The response code, e.g. `NOERROR` or `REFUSED`
+    pub rcode: String,
+    /// Whether the server says it would resolve names it isn't
+    /// authoritative for, which it shouldn't for the whole internet
+    pub recursion_available: bool,
+    /// The TXT strings or addresses answered
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub answers: Vec<String>,
+}
+
+impl DnsSummary {
+    /// The summary for a result line, e.g. `answers over UDP and TCP,
+    /// NOERROR, recursion available: 9.18.24`.
+    pub fn describe(&self) -> String {
+        let transports = match (self.udp, self.tcp) {
+            (true, true) => "UDP and TCP",
+            (true, false) => "UDP only",
+            _ => "TCP only",
+        };
+        let mut description = format!("answers over {}, {}", transports, self.rcode);
+        if self.recursion_available {
+            description.push_str(", recursion available");
+        }
+        if !self.answers.is_empty() {
+            description.push_str(&format!(": {}", self.answers.join(", ")));
+        }
+        description
+    }
+}
+
+/// What a reply said, before the transports are put together.
+struct Reply {
+    rcode: u8,
+    recursion_available: bool,
+    answers: Vec<String>,
+}
+
+fn rcode_name(rcode: u8) -> String {
+    match rcode {
+        0 => String::from("NOERROR"),
+        1 => String::from("FORMERR"),
+        2 => String::from("SERVFAIL"),
+        3 => String::from("NXDOMAIN"),
+        4 => String::from("NOTIMP"),
+        5 => String::from("REFUSED"),
+        other => format!("RCODE{}", other),
+    }
+}
+
+/// Steps over a possibly compressed name, returning where what follows it
+/// starts.
+fn skip_name(message: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let length = *message.get(offset)? as usize;
+        match length {
+            0 => return Some(offset + 1),
+            // A pointer ends the name where it is
+            _ if length & 0xc0 == 0xc0 => return Some(offset + 2),
+            _ => offset += 1 + length,
+        }
+    }
+}
+
+fn read_u16(message: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        message.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+/// The strings of a TXT record's data, joined.
+fn txt_strings(mut data: &[u8]) -> String {
+    let mut text = String::new();
+    while let Some((&length, rest)) = data.split_first() {
+        let length = (length as usize).min(rest.len());
+        text.push_str(&String::from_utf8_lossy(&rest[..length]));
+        data = &rest[length..];
+    }
+    text
+}
+
+/// Parses the reply to query `id`.
+fn parse_reply(message: &[u8], id: u16) -> Option<Reply> {
+    if read_u16(message, 0)? != id || message.get(2)? & 0x80 == 0 {
+        return None;
+    }
+    let flags = read_u16(message, 2)?;
+    let questions = read_u16(message, 4)?;
+    let answer_count = read_u16(message, 6)?;
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = skip_name(message, offset)? + 4;
+    }
+    let mut answers = Vec::new();
+    for _ in 0..answer_count {
+        offset = skip_name(message, offset)?;
+        let kind = read_u16(message, offset)?;
+        let length = read_u16(message, offset + 8)? as usize;
+        let data = message.get(offset + 10..offset + 10 + length)?;
+        offset += 10 + length;
+        match kind {
+            TYPE_TXT => answers.push(txt_strings(data)),
+            TYPE_A if data.len() == 4 => {
+                answers.push(Ipv4Addr::from(<[u8; 4]>::try_from(data).ok()?).to_string())
+            }
+            TYPE_AAAA if data.len() == 16 => {
+                answers.push(Ipv6Addr::from(<[u8; 16]>::try_from(data).ok()?).to_string())
+            }
+            _ => {}
+        }
+    }
+    Some(Reply {
+        rcode: (flags & 0x000f) as u8,
+        recursion_available: flags & 0x0080 != 0,
+        answers,
+    })
+}
+
+fn not_a_reply() -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, "not a DNS reply")
+}
+
+async fn query_udp(target: SocketAddr, query: &[u8], id: u16) -> io::Result<Reply> {
+    let local = if target.is_ipv4() {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    } else {
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(local, 0)).await?;
+    socket.connect(target).await?;
+    socket.send(query).await?;
+    usage::sent(query.len());
+    let mut buffer = [0u8; MAX_UDP_BYTES];
+    // Stray datagrams that aren't our reply are skipped until the wait runs out
+    loop {
+        let read = socket.recv(&mut buffer).await?;
+        usage::received(read);
+        if let Some(reply) = parse_reply(&buffer[..read], id) {
+            return Ok(reply);
+        }
+    }
+}
+
+async fn query_tcp(target: SocketAddr, query: &[u8], id: u16) -> io::Result<Reply> {
+    usage::socket_opened();
+    let mut stream = TcpStream::connect(target).await?;
+    let mut framed = (query.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(query);
+    stream.write_all(&framed).await?;
+    usage::sent(framed.len());
+    let mut length = [0u8; 2];
+    stream.read_exact(&mut length).await?;
+    let mut message = vec![0u8; u16::from_be_bytes(length) as usize];
+    stream.read_exact(&mut message).await?;
+    usage::received(2 + message.len());
+    parse_reply(&message, id).ok_or_else(not_a_reply)
+}
+
+async fn within_wait(query: impl Future<Output = io::Result<Reply>>) -> io::Result<Reply> {
+    match timeout(QUERY_WAIT, query).await {
+        Ok(reply) => reply,
+        Err(_) => Err(io::Error::from(ErrorKind::TimedOut)),
+    }
+}
+
+/// Asks `question` over UDP and then TCP, keeping the UDP reply where both
+/// answer.
+async fn probe(target: SocketAddr, question: DnsQuestion) -> io::Result<DnsSummary> {
+    let id = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.subsec_nanos() as u16)
+        .unwrap_or(0x1337);
+    let query = question.message(id);
+    let udp = within_wait(query_udp(target, &query, id)).await;
+    let tcp = within_wait(query_tcp(target, &query, id)).await;
+    let (udp_answered, tcp_answered) = (udp.is_ok(), tcp.is_ok());
+    let reply = match (udp, tcp) {
+        (Ok(reply), _) | (Err(_), Ok(reply)) => reply,
+        (Err(_), Err(e)) => return Err(e),
+    };
+    Ok(DnsSummary {
+        query: question.describe(),
+        udp: udp_answered,
+        tcp: tcp_answered,
+        rcode: rcode_name(reply.rcode),
+        recursion_available: reply.recursion_available,
+        answers: reply.answers,
+    })
+}
+
+/// Sends a DNS query to every port in `results` that's hinted as DNS, port
+/// 53 included, and open, or open|filtered in a `--udp` scan, over both UDP
+/// and TCP. Records whether each server answers, its response code and
+/// answers, and whether it offers recursion, warning when it does. At most
+/// `max_enrichment_probes` servers are asked at once.
+pub async fn probe_dns(
+    settings: &Arc<ScanSettings>,
+    results: &mut [ScanResult],
+    question: DnsQuestion,
+) {
+    let limits = settings.config.limits;
+    let slots = Arc::new(Semaphore::new(limits.max_enrichment_probes.max(1)));
+    let mut set: JoinSet<(SocketAddr, Result<io::Result<DnsSummary>, String>)> = JoinSet::new();
+    for scan_result in results.iter().filter(|scan_result| {
+        matches!(
+            scan_result.status,
+            ConnectionStatus::Open | ConnectionStatus::OpenFiltered
+        ) && settings.hints.protocol_for(scan_result.ip.port()) == Some(ServiceProtocol::Dns)
+    }) {
+        let target = scan_result.ip;
+        let question = question.clone();
+        let slots = slots.clone();
+        set.spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            let summary =
+                sandbox::run_isolated(limits.max_probe_time, probe(target, question)).await;
+            (target, summary)
+        });
+    }
+    if set.is_empty() {
+        return;
+    }
+    print_to_terminal(
+        format!("Asking DNS servers for {}", question.describe()),
+        VerbosityLevel::INFO,
+    );
+
+    let mut summaries: HashMap<SocketAddr, DnsSummary> = HashMap::new();
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok((target, Ok(Ok(summary)))) => {
+                print_to_terminal(
+                    format!("{} - {}", target, summary.describe()),
+                    VerbosityLevel::INFO,
+                );
+                if summary.recursion_available {
+                    print_to_terminal(
+                        format!("{} - resolves recursively for this scanner", target),
+                        VerbosityLevel::WARN,
+                    );
+                }
+                summaries.insert(target, summary);
+            }
+            Ok((target, Ok(Err(e)))) => print_to_terminal(
+                format!("{} - no DNS answer: {}", target, e),
+                VerbosityLevel::WARN,
+            ),
+            Ok((target, Err(problem))) => {
+                print_to_terminal(format!("{} - {}", target, problem), VerbosityLevel::WARN)
+            }
+            Err(e) => {
+                print_to_terminal(
+                    format!("An error has occured: {}", e),
+                    VerbosityLevel::ERROR,
+                );
+            }
+        }
+    }
+    for scan_result in results.iter_mut() {
+        if let Some(summary) = summaries.remove(&scan_result.ip) {
+            scan_result.dns = Some(summary);
+        }
+    }
+}
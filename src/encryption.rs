@@ -0,0 +1,172 @@
+use crate::certificate::CertificateSummary;
+use crate::{
+    ConnectionStatus, ScanResult, ScanSettings, VerbosityLevel, print_to_terminal, sandbox, tls,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, timeout};
+
+/// How long a server gets to answer the ClientHello. Servers that say
+/// nothing in that time are left untagged, since a plaintext service
+/// waiting for its own protocol looks the same as a stalled TLS one.
+const HANDSHAKE_WAIT: Duration = Duration::from_secs(5);
+
+/// Whether an open port speaks TLS.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encryption {
+    Tls,
+    Plaintext,
+}
+
+impl fmt::Display for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Encryption::Tls => write!(f, "tls"),
+            Encryption::Plaintext => write!(f, "plaintext"),
+        }
+    }
+}
+
+/// What the handshake with a port found: whether it speaks TLS, and the
+/// certificate chain it presented if the handshake went through, the
+/// server's own certificate first.
+type Detection = (Encryption, Vec<CertificateSummary>);
+
+/// Sends a ClientHello and judges the answer. A handshake the server
+/// aborted with an alert still counts as TLS; bytes that aren't TLS, or
+/// the server hanging up on the hello, count as plaintext. The hostname
+/// the target was given as, if any, is sent as SNI.
+async fn detect(target: SocketAddr, server_name: Option<String>) -> Option<Detection> {
+    let handshake = tls::handshake(target, server_name.as_deref(), None);
+    match timeout(HANDSHAKE_WAIT, handshake).await {
+        Ok(Ok(stream)) => {
+            let chain = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|der| CertificateSummary::parse(der))
+                .collect();
+            Some((Encryption::Tls, chain))
+        }
+        Ok(Err(e)) if tls::is_tls_failure(&e) => Some((Encryption::Tls, Vec::new())),
+        Ok(Err(e)) if e.kind() == io::ErrorKind::ConnectionRefused => None,
+        Ok(Err(_)) => Some((Encryption::Plaintext, Vec::new())),
+        Err(_) => None,
+    }
+}
+
+/// The soonest any certificate in the chain expires, in days.
+pub fn days_left(chain: &[CertificateSummary]) -> Option<i64> {
+    chain.iter().map(|certificate| certificate.days_left).min()
+}
+
+/// The results whose certificate chains expire within `warn_days`.
+pub fn expiring(results: &[ScanResult], warn_days: u64) -> usize {
+    results
+        .iter()
+        .filter(|scan_result| {
+            days_left(&scan_result.certificates).is_some_and(|days| days < warn_days as i64)
+        })
+        .count()
+}
+
+/// Tries a TLS handshake with every open port in `results` and records on
+/// each whether it speaks TLS and the certificates it presented, printing
+/// a line per port and per certificate. Certificates that expire within
+/// `warn_days` are warned about. At most `max_enrichment_probes` endpoints
+/// are tried at once.
+pub async fn detect_encryption(
+    settings: &Arc<ScanSettings>,
+    results: &mut [ScanResult],
+    warn_days: Option<u64>,
+) {
+    let limits = settings.config.limits;
+    let slots = Arc::new(Semaphore::new(limits.max_enrichment_probes.max(1)));
+    let mut set: JoinSet<(SocketAddr, Result<Option<Detection>, String>)> = JoinSet::new();
+    for scan_result in results
+        .iter()
+        .filter(|scan_result| scan_result.status == ConnectionStatus::Open)
+    {
+        let target = scan_result.ip;
+        let server_name = scan_result.hostname.clone();
+        let slots = slots.clone();
+        set.spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            let encryption =
+                sandbox::run_isolated(limits.max_probe_time, detect(target, server_name)).await;
+            (target, encryption)
+        });
+    }
+    if set.is_empty() {
+        return;
+    }
+    print_to_terminal(String::from("Checking for TLS"), VerbosityLevel::INFO);
+
+    let mut found: HashMap<SocketAddr, Detection> = HashMap::new();
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok((target, Ok(Some((encryption, chain))))) => {
+                let level = match encryption {
+                    Encryption::Tls => VerbosityLevel::INFO,
+                    Encryption::Plaintext => VerbosityLevel::WARN,
+                };
+                print_to_terminal(format!("{} - open ({})", target, encryption), level);
+                for certificate in &chain {
+                    let level = match warn_days {
+                        Some(days) if certificate.days_left < days as i64 => VerbosityLevel::WARN,
+                        _ => VerbosityLevel::INFO,
+                    };
+                    print_to_terminal(
+                        format!("{} - certificate {}", target, certificate.describe()),
+                        level,
+                    );
+                }
+                found.insert(target, (encryption, chain));
+            }
+            Ok((target, Ok(None))) => print_to_terminal(
+                format!("{} - no answer to the TLS handshake", target),
+                VerbosityLevel::DEBUG,
+            ),
+            Ok((target, Err(problem))) => {
+                print_to_terminal(format!("{} - {}", target, problem), VerbosityLevel::WARN)
+            }
+            Err(e) => {
+                print_to_terminal(
+                    format!("An error has occured: {}", e),
+                    VerbosityLevel::ERROR,
+                );
+            }
+        }
+    }
+    for scan_result in results.iter_mut() {
+        if let Some((encryption, chain)) = found.remove(&scan_result.ip) {
+            scan_result.encryption = Some(encryption);
+            scan_result.certificates = chain;
+        }
+    }
+
+    if let Some(days) = warn_days {
+        let expiring = expiring(results, days);
+        let level = if expiring > 0 {
+            VerbosityLevel::WARN
+        } else {
+            VerbosityLevel::INFO
+        };
+        print_to_terminal(
+            format!(
+                "Certificates: {} endpoints have a certificate expiring within {} days",
+                expiring, days
+            ),
+            level,
+        );
+    }
+}
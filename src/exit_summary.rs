@@ -0,0 +1,90 @@
+use crate::output::ScanMetadata;
+use crate::{ConnectionStatus, ErrorCodes, ScanResult};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by `--summary-json` until the line has been written, so a scan that
+/// errors out after its summary doesn't get a second one.
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+/// The one line a wrapper reads to learn how the scan went. `exit` is `ok`,
+/// `fail-policy` when `--fail-if` failed it, `cert-expiring` when
+/// `--cert-warn-days` did, or `error` when it stopped on an error, in which
+/// case only the exit code is known.
+#[derive(Serialize)]
+struct ExitSummary {
+    exit: &'static str,
+    exit_code: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    endpoints: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hosts: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    statuses: Option<BTreeMap<String, usize>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expiring_certificates: Option<usize>,
+}
+
+pub fn request() {
+    PENDING.store(true, Ordering::Relaxed);
+}
+
+fn write(summary: ExitSummary) {
+    if PENDING.swap(false, Ordering::Relaxed) {
+        eprintln!("{}", serde_json::to_string(&summary).unwrap_or_default());
+    }
+}
+
+/// Writes the summary of a finished scan, which exits with `exit_code`.
+/// `expiring` is how many endpoints `--cert-warn-days` flagged, if given.
+pub fn write_finished(
+    metadata: &ScanMetadata,
+    results: &[ScanResult],
+    expiring: Option<usize>,
+    exit_code: i32,
+) {
+    let statuses = ConnectionStatus::ALL
+        .iter()
+        .map(|status| {
+            let count = results
+                .iter()
+                .filter(|scan_result| scan_result.status == *status)
+                .count();
+            (status.to_string().to_lowercase(), count)
+        })
+        .collect();
+    let hosts: HashSet<IpAddr> = results
+        .iter()
+        .map(|scan_result| scan_result.ip.ip())
+        .collect();
+    write(ExitSummary {
+        exit: match exit_code {
+            0 => "ok",
+            ErrorCodes::CERTIFICATES_EXPIRING => "cert-expiring",
+            _ => "fail-policy",
+        },
+        exit_code,
+        endpoints: Some(results.len()),
+        hosts: Some(hosts.len()),
+        statuses: Some(statuses),
+        duration_ms: Some(metadata.duration_ms),
+        expiring_certificates: expiring,
+    });
+}
+
+/// Writes the summary of a scan stopped by an error, if none was already.
+pub fn write_error(exit_code: i32) {
+    write(ExitSummary {
+        exit: "error",
+        exit_code,
+        endpoints: None,
+        hosts: None,
+        statuses: None,
+        duration_ms: None,
+        expiring_certificates: None,
+    });
+}
@@ -0,0 +1,77 @@
+use crate::{VerbosityLevel, print_to_terminal};
+
+/// File descriptors left over for everything that isn't a probe: stdio,
+/// output and log files, the database, DNS lookups and the like.
+const RESERVED: u64 = 64;
+
+/// The process's soft and hard limits on open files.
+#[cfg(unix)]
+fn open_file_limits() -> Option<(u64, u64)> {
+    // getrlimit only fills in the struct it's handed
+    let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+    Some((limit.rlim_cur as u64, limit.rlim_max as u64))
+}
+
+/// Sets the soft limit on open files, which may go as high as the hard one
+/// without privileges.
+#[cfg(unix)]
+fn raise_open_file_limit(soft: u64, hard: u64) -> bool {
+    let limit = libc::rlimit {
+        rlim_cur: soft as libc::rlim_t,
+        rlim_max: hard as libc::rlim_t,
+    };
+    unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) == 0 }
+}
+
+#[cfg(not(unix))]
+fn open_file_limits() -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(not(unix))]
+fn raise_open_file_limit(_soft: u64, _hard: u64) -> bool {
+    false
+}
+
+/// How many probes can run at once without running out of file
+/// descriptors, which would otherwise fail mid-scan with errors that look
+/// like the endpoints' fault. Raises the soft limit on open files as far as
+/// `requested` needs when the hard limit allows, and otherwise warns and
+/// gives back as many as fit.
+pub fn fit_concurrency(requested: usize) -> usize {
+    let Some((soft, hard)) = open_file_limits() else {
+        return requested;
+    };
+    let needed = requested as u64 + RESERVED;
+    if needed <= soft {
+        return requested;
+    }
+    if needed <= hard && raise_open_file_limit(needed, hard) {
+        print_to_terminal(
+            format!(
+                "Raised the open file limit from {} to {} to probe {} endpoints at once",
+                soft, needed, requested
+            ),
+            VerbosityLevel::INFO,
+        );
+        return requested;
+    }
+    // Failing that, as far as it will go
+    let available = if soft < hard && raise_open_file_limit(hard, hard) {
+        hard
+    } else {
+        soft
+    };
+    let fitted = available.saturating_sub(RESERVED).max(1) as usize;
+    print_to_terminal(
+        format!(
+            "Probing {} endpoints at once needs more file descriptors than the open file limit of {} allows, so at most {} will be. Raise the limit with ulimit -n",
+            requested, available, fitted
+        ),
+        VerbosityLevel::WARN,
+    );
+    fitted
+}
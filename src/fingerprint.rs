@@ -0,0 +1,321 @@
+use crate::config::deserialize_regex;
+use crate::encryption::Encryption;
+use crate::hints::ServiceProtocol;
+use crate::{
+    ConnectionStatus, ScanResult, ScanSettings, VerbosityLevel, print_to_terminal, sandbox, tls,
+    usage,
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, Instant, timeout_at};
+
+/// How long each probe waits for the service to answer.
+const PROBE_WAIT: Duration = Duration::from_secs(3);
+
+/// The probes shipped with the binary, in the same form as the
+/// `[[fingerprints]]` tables of a config file.
+const BUILT_IN: &str = r#"
+[[fingerprints]]
+name = "banner"
+
+[[fingerprints.matches]]
+service = "ssh"
+pattern = '^SSH-[\d.]+-([^\s]+)'
+version = "$1"
+
+[[fingerprints.matches]]
+service = "ftp"
+pattern = '^220[ -][^\r\n]*?((?:vsFTPd|ProFTPD|Pure-FTPd|FileZilla Server)[ \d.a-z]*)'
+version = "$1"
+
+[[fingerprints.matches]]
+service = "ftp"
+pattern = '^220[ -][^\r\n]*FTP'
+
+[[fingerprints.matches]]
+service = "smtp"
+pattern = '^220[ -][^\r\n]*?((?:Postfix|Exim [\d.]+|Sendmail [\d./]+|Microsoft ESMTP MAIL Service))'
+version = "$1"
+
+[[fingerprints.matches]]
+service = "smtp"
+pattern = '^220[ -][^\r\n]*SMTP'
+
+[[fingerprints.matches]]
+service = "pop3"
+pattern = '^\+OK'
+
+[[fingerprints.matches]]
+service = "imap"
+pattern = '^\* OK'
+
+[[fingerprints.matches]]
+service = "mysql"
+pattern = '(?s)^.{1,4}?\x0a(\d+\.[\d.]+[^\x00]*)\x00'
+version = "$1"
+
+[[fingerprints]]
+name = "http"
+ports = [80, 443, 8000, 8008, 8080, 8443, 8888, 9443]
+send = "GET / HTTP/1.0\r\n\r\n"
+
+[[fingerprints.matches]]
+service = "http"
+pattern = '(?is)^HTTP/1\.[01] \d{3}.*?\r\nServer: *([^\r\n]+)'
+version = "$1"
+
+[[fingerprints.matches]]
+service = "http"
+pattern = '^HTTP/1\.[01] \d{3}'
+
+[[fingerprints]]
+name = "redis"
+ports = [6379]
+send = "PING\r\n"
+
+[[fingerprints.matches]]
+service = "redis"
+pattern = '^\+PONG'
+
+[[fingerprints]]
+name = "memcached"
+ports = [11211]
+send = "version\r\n"
+
+[[fingerprints.matches]]
+service = "memcached"
+pattern = '^VERSION ([^\r\n]+)'
+version = "$1"
+"#;
+
+/// What runs behind an open port, as far as detection could tell.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Service {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+impl fmt::Display for Service {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.version {
+            Some(version) => write!(f, "{} {}", self.name, version),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// Something to send to a port, and what the answers to it look like.
+/// Probes without `send` just wait for a banner, and probes without
+/// `ports` are tried on every port.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceProbe {
+    pub name: String,
+    #[serde(default)]
+    pub ports: Vec<u16>,
+    #[serde(default)]
+    pub send: String,
+    pub matches: Vec<ServiceMatch>,
+}
+
+/// A response pattern. `version` may refer to the pattern's groups as
+/// `$1`, `$2` and so on.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceMatch {
+    pub service: String,
+    #[serde(deserialize_with = "deserialize_regex")]
+    pub pattern: Regex,
+    pub version: Option<String>,
+}
+
+impl ServiceMatch {
+    fn identify(&self, response: &str) -> Option<Service> {
+        let captures = self.pattern.captures(response)?;
+        let version = self.version.as_ref().and_then(|template| {
+            let mut version = String::new();
+            captures.expand(template, &mut version);
+            let version = version.trim().to_string();
+            (!version.is_empty()).then_some(version)
+        });
+        Some(Service {
+            name: self.service.clone(),
+            version,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct ProbeDatabase {
+    fingerprints: Vec<ServiceProbe>,
+}
+
+fn built_in() -> &'static [ServiceProbe] {
+    static PROBES: OnceLock<Vec<ServiceProbe>> = OnceLock::new();
+    PROBES.get_or_init(|| {
+        toml::from_str::<ProbeDatabase>(BUILT_IN)
+            .map(|database| database.fingerprints)
+            .unwrap_or_default()
+    })
+}
+
+/// The probes worth trying on `port`, most likely first: those meant for
+/// the port, then those tried everywhere, then the rest as a last resort
+/// for services on unusual ports. The config's come before the built-in
+/// ones at each stage.
+fn probes_for(configured: &[ServiceProbe], port: u16) -> Vec<&ServiceProbe> {
+    let all: Vec<&ServiceProbe> = configured.iter().chain(built_in()).collect();
+    let specific = all.iter().filter(|probe| probe.ports.contains(&port));
+    let general = all.iter().filter(|probe| probe.ports.is_empty());
+    let rest = all
+        .iter()
+        .filter(|probe| !probe.ports.is_empty() && !probe.ports.contains(&port));
+    specific.chain(general).chain(rest).copied().collect()
+}
+
+/// Sends the probe and reads until a pattern matches, the service stops
+/// sending, `max_bytes` have come in or the wait runs out.
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    probe: &ServiceProbe,
+    max_bytes: usize,
+) -> Option<Service> {
+    let deadline = Instant::now() + PROBE_WAIT;
+    if !probe.send.is_empty() {
+        timeout_at(deadline, stream.write_all(probe.send.as_bytes()))
+            .await
+            .ok()?
+            .ok()?;
+        usage::sent(probe.send.len());
+    }
+    let mut received: Vec<u8> = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while received.len() < max_bytes {
+        let read = match timeout_at(deadline, stream.read(&mut buffer)).await {
+            Ok(Ok(read)) if read > 0 => read,
+            _ => break,
+        };
+        usage::received(read);
+        received.extend_from_slice(&buffer[..read.min(max_bytes - received.len())]);
+        let response = String::from_utf8_lossy(&received);
+        if let Some(service) = probe
+            .matches
+            .iter()
+            .find_map(|service_match| service_match.identify(&response))
+        {
+            return Some(service);
+        }
+    }
+    None
+}
+
+/// Tries each probe for the endpoint on a fresh connection until one
+/// identifies the service. Over TLS, `server_name` is sent as SNI.
+async fn identify(
+    target: SocketAddr,
+    server_name: Option<String>,
+    probes: Vec<ServiceProbe>,
+    protocol: Option<ServiceProtocol>,
+    max_bytes: usize,
+) -> Option<Service> {
+    for probe in &probes {
+        let service = if protocol == Some(ServiceProtocol::Tls) {
+            let stream = tls::handshake(target, server_name.as_deref(), None)
+                .await
+                .ok()?;
+            exchange(stream, probe, max_bytes).await
+        } else {
+            usage::socket_opened();
+            let stream = TcpStream::connect(target).await.ok()?;
+            exchange(stream, probe, max_bytes).await
+        };
+        if service.is_some() {
+            return service;
+        }
+        print_to_terminal(
+            format!("{} - no match for the {} probe", target, probe.name),
+            VerbosityLevel::DEBUG,
+        );
+    }
+    None
+}
+
+/// Identifies the service behind every open port in `results` and records
+/// it on the result, printing a line per service found. At most
+/// `max_enrichment_probes` endpoints are probed at once.
+pub async fn detect_services(settings: &Arc<ScanSettings>, results: &mut [ScanResult]) {
+    let limits = settings.config.limits;
+    let slots = Arc::new(Semaphore::new(limits.max_enrichment_probes.max(1)));
+    let mut set: JoinSet<(SocketAddr, Result<Option<Service>, String>)> = JoinSet::new();
+    for scan_result in results
+        .iter()
+        .filter(|scan_result| scan_result.status == ConnectionStatus::Open)
+    {
+        let target = scan_result.ip;
+        let probes: Vec<ServiceProbe> = probes_for(&settings.config.fingerprints, target.port())
+            .into_iter()
+            .cloned()
+            .collect();
+        // A port found speaking TLS is probed through it, hinted or not
+        let protocol = match scan_result.encryption {
+            Some(Encryption::Tls) => Some(ServiceProtocol::Tls),
+            _ => settings.hints.protocol_for(target.port()),
+        };
+        let server_name = scan_result.hostname.clone();
+        let slots = slots.clone();
+        set.spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            let service = sandbox::run_isolated(limits.max_probe_time, async move {
+                identify(
+                    target,
+                    server_name,
+                    probes,
+                    protocol,
+                    limits.max_probe_bytes,
+                )
+                .await
+            })
+            .await;
+            (target, service)
+        });
+    }
+    if set.is_empty() {
+        return;
+    }
+    print_to_terminal(String::from("Detecting services"), VerbosityLevel::INFO);
+
+    let mut services: HashMap<SocketAddr, Service> = HashMap::new();
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok((target, Ok(Some(service)))) => {
+                print_to_terminal(format!("{} - {}", target, service), VerbosityLevel::INFO);
+                services.insert(target, service);
+            }
+            Ok((target, Ok(None))) => print_to_terminal(
+                format!("{} - service not recognised", target),
+                VerbosityLevel::DEBUG,
+            ),
+            Ok((target, Err(problem))) => {
+                print_to_terminal(format!("{} - {}", target, problem), VerbosityLevel::WARN)
+            }
+            Err(e) => {
+                print_to_terminal(
+                    format!("An error has occured: {}", e),
+                    VerbosityLevel::ERROR,
+                );
+            }
+        }
+    }
+    for scan_result in results.iter_mut() {
+        scan_result.service = services.remove(&scan_result.ip);
+    }
+}
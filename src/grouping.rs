@@ -0,0 +1,182 @@
+use crate::hints::PortHints;
+use crate::{ConnectionStatus, ScanResult, VerbosityLevel};
+use cidr::IpInet;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+
+/// Collects results per host so each host can be printed as one line once
+/// every one of its ports has answered.
+pub struct HostGroups {
+    remaining: HashMap<IpAddr, usize>,
+    finished: HashMap<IpAddr, Vec<(u16, ConnectionStatus, bool)>>,
+}
+
+impl HostGroups {
+    pub fn new(scan_targets: impl IntoIterator<Item = SocketAddr>) -> HostGroups {
+        let mut remaining: HashMap<IpAddr, usize> = HashMap::new();
+        for target in scan_targets {
+            *remaining.entry(target.ip()).or_default() += 1;
+        }
+        HostGroups {
+            remaining,
+            finished: HashMap::new(),
+        }
+    }
+
+    /// Records a result, returning the host's line and the level to print it
+    /// at if that was the host's last outstanding port. With `only_open`,
+    /// only open ports are listed and hosts without any give no line at all.
+    pub fn record(&mut self, scan_result: &ScanResult, only_open: bool) -> Option<(String, u8)> {
+        let ip = scan_result.ip.ip();
+        let ports = self.finished.entry(ip).or_default();
+        ports.push((
+            scan_result.ip.port(),
+            scan_result.status,
+            scan_result.acknowledgment.is_some(),
+        ));
+
+        let remaining = self.remaining.entry(ip).or_insert(1);
+        *remaining = remaining.saturating_sub(1);
+        if *remaining > 0 {
+            return None;
+        }
+
+        let mut ports = self.finished.remove(&ip).unwrap_or_default();
+        ports.sort_by_key(|(port, _, _)| *port);
+        let any_open = ports
+            .iter()
+            .any(|(_, status, _)| *status == ConnectionStatus::Open);
+        if only_open {
+            ports.retain(|(_, status, _)| *status == ConnectionStatus::Open);
+            if ports.is_empty() {
+                return None;
+            }
+        }
+
+        let listed: Vec<String> = ports
+            .iter()
+            .map(|(port, status, acknowledged)| {
+                let marker = if *acknowledged { " (acknowledged)" } else { "" };
+                format!("{} {}{}", port, status.to_string().to_lowercase(), marker)
+            })
+            .collect();
+        let labels = if scan_result.labels.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", scan_result.labels.join(", "))
+        };
+        let level = if any_open {
+            VerbosityLevel::INFO
+        } else {
+            VerbosityLevel::WARN
+        };
+        let host = match ip {
+            IpAddr::V4(_) => ip.to_string(),
+            IpAddr::V6(_) => format!("[{}]", ip),
+        };
+        let host = match &scan_result.reverse_dns {
+            Some(name) => format!("{} ({})", name, host),
+            None => host,
+        };
+        Some((format!("{}: {}{}", host, listed.join(", "), labels), level))
+    }
+}
+
+/// How `--group-by` organizes the results in reports.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum GroupBy {
+    /// By labels written `KEY:VALUE` or `KEY=VALUE`, or by the first label
+    /// when no key is given
+    Label(Option<String>),
+    /// By network, /24 for IPv4 and /64 for IPv6 unless a prefix is given
+    Subnet(Option<u8>),
+    /// By what the port speaks
+    Service,
+}
+
+impl GroupBy {
+    pub fn parse(value: &str) -> Result<GroupBy, String> {
+        match value.split_once([':', '/']) {
+            None if value == "label" => Ok(GroupBy::Label(None)),
+            None if value == "subnet" => Ok(GroupBy::Subnet(None)),
+            None if value == "service" => Ok(GroupBy::Service),
+            Some(("label", key)) if !key.is_empty() => Ok(GroupBy::Label(Some(key.to_string()))),
+            Some(("subnet", prefix)) => match prefix.parse::<u8>() {
+                Ok(prefix) if prefix <= 128 => Ok(GroupBy::Subnet(Some(prefix))),
+                _ => Err(format!("{} is not a prefix length", prefix)),
+            },
+            _ => Err(String::from(
+                "expected label, label:KEY, subnet, subnet/PREFIX or service",
+            )),
+        }
+    }
+
+    /// The group the result for `endpoint`, with `labels`, belongs in.
+    pub fn group_for(&self, endpoint: SocketAddr, labels: &[String], hints: &PortHints) -> String {
+        match self {
+            GroupBy::Label(None) => labels
+                .first()
+                .cloned()
+                .unwrap_or_else(|| String::from("unlabelled")),
+            GroupBy::Label(Some(key)) => labels
+                .iter()
+                .find(|label| {
+                    label
+                        .strip_prefix(key.as_str())
+                        .is_some_and(|value| value.starts_with([':', '=']))
+                })
+                .cloned()
+                .unwrap_or_else(|| format!("no {} label", key)),
+            GroupBy::Subnet(prefix) => {
+                let ip = endpoint.ip();
+                let (default, longest) = if ip.is_ipv4() { (24, 32) } else { (64, 128) };
+                let prefix = prefix.unwrap_or(default).min(longest);
+                match IpInet::new(ip, prefix) {
+                    Ok(inet) => inet.network().to_string(),
+                    Err(_) => ip.to_string(),
+                }
+            }
+            GroupBy::Service => {
+                let port = endpoint.port();
+                match hints.protocol_for(port) {
+                    Some(protocol) => protocol.name().to_string(),
+                    None => format!("port {}", port),
+                }
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for GroupBy {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<GroupBy, String> {
+        GroupBy::parse(&value)
+    }
+}
+
+impl From<GroupBy> for String {
+    fn from(group_by: GroupBy) -> String {
+        match group_by {
+            GroupBy::Label(None) => String::from("label"),
+            GroupBy::Label(Some(key)) => format!("label:{}", key),
+            GroupBy::Subnet(None) => String::from("subnet"),
+            GroupBy::Subnet(Some(prefix)) => format!("subnet/{}", prefix),
+            GroupBy::Service => String::from("service"),
+        }
+    }
+}
+
+/// Results split up by their group, in group order. Empty when the results
+/// weren't grouped.
+pub fn by_group(results: &[ScanResult]) -> BTreeMap<&str, Vec<&ScanResult>> {
+    let mut groups: BTreeMap<&str, Vec<&ScanResult>> = BTreeMap::new();
+    for scan_result in results {
+        if let Some(group) = &scan_result.group {
+            groups.entry(group).or_default().push(scan_result);
+        }
+    }
+    groups
+}
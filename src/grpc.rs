@@ -0,0 +1,508 @@
+use crate::encryption::Encryption;
+use crate::hints::ServiceProtocol;
+use crate::{
+    ConnectionStatus, ScanResult, ScanSettings, VerbosityLevel, hpack, print_to_terminal, sandbox,
+    tls, usage,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, timeout};
+
+/// How long the server gets to answer the health check.
+const CHECK_WAIT: Duration = Duration::from_secs(5);
+const HEALTH_CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+/// Every HTTP/2 connection starts with this, so servers can tell it from
+/// HTTP/1.1.
+const CONNECTION_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+/// The largest frame a server may send without being told otherwise.
+const MAX_FRAME_BYTES: usize = 16384;
+/// Health check responses are a few bytes; more than this isn't one.
+const MAX_RESPONSE_BYTES: usize = 64 * 1024;
+/// The request goes on the client's first stream.
+const STREAM: u32 = 1;
+
+const DATA: u8 = 0;
+const HEADERS: u8 = 1;
+const RST_STREAM: u8 = 3;
+const SETTINGS: u8 = 4;
+const PING: u8 = 6;
+const GOAWAY: u8 = 7;
+const CONTINUATION: u8 = 9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+/// The names of gRPC status codes, by code.
+const GRPC_STATUSES: &[&str] = &[
+    "OK",
+    "CANCELLED",
+    "UNKNOWN",
+    "INVALID_ARGUMENT",
+    "DEADLINE_EXCEEDED",
+    "NOT_FOUND",
+    "ALREADY_EXISTS",
+    "PERMISSION_DENIED",
+    "RESOURCE_EXHAUSTED",
+    "FAILED_PRECONDITION",
+    "ABORTED",
+    "OUT_OF_RANGE",
+    "UNIMPLEMENTED",
+    "INTERNAL",
+    "UNAVAILABLE",
+    "DATA_LOSS",
+    "UNAUTHENTICATED",
+];
+/// The statuses a `HealthCheckResponse` can carry, by number.
+const SERVING_STATUSES: &[&str] = &["UNKNOWN", "SERVING", "NOT_SERVING", "SERVICE_UNKNOWN"];
+
+/// How a gRPC server answered `grpc.health.v1.Health/Check`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GrpcSummary {
+    /// The service asked about, empty for the server as a whole
+    pub service: String,
+    /// Whether the check was made over TLS
+    pub tls: bool,
+    /// The status the server gave, e.g. `SERVING` or `NOT_SERVING`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    /// Why there's no status, e.g. `UNIMPLEMENTED` from a server without
+    /// the health service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub problem: Option<String>,
+}
+
+impl GrpcSummary {
+    pub fn serving(&self) -> bool {
+        self.status.as_deref() == Some("SERVING")
+    }
+
+    /// The summary for a result line, e.g. `SERVING` or `NOT_SERVING for
+    /// orders.Orders`.
+    pub fn describe(&self) -> String {
+        let service = match self.service.as_str() {
+            "" => String::new(),
+            service => format!(" for {}", service),
+        };
+        match &self.status {
+            Some(status) => format!("{}{}", status, service),
+            None => format!(
+                "health check failed{}: {}",
+                service,
+                self.problem.as_deref().unwrap_or("no status")
+            ),
+        }
+    }
+}
+
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream: u32,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    /// The payload without padding and, for HEADERS, the priority fields.
+    fn content(&self) -> io::Result<&[u8]> {
+        let mut content = self.payload.as_slice();
+        if self.flags & PADDED != 0 && matches!(self.kind, DATA | HEADERS) {
+            let (&padding, rest) = content
+                .split_first()
+                .ok_or_else(|| protocol_error("empty padded frame"))?;
+            content = rest
+                .get(..rest.len().saturating_sub(padding as usize))
+                .filter(|_| padding as usize <= rest.len())
+                .ok_or_else(|| protocol_error("padding longer than the frame"))?;
+        }
+        if self.flags & PRIORITY != 0 && self.kind == HEADERS {
+            content = content
+                .get(5..)
+                .ok_or_else(|| protocol_error("truncated priority"))?;
+        }
+        Ok(content)
+    }
+
+    /// The error code RST_STREAM and GOAWAY frames carry.
+    fn error_code(&self) -> u32 {
+        let offset = if self.kind == GOAWAY { 4 } else { 0 };
+        self.payload
+            .get(offset..offset + 4)
+            .map(|code| u32::from_be_bytes([code[0], code[1], code[2], code[3]]))
+            .unwrap_or_default()
+    }
+}
+
+fn protocol_error(what: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, what.to_string())
+}
+
+fn push_frame(buffer: &mut Vec<u8>, kind: u8, flags: u8, id: u32, payload: &[u8]) {
+    buffer.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    buffer.push(kind);
+    buffer.push(flags);
+    buffer.extend_from_slice(&id.to_be_bytes());
+    buffer.extend_from_slice(payload);
+}
+
+async fn write_frame<S>(
+    stream: &mut S,
+    kind: u8,
+    flags: u8,
+    id: u32,
+    payload: &[u8],
+) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut frame = Vec::with_capacity(9 + payload.len());
+    push_frame(&mut frame, kind, flags, id, payload);
+    stream.write_all(&frame).await?;
+    usage::sent(frame.len());
+    Ok(())
+}
+
+async fn read_frame<S>(stream: &mut S) -> io::Result<Frame>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut header = [0u8; 9];
+    stream.read_exact(&mut header).await?;
+    let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+    if length > MAX_FRAME_BYTES {
+        return Err(protocol_error("frame larger than allowed"));
+    }
+    let mut payload = vec![0u8; length];
+    stream.read_exact(&mut payload).await?;
+    usage::received(9 + length);
+    Ok(Frame {
+        kind: header[3],
+        flags: header[4],
+        stream: u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff,
+        payload,
+    })
+}
+
+fn push_varint(buffer: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        buffer.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn read_varint(message: &[u8], offset: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *message.get(*offset)?;
+        *offset += 1;
+        value |= (byte as u64 & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// A length-prefixed gRPC message holding a `HealthCheckRequest`, whose one
+/// field is the service name.
+fn health_check_request(service: &str) -> Vec<u8> {
+    let mut message = Vec::new();
+    if !service.is_empty() {
+        message.push(0x0a);
+        push_varint(&mut message, service.len());
+        message.extend_from_slice(service.as_bytes());
+    }
+    let mut framed = vec![0];
+    framed.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    framed.extend_from_slice(&message);
+    framed
+}
+
+/// The status in a length-prefixed `HealthCheckResponse`, whose first field
+/// is the status number and left out when it's 0.
+fn parse_health_response(data: &[u8]) -> io::Result<&'static str> {
+    let invalid = || protocol_error("not a HealthCheckResponse");
+    let (&compressed, rest) = data.split_first().ok_or_else(invalid)?;
+    if compressed != 0 {
+        return Err(protocol_error("compressed response"));
+    }
+    let length = u32::from_be_bytes(rest.get(..4).ok_or_else(invalid)?.try_into().unwrap());
+    let message = rest.get(4..4 + length as usize).ok_or_else(invalid)?;
+    let mut status = 0;
+    let mut offset = 0;
+    while offset < message.len() {
+        let tag = read_varint(message, &mut offset).ok_or_else(invalid)?;
+        match tag & 0x7 {
+            0 => {
+                let value = read_varint(message, &mut offset).ok_or_else(invalid)?;
+                if tag >> 3 == 1 {
+                    status = value;
+                }
+            }
+            1 => offset += 8,
+            2 => {
+                let length = read_varint(message, &mut offset).ok_or_else(invalid)?;
+                offset += length as usize;
+            }
+            5 => offset += 4,
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(SERVING_STATUSES
+        .get(status as usize)
+        .copied()
+        .unwrap_or("UNKNOWN"))
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header, _)| header == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// Sends the health check as the first request on a fresh HTTP/2
+/// connection and reads the response headers, body and trailers.
+async fn check<S>(
+    stream: &mut S,
+    authority: &str,
+    service: &str,
+    use_tls: bool,
+) -> io::Result<GrpcSummary>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut block = Vec::new();
+    hpack::push_indexed(&mut block, 3);
+    hpack::push_indexed(&mut block, if use_tls { 7 } else { 6 });
+    hpack::push_literal_indexed_name(&mut block, 4, HEALTH_CHECK_PATH);
+    hpack::push_literal_indexed_name(&mut block, 1, authority);
+    hpack::push_literal_indexed_name(&mut block, 31, "application/grpc");
+    hpack::push_literal(&mut block, "te", "trailers");
+    hpack::push_literal_indexed_name(
+        &mut block,
+        58,
+        concat!("connection-tester/", env!("CARGO_PKG_VERSION")),
+    );
+    // Sent in one go, so servers that close on seeing the preface can't
+    // break the pipe halfway
+    let mut request = CONNECTION_PREFACE.to_vec();
+    push_frame(&mut request, SETTINGS, 0, 0, &[]);
+    push_frame(&mut request, HEADERS, END_HEADERS, STREAM, &block);
+    push_frame(
+        &mut request,
+        DATA,
+        END_STREAM,
+        STREAM,
+        &health_check_request(service),
+    );
+    stream.write_all(&request).await?;
+    usage::sent(request.len());
+
+    // A server's connection preface is a SETTINGS frame; anything else means
+    // it doesn't speak HTTP/2
+    let first = read_frame(stream).await.map_err(|e| match e.kind() {
+        ErrorKind::InvalidData => protocol_error("not an HTTP/2 server"),
+        _ => e,
+    })?;
+    if first.kind != SETTINGS || first.flags & ACK != 0 {
+        return Err(protocol_error("not an HTTP/2 server"));
+    }
+    write_frame(stream, SETTINGS, ACK, 0, &[]).await?;
+
+    let mut decoder = hpack::Decoder::new();
+    let mut header_blocks: Vec<Vec<(String, String)>> = Vec::new();
+    let mut pending_block: Option<(Vec<u8>, bool)> = None;
+    let mut data = Vec::new();
+    loop {
+        let frame = read_frame(stream).await?;
+        if let Some((block, _)) = pending_block.as_mut() {
+            if frame.kind != CONTINUATION || frame.stream != STREAM {
+                return Err(protocol_error("header block interrupted"));
+            }
+            block.extend_from_slice(&frame.payload);
+        }
+        match frame.kind {
+            SETTINGS if frame.flags & ACK == 0 => {
+                write_frame(stream, SETTINGS, ACK, 0, &[]).await?
+            }
+            PING if frame.flags & ACK == 0 => {
+                write_frame(stream, PING, ACK, 0, &frame.payload).await?
+            }
+            GOAWAY => {
+                return Err(protocol_error(&format!(
+                    "connection refused with GOAWAY code {}",
+                    frame.error_code()
+                )));
+            }
+            RST_STREAM if frame.stream == STREAM => {
+                return Err(protocol_error(&format!(
+                    "request reset with code {}",
+                    frame.error_code()
+                )));
+            }
+            HEADERS if frame.stream == STREAM => {
+                pending_block = Some((frame.content()?.to_vec(), frame.flags & END_STREAM != 0));
+            }
+            DATA if frame.stream == STREAM => {
+                data.extend_from_slice(frame.content()?);
+                if data.len() > MAX_RESPONSE_BYTES {
+                    return Err(protocol_error("response too long"));
+                }
+                if frame.flags & END_STREAM != 0 {
+                    break;
+                }
+            }
+            _ => {}
+        }
+        if frame.flags & END_HEADERS != 0
+            && matches!(frame.kind, HEADERS | CONTINUATION)
+            && let Some((block, ended)) = pending_block.take()
+        {
+            header_blocks.push(decoder.decode(&block)?);
+            if ended {
+                break;
+            }
+        }
+    }
+
+    let Some(response) = header_blocks.first() else {
+        return Err(protocol_error("no response headers"));
+    };
+    let mut summary = GrpcSummary {
+        service: service.to_string(),
+        tls: use_tls,
+        status: None,
+        problem: None,
+    };
+    let http_status = header(response, ":status").unwrap_or_default();
+    if http_status != "200" {
+        summary.problem = Some(format!("HTTP {}", http_status));
+        return Ok(summary);
+    }
+    // Errors can come as trailers or, with no body, in the response headers
+    let trailers = header_blocks.last().unwrap_or(response);
+    let grpc_status = header(trailers, "grpc-status")
+        .and_then(|status| status.parse::<usize>().ok())
+        .unwrap_or(2);
+    if grpc_status != 0 {
+        let name = GRPC_STATUSES.get(grpc_status).copied().unwrap_or("UNKNOWN");
+        summary.problem = Some(match header(trailers, "grpc-message") {
+            Some(message) if !message.is_empty() => format!("{}: {}", name, message),
+            _ => name.to_string(),
+        });
+        return Ok(summary);
+    }
+    summary.status = Some(parse_health_response(&data)?.to_string());
+    Ok(summary)
+}
+
+/// Asks about `service` over cleartext HTTP/2, or over TLS with `h2`
+/// offered and `server_name` as SNI if `use_tls`.
+async fn probe(
+    target: SocketAddr,
+    authority: String,
+    server_name: Option<String>,
+    service: String,
+    use_tls: bool,
+) -> io::Result<GrpcSummary> {
+    if use_tls {
+        let mut stream = tls::handshake_offering(target, server_name.as_deref(), b"h2").await?;
+        check(&mut stream, &authority, &service, use_tls).await
+    } else {
+        usage::socket_opened();
+        let mut stream = TcpStream::connect(target).await?;
+        check(&mut stream, &authority, &service, use_tls).await
+    }
+}
+
+/// Runs the standard gRPC health check against every open port in
+/// `results` hinted as gRPC, 50051 included, asking about `service` or
+/// the server as a whole if it's empty. The check is made over TLS where
+/// `--detect-tls` found it. Anything but SERVING is warned about. At most
+/// `max_enrichment_probes` servers are checked at once.
+pub async fn probe_grpc(settings: &Arc<ScanSettings>, results: &mut [ScanResult], service: &str) {
+    let limits = settings.config.limits;
+    let slots = Arc::new(Semaphore::new(limits.max_enrichment_probes.max(1)));
+    let mut set: JoinSet<(SocketAddr, Result<io::Result<GrpcSummary>, String>)> = JoinSet::new();
+    for scan_result in results.iter().filter(|scan_result| {
+        scan_result.status == ConnectionStatus::Open
+            && settings.hints.protocol_for(scan_result.ip.port()) == Some(ServiceProtocol::Grpc)
+    }) {
+        let target = scan_result.ip;
+        let use_tls = scan_result.encryption == Some(Encryption::Tls);
+        let authority = match &scan_result.hostname {
+            Some(hostname) => format!("{}:{}", hostname, target.port()),
+            None => target.to_string(),
+        };
+        let server_name = scan_result.hostname.clone();
+        let service = service.to_string();
+        let slots = slots.clone();
+        set.spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            let summary = sandbox::run_isolated(limits.max_probe_time, async move {
+                match timeout(
+                    CHECK_WAIT,
+                    probe(target, authority, server_name, service, use_tls),
+                )
+                .await
+                {
+                    Ok(summary) => summary,
+                    Err(_) => Err(io::Error::from(ErrorKind::TimedOut)),
+                }
+            })
+            .await;
+            (target, summary)
+        });
+    }
+    if set.is_empty() {
+        return;
+    }
+    print_to_terminal(
+        String::from("Checking the health of gRPC servers"),
+        VerbosityLevel::INFO,
+    );
+
+    let mut summaries: HashMap<SocketAddr, GrpcSummary> = HashMap::new();
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok((target, Ok(Ok(summary)))) => {
+                let level = if summary.serving() {
+                    VerbosityLevel::INFO
+                } else {
+                    VerbosityLevel::WARN
+                };
+                print_to_terminal(format!("{} - {}", target, summary.describe()), level);
+                summaries.insert(target, summary);
+            }
+            Ok((target, Ok(Err(e)))) => print_to_terminal(
+                format!("{} - no gRPC server: {}", target, e),
+                VerbosityLevel::WARN,
+            ),
+            Ok((target, Err(problem))) => {
+                print_to_terminal(format!("{} - {}", target, problem), VerbosityLevel::WARN)
+            }
+            Err(e) => {
+                print_to_terminal(
+                    format!("An error has occured: {}", e),
+                    VerbosityLevel::ERROR,
+                );
+            }
+        }
+    }
+    for scan_result in results.iter_mut() {
+        if let Some(summary) = summaries.remove(&scan_result.ip) {
+            scan_result.grpc = Some(summary);
+        }
+    }
+}
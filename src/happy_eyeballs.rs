@@ -0,0 +1,172 @@
+use crate::{ConnectionStatus, ScanResult, ScanSettings, VerbosityLevel, print_to_terminal, usage};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, Instant, timeout};
+
+/// How one address family fared in a `--happy-eyeballs` race.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FamilyAttempt {
+    /// The address of the family that was tried, the lowest the name has
+    pub address: SocketAddr,
+    /// How the connection went, as a result line writes it, e.g. `Open`
+    pub outcome: String,
+    /// How long the connection took to go through or fail
+    pub latency_ms: f64,
+}
+
+impl FamilyAttempt {
+    fn connected(&self) -> bool {
+        self.outcome == ConnectionStatus::Open.to_string()
+    }
+}
+
+/// The IPv4 and IPv6 connections `--happy-eyeballs` raced to one hostname
+/// and port.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DualStackSummary {
+    pub ipv4: FamilyAttempt,
+    pub ipv6: FamilyAttempt,
+}
+
+impl DualStackSummary {
+    /// The family whose connection went through first, if either did.
+    pub fn faster(&self) -> Option<&'static str> {
+        match (self.ipv4.connected(), self.ipv6.connected()) {
+            (true, true) if self.ipv6.latency_ms <= self.ipv4.latency_ms => Some("IPv6"),
+            (true, _) => Some("IPv4"),
+            (false, true) => Some("IPv6"),
+            (false, false) => None,
+        }
+    }
+
+    /// Whether only one family connected, so clients that try the other
+    /// first stall until they fall back.
+    pub fn one_sided(&self) -> bool {
+        self.ipv4.connected() != self.ipv6.connected()
+    }
+
+    /// The summary for a result line, e.g. `IPv4 Open in 1.2 ms, IPv6
+    /// Timeout in 3000.0 ms, IPv4 faster`.
+    pub fn describe(&self) -> String {
+        let winner = match self.faster() {
+            Some(family) => format!("{} faster", family),
+            None => String::from("neither connected"),
+        };
+        format!(
+            "IPv4 {} in {:.1} ms, IPv6 {} in {:.1} ms, {}",
+            self.ipv4.outcome,
+            self.ipv4.latency_ms,
+            self.ipv6.outcome,
+            self.ipv6.latency_ms,
+            winner
+        )
+    }
+}
+
+/// Connects to `target` and times it, the way a plain scan would.
+async fn attempt(target: SocketAddr, connect_timeout: Duration) -> FamilyAttempt {
+    usage::socket_opened();
+    let started = Instant::now();
+    let status = match timeout(connect_timeout, TcpStream::connect(target)).await {
+        Err(_) => ConnectionStatus::Timeout,
+        Ok(Ok(_)) => ConnectionStatus::Open,
+        Ok(Err(e)) => match e.kind() {
+            ErrorKind::ConnectionRefused => ConnectionStatus::Refused,
+            ErrorKind::HostUnreachable | ErrorKind::NetworkUnreachable => {
+                ConnectionStatus::Unreachable
+            }
+            _ => ConnectionStatus::Timeout,
+        },
+    };
+    FamilyAttempt {
+        address: target,
+        outcome: status.to_string(),
+        latency_ms: started.elapsed().as_secs_f64() * 1000.0,
+    }
+}
+
+/// Races an IPv4 and an IPv6 connection to every hostname and port in
+/// `results` that resolved to both, started at the same moment, and reports
+/// which family got through faster. A name reachable over only one family is
+/// warned about: a broken IPv6 route makes clients that prefer it hang until
+/// they fall back. At most `max_enrichment_probes` races run at once.
+pub async fn race_families(settings: &Arc<ScanSettings>, results: &mut [ScanResult]) {
+    let mut families: BTreeMap<(String, u16), (Option<SocketAddr>, Option<SocketAddr>)> =
+        BTreeMap::new();
+    for scan_result in results.iter() {
+        let Some(hostname) = &scan_result.hostname else {
+            continue;
+        };
+        let (ipv4, ipv6) = families
+            .entry((hostname.clone(), scan_result.ip.port()))
+            .or_default();
+        let family = if scan_result.ip.is_ipv4() { ipv4 } else { ipv6 };
+        if family.is_none_or(|address| scan_result.ip < address) {
+            *family = Some(scan_result.ip);
+        }
+    }
+
+    let slots = Arc::new(Semaphore::new(
+        settings.config.limits.max_enrichment_probes.max(1),
+    ));
+    let mut set: JoinSet<((String, u16), DualStackSummary)> = JoinSet::new();
+    for (key, addresses) in families {
+        let (Some(ipv4), Some(ipv6)) = addresses else {
+            continue;
+        };
+        let slots = slots.clone();
+        let (ipv4_timeout, ipv6_timeout) = (
+            settings.connect_timeout(ipv4),
+            settings.connect_timeout(ipv6),
+        );
+        set.spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            let (ipv4, ipv6) =
+                tokio::join!(attempt(ipv4, ipv4_timeout), attempt(ipv6, ipv6_timeout));
+            (key, DualStackSummary { ipv4, ipv6 })
+        });
+    }
+    if set.is_empty() {
+        return;
+    }
+    print_to_terminal(
+        String::from("Racing IPv4 and IPv6 connections to each dual-stack hostname"),
+        VerbosityLevel::INFO,
+    );
+
+    let mut summaries: HashMap<(String, u16), DualStackSummary> = HashMap::new();
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok(((hostname, port), summary)) => {
+                let level = if summary.one_sided() {
+                    VerbosityLevel::WARN
+                } else {
+                    VerbosityLevel::INFO
+                };
+                print_to_terminal(
+                    format!("{}:{} - {}", hostname, port, summary.describe()),
+                    level,
+                );
+                summaries.insert((hostname, port), summary);
+            }
+            Err(e) => print_to_terminal(
+                format!("An error has occured: {}", e),
+                VerbosityLevel::ERROR,
+            ),
+        }
+    }
+    for scan_result in results.iter_mut() {
+        let Some(hostname) = &scan_result.hostname else {
+            continue;
+        };
+        if let Some(summary) = summaries.get(&(hostname.clone(), scan_result.ip.port())) {
+            scan_result.dual_stack = Some(summary.clone());
+        }
+    }
+}
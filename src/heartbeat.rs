@@ -0,0 +1,77 @@
+use crate::cli::ScanArgs;
+use crate::{ErrorCodes, VerbosityLevel, error_handler, http, print_to_terminal};
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use tokio::time::{Duration, timeout};
+
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+const PING_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Proof that a long-running watch is still alive, for monitoring that
+/// would otherwise only notice the lack of alerts.
+pub struct Heartbeat {
+    file: Option<PathBuf>,
+    url: Option<http::Url>,
+}
+
+impl Heartbeat {
+    pub fn from_args(scan: &ScanArgs) -> Option<Heartbeat> {
+        if scan.heartbeat_file.is_none() && scan.heartbeat_url.is_none() {
+            return None;
+        }
+        let url = scan
+            .heartbeat_url
+            .as_deref()
+            .map(|url| match http::Url::parse(url) {
+                Some(url_result) => url_result,
+                None => error_handler(ErrorCodes::INVALID_VARIABLE, line!(), Some("heartbeat url")),
+            });
+
+        Some(Heartbeat {
+            file: scan.heartbeat_file.clone(),
+            url,
+        })
+    }
+
+    /// Rewrites the heartbeat file and pings the URL in the background, so
+    /// a slow monitoring endpoint never holds up the watch.
+    pub fn beat(&self, endpoints: usize) {
+        if let Some(path) = &self.file {
+            let contents = format!(
+                "{} watching {} endpoints\n",
+                humantime::format_rfc3339_seconds(SystemTime::now()),
+                endpoints
+            );
+            if fs::write(path, contents).is_err() {
+                print_to_terminal(
+                    format!("Could not write the heartbeat file {}", path.display()),
+                    VerbosityLevel::WARN,
+                );
+            }
+        }
+
+        if let Some(url) = self.url.clone() {
+            tokio::spawn(async move {
+                match timeout(PING_TIMEOUT, url.get()).await {
+                    Ok(Ok(response)) if (200..300).contains(&response.status) => print_to_terminal(
+                        format!("Heartbeat sent to {}", url.host),
+                        VerbosityLevel::DEBUG,
+                    ),
+                    Ok(Ok(response)) => print_to_terminal(
+                        format!("Heartbeat to {} got status {}", url.host, response.status),
+                        VerbosityLevel::WARN,
+                    ),
+                    Ok(Err(e)) => print_to_terminal(
+                        format!("Heartbeat to {} failed: {}", url.host, e),
+                        VerbosityLevel::WARN,
+                    ),
+                    Err(_) => print_to_terminal(
+                        format!("Heartbeat to {} timed out", url.host),
+                        VerbosityLevel::WARN,
+                    ),
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use crate::ports::PortSet;
+use std::collections::HashMap;
+
+/// What a port speaks, so enrichment probes talk to it the right way.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ServiceProtocol {
+    Http,
+    Tls,
+    Ssh,
+    Dns,
+    Smtp,
+    Imap,
+    Pop3,
+    Grpc,
+    Ntp,
+    Snmp,
+}
+
+impl ServiceProtocol {
+    pub fn parse(name: &str) -> Option<ServiceProtocol> {
+        match name {
+            "http" => Some(ServiceProtocol::Http),
+            "tls" => Some(ServiceProtocol::Tls),
+            "ssh" => Some(ServiceProtocol::Ssh),
+            "dns" => Some(ServiceProtocol::Dns),
+            "smtp" => Some(ServiceProtocol::Smtp),
+            "imap" => Some(ServiceProtocol::Imap),
+            "pop3" => Some(ServiceProtocol::Pop3),
+            "grpc" => Some(ServiceProtocol::Grpc),
+            "ntp" => Some(ServiceProtocol::Ntp),
+            "snmp" => Some(ServiceProtocol::Snmp),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ServiceProtocol::Http => "http",
+            ServiceProtocol::Tls => "tls",
+            ServiceProtocol::Ssh => "ssh",
+            ServiceProtocol::Dns => "dns",
+            ServiceProtocol::Smtp => "smtp",
+            ServiceProtocol::Imap => "imap",
+            ServiceProtocol::Pop3 => "pop3",
+            ServiceProtocol::Grpc => "grpc",
+            ServiceProtocol::Ntp => "ntp",
+            ServiceProtocol::Snmp => "snmp",
+        }
+    }
+
+    /// The protocol a well-known port usually speaks.
+    fn well_known(port: u16) -> Option<ServiceProtocol> {
+        match port {
+            22 => Some(ServiceProtocol::Ssh),
+            25 | 587 | 2525 => Some(ServiceProtocol::Smtp),
+            53 => Some(ServiceProtocol::Dns),
+            110 => Some(ServiceProtocol::Pop3),
+            123 => Some(ServiceProtocol::Ntp),
+            161 => Some(ServiceProtocol::Snmp),
+            143 => Some(ServiceProtocol::Imap),
+            80 | 8000 | 8080 => Some(ServiceProtocol::Http),
+            443 | 8443 => Some(ServiceProtocol::Tls),
+            50051 => Some(ServiceProtocol::Grpc),
+            _ => None,
+        }
+    }
+}
+
+/// Protocols given in `--ports`, e.g. `8443=tls,2222=ssh` or implied by a
+/// service name like `https`, which win over any guess made from the port
+/// number.
+#[derive(Default)]
+pub struct PortHints(HashMap<u16, ServiceProtocol>);
+
+impl PortHints {
+    pub fn new(hints: HashMap<u16, ServiceProtocol>) -> PortHints {
+        PortHints(hints)
+    }
+
+    /// The hints in a `--ports` list, or none if it doesn't parse.
+    pub fn parse(ports: &str) -> PortHints {
+        PortSet::parse(ports, None)
+            .map(|set| set.hints())
+            .unwrap_or_default()
+    }
+
+    pub fn protocol_for(&self, port: u16) -> Option<ServiceProtocol> {
+        self.0
+            .get(&port)
+            .copied()
+            .or_else(|| ServiceProtocol::well_known(port))
+    }
+}
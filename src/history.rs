@@ -0,0 +1,395 @@
+use crate::cli::{DumpArgs, HistoryArgs};
+use crate::output::{ResultRecord, SavedReport, ScanMetadata};
+use crate::{
+    ErrorCodes, ScanResult, VerbosityLevel, error_handler, format_latency, print_to_terminal,
+};
+use rusqlite::{Connection, Transaction, params};
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS scans (
+        id INTEGER PRIMARY KEY,
+        version TEXT NOT NULL,
+        targets TEXT NOT NULL,
+        ports TEXT NOT NULL,
+        started_at TEXT NOT NULL,
+        finished_at TEXT NOT NULL,
+        duration_ms REAL NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS results (
+        scan_id INTEGER NOT NULL REFERENCES scans(id),
+        target TEXT NOT NULL,
+        port INTEGER NOT NULL,
+        status TEXT NOT NULL,
+        latency_ms REAL NOT NULL,
+        timestamp TEXT NOT NULL,
+        hostname TEXT,
+        labels TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS results_by_endpoint ON results (target, port);
+";
+
+/// Changes to the schema since the first release, applied in order to
+/// databases that predate them. A database's `user_version` counts how many
+/// it has had.
+const MIGRATIONS: &[&str] = &[
+    "
+    ALTER TABLE scans ADD COLUMN label TEXT;
+    ALTER TABLE scans ADD COLUMN operator TEXT;
+    ",
+    "
+    ALTER TABLE scans ADD COLUMN clock_adjustment_ms REAL;
+    ALTER TABLE results ADD COLUMN monotonic_ms REAL;
+    ",
+    "ALTER TABLE scans ADD COLUMN udp INTEGER NOT NULL DEFAULT 0;",
+];
+
+/// Opens the database, creating it and its tables the first time and
+/// bringing an older one up to date.
+fn open(path: &Path) -> Connection {
+    match Connection::open(path).and_then(|connection| {
+        connection.execute_batch(SCHEMA)?;
+        let applied: u32 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied as usize) {
+            connection.execute_batch(&format!(
+                "BEGIN; {} PRAGMA user_version = {}; COMMIT;",
+                migration,
+                version + 1
+            ))?;
+        }
+        Ok(connection)
+    }) {
+        Ok(connection_result) => connection_result,
+        Err(_) => error_handler(
+            ErrorCodes::DATABASE_UNAVAILABLE,
+            line!(),
+            Some(&path.display().to_string()),
+        ),
+    }
+}
+
+/// Adds the scan and every one of its results to the database in one
+/// transaction, so a scan is never half recorded.
+pub fn record_scan(path: &Path, metadata: &ScanMetadata, results: &[ScanResult]) {
+    let records: Vec<ResultRecord> = results.iter().map(ResultRecord::from_scan_result).collect();
+    let mut connection = open(path);
+    let recorded = connection.transaction().and_then(|transaction| {
+        insert_scan(&transaction, metadata, &records)?;
+        transaction.commit()
+    });
+
+    match recorded {
+        Ok(()) => print_to_terminal(
+            format!("Scan recorded in {}", path.display()),
+            VerbosityLevel::INFO,
+        ),
+        Err(_) => error_handler(
+            ErrorCodes::DATABASE_UNAVAILABLE,
+            line!(),
+            Some(&path.display().to_string()),
+        ),
+    }
+}
+
+fn insert_scan(
+    transaction: &Transaction,
+    metadata: &ScanMetadata,
+    records: &[ResultRecord],
+) -> rusqlite::Result<()> {
+    transaction.execute(
+        "INSERT INTO scans
+         (version, targets, ports, started_at, finished_at, duration_ms, label, operator,
+          clock_adjustment_ms, udp)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            metadata.version,
+            metadata.targets.join(" "),
+            metadata.ports,
+            metadata.started_at,
+            metadata.finished_at,
+            metadata.duration_ms,
+            metadata.label,
+            metadata.operator,
+            metadata.clock_adjustment_ms,
+            metadata.udp
+        ],
+    )?;
+    let scan_id = transaction.last_insert_rowid();
+    let mut insert = transaction.prepare(
+        "INSERT INTO results
+         (scan_id, target, port, status, latency_ms, timestamp, hostname, labels,
+          monotonic_ms)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+    )?;
+    for record in records {
+        insert.execute(params![
+            scan_id,
+            record.target,
+            record.port,
+            record.status,
+            record.latency_ms,
+            record.timestamp,
+            record.hostname,
+            record.labels.join(","),
+            record.monotonic_ms
+        ])?;
+    }
+    Ok(())
+}
+
+/// Every recorded scan with its results, oldest first.
+fn read_scans(connection: &Connection) -> rusqlite::Result<Vec<SavedReport>> {
+    let mut scans = connection.prepare(
+        "SELECT id, version, targets, ports, started_at, finished_at, duration_ms, label,
+                operator, clock_adjustment_ms, udp
+         FROM scans ORDER BY id",
+    )?;
+    let mut results = connection.prepare(
+        "SELECT target, port, status, latency_ms, timestamp, hostname, labels, monotonic_ms
+         FROM results WHERE scan_id = ?1 ORDER BY rowid",
+    )?;
+
+    let mut reports = Vec::new();
+    let rows = scans.query_map([], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            ScanMetadata {
+                version: row.get(1)?,
+                targets: row
+                    .get::<_, String>(2)?
+                    .split_whitespace()
+                    .map(String::from)
+                    .collect(),
+                ports: row.get(3)?,
+                started_at: row.get(4)?,
+                finished_at: row.get(5)?,
+                duration_ms: row.get(6)?,
+                label: row.get(7)?,
+                operator: row.get(8)?,
+                clock_adjustment_ms: row.get(9)?,
+                udp: row.get(10)?,
+                partial: false,
+            },
+        ))
+    })?;
+    for row in rows {
+        let (scan_id, scan) = row?;
+        let records = results
+            .query_map([scan_id], |row| {
+                Ok(ResultRecord {
+                    target: row.get(0)?,
+                    port: row.get(1)?,
+                    status: row.get(2)?,
+                    latency_ms: row.get(3)?,
+                    timestamp: row.get(4)?,
+                    hostname: row.get(5)?,
+                    reverse_dns: None,
+                    labels: row
+                        .get::<_, String>(6)?
+                        .split(",")
+                        .filter(|label| !label.is_empty())
+                        .map(String::from)
+                        .collect(),
+                    acknowledgment: None,
+                    change: None,
+                    monotonic_ms: row.get(7)?,
+                    group: None,
+                    annotation: None,
+                    service: None,
+                    encryption: None,
+                    certificates: Vec::new(),
+                    tls_versions: Vec::new(),
+                    http: None,
+                    interface: None,
+                    ssh: None,
+                    dns: None,
+                    starttls: None,
+                    quic: None,
+                    websocket: None,
+                    grpc: None,
+                    ntp: None,
+                    snmp: None,
+                    trace: None,
+                    path_mtu: None,
+                    os_guess: None,
+                    dual_stack: None,
+                    attempts: None,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        reports.push(SavedReport {
+            scan,
+            results: records,
+        });
+    }
+    Ok(reports)
+}
+
+/// Writes the whole database out as JSON lines, one scan and its results per
+/// line, which `import` can load on any host whatever its SQLite version.
+pub fn export(dump_args: DumpArgs) {
+    let scans = match read_scans(&open(&dump_args.db)) {
+        Ok(scans_result) => scans_result,
+        Err(_) => error_handler(
+            ErrorCodes::DATABASE_UNAVAILABLE,
+            line!(),
+            Some(&dump_args.db.display().to_string()),
+        ),
+    };
+    let dump: String = scans
+        .iter()
+        .map(|report| serde_json::to_string(report).unwrap_or_default() + "\n")
+        .collect();
+    if fs::write(&dump_args.dump, dump).is_err() {
+        error_handler(
+            ErrorCodes::OUTPUT_WRITE_FAILURE,
+            line!(),
+            Some(&dump_args.dump.display().to_string()),
+        );
+    }
+    print_to_terminal(
+        format!(
+            "Exported {} scans to {}",
+            scans.len(),
+            dump_args.dump.display()
+        ),
+        VerbosityLevel::INFO,
+    );
+}
+
+/// Adds the scans of a dump to the database in one transaction. A scan that
+/// is already recorded, going by when it started and what it scanned, is
+/// skipped, so restoring the same dump twice changes nothing.
+pub fn import(dump_args: DumpArgs) {
+    let dump_invalid = || -> ! {
+        error_handler(
+            ErrorCodes::DUMP_FILE_INVALID,
+            line!(),
+            Some(&dump_args.dump.display().to_string()),
+        )
+    };
+    let Ok(dump) = fs::read_to_string(&dump_args.dump) else {
+        dump_invalid();
+    };
+    let mut reports: Vec<SavedReport> = Vec::new();
+    for line in dump.lines().filter(|line| !line.trim().is_empty()) {
+        match serde_json::from_str(line) {
+            Ok(report_result) => reports.push(report_result),
+            Err(_) => dump_invalid(),
+        }
+    }
+
+    let mut connection = open(&dump_args.db);
+    let imported = connection.transaction().and_then(|transaction| {
+        let mut imported = 0;
+        for report in &reports {
+            let recorded: bool = transaction.query_row(
+                "SELECT EXISTS (SELECT 1 FROM scans
+                 WHERE started_at = ?1 AND targets = ?2 AND ports = ?3 AND udp = ?4)",
+                params![
+                    report.scan.started_at,
+                    report.scan.targets.join(" "),
+                    report.scan.ports,
+                    report.scan.udp
+                ],
+                |row| row.get(0),
+            )?;
+            if !recorded {
+                insert_scan(&transaction, &report.scan, &report.results)?;
+                imported += 1;
+            }
+        }
+        transaction.commit()?;
+        Ok(imported)
+    });
+
+    match imported {
+        Ok(imported) => print_to_terminal(
+            format!(
+                "Imported {} scans into {}, {} were already recorded",
+                imported,
+                dump_args.db.display(),
+                reports.len() - imported
+            ),
+            VerbosityLevel::INFO,
+        ),
+        Err(_) => error_handler(
+            ErrorCodes::DATABASE_UNAVAILABLE,
+            line!(),
+            Some(&dump_args.db.display().to_string()),
+        ),
+    }
+}
+
+/// Prints past results from the database, newest first, optionally only
+/// for one host or port or for the scans with a given label or operator.
+pub fn show_history(history_args: HistoryArgs) {
+    let connection = open(&history_args.db);
+    let rows = connection
+        .prepare(
+            "SELECT results.timestamp, results.target, results.port, results.status,
+                    results.latency_ms, scans.label
+             FROM results JOIN scans ON scans.id = results.scan_id
+             WHERE (?1 IS NULL OR results.target = ?1) AND (?2 IS NULL OR results.port = ?2)
+               AND (?3 IS NULL OR instr(scans.label, ?3) > 0)
+               AND (?4 IS NULL OR scans.operator = ?4)
+             ORDER BY results.timestamp DESC LIMIT ?5",
+        )
+        .and_then(|mut query| {
+            query
+                .query_map(
+                    params![
+                        history_args.host,
+                        history_args.port,
+                        history_args.label,
+                        history_args.operator,
+                        history_args.limit
+                    ],
+                    |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, String>(1)?,
+                            row.get::<_, u16>(2)?,
+                            row.get::<_, String>(3)?,
+                            row.get::<_, f64>(4)?,
+                            row.get::<_, Option<String>>(5)?,
+                        ))
+                    },
+                )?
+                .collect::<Result<Vec<_>, _>>()
+        });
+    let rows = match rows {
+        Ok(rows_result) => rows_result,
+        Err(_) => error_handler(
+            ErrorCodes::DATABASE_UNAVAILABLE,
+            line!(),
+            Some(&history_args.db.display().to_string()),
+        ),
+    };
+
+    if rows.is_empty() {
+        print_to_terminal(
+            String::from("No matching results recorded"),
+            VerbosityLevel::INFO,
+        );
+        return;
+    }
+    for (timestamp, target, port, status, latency_ms, label) in rows {
+        let endpoint = match target.parse::<IpAddr>() {
+            Ok(ip) => SocketAddr::new(ip, port).to_string(),
+            Err(_) => format!("{}:{}", target, port),
+        };
+        let mut line = format!("{} {} - {}", timestamp, endpoint, status);
+        if status == "open" {
+            let latency = Duration::from_secs_f64(latency_ms / 1000.0);
+            line.push_str(&format!(" ({})", format_latency(latency)));
+        }
+        if let Some(label) = label {
+            line.push_str(&format!(" [{}]", label));
+        }
+        println!("{}", line);
+    }
+}
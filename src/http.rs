@@ -0,0 +1,278 @@
+use crate::{tls, usage};
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, lookup_host};
+
+/// Responses are cut off here, unless the caller sets its own cap, so a
+/// misbehaving server can't make a probe read forever.
+const MAX_RESPONSE_BYTES: usize = 64 * 1024;
+
+/// Either kind of connection a `Url` can be reached over.
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Stream for S {}
+
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    /// The first header called `name`, whatever its case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Sends a bare `GET` over an already connected stream and parses whatever
+/// comes back.
+pub async fn get<S>(stream: S, host: &str, path: &str) -> io::Result<HttpResponse>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    get_limited(stream, host, path, MAX_RESPONSE_BYTES).await
+}
+
+/// Like `get`, reading at most `max_bytes` of the response.
+pub async fn get_limited<S>(
+    stream: S,
+    host: &str,
+    path: &str,
+    max_bytes: usize,
+) -> io::Result<HttpResponse>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let head = request_head("GET", host, path, "Accept: */*\r\n");
+    exchange(stream, head.as_bytes(), max_bytes).await
+}
+
+/// Sends `body` in a `POST` over an already connected stream and parses
+/// whatever comes back.
+pub async fn post<S>(
+    stream: S,
+    host: &str,
+    path: &str,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<HttpResponse>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let headers = format!(
+        "Content-Type: {}\r\nContent-Length: {}\r\n",
+        content_type,
+        body.len()
+    );
+    let mut request = request_head("POST", host, path, &headers).into_bytes();
+    request.extend_from_slice(body);
+    exchange(stream, &request, MAX_RESPONSE_BYTES).await
+}
+
+/// Sends a `GET` asking the server to switch protocols, with `headers`
+/// naming the protocol, and reads only the response head, since a server
+/// that agrees keeps the connection open. The stream is left as it is for
+/// whatever follows.
+pub async fn request_upgrade<S>(
+    stream: &mut S,
+    host: &str,
+    path: &str,
+    headers: &str,
+) -> io::Result<HttpResponse>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: connection-tester/{}\r\nConnection: Upgrade\r\n{}\r\n",
+        path,
+        host,
+        env!("CARGO_PKG_VERSION"),
+        headers
+    );
+    stream.write_all(request.as_bytes()).await?;
+    usage::sent(request.len());
+
+    let mut response: Vec<u8> = Vec::new();
+    let mut byte = [0u8; 1];
+    // A byte at a time, so nothing after the head is taken off the stream
+    while response.len() < MAX_RESPONSE_BYTES && !response.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut byte).await? {
+            0 => break,
+            _ => response.push(byte[0]),
+        }
+    }
+    usage::received(response.len());
+    parse_response(&response)
+}
+
+fn request_head(method: &str, host: &str, path: &str, headers: &str) -> String {
+    format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: connection-tester/{}\r\n{}Connection: close\r\n\r\n",
+        method,
+        path,
+        host,
+        env!("CARGO_PKG_VERSION"),
+        headers
+    )
+}
+
+async fn exchange<S>(mut stream: S, request: &[u8], max_bytes: usize) -> io::Result<HttpResponse>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(request).await?;
+    usage::sent(request.len());
+
+    let mut response: Vec<u8> = Vec::new();
+    let mut buffer = [0u8; 4096];
+    while response.len() < max_bytes {
+        let wanted = buffer.len().min(max_bytes - response.len());
+        match stream.read(&mut buffer[..wanted]).await {
+            Ok(0) => break,
+            Ok(read) => {
+                usage::received(read);
+                response.extend_from_slice(&buffer[..read]);
+            }
+            // Plenty of servers drop TLS connections without a close_notify
+            // once they've sent the response.
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && !response.is_empty() => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    parse_response(&response)
+}
+
+/// A plain `http://` or `https://` URL to send requests to.
+#[derive(Clone)]
+pub struct Url {
+    pub use_tls: bool,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Option<Url> {
+        let (use_tls, rest) = match url.split_once("://")? {
+            ("http", rest) => (false, rest),
+            ("https", rest) => (true, rest),
+            _ => return None,
+        };
+        let (authority, path) = match rest.find("/") {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(":") {
+            Some((host, port)) => (host, port.parse().ok()?),
+            None => (authority, if use_tls { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            return None;
+        }
+
+        Some(Url {
+            use_tls,
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    async fn connect(&self) -> io::Result<Box<dyn Stream>> {
+        let address = lookup_host((self.host.as_str(), self.port))
+            .await?
+            .next()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        if self.use_tls {
+            Ok(Box::new(
+                tls::handshake(address, Some(&self.host), None).await?,
+            ))
+        } else {
+            Ok(Box::new(TcpStream::connect(address).await?))
+        }
+    }
+
+    pub async fn get(&self) -> io::Result<HttpResponse> {
+        get(self.connect().await?, &self.host, &self.path).await
+    }
+
+    pub async fn post(&self, content_type: &str, body: &[u8]) -> io::Result<HttpResponse> {
+        post(
+            self.connect().await?,
+            &self.host,
+            &self.path,
+            content_type,
+            body,
+        )
+        .await
+    }
+}
+
+fn parse_response(response: &[u8]) -> io::Result<HttpResponse> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not an HTTP response");
+
+    let header_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .unwrap_or(response.len());
+    let head = String::from_utf8_lossy(&response[..header_end]);
+
+    let status_line = head.split("\r\n").next().ok_or_else(invalid)?;
+    if !status_line.starts_with("HTTP/") {
+        return Err(invalid());
+    }
+    let status: u16 = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(invalid)?;
+
+    let headers: Vec<(String, String)> = head
+        .split("\r\n")
+        .skip(1)
+        .filter_map(|header| header.split_once(":"))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+
+    let mut parsed = HttpResponse {
+        status,
+        headers,
+        body: Vec::new(),
+    };
+    let body = response.get(header_end + 4..).unwrap_or_default();
+    let chunked = parsed
+        .header("transfer-encoding")
+        .is_some_and(|value| value.eq_ignore_ascii_case("chunked"));
+    parsed.body = if chunked {
+        decode_chunked(body)
+    } else {
+        body.to_vec()
+    };
+
+    Ok(parsed)
+}
+
+/// Joins a chunked body back together, stopping at the last chunk or at
+/// whatever was cut off.
+fn decode_chunked(mut body: &[u8]) -> Vec<u8> {
+    let mut decoded: Vec<u8> = Vec::new();
+    while let Some(line_end) = body.windows(2).position(|window| window == b"\r\n") {
+        let size_line = String::from_utf8_lossy(&body[..line_end]);
+        let size_field = size_line.split(";").next().unwrap_or_default().trim();
+        let Ok(size) = usize::from_str_radix(size_field, 16) else {
+            break;
+        };
+        let chunk_start = line_end + 2;
+        if size == 0 || chunk_start + size > body.len() {
+            break;
+        }
+        decoded.extend_from_slice(&body[chunk_start..chunk_start + size]);
+        body = body.get(chunk_start + size + 2..).unwrap_or_default();
+    }
+    decoded
+}
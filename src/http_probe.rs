@@ -0,0 +1,199 @@
+use crate::encryption::Encryption;
+use crate::hints::ServiceProtocol;
+use crate::{
+    ConnectionStatus, ScanResult, ScanSettings, VerbosityLevel, http, print_to_terminal, sandbox,
+    tls, usage,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, timeout};
+
+/// How long the server gets to answer the request.
+const REQUEST_WAIT: Duration = Duration::from_secs(5);
+/// Titles are cut off here, so a page that puts its whole text in one
+/// doesn't flood the output.
+const MAX_TITLE_CHARS: usize = 120;
+
+/// What a web server answered to `GET /`, or the `--http-path`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HttpSummary {
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    /// Where a redirect points
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+impl HttpSummary {
+    /// The summary for a result line, e.g. `HTTP 301 -> https://example.com/`
+    /// or `HTTP 200, nginx, "Welcome"`.
+    pub fn describe(&self) -> String {
+        let mut description = format!("HTTP {}", self.status);
+        if let Some(location) = &self.location {
+            description.push_str(&format!(" -> {}", location));
+        }
+        if let Some(server) = &self.server {
+            description.push_str(&format!(", {}", server));
+        }
+        if let Some(title) = &self.title {
+            description.push_str(&format!(", {:?}", title));
+        }
+        description
+    }
+}
+
+/// The page's `<title>`, with entities decoded and whitespace collapsed.
+fn page_title(body: &[u8]) -> Option<String> {
+    let page = String::from_utf8_lossy(body);
+    let lower = page.to_ascii_lowercase();
+    let open = lower.find("<title")?;
+    let start = open + lower[open..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+    let title = page[start..end]
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+    let title: String = title
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .chars()
+        .take(MAX_TITLE_CHARS)
+        .collect();
+    (!title.is_empty()).then_some(title)
+}
+
+/// Requests `path` with `host` as the Host header. Over TLS, `server_name`
+/// is sent as SNI.
+async fn request(
+    target: SocketAddr,
+    host: String,
+    server_name: Option<String>,
+    path: String,
+    use_tls: bool,
+    max_bytes: usize,
+) -> io::Result<HttpSummary> {
+    let response = if use_tls {
+        let stream = tls::handshake(target, server_name.as_deref(), None).await?;
+        http::get_limited(stream, &host, &path, max_bytes).await?
+    } else {
+        usage::socket_opened();
+        let stream = TcpStream::connect(target).await?;
+        http::get_limited(stream, &host, &path, max_bytes).await?
+    };
+    Ok(HttpSummary {
+        status: response.status,
+        server: response.header("server").map(String::from),
+        location: response.header("location").map(String::from),
+        title: page_title(&response.body),
+    })
+}
+
+/// Whether the scan has reason to think the port serves HTTP, and over TLS
+/// if so: a hint or well-known port, or what detection found on it.
+pub fn http_port(settings: &ScanSettings, scan_result: &ScanResult) -> Option<bool> {
+    let protocol = settings.hints.protocol_for(scan_result.ip.port());
+    let detected_http = scan_result
+        .service
+        .as_ref()
+        .is_some_and(|service| service.name == "http");
+    let use_tls = scan_result.encryption == Some(Encryption::Tls)
+        || (scan_result.encryption.is_none() && protocol == Some(ServiceProtocol::Tls));
+    (detected_http || protocol == Some(ServiceProtocol::Http) || use_tls).then_some(use_tls)
+}
+
+/// Requests `path` from every open port in `results` that looks like it
+/// serves HTTP, and records the status, server, redirect and page title on
+/// the result. At most `max_enrichment_probes` endpoints are asked at once.
+pub async fn probe_http(settings: &Arc<ScanSettings>, results: &mut [ScanResult], path: &str) {
+    let limits = settings.config.limits;
+    let slots = Arc::new(Semaphore::new(limits.max_enrichment_probes.max(1)));
+    let mut set: JoinSet<(SocketAddr, Result<io::Result<HttpSummary>, String>)> = JoinSet::new();
+    for scan_result in results
+        .iter()
+        .filter(|scan_result| scan_result.status == ConnectionStatus::Open)
+    {
+        let Some(use_tls) = http_port(settings, scan_result) else {
+            continue;
+        };
+        let target = scan_result.ip;
+        let host = match &scan_result.hostname {
+            Some(hostname) => hostname.clone(),
+            None if target.is_ipv6() => format!("[{}]", target.ip()),
+            None => target.ip().to_string(),
+        };
+        let server_name = scan_result.hostname.clone();
+        let path = path.to_string();
+        let slots = slots.clone();
+        set.spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            let summary = sandbox::run_isolated(limits.max_probe_time, async move {
+                match timeout(
+                    REQUEST_WAIT,
+                    request(
+                        target,
+                        host,
+                        server_name,
+                        path,
+                        use_tls,
+                        limits.max_probe_bytes,
+                    ),
+                )
+                .await
+                {
+                    Ok(summary) => summary,
+                    Err(_) => Err(io::Error::from(io::ErrorKind::TimedOut)),
+                }
+            })
+            .await;
+            (target, summary)
+        });
+    }
+    if set.is_empty() {
+        return;
+    }
+    print_to_terminal(String::from("Requesting web pages"), VerbosityLevel::INFO);
+
+    let mut summaries: HashMap<SocketAddr, HttpSummary> = HashMap::new();
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok((target, Ok(Ok(summary)))) => {
+                print_to_terminal(
+                    format!("{} - {}", target, summary.describe()),
+                    VerbosityLevel::INFO,
+                );
+                summaries.insert(target, summary);
+            }
+            // Ports only guessed to be web servers often aren't
+            Ok((target, Ok(Err(e)))) => print_to_terminal(
+                format!("{} - no HTTP response: {}", target, e),
+                VerbosityLevel::DEBUG,
+            ),
+            Ok((target, Err(problem))) => {
+                print_to_terminal(format!("{} - {}", target, problem), VerbosityLevel::WARN)
+            }
+            Err(e) => {
+                print_to_terminal(
+                    format!("An error has occured: {}", e),
+                    VerbosityLevel::ERROR,
+                );
+            }
+        }
+    }
+    for scan_result in results.iter_mut() {
+        if let Some(summary) = summaries.remove(&scan_result.ip) {
+            scan_result.http = Some(summary);
+        }
+    }
+}
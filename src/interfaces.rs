@@ -0,0 +1,303 @@
+use crate::{
+    ConnectionStatus, ErrorCodes, ScanResult, VerbosityLevel, error_handler, print_to_terminal,
+};
+use std::ffi::{CStr, CString};
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+/// How many of the addresses an interface can't reach are named when the
+/// routing check fails.
+const UNROUTABLE_EXAMPLES: usize = 5;
+const NLMSG_HEADER_LEN: usize = 16;
+const RTMSG_LEN: usize = 12;
+
+/// A network interface given with `--interface`, and the addresses scans
+/// through it send from.
+#[derive(Clone, Debug)]
+pub struct Interface {
+    pub name: String,
+    index: u32,
+    addresses: Vec<IpAddr>,
+}
+
+impl Interface {
+    /// Looks the interface up by name, e.g. `eth0` or a VRF's `vrf-blue`.
+    /// `None` if there is no such interface or it has no addresses.
+    fn find(name: &str) -> Option<Interface> {
+        let index = unsafe { libc::if_nametoindex(CString::new(name).ok()?.as_ptr()) };
+        if index == 0 {
+            return None;
+        }
+        let mut first: *mut libc::ifaddrs = std::ptr::null_mut();
+        if unsafe { libc::getifaddrs(&mut first) } != 0 {
+            return None;
+        }
+        let mut addresses = Vec::new();
+        let mut current = first;
+        while let Some(entry) = unsafe { current.as_ref() } {
+            current = entry.ifa_next;
+            if entry.ifa_addr.is_null()
+                || unsafe { CStr::from_ptr(entry.ifa_name) }.to_bytes() != name.as_bytes()
+            {
+                continue;
+            }
+            match unsafe { (*entry.ifa_addr).sa_family } as i32 {
+                libc::AF_INET => {
+                    let address = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in) };
+                    addresses.push(IpAddr::V4(Ipv4Addr::from(
+                        address.sin_addr.s_addr.to_ne_bytes(),
+                    )));
+                }
+                libc::AF_INET6 => {
+                    let address = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in6) };
+                    let ipv6 = Ipv6Addr::from(address.sin6_addr.s6_addr);
+                    // Link-local addresses need a scope to send from
+                    if !ipv6.is_unicast_link_local() {
+                        addresses.push(IpAddr::V6(ipv6));
+                    }
+                }
+                _ => {}
+            }
+        }
+        unsafe { libc::freeifaddrs(first) };
+        (!addresses.is_empty()).then(|| Interface {
+            name: name.to_string(),
+            index,
+            addresses,
+        })
+    }
+
+    /// The address probes to `target` are sent from.
+    pub fn source_for(&self, target: IpAddr) -> Option<IpAddr> {
+        self.addresses
+            .iter()
+            .find(|address| address.is_ipv4() == target.is_ipv4())
+            .copied()
+    }
+
+    /// Whether traffic to `target` from the interface's address leaves
+    /// through the interface, going by the kernel's routing tables and
+    /// rules.
+    fn routes(&self, target: IpAddr) -> io::Result<bool> {
+        let Some(source) = self.source_for(target) else {
+            return Ok(false);
+        };
+        match route_lookup(target, source) {
+            Ok(oif) => Ok(oif == Some(self.index)),
+            Err(e)
+                if matches!(
+                    e.raw_os_error(),
+                    Some(libc::ENETUNREACH | libc::EHOSTUNREACH)
+                ) =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn push_attribute(message: &mut Vec<u8>, kind: u16, value: &[u8]) {
+    let length = 4 + value.len();
+    message.extend_from_slice(&(length as u16).to_ne_bytes());
+    message.extend_from_slice(&kind.to_ne_bytes());
+    message.extend_from_slice(value);
+    message.resize(message.len().next_multiple_of(4), 0);
+}
+
+fn address_bytes(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ipv4) => ipv4.octets().to_vec(),
+        IpAddr::V6(ipv6) => ipv6.octets().to_vec(),
+    }
+}
+
+/// Asks the kernel over rtnetlink which interface it would send from
+/// `source` to `target` through, as `ip route get TARGET from SOURCE` does.
+fn route_lookup(target: IpAddr, source: IpAddr) -> io::Result<Option<u32>> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::NETLINK_ROUTE,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let (family, length) = match target {
+        IpAddr::V4(_) => (libc::AF_INET as u8, 32),
+        IpAddr::V6(_) => (libc::AF_INET6 as u8, 128),
+    };
+    let mut message = vec![0u8; NLMSG_HEADER_LEN];
+    message.extend_from_slice(&[family, length, length, 0, 0, 0, 0, 0]);
+    message.extend_from_slice(&0u32.to_ne_bytes());
+    push_attribute(&mut message, libc::RTA_DST, &address_bytes(target));
+    push_attribute(&mut message, libc::RTA_SRC, &address_bytes(source));
+    let total = message.len() as u32;
+    message[0..4].copy_from_slice(&total.to_ne_bytes());
+    message[4..6].copy_from_slice(&libc::RTM_GETROUTE.to_ne_bytes());
+    message[6..8].copy_from_slice(&(libc::NLM_F_REQUEST as u16).to_ne_bytes());
+    message[8..12].copy_from_slice(&1u32.to_ne_bytes());
+
+    let mut kernel: libc::sockaddr_nl = unsafe { mem::zeroed() };
+    kernel.nl_family = libc::AF_NETLINK as u16;
+    let sent = unsafe {
+        libc::sendto(
+            socket.as_raw_fd(),
+            message.as_ptr() as *const libc::c_void,
+            message.len(),
+            0,
+            &kernel as *const libc::sockaddr_nl as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_nl>() as u32,
+        )
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut reply = [0u8; 4096];
+    let received = unsafe {
+        libc::recv(
+            socket.as_raw_fd(),
+            reply.as_mut_ptr() as *mut libc::c_void,
+            reply.len(),
+            0,
+        )
+    };
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let reply = &reply[..received as usize];
+    let kind = reply
+        .get(4..6)
+        .map(|kind| u16::from_ne_bytes([kind[0], kind[1]]));
+    if kind == Some(libc::NLMSG_ERROR as u16) {
+        let code = reply
+            .get(16..20)
+            .map(|code| i32::from_ne_bytes([code[0], code[1], code[2], code[3]]))
+            .unwrap_or(-libc::EIO);
+        return Err(io::Error::from_raw_os_error(-code));
+    }
+    let mut offset = NLMSG_HEADER_LEN + RTMSG_LEN;
+    while let Some(header) = reply.get(offset..offset + 4) {
+        let length = u16::from_ne_bytes([header[0], header[1]]) as usize;
+        let kind = u16::from_ne_bytes([header[2], header[3]]);
+        if length < 4 {
+            break;
+        }
+        if kind == libc::RTA_OIF
+            && let Some(value) = reply.get(offset + 4..offset + 8)
+        {
+            return Ok(Some(u32::from_ne_bytes([
+                value[0], value[1], value[2], value[3],
+            ])));
+        }
+        offset += length.next_multiple_of(4);
+    }
+    Ok(None)
+}
+
+/// The MTU of the interface the kernel would send from `source` to `target`
+/// through, before anything it has learned about the path narrows it.
+pub fn link_mtu(target: IpAddr, source: IpAddr) -> Option<u16> {
+    let index = route_lookup(target, source).ok()??;
+    let mut name = [0 as libc::c_char; libc::IF_NAMESIZE];
+    if unsafe { libc::if_indextoname(index, name.as_mut_ptr()) }.is_null() {
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr(name.as_ptr()) }.to_str().ok()?;
+    std::fs::read_to_string(format!("/sys/class/net/{}/mtu", name))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Looks up every `--interface` and checks that each can reach every target
+/// before any probe is sent, so a range routed out of another interface or
+/// VRF fails up front instead of showing up as a wall of timeouts.
+pub fn resolve(names: &[String], hosts: &[IpAddr]) -> Vec<Interface> {
+    let mut interfaces = Vec::new();
+    for name in names {
+        let Some(interface) = Interface::find(name) else {
+            error_handler(ErrorCodes::INTERFACE_NOT_FOUND, line!(), Some(name));
+        };
+        let mut unroutable = Vec::new();
+        for host in hosts {
+            match interface.routes(*host) {
+                Ok(true) => {}
+                Ok(false) => unroutable.push(host.to_string()),
+                Err(e) => {
+                    print_to_terminal(
+                        format!("Couldn't check the routes of {}: {}", name, e),
+                        VerbosityLevel::WARN,
+                    );
+                    break;
+                }
+            }
+        }
+        if !unroutable.is_empty() {
+            let mut examples = unroutable[..unroutable.len().min(UNROUTABLE_EXAMPLES)].join(", ");
+            if unroutable.len() > UNROUTABLE_EXAMPLES {
+                examples.push_str(&format!(
+                    " and {} more",
+                    unroutable.len() - UNROUTABLE_EXAMPLES
+                ));
+            }
+            error_handler(
+                ErrorCodes::UNROUTABLE_TARGETS,
+                line!(),
+                Some(&format!("{} can't reach {}", name, examples)),
+            );
+        }
+        print_to_terminal(
+            format!(
+                "Scanning through {} from {}",
+                name,
+                interface
+                    .addresses
+                    .iter()
+                    .map(|address| address.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            VerbosityLevel::INFO,
+        );
+        interfaces.push(interface);
+    }
+    interfaces
+}
+
+/// Prints a line per interface of how its endpoints ended up, so paths
+/// through different interfaces or VRFs can be compared.
+pub fn print_interface_summaries(interfaces: &[Interface], results: &[ScanResult]) {
+    for interface in interfaces {
+        let via: Vec<&ScanResult> = results
+            .iter()
+            .filter(|scan_result| scan_result.interface.as_deref() == Some(&interface.name))
+            .collect();
+        let counts: Vec<String> = ConnectionStatus::ALL
+            .iter()
+            .filter_map(|status| {
+                let count = via
+                    .iter()
+                    .filter(|scan_result| scan_result.status == *status)
+                    .count();
+                (count > 0).then(|| format!("{} {}", count, status.to_string().to_lowercase()))
+            })
+            .collect();
+        print_to_terminal(
+            format!(
+                "Via {}: {} endpoints checked ({})",
+                interface.name,
+                via.len(),
+                counts.join(", ")
+            ),
+            VerbosityLevel::INFO,
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{VerbosityLevel, print_to_terminal, usage};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, sleep, timeout};
+
+/// The pause between knocks. knockd takes them in order within a window
+/// of several seconds, but packets sent back to back can overtake each
+/// other on the way.
+const KNOCK_GAP: Duration = Duration::from_millis(200);
+/// How long after the last knock the firewall gets to open up before the
+/// scan starts.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+/// Hosts knocked on at once.
+const MAX_HOSTS: usize = 256;
+
+/// One knock: a port, and whether it's knocked on with a UDP datagram
+/// rather than a SYN.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Knock {
+    port: u16,
+    udp: bool,
+}
+
+/// The ports `--knock` knocks on in turn, given like knockd's own
+/// `sequence`: `7000,8000,9000`, with `:udp` after any knocked on over UDP.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct KnockSequence(Vec<Knock>);
+
+impl KnockSequence {
+    pub fn parse(value: &str) -> Result<KnockSequence, String> {
+        let knocks = value
+            .split(',')
+            .map(|knock| {
+                let knock = knock.trim();
+                let (port, protocol) = knock.split_once(':').unwrap_or((knock, "tcp"));
+                let udp = match protocol {
+                    "tcp" => false,
+                    "udp" => true,
+                    _ => return Err(format!("{} is neither tcp nor udp", protocol)),
+                };
+                match port.parse::<u16>() {
+                    Ok(port) if port > 0 => Ok(Knock { port, udp }),
+                    _ => Err(format!("{} is not a port", port)),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(KnockSequence(knocks))
+    }
+}
+
+impl TryFrom<String> for KnockSequence {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<KnockSequence, String> {
+        KnockSequence::parse(&value)
+    }
+}
+
+impl From<KnockSequence> for String {
+    fn from(sequence: KnockSequence) -> String {
+        sequence
+            .0
+            .iter()
+            .map(|knock| {
+                if knock.udp {
+                    format!("{}:udp", knock.port)
+                } else {
+                    knock.port.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// Sends one knock. A SYN is all knockd looks for, so the connection
+/// attempt is abandoned as soon as it's sent; whatever comes back doesn't
+/// matter.
+async fn knock(ip: IpAddr, knock: Knock) {
+    let target = SocketAddr::new(ip, knock.port);
+    usage::socket_opened();
+    if knock.udp {
+        let local = if ip.is_ipv4() {
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        } else {
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+        };
+        if let Ok(socket) = UdpSocket::bind(SocketAddr::new(local, 0)).await {
+            let _ = socket.send_to(&[], target).await;
+        }
+    } else {
+        let _ = timeout(KNOCK_GAP, TcpStream::connect(target)).await;
+    }
+}
+
+/// Knocks on every host in `hosts` with `sequence` before the scan, so
+/// ports a knock daemon keeps closed are open by the time they're probed.
+pub async fn knock_hosts(sequence: &KnockSequence, hosts: &[IpAddr]) {
+    if hosts.is_empty() || sequence.0.is_empty() {
+        return;
+    }
+    print_to_terminal(
+        format!(
+            "Knocking on {} hosts with {}",
+            hosts.len(),
+            String::from(sequence.clone())
+        ),
+        VerbosityLevel::INFO,
+    );
+    let slots = Arc::new(Semaphore::new(MAX_HOSTS));
+    let mut set = JoinSet::new();
+    for ip in hosts.iter().copied() {
+        let knocks = sequence.0.clone();
+        let slots = slots.clone();
+        set.spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            for (index, next) in knocks.into_iter().enumerate() {
+                if index > 0 {
+                    sleep(KNOCK_GAP).await;
+                }
+                knock(ip, next).await;
+            }
+        });
+    }
+    while let Some(res) = set.join_next().await {
+        if let Err(e) = res {
+            print_to_terminal(
+                format!("An error has occured: {}", e),
+                VerbosityLevel::ERROR,
+            );
+        }
+    }
+    sleep(SETTLE_TIME).await;
+}
@@ -0,0 +1,80 @@
+use crate::{ErrorCodes, VerbosityLevel, error_handler, print_to_terminal};
+use cidr::IpCidr;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Ranges of addresses tagged with a label, such as `corporate-vpn` or
+/// `known-scanner`, read from one or more label files.
+///
+/// Each line of a label file is a CIDR followed by its label, separated by a
+/// comma or whitespace. Blank lines and lines starting with `#` are ignored.
+#[derive(Default)]
+pub struct LabelSet {
+    entries: Vec<(IpCidr, String)>,
+}
+
+impl LabelSet {
+    pub fn load(paths: &[PathBuf]) -> LabelSet {
+        let mut label_set = LabelSet::default();
+
+        for path in paths {
+            let path_name = path.display().to_string();
+            let contents = match fs::read_to_string(path) {
+                Ok(contents_result) => contents_result,
+                Err(_) => error_handler(ErrorCodes::LABEL_FILE_INVALID, line!(), Some(&path_name)),
+            };
+
+            for (line_index, line) in contents.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with("#") {
+                    continue;
+                }
+
+                let entry = line
+                    .split_once(|c: char| c == ',' || c.is_whitespace())
+                    .and_then(|(network, label)| {
+                        Some((IpCidr::from_str(network.trim()).ok()?, label.trim()))
+                    });
+                match entry {
+                    Some((network, label)) if !label.is_empty() => {
+                        label_set.entries.push((network, label.to_string()))
+                    }
+                    _ => error_handler(
+                        ErrorCodes::LABEL_FILE_INVALID,
+                        line!(),
+                        Some(&format!("{}:{}", path_name, line_index + 1)),
+                    ),
+                }
+            }
+        }
+
+        print_to_terminal(
+            format!("Loaded {} labelled ranges", label_set.entries.len()),
+            VerbosityLevel::DEBUG,
+        );
+        label_set
+    }
+
+    /// Every label whose range contains `ip`, in the order they were loaded.
+    pub fn labels_for(&self, ip: IpAddr) -> Vec<String> {
+        let mut labels: Vec<String> = Vec::new();
+        for (network, label) in &self.entries {
+            if network.contains(&ip) && !labels.contains(label) {
+                labels.push(label.clone());
+            }
+        }
+        labels
+    }
+
+    /// Whether `ip` should be scanned given the `--only-label` and
+    /// `--skip-label` filters.
+    pub fn allows(&self, ip: IpAddr, only_labels: &[String], skip_labels: &[String]) -> bool {
+        let labels = self.labels_for(ip);
+        if labels.iter().any(|label| skip_labels.contains(label)) {
+            return false;
+        }
+        only_labels.is_empty() || labels.iter().any(|label| only_labels.contains(label))
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{
+    ConnectionStatus, ScanResult, VerbosityLevel, format_latency, print_to_terminal, write_message,
+};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::time::Duration;
+
+const HISTOGRAM_WIDTH: usize = 40;
+/// Upper bounds of the histogram buckets, in milliseconds. Anything slower
+/// lands in a last open-ended bucket.
+const BUCKET_BOUNDS_MS: [u64; 10] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000];
+
+/// The latency below which `percentile` percent of `sorted` falls, by
+/// nearest rank.
+fn percentile(sorted: &[Duration], percentile: usize) -> Duration {
+    let rank = (sorted.len() * percentile).div_ceil(100).max(1);
+    sorted[rank.min(sorted.len()) - 1]
+}
+
+fn describe_percentiles(sorted: &[Duration]) -> String {
+    format!(
+        "p50 {}, p90 {}, p99 {}",
+        format_latency(percentile(sorted, 50)),
+        format_latency(percentile(sorted, 90)),
+        format_latency(percentile(sorted, 99))
+    )
+}
+
+/// Prints latency percentiles of the open connections for each host and
+/// overall, so a slow path shows up as well as an unreachable one. With
+/// `histogram`, also draws how the overall latencies are spread.
+pub fn print_latency_summary(results: &[ScanResult], histogram: bool) {
+    let mut by_host: BTreeMap<IpAddr, Vec<Duration>> = BTreeMap::new();
+    for scan_result in results {
+        if scan_result.status == ConnectionStatus::Open {
+            by_host
+                .entry(scan_result.ip.ip())
+                .or_default()
+                .push(scan_result.latency);
+        }
+    }
+    if by_host.is_empty() {
+        return;
+    }
+
+    // A single host's line would only repeat the overall one
+    if by_host.len() > 1 {
+        for (ip, latencies) in &mut by_host {
+            latencies.sort();
+            print_to_terminal(
+                format!(
+                    "{}: {} ({} open)",
+                    ip,
+                    describe_percentiles(latencies),
+                    latencies.len()
+                ),
+                VerbosityLevel::INFO,
+            );
+        }
+    }
+
+    let mut overall: Vec<Duration> = by_host.into_values().flatten().collect();
+    overall.sort();
+    write_message(
+        format!(
+            "Latency: {} over {} open connections",
+            describe_percentiles(&overall),
+            overall.len()
+        ),
+        VerbosityLevel::INFO,
+    );
+
+    if histogram {
+        print_histogram(&overall);
+    }
+}
+
+fn print_histogram(latencies: &[Duration]) {
+    let mut counts = [0usize; BUCKET_BOUNDS_MS.len() + 1];
+    for latency in latencies {
+        let bucket = BUCKET_BOUNDS_MS
+            .iter()
+            .position(|bound| latency.as_secs_f64() * 1000.0 < *bound as f64)
+            .unwrap_or(BUCKET_BOUNDS_MS.len());
+        counts[bucket] += 1;
+    }
+    let largest = counts.iter().copied().max().unwrap_or(1).max(1);
+
+    let mut lower = 0;
+    for (bucket, count) in counts.iter().enumerate() {
+        let label = match BUCKET_BOUNDS_MS.get(bucket) {
+            Some(upper) => format!("{}-{}ms", lower, upper),
+            None => format!(">={}ms", lower),
+        };
+        lower = BUCKET_BOUNDS_MS.get(bucket).copied().unwrap_or(lower);
+        if *count == 0 {
+            continue;
+        }
+        // Every bucket with something in it gets at least one mark
+        let bar = "#".repeat((count * HISTOGRAM_WIDTH).div_ceil(largest));
+        write_message(
+            format!(
+                "{:>10} | {:<width$} {}",
+                label,
+                bar,
+                count,
+                width = HISTOGRAM_WIDTH
+            ),
+            VerbosityLevel::INFO,
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use crate::diff::ChangeKind;
+use crate::syslog::{self, Facility, SyslogLayer};
+use crate::{ErrorCodes, MESSAGES_TO_STDERR, QUIET, error_handler, progress};
+use clap::ValueEnum;
+use colored::Colorize;
+use std::env;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::{EnvFilter, LevelFilter, Targets};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// Results and summaries are logged under this target. `--quiet` hides
+/// everything else below an error on the terminal.
+pub const RESULTS_TARGET: &str = concat!(env!("CARGO_CRATE_NAME"), "::results");
+
+/// Which messages reach the terminal when `RUST_LOG` isn't set.
+const DEFAULT_FILTER: &str = concat!(env!("CARGO_CRATE_NAME"), "=info");
+
+#[derive(ValueEnum, Clone, Copy, Default)]
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether lines written to stderr, or otherwise stdout, get colored.
+    fn colors(self, to_stderr: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+                let is_terminal = if to_stderr {
+                    io::stderr().is_terminal()
+                } else {
+                    io::stdout().is_terminal()
+                };
+                !no_color && is_terminal
+            }
+        }
+    }
+}
+
+/// What `--color` asked for, for output written outside the terminal layer.
+static COLOR: OnceLock<ColorChoice> = OnceLock::new();
+
+/// This run's messages, once `start_capture` is called, for `--bundle`.
+static CAPTURED: Mutex<Option<String>> = Mutex::new(None);
+
+/// Starts keeping every message from here on, whatever the terminal shows.
+pub fn start_capture() {
+    if let Ok(mut captured) = CAPTURED.lock() {
+        captured.get_or_insert_with(String::new);
+    }
+}
+
+/// The messages kept since `start_capture`, one timestamped line each.
+pub fn captured() -> String {
+    CAPTURED
+        .lock()
+        .ok()
+        .and_then(|captured| captured.clone())
+        .unwrap_or_default()
+}
+
+/// Whether output printed straight to stdout, or stderr, should be colored.
+pub fn use_color(to_stderr: bool) -> bool {
+    COLOR.get().copied().unwrap_or_default().colors(to_stderr)
+}
+
+/// Sets up where messages go: the terminal, filtered by `RUST_LOG`; with
+/// `--log-file` a file that gets every message, timestamped and with its
+/// fields, whatever the terminal shows; and with `--syslog` the local syslog
+/// daemon, which gets results and errors.
+pub fn init(log_file: Option<&Path>, syslog_facility: Option<Facility>, color: ColorChoice) {
+    let _ = COLOR.set(color);
+    // Whether to color is decided per line below, by the stream it goes to,
+    // rather than by colored only ever looking at stdout
+    colored::control::set_override(true);
+    let terminal_filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    // Problems are only reported once the terminal is set up to show them
+    let mut problem: Option<(i32, String)> = None;
+
+    let file_layer =
+        log_file.and_then(
+            |path| match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => Some(
+                    tracing_subscriber::fmt::layer()
+                        .with_writer(Mutex::new(file))
+                        .with_ansi(false)
+                        .with_filter(
+                            Targets::new()
+                                .with_target(env!("CARGO_CRATE_NAME"), LevelFilter::DEBUG),
+                        ),
+                ),
+                Err(_) => {
+                    problem = Some((ErrorCodes::LOG_FILE_UNAVAILABLE, path.display().to_string()));
+                    None
+                }
+            },
+        );
+    let syslog_layer = syslog_facility.and_then(|facility| {
+        let syslog_layer = SyslogLayer::connect(facility);
+        if syslog_layer.is_none() {
+            problem = Some((
+                ErrorCodes::SYSLOG_UNAVAILABLE,
+                syslog::SYSLOG_SOCKETS.join(" or "),
+            ));
+        }
+        syslog_layer
+    });
+
+    tracing_subscriber::registry()
+        .with(TerminalLayer { color }.with_filter(terminal_filter))
+        .with(file_layer)
+        .with(syslog_layer)
+        .with(
+            CaptureLayer.with_filter(
+                Targets::new().with_target(env!("CARGO_CRATE_NAME"), LevelFilter::DEBUG),
+            ),
+        )
+        .init();
+
+    if let Some((error_code, name)) = problem {
+        error_handler(error_code, line!(), Some(&name));
+    }
+}
+
+/// Prints events the way the tool always has: a colored level and the
+/// message, on stdout unless it's an error or stdout carries results.
+struct TerminalLayer {
+    color: ColorChoice,
+}
+
+impl<S: Subscriber> Layer<S> for TerminalLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let level = *metadata.level();
+        if level != Level::ERROR
+            && metadata.target() != RESULTS_TARGET
+            && QUIET.load(Ordering::Relaxed)
+        {
+            return;
+        }
+
+        let mut message = MessageVisitor::default();
+        event.record(&mut message);
+        let to_stderr = level == Level::ERROR || MESSAGES_TO_STDERR.load(Ordering::Relaxed);
+        let (prefix, color) = match level {
+            Level::ERROR => ("[ERROR]", colored::Color::Red),
+            Level::WARN => ("[WARN]", colored::Color::Yellow),
+            Level::INFO => ("[INFO]", colored::Color::White),
+            _ => ("[DEBUG]", colored::Color::Green),
+        };
+        let prefix = if self.color.colors(to_stderr) {
+            prefix.color(color).to_string()
+        } else {
+            prefix.to_string()
+        };
+
+        // Diff lines are colored whole by the kind of change they show
+        let text = match message.change.and_then(|kind| kind.color()) {
+            Some(change_color) if self.color.colors(to_stderr) => {
+                message.text.color(change_color).to_string()
+            }
+            _ => message.text,
+        };
+
+        progress::suspend(|| {
+            if to_stderr {
+                eprintln!("{} {}", prefix, text)
+            } else {
+                println!("{} {}", prefix, text)
+            }
+        });
+    }
+}
+
+/// Keeps messages for `captured` once capturing has started.
+struct CaptureLayer;
+
+impl<S: Subscriber> Layer<S> for CaptureLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let Ok(mut captured) = CAPTURED.lock() else {
+            return;
+        };
+        let Some(captured) = captured.as_mut() else {
+            return;
+        };
+        let mut message = MessageVisitor::default();
+        event.record(&mut message);
+        captured.push_str(&format!(
+            "{} {} {}\n",
+            humantime::format_rfc3339_millis(SystemTime::now()),
+            event.metadata().level(),
+            message.text
+        ));
+    }
+}
+
+/// Pulls out just the message, and the kind of change a diff line shows.
+/// The other structured fields are for the log file and other subscribers;
+/// the terminal line already says the same thing.
+#[derive(Default)]
+struct MessageVisitor {
+    text: String,
+    change: Option<ChangeKind>,
+}
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "change" => self.change = ChangeKind::from_field(value),
+            "message" => self.text = value.to_string(),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.text = format!("{:?}", value);
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use crate::cli::{MatrixArgs, MatrixFormat};
+use crate::{
+    ConnectionStatus, ErrorCodes, ScanResult, ScanSettings, VerbosityLevel, build_scan_targets,
+    check_target, error_handler, print_to_terminal,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use tokio::net::TcpSocket;
+use tokio::task::JoinSet;
+
+pub async fn run_matrix(mut matrix_args: MatrixArgs) {
+    let mut set: JoinSet<(IpAddr, ScanResult)> = JoinSet::new();
+    let mut cells: HashMap<(IpAddr, SocketAddr), ConnectionStatus> = HashMap::new();
+
+    for source in &matrix_args.sources {
+        verify_source_address(*source);
+    }
+
+    let destinations: Vec<SocketAddr> = build_scan_targets(&mut matrix_args.scan).await;
+    let settings = ScanSettings::from_args(&matrix_args.scan);
+
+    for source in &matrix_args.sources {
+        for destination in &destinations {
+            if source.is_ipv4() != destination.is_ipv4() {
+                continue;
+            }
+            let (source, destination, settings) = (*source, *destination, settings.clone());
+            set.spawn(async move {
+                let scan_result = check_target(destination, Some(source), settings).await;
+                (source, scan_result)
+            });
+        }
+    }
+
+    print_to_terminal(String::from("Waiting for results"), VerbosityLevel::DEBUG);
+
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok((source, scan_result)) => {
+                print_to_terminal(
+                    format!("{} -> {} - {}", source, scan_result.ip, scan_result.status),
+                    VerbosityLevel::DEBUG,
+                );
+                cells.insert((source, scan_result.ip), scan_result.status);
+            }
+            Err(e) => {
+                print_to_terminal(
+                    format!("An error has occured: {}", e),
+                    VerbosityLevel::ERROR,
+                );
+            }
+        }
+    }
+
+    let rendered = match matrix_args.format {
+        MatrixFormat::Csv => render_csv(&matrix_args.sources, &destinations, &cells),
+        MatrixFormat::Html => render_html(&matrix_args.sources, &destinations, &cells),
+    };
+
+    match &matrix_args.out {
+        Some(path) => {
+            if fs::write(path, rendered).is_err() {
+                error_handler(
+                    ErrorCodes::OUTPUT_WRITE_FAILURE,
+                    line!(),
+                    Some(&path.display().to_string()),
+                );
+            }
+            print_to_terminal(
+                format!("Matrix written to {}", path.display()),
+                VerbosityLevel::INFO,
+            );
+        }
+        None => print!("{}", rendered),
+    }
+}
+
+/// Binding ahead of time means a typo in `--source` fails once up front,
+/// rather than showing up as a column of failed connections.
+fn verify_source_address(source: IpAddr) {
+    let socket = if source.is_ipv4() {
+        TcpSocket::new_v4()
+    } else {
+        TcpSocket::new_v6()
+    };
+    let bound = socket.and_then(|socket| socket.bind(SocketAddr::new(source, 0)));
+    if bound.is_err() {
+        error_handler(
+            ErrorCodes::SOURCE_ADDRESS_UNAVAILABLE,
+            line!(),
+            Some(&source.to_string()),
+        );
+    }
+}
+
+fn cell_label(
+    cells: &HashMap<(IpAddr, SocketAddr), ConnectionStatus>,
+    source: &IpAddr,
+    destination: &SocketAddr,
+) -> String {
+    match cells.get(&(*source, *destination)) {
+        Some(status) => status.to_string(),
+        None => String::from("-"),
+    }
+}
+
+fn render_csv(
+    sources: &[IpAddr],
+    destinations: &[SocketAddr],
+    cells: &HashMap<(IpAddr, SocketAddr), ConnectionStatus>,
+) -> String {
+    let mut csv = String::from("source");
+    for destination in destinations {
+        csv.push_str(&format!(",{}", destination));
+    }
+    csv.push('\n');
+
+    for source in sources {
+        csv.push_str(&source.to_string());
+        for destination in destinations {
+            csv.push_str(&format!(",{}", cell_label(cells, source, destination)));
+        }
+        csv.push('\n');
+    }
+    csv
+}
+
+fn render_html(
+    sources: &[IpAddr],
+    destinations: &[SocketAddr],
+    cells: &HashMap<(IpAddr, SocketAddr), ConnectionStatus>,
+) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Connectivity matrix</title>\n<style>\n\
+         table { border-collapse: collapse; font-family: monospace; }\n\
+         th, td { border: 1px solid #999; padding: 4px 8px; }\n\
+         .Open { background: #b7e1b5; }\n\
+         .Refused, .Unconfirmed { background: #f5e3a3; }\n\
+         .Timeout, .Unreachable { background: #f0b2b2; }\n\
+         </style>\n</head>\n<body>\n<table>\n<tr><th>source</th>",
+    );
+    for destination in destinations {
+        html.push_str(&format!("<th>{}</th>", destination));
+    }
+    html.push_str("</tr>\n");
+
+    for source in sources {
+        html.push_str(&format!("<tr><th>{}</th>", source));
+        for destination in destinations {
+            let label = cell_label(cells, source, destination);
+            html.push_str(&format!("<td class=\"{}\">{}</td>", label, label));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
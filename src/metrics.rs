@@ -0,0 +1,160 @@
+use crate::{ConnectionStatus, ErrorCodes, ScanResult, error_handler};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{Duration, timeout};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A watched endpoint, by the hostname it was resolved from if any.
+type SeriesKey = (Option<String>, SocketAddr);
+
+#[derive(Default)]
+struct EndpointSeries {
+    open: bool,
+    latency: Duration,
+    /// Probes so far, by lowercase status
+    probes: BTreeMap<String, u64>,
+}
+
+/// The latest state of every watched endpoint, served in the Prometheus
+/// text format so dashboards can be built straight on the watch.
+#[derive(Default)]
+pub struct Metrics {
+    endpoints: Mutex<BTreeMap<SeriesKey, EndpointSeries>>,
+}
+
+impl Metrics {
+    pub fn record(&self, scan_result: &ScanResult) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let series = endpoints
+            .entry((scan_result.hostname.clone(), scan_result.ip))
+            .or_default();
+        series.open = scan_result.status == ConnectionStatus::Open;
+        if series.open {
+            series.latency = scan_result.latency;
+        }
+        *series
+            .probes
+            .entry(scan_result.status.to_string().to_lowercase())
+            .or_default() += 1;
+    }
+
+    /// Drops an endpoint that's no longer watched, so its last state doesn't
+    /// linger on the dashboards.
+    pub fn forget(&self, hostname: Option<String>, endpoint: SocketAddr) {
+        self.endpoints.lock().unwrap().remove(&(hostname, endpoint));
+    }
+
+    fn render(&self) -> String {
+        let endpoints = self.endpoints.lock().unwrap();
+        let labels = |(hostname, endpoint): &SeriesKey| {
+            let mut labels = format!("host=\"{}\",port=\"{}\"", endpoint.ip(), endpoint.port());
+            if let Some(hostname) = hostname {
+                let _ = write!(labels, ",hostname=\"{}\"", hostname);
+            }
+            labels
+        };
+
+        let mut rendered = String::new();
+        rendered.push_str("# HELP watched_endpoints Endpoints the watch has results for.\n");
+        rendered.push_str("# TYPE watched_endpoints gauge\n");
+        let _ = writeln!(rendered, "watched_endpoints {}", endpoints.len());
+
+        rendered.push_str("# HELP port_open Whether the endpoint was open when last probed.\n");
+        rendered.push_str("# TYPE port_open gauge\n");
+        for (key, series) in endpoints.iter() {
+            let _ = writeln!(
+                rendered,
+                "port_open{{{}}} {}",
+                labels(key),
+                series.open as u8
+            );
+        }
+
+        rendered.push_str(
+            "# HELP connect_latency_seconds How long the last successful connect took.\n",
+        );
+        rendered.push_str("# TYPE connect_latency_seconds gauge\n");
+        for (key, series) in endpoints.iter() {
+            if series.probes.contains_key("open") {
+                let _ = writeln!(
+                    rendered,
+                    "connect_latency_seconds{{{}}} {}",
+                    labels(key),
+                    series.latency.as_secs_f64()
+                );
+            }
+        }
+
+        rendered.push_str("# HELP probes_total Probes of the endpoint, by their result.\n");
+        rendered.push_str("# TYPE probes_total counter\n");
+        for (key, series) in endpoints.iter() {
+            for (status, count) in &series.probes {
+                let _ = writeln!(
+                    rendered,
+                    "probes_total{{{},status=\"{}\"}} {}",
+                    labels(key),
+                    status,
+                    count
+                );
+            }
+        }
+        rendered
+    }
+}
+
+/// Serves `GET /metrics` on `address` in the background for as long as the
+/// process runs.
+pub async fn serve(address: SocketAddr, metrics: Arc<Metrics>) {
+    let listener = match TcpListener::bind(address).await {
+        Ok(listener_result) => listener_result,
+        Err(_) => error_handler(
+            ErrorCodes::METRICS_LISTEN_FAILED,
+            line!(),
+            Some(&address.to_string()),
+        ),
+    };
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let metrics = metrics.clone();
+            tokio::spawn(async move {
+                // A scraper that never finishes its request is dropped
+                let _ = timeout(REQUEST_TIMEOUT, respond(stream, &metrics)).await;
+            });
+        }
+    });
+}
+
+async fn respond(mut stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    let mut request: Vec<u8> = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") && request.len() < 8 * 1024 {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let request_line = String::from_utf8_lossy(&request);
+    let response = match request_line.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", path] if path == "/metrics" || path.starts_with("/metrics?") => {
+            let body = metrics.render();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        _ => {
+            String::from("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+        }
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
@@ -0,0 +1,240 @@
+use crate::hints::ServiceProtocol;
+use crate::{
+    ConnectionStatus, ScanResult, ScanSettings, VerbosityLevel, print_to_terminal, sandbox, usage,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, timeout};
+
+/// How long the server gets to answer each request, and how many are sent.
+const REQUEST_WAIT: Duration = Duration::from_secs(1);
+const ATTEMPTS: u32 = 3;
+/// Seconds from the NTP era's start in 1900 to the Unix epoch.
+const NTP_EPOCH_OFFSET: u64 = 2_208_988_800;
+const PACKET_BYTES: usize = 48;
+/// Leap indicator 0, version 4, client mode.
+const CLIENT_REQUEST: u8 = 0x23;
+const SERVER_MODE: u8 = 4;
+/// The leap indicator a server sets while its clock isn't synchronized.
+const LEAP_ALARM: u8 = 3;
+/// The stratum servers report while unsynchronized.
+const STRATUM_UNSYNCHRONIZED: u8 = 16;
+/// Clocks further off than this from the scanner's are warned about.
+const MAX_OFFSET_MS: f64 = 1000.0;
+
+/// How an NTP server answered `--ntp-probe`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NtpSummary {
+    pub version: u8,
+    /// How many hops the server is from a reference clock, 1 being
+    /// attached to one
+    pub stratum: u8,
+    /// What the server synchronizes to: a reference clock's code like
+    /// `GPS` at stratum 1, the upstream server's address below it, or the
+    /// kiss code of a server turning the scanner away
+    pub reference: String,
+    /// Whether the server says its clock is synchronized
+    pub synchronized: bool,
+    /// How far the server's clock is from the scanner's, in milliseconds,
+    /// positive when it's ahead
+    pub offset_ms: f64,
+    /// The round trip to the server and back, in milliseconds
+    pub delay_ms: f64,
+}
+
+impl NtpSummary {
+    /// Whether the server's clock is worth warning about: unsynchronized,
+    /// refusing to serve time, or far from the scanner's.
+    pub fn unhealthy(&self) -> bool {
+        !self.synchronized || self.stratum == 0 || self.offset_ms.abs() > MAX_OFFSET_MS
+    }
+
+    /// The summary for a result line, e.g. `stratum 2 from 192.0.2.1,
+    /// offset +0.412 ms, delay 1.208 ms`.
+    pub fn describe(&self) -> String {
+        if self.stratum == 0 {
+            return format!("refused with kiss code {}", self.reference);
+        }
+        let state = if self.synchronized {
+            ""
+        } else {
+            "unsynchronized, "
+        };
+        format!(
+            "{}stratum {} from {}, offset {:+.3} ms, delay {:.3} ms",
+            state, self.stratum, self.reference, self.offset_ms, self.delay_ms
+        )
+    }
+}
+
+/// A time as a 64-bit NTP timestamp: seconds since 1900, then a binary
+/// fraction of a second.
+fn ntp_timestamp(time: SystemTime) -> u64 {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since.as_secs() + NTP_EPOCH_OFFSET;
+    let fraction = ((since.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    seconds << 32 | fraction
+}
+
+/// An NTP timestamp in seconds, as a float for offset arithmetic.
+fn seconds(timestamp: u64) -> f64 {
+    (timestamp >> 32) as f64 + (timestamp & 0xffff_ffff) as f64 / 4_294_967_296.0
+}
+
+fn read_u64(packet: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(packet[offset..offset + 8].try_into().unwrap())
+}
+
+/// Parses the reply to a request sent at `sent` and received at `received`,
+/// both NTP timestamps. The request's transmit time comes back as the
+/// reply's origin time, which tells replies to this request from others.
+fn parse_reply(packet: &[u8], sent: u64, received: u64) -> Option<NtpSummary> {
+    if packet.len() < PACKET_BYTES || packet[0] & 0x07 != SERVER_MODE {
+        return None;
+    }
+    if read_u64(packet, 24) != sent {
+        return None;
+    }
+    let leap = packet[0] >> 6;
+    let version = packet[0] >> 3 & 0x07;
+    let stratum = packet[1];
+    let reference_id = &packet[12..16];
+    let reference = if stratum <= 1 || stratum == STRATUM_UNSYNCHRONIZED {
+        String::from_utf8_lossy(reference_id)
+            .trim_end_matches('\0')
+            .to_string()
+    } else {
+        Ipv4Addr::new(
+            reference_id[0],
+            reference_id[1],
+            reference_id[2],
+            reference_id[3],
+        )
+        .to_string()
+    };
+    // T1 to T4: request sent, request received, reply sent, reply received
+    let (t1, t2, t3, t4) = (
+        seconds(sent),
+        seconds(read_u64(packet, 32)),
+        seconds(read_u64(packet, 40)),
+        seconds(received),
+    );
+    Some(NtpSummary {
+        version,
+        stratum,
+        reference,
+        synchronized: leap != LEAP_ALARM && stratum != STRATUM_UNSYNCHRONIZED,
+        offset_ms: ((t2 - t1) + (t3 - t4)) / 2.0 * 1000.0,
+        delay_ms: ((t4 - t1) - (t3 - t2)).max(0.0) * 1000.0,
+    })
+}
+
+/// Sends a client request and reads the server's reply, sending again a
+/// few times over if none comes.
+async fn probe(target: SocketAddr) -> io::Result<NtpSummary> {
+    let local = if target.is_ipv4() {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    } else {
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(local, 0)).await?;
+    socket.connect(target).await?;
+    let mut buffer = [0u8; 1500];
+    for _ in 0..ATTEMPTS {
+        let mut request = [0u8; PACKET_BYTES];
+        request[0] = CLIENT_REQUEST;
+        let sent = ntp_timestamp(SystemTime::now());
+        request[40..48].copy_from_slice(&sent.to_be_bytes());
+        socket.send(&request).await?;
+        usage::sent(request.len());
+        let reply = timeout(REQUEST_WAIT, async {
+            // Stray datagrams that aren't our reply are skipped until the
+            // wait runs out
+            loop {
+                let read = socket.recv(&mut buffer).await?;
+                let received = ntp_timestamp(SystemTime::now());
+                usage::received(read);
+                if let Some(summary) = parse_reply(&buffer[..read], sent, received) {
+                    return Ok::<_, io::Error>(summary);
+                }
+            }
+        })
+        .await;
+        if let Ok(reply) = reply {
+            return reply;
+        }
+    }
+    Err(io::Error::from(ErrorKind::TimedOut))
+}
+
+/// Asks every port in `results` that's hinted as NTP, 123 included, and
+/// open or open|filtered in a `--udp` scan for the time, recording its
+/// stratum, reference and how far its clock is from the scanner's. Servers
+/// that are unsynchronized or more than a second off are warned about. At
+/// most `max_enrichment_probes` servers are asked at once.
+pub async fn probe_ntp(settings: &Arc<ScanSettings>, results: &mut [ScanResult]) {
+    let limits = settings.config.limits;
+    let slots = Arc::new(Semaphore::new(limits.max_enrichment_probes.max(1)));
+    let mut set: JoinSet<(SocketAddr, Result<io::Result<NtpSummary>, String>)> = JoinSet::new();
+    for scan_result in results.iter().filter(|scan_result| {
+        matches!(
+            scan_result.status,
+            ConnectionStatus::Open | ConnectionStatus::OpenFiltered
+        ) && settings.hints.protocol_for(scan_result.ip.port()) == Some(ServiceProtocol::Ntp)
+    }) {
+        let target = scan_result.ip;
+        let slots = slots.clone();
+        set.spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            let summary = sandbox::run_isolated(limits.max_probe_time, probe(target)).await;
+            (target, summary)
+        });
+    }
+    if set.is_empty() {
+        return;
+    }
+    print_to_terminal(
+        String::from("Asking NTP servers for the time"),
+        VerbosityLevel::INFO,
+    );
+
+    let mut summaries: HashMap<SocketAddr, NtpSummary> = HashMap::new();
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok((target, Ok(Ok(summary)))) => {
+                let level = if summary.unhealthy() {
+                    VerbosityLevel::WARN
+                } else {
+                    VerbosityLevel::INFO
+                };
+                print_to_terminal(format!("{} - {}", target, summary.describe()), level);
+                summaries.insert(target, summary);
+            }
+            Ok((target, Ok(Err(e)))) => print_to_terminal(
+                format!("{} - no NTP answer: {}", target, e),
+                VerbosityLevel::WARN,
+            ),
+            Ok((target, Err(problem))) => {
+                print_to_terminal(format!("{} - {}", target, problem), VerbosityLevel::WARN)
+            }
+            Err(e) => {
+                print_to_terminal(
+                    format!("An error has occured: {}", e),
+                    VerbosityLevel::ERROR,
+                );
+            }
+        }
+    }
+    for scan_result in results.iter_mut() {
+        if let Some(summary) = summaries.remove(&scan_result.ip) {
+            scan_result.ntp = Some(summary);
+        }
+    }
+}
@@ -0,0 +1,36 @@
+/// The TTLs network stacks start their packets with. A reply arrives with
+/// what's left of one of them after the hops back, so the nearest at or
+/// above the TTL seen is the one it started from.
+const INITIAL_TTLS: [u8; 4] = [32, 64, 128, 255];
+
+/// Initial TTL, the receive windows SYN/ACKs are known to offer, and the OS
+/// family they point to. An empty list matches any window, and is all an
+/// RST, whose window means nothing, can match. The first match wins.
+const SIGNATURES: &[(u8, &[u16], &str)] = &[
+    (64, &[65535], "macOS or BSD"),
+    (
+        64,
+        &[
+            5720, 5792, 5840, 14480, 14600, 28960, 29200, 43440, 43690, 64240, 65160,
+        ],
+        "Linux",
+    ),
+    (64, &[], "Linux, BSD or macOS"),
+    (128, &[], "Windows"),
+    (255, &[4128], "Cisco IOS"),
+    (255, &[], "Solaris or a network device"),
+];
+
+/// Guesses the OS family of a host from the TTL of a reply and, for a
+/// SYN/ACK, the window it offered. `None` where nothing in the table fits,
+/// e.g. an initial TTL of 32, which few stacks still use.
+pub fn guess(ttl: u8, window: Option<u16>) -> Option<String> {
+    let initial = INITIAL_TTLS.into_iter().find(|initial| *initial >= ttl)?;
+    SIGNATURES
+        .iter()
+        .find(|(signature_ttl, windows, _)| {
+            *signature_ttl == initial
+                && (windows.is_empty() || window.is_some_and(|window| windows.contains(&window)))
+        })
+        .map(|(_, _, family)| family.to_string())
+}
@@ -0,0 +1,176 @@
+use crate::output::ScanMetadata;
+use crate::{ScanResult, VerbosityLevel, http, print_to_terminal};
+use serde_json::{Value, json};
+use std::fs::File;
+use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::time::{Duration, timeout};
+
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+const TRACES_PATH: &str = "/v1/traces";
+
+/// Where traces are sent. A bare collector address like
+/// `http://localhost:4318` gets the standard `/v1/traces` path, any other
+/// path is used as given.
+pub fn parse_endpoint(endpoint: &str) -> Option<http::Url> {
+    let mut url = http::Url::parse(endpoint)?;
+    if url.path == "/" {
+        url.path = TRACES_PATH.to_string();
+    }
+    Some(url)
+}
+
+/// Random bytes for trace and span ids. Falling back to the clock only
+/// risks ids colliding with another trace, which is harmless here.
+fn random_bytes(count: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; count];
+    if File::open("/dev/urandom")
+        .and_then(|mut urandom| urandom.read_exact(&mut bytes))
+        .is_err()
+    {
+        let seed = unix_nanos(SystemTime::now()) ^ std::process::id() as u128;
+        for (index, byte) in bytes.iter_mut().enumerate() {
+            *byte = (seed >> ((index % 16) * 8)) as u8 ^ index as u8;
+        }
+    }
+    bytes
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    json!({ "key": key, "value": value })
+}
+
+/// One trace for the whole scan in the OTLP JSON encoding, with a span per
+/// connection under the scan's own span.
+fn build_trace(started_at: SystemTime, metadata: &ScanMetadata, results: &[ScanResult]) -> Value {
+    let ids = random_bytes(16 + 8 * (results.len() + 1));
+    let trace_id = hex(&ids[..16]);
+    let span_id = |index: usize| hex(&ids[16 + 8 * index..24 + 8 * index]);
+    let scan_span_id = span_id(0);
+
+    let mut scan_attributes = vec![
+        attribute(
+            "targets",
+            json!({ "stringValue": metadata.targets.join(" ") }),
+        ),
+        attribute("ports", json!({ "stringValue": metadata.ports })),
+        attribute(
+            "endpoints",
+            json!({ "intValue": results.len().to_string() }),
+        ),
+    ];
+    if let Some(label) = &metadata.label {
+        scan_attributes.push(attribute("label", json!({ "stringValue": label })));
+    }
+    if let Some(operator) = &metadata.operator {
+        scan_attributes.push(attribute("operator", json!({ "stringValue": operator })));
+    }
+    let mut spans = vec![json!({
+        "traceId": trace_id,
+        "spanId": scan_span_id,
+        "name": "scan",
+        "kind": 1,
+        "startTimeUnixNano": unix_nanos(started_at).to_string(),
+        "endTimeUnixNano": unix_nanos(SystemTime::now()).to_string(),
+        "attributes": scan_attributes,
+    })];
+    for (index, scan_result) in results.iter().enumerate() {
+        let ended = scan_result.timestamp;
+        let mut attributes = vec![
+            attribute(
+                "ip",
+                json!({ "stringValue": scan_result.ip.ip().to_string() }),
+            ),
+            attribute(
+                "port",
+                json!({ "intValue": scan_result.ip.port().to_string() }),
+            ),
+            attribute(
+                "status",
+                json!({ "stringValue": scan_result.status.to_string().to_lowercase() }),
+            ),
+            attribute(
+                "latency_ms",
+                json!({ "doubleValue": scan_result.latency.as_secs_f64() * 1000.0 }),
+            ),
+        ];
+        if let Some(hostname) = &scan_result.hostname {
+            attributes.push(attribute("hostname", json!({ "stringValue": hostname })));
+        }
+        spans.push(json!({
+            "traceId": trace_id,
+            "spanId": span_id(index + 1),
+            "parentSpanId": scan_span_id,
+            "name": "connect",
+            "kind": 3,
+            "startTimeUnixNano": unix_nanos(ended - scan_result.latency).to_string(),
+            "endTimeUnixNano": unix_nanos(ended).to_string(),
+            "attributes": attributes,
+        }));
+    }
+
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    attribute("service.name", json!({ "stringValue": "connection-tester" })),
+                    attribute(
+                        "service.version",
+                        json!({ "stringValue": env!("CARGO_PKG_VERSION") }),
+                    ),
+                ],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "connection-tester", "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+/// Sends the scan's trace to an OTLP/HTTP collector. The scan has already
+/// finished by now, so a collector that's down only gets a warning.
+pub async fn export(
+    endpoint: &http::Url,
+    started_at: SystemTime,
+    metadata: &ScanMetadata,
+    results: &[ScanResult],
+) {
+    let body = build_trace(started_at, metadata, results).to_string();
+    match timeout(
+        EXPORT_TIMEOUT,
+        endpoint.post("application/json", body.as_bytes()),
+    )
+    .await
+    {
+        Ok(Ok(response)) if (200..300).contains(&response.status) => print_to_terminal(
+            format!("Trace exported to {}", endpoint.host),
+            VerbosityLevel::DEBUG,
+        ),
+        Ok(Ok(response)) => print_to_terminal(
+            format!(
+                "Trace export to {} got status {}",
+                endpoint.host, response.status
+            ),
+            VerbosityLevel::WARN,
+        ),
+        Ok(Err(e)) => print_to_terminal(
+            format!("Trace export to {} failed: {}", endpoint.host, e),
+            VerbosityLevel::WARN,
+        ),
+        Err(_) => print_to_terminal(
+            format!("Trace export to {} timed out", endpoint.host),
+            VerbosityLevel::WARN,
+        ),
+    }
+}
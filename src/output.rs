@@ -0,0 +1,828 @@
+use crate::annotations::Annotation;
+use crate::certificate::CertificateSummary;
+use crate::cli::ScanArgs;
+use crate::clock::RunClock;
+use crate::dns_probe::DnsSummary;
+use crate::encryption::Encryption;
+use crate::fingerprint::Service;
+use crate::grpc::GrpcSummary;
+use crate::happy_eyeballs::DualStackSummary;
+use crate::http_probe::HttpSummary;
+use crate::ntp::NtpSummary;
+use crate::pmtu::PathMtuSummary;
+use crate::quic::QuicSummary;
+use crate::snmp::SnmpSummary;
+use crate::ssh::SshSummary;
+use crate::starttls::StarttlsSummary;
+use crate::tls_audit::VersionSupport;
+use crate::traceroute::TraceSummary;
+use crate::websocket::WebSocketSummary;
+use crate::{ConnectionStatus, ErrorCodes, ScanResult, error_handler, grouping, report};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Colored lines as results arrive
+    #[default]
+    Text,
+    /// A single JSON document with every result and the scan metadata
+    Json,
+    /// One row per result with a header line
+    Csv,
+    /// One JSON object per line, written as each result arrives
+    Ndjson,
+    /// One line per host listing every port, for grep and awk
+    Grepable,
+    /// A standalone HTML page with per-host tables and a summary chart
+    Html,
+}
+
+impl OutputFormat {
+    /// Whether this format needs stdout to itself when it isn't going to a file.
+    pub fn is_structured(&self) -> bool {
+        *self != OutputFormat::Text
+    }
+
+    /// Whether results are written one at a time as they arrive rather than
+    /// all together once the scan completes.
+    pub fn is_streaming(&self) -> bool {
+        *self == OutputFormat::Ndjson
+    }
+}
+
+/// Facts about the scan as a whole, kept alongside the per-target results.
+#[derive(Serialize, Deserialize)]
+pub struct ScanMetadata {
+    pub version: String,
+    pub targets: Vec<String>,
+    pub ports: String,
+    pub started_at: String,
+    pub finished_at: String,
+    pub duration_ms: f64,
+    /// What the scan was run for, from `--label`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operator: Option<String>,
+    /// How far the wall clock was set forward (or back) during the scan,
+    /// which `started_at`, `finished_at` and result timestamps are off by
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock_adjustment_ms: Option<f64>,
+    /// Whether the ports are UDP rather than TCP
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub udp: bool,
+    /// Whether the file was written while the scan was still running, or
+    /// after it was interrupted, so holds only some of its results
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub partial: bool,
+}
+
+impl ScanMetadata {
+    pub fn new(scan: &ScanArgs, clock: &RunClock) -> ScanMetadata {
+        ScanMetadata {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            targets: scan.targets.clone(),
+            ports: scan.ports.clone().unwrap_or_default(),
+            started_at: humantime::format_rfc3339_millis(clock.started_at).to_string(),
+            finished_at: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            duration_ms: clock.elapsed().as_secs_f64() * 1000.0,
+            label: scan.run_label.clone(),
+            operator: scan.operator.clone(),
+            clock_adjustment_ms: clock.adjustment_ms(),
+            udp: scan.udp,
+            partial: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ResultRecord {
+    pub target: String,
+    pub port: u16,
+    pub status: String,
+    pub latency_ms: f64,
+    pub timestamp: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// The name the address's PTR record points to, for `--reverse-dns`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reverse_dns: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acknowledgment: Option<String>,
+    /// How the state differs from the last scan of the same targets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change: Option<String>,
+    /// When the result came in, in milliseconds since the process started
+    /// on the monotonic clock. Results are in the order they came in by
+    /// this even if the wall clock was adjusted between them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monotonic_ms: Option<f64>,
+    /// The report group `--group-by` put the result in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// What the `--annotate` inventory says about the host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotation: Option<Annotation>,
+    /// What `--detect-services` found running on the port
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<Service>,
+    /// Whether `--detect-tls` found the port speaking TLS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<Encryption>,
+    /// The certificate chain the port presented, its own certificate first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub certificates: Vec<CertificateSummary>,
+    /// The TLS versions `--tls-audit` found the port accepting
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tls_versions: Vec<VersionSupport>,
+    /// What the port answered to `--http-probe`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpSummary>,
+    /// The `--interface` the probe was sent through
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    /// What the port's SSH server said about itself to `--ssh-probe`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh: Option<SshSummary>,
+    /// How the port's DNS server answered `--dns-probe`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DnsSummary>,
+    /// Whether the port's mail server offered STARTTLS to `--starttls-probe`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub starttls: Option<StarttlsSummary>,
+    /// What a QUIC handshake with the port found for `--quic-probe`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quic: Option<QuicSummary>,
+    /// How the port answered a WebSocket upgrade for `--websocket-probe`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub websocket: Option<WebSocketSummary>,
+    /// What the port's gRPC health check said for `--grpc-probe`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grpc: Option<GrpcSummary>,
+    /// What the port's NTP server said about its clock for `--ntp-probe`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ntp: Option<NtpSummary>,
+    /// The community the port's SNMP agent answered to for `--snmp-probe`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snmp: Option<SnmpSummary>,
+    /// Where a `--traceroute` toward the port, if it didn't answer, got to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<TraceSummary>,
+    /// The path MTU `--pmtu` measured to the host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_mtu: Option<PathMtuSummary>,
+    /// The OS family a `--syn` reply's TTL and window suggest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os_guess: Option<String>,
+    /// How IPv4 and IPv6 connections to the hostname raced for
+    /// `--happy-eyeballs`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dual_stack: Option<DualStackSummary>,
+    /// How many times the endpoint was probed to get this result, with
+    /// `--retries`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attempts: Option<u32>,
+}
+
+impl ResultRecord {
+    pub fn from_scan_result(scan_result: &ScanResult) -> ResultRecord {
+        ResultRecord {
+            target: scan_result.ip.ip().to_string(),
+            port: scan_result.ip.port(),
+            status: scan_result.status.to_string().to_lowercase(),
+            latency_ms: scan_result.latency.as_secs_f64() * 1000.0,
+            timestamp: humantime::format_rfc3339_millis(scan_result.timestamp).to_string(),
+            labels: scan_result.labels.clone(),
+            hostname: scan_result.hostname.clone(),
+            reverse_dns: scan_result.reverse_dns.clone(),
+            acknowledgment: scan_result.acknowledgment.clone(),
+            change: scan_result.change.clone(),
+            monotonic_ms: Some(scan_result.monotonic.as_secs_f64() * 1000.0),
+            group: scan_result.group.clone(),
+            annotation: scan_result.annotation.clone(),
+            service: scan_result.service.clone(),
+            encryption: scan_result.encryption,
+            certificates: scan_result.certificates.clone(),
+            tls_versions: scan_result.tls_versions.clone(),
+            http: scan_result.http.clone(),
+            interface: scan_result.interface.clone(),
+            ssh: scan_result.ssh.clone(),
+            dns: scan_result.dns.clone(),
+            starttls: scan_result.starttls.clone(),
+            quic: scan_result.quic.clone(),
+            websocket: scan_result.websocket.clone(),
+            grpc: scan_result.grpc.clone(),
+            ntp: scan_result.ntp.clone(),
+            snmp: scan_result.snmp.clone(),
+            trace: scan_result.trace.clone(),
+            path_mtu: scan_result.path_mtu.clone(),
+            os_guess: scan_result.os_guess.clone(),
+            dual_stack: scan_result.dual_stack.clone(),
+            attempts: scan_result.attempts,
+        }
+    }
+
+    /// The result the record was written from, as far as the record keeps
+    /// it. `None` if it names no address or status this build knows.
+    pub fn to_scan_result(&self) -> Option<ScanResult> {
+        let ip: IpAddr = self.target.parse().ok()?;
+        let status = ConnectionStatus::from_name(&self.status)?;
+        let mut scan_result = ScanResult::new(
+            SocketAddr::new(ip, self.port),
+            status,
+            Duration::from_secs_f64(self.latency_ms.max(0.0) / 1000.0),
+        );
+        if let Ok(timestamp) = humantime::parse_rfc3339_weak(&self.timestamp) {
+            scan_result.timestamp = timestamp;
+        }
+        if let Some(monotonic_ms) = self.monotonic_ms {
+            scan_result.monotonic = Duration::from_secs_f64(monotonic_ms.max(0.0) / 1000.0);
+        }
+        scan_result.labels = self.labels.clone();
+        scan_result.hostname = self.hostname.clone();
+        scan_result.reverse_dns = self.reverse_dns.clone();
+        scan_result.acknowledgment = self.acknowledgment.clone();
+        scan_result.change = self.change.clone();
+        scan_result.group = self.group.clone();
+        scan_result.annotation = self.annotation.clone();
+        scan_result.service = self.service.clone();
+        scan_result.encryption = self.encryption;
+        scan_result.certificates = self.certificates.clone();
+        scan_result.tls_versions = self.tls_versions.clone();
+        scan_result.http = self.http.clone();
+        scan_result.interface = self.interface.clone();
+        scan_result.ssh = self.ssh.clone();
+        scan_result.dns = self.dns.clone();
+        scan_result.starttls = self.starttls.clone();
+        scan_result.quic = self.quic.clone();
+        scan_result.websocket = self.websocket.clone();
+        scan_result.grpc = self.grpc.clone();
+        scan_result.ntp = self.ntp.clone();
+        scan_result.snmp = self.snmp.clone();
+        scan_result.trace = self.trace.clone();
+        scan_result.path_mtu = self.path_mtu.clone();
+        scan_result.os_guess = self.os_guess.clone();
+        scan_result.dual_stack = self.dual_stack.clone();
+        scan_result.attempts = self.attempts;
+        Some(scan_result)
+    }
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    scan: &'a ScanMetadata,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    groups: Vec<GroupSummary<'a>>,
+    results: Vec<ResultRecord>,
+}
+
+/// How many of a `--group-by` group's endpoints ended up in each state.
+#[derive(Serialize)]
+struct GroupSummary<'a> {
+    name: &'a str,
+    endpoints: usize,
+    summary: BTreeMap<String, usize>,
+}
+
+/// A results file written by `--output json`, read back in.
+#[derive(Serialize, Deserialize)]
+pub struct SavedReport {
+    pub scan: ScanMetadata,
+    pub results: Vec<ResultRecord>,
+}
+
+impl SavedReport {
+    pub fn read(path: &Path) -> Option<SavedReport> {
+        let contents = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+}
+
+pub fn render(format: OutputFormat, metadata: &ScanMetadata, results: &[ScanResult]) -> String {
+    match format {
+        OutputFormat::Text => {
+            let line =
+                |scan_result: &ScanResult| format!("{} - {}\n", scan_result.ip, scan_result.status);
+            let groups = grouping::by_group(results);
+            if groups.is_empty() {
+                return results.iter().map(line).collect();
+            }
+            groups
+                .into_iter()
+                .map(|(name, members)| {
+                    let lines: String = members.into_iter().map(line).collect();
+                    format!("[{}]\n{}", name, lines)
+                })
+                .collect::<Vec<String>>()
+                .join("\n")
+        }
+        OutputFormat::Json => {
+            let groups = grouping::by_group(results)
+                .into_iter()
+                .map(|(name, members)| {
+                    let mut summary: BTreeMap<String, usize> = BTreeMap::new();
+                    for scan_result in &members {
+                        *summary
+                            .entry(scan_result.status.to_string().to_lowercase())
+                            .or_default() += 1;
+                    }
+                    GroupSummary {
+                        name,
+                        endpoints: members.len(),
+                        summary,
+                    }
+                })
+                .collect();
+            let report = JsonReport {
+                scan: metadata,
+                groups,
+                results: results.iter().map(ResultRecord::from_scan_result).collect(),
+            };
+            serde_json::to_string_pretty(&report).unwrap_or_default() + "\n"
+        }
+        OutputFormat::Csv => {
+            // The labels, group, annotation, service and encryption columns
+            // are only there when results were labelled, grouped, annotated,
+            // fingerprinted or checked for TLS, so existing consumers see the
+            // same columns as before
+            let labelled = results
+                .iter()
+                .any(|scan_result| !scan_result.labels.is_empty());
+            let grouped = results
+                .iter()
+                .any(|scan_result| scan_result.group.is_some());
+            let annotated = results
+                .iter()
+                .any(|scan_result| scan_result.annotation.is_some());
+            let fingerprinted = results
+                .iter()
+                .any(|scan_result| scan_result.service.is_some());
+            let tls_checked = results
+                .iter()
+                .any(|scan_result| scan_result.encryption.is_some());
+            let web_probed = results.iter().any(|scan_result| scan_result.http.is_some());
+            let ssh_probed = results.iter().any(|scan_result| scan_result.ssh.is_some());
+            let dns_probed = results.iter().any(|scan_result| scan_result.dns.is_some());
+            let quic_probed = results.iter().any(|scan_result| scan_result.quic.is_some());
+            let websocket_probed = results
+                .iter()
+                .any(|scan_result| scan_result.websocket.is_some());
+            let ntp_probed = results.iter().any(|scan_result| scan_result.ntp.is_some());
+            let snmp_probed = results.iter().any(|scan_result| scan_result.snmp.is_some());
+            let traced = results
+                .iter()
+                .any(|scan_result| scan_result.trace.is_some());
+            let mtu_probed = results
+                .iter()
+                .any(|scan_result| scan_result.path_mtu.is_some());
+            let os_guessed = results
+                .iter()
+                .any(|scan_result| scan_result.os_guess.is_some());
+            let raced = results
+                .iter()
+                .any(|scan_result| scan_result.dual_stack.is_some());
+            let retried = results
+                .iter()
+                .any(|scan_result| scan_result.attempts.is_some());
+            let grpc_probed = results.iter().any(|scan_result| scan_result.grpc.is_some());
+            let mail_probed = results
+                .iter()
+                .any(|scan_result| scan_result.starttls.is_some());
+            let through_interfaces = results
+                .iter()
+                .any(|scan_result| scan_result.interface.is_some());
+            let named = results
+                .iter()
+                .any(|scan_result| scan_result.reverse_dns.is_some());
+            let mut csv = String::from("ip,port,status,latency_ms");
+            if labelled {
+                csv.push_str(",labels");
+            }
+            if named {
+                csv.push_str(",reverse_dns");
+            }
+            if through_interfaces {
+                csv.push_str(",interface");
+            }
+            if grouped {
+                csv.push_str(",group");
+            }
+            if annotated {
+                csv.push_str(",owner,cmdb_hostname,description");
+            }
+            if fingerprinted {
+                csv.push_str(",service,version");
+            }
+            if tls_checked {
+                csv.push_str(",encryption,certificate,issuer,days_left,tls_versions");
+            }
+            if web_probed {
+                csv.push_str(",http_status,http_server,http_location,http_title");
+            }
+            if ssh_probed {
+                csv.push_str(",ssh_version,ssh_kex_algorithms,ssh_host_key_algorithms");
+            }
+            if dns_probed {
+                csv.push_str(",dns_udp,dns_tcp,dns_rcode,dns_recursion,dns_answers");
+            }
+            if websocket_probed {
+                csv.push_str(",websocket_path,websocket_status,websocket_upgraded");
+            }
+            if quic_probed {
+                csv.push_str(",http3,quic_alpn,quic_versions,quic_closed");
+            }
+            if mail_probed {
+                csv.push_str(",mail_protocol,mail_greeting,starttls,starttls_version");
+            }
+            if grpc_probed {
+                csv.push_str(",grpc_service,grpc_tls,grpc_status,grpc_problem");
+            }
+            if ntp_probed {
+                csv.push_str(
+                    ",ntp_stratum,ntp_reference,ntp_synchronized,ntp_offset_ms,ntp_delay_ms",
+                );
+            }
+            if snmp_probed {
+                csv.push_str(",snmp_community,snmp_description");
+            }
+            if traced {
+                csv.push_str(",trace_reached,trace_last_hop,trace_last_distance,trace_path");
+            }
+            if mtu_probed {
+                csv.push_str(",path_mtu,path_mtu_local,path_mtu_black_hole");
+            }
+            if os_guessed {
+                csv.push_str(",os_guess");
+            }
+            if raced {
+                csv.push_str(
+                    ",ipv4_outcome,ipv4_latency_ms,ipv6_outcome,ipv6_latency_ms,faster_family",
+                );
+            }
+            if retried {
+                csv.push_str(",attempts");
+            }
+            csv.push('\n');
+            for record in results.iter().map(ResultRecord::from_scan_result) {
+                csv.push_str(&format!(
+                    "{},{},{},{:.3}",
+                    record.target, record.port, record.status, record.latency_ms
+                ));
+                if labelled {
+                    csv.push_str(&format!(",{}", csv_field(&record.labels.join(";"))));
+                }
+                if named {
+                    csv.push_str(&format!(
+                        ",{}",
+                        csv_field(&record.reverse_dns.unwrap_or_default())
+                    ));
+                }
+                if through_interfaces {
+                    csv.push_str(&format!(
+                        ",{}",
+                        csv_field(&record.interface.unwrap_or_default())
+                    ));
+                }
+                if grouped {
+                    csv.push_str(&format!(
+                        ",{}",
+                        csv_field(&record.group.unwrap_or_default())
+                    ));
+                }
+                if annotated {
+                    let annotation = record.annotation.unwrap_or_default();
+                    for field in [
+                        annotation.owner,
+                        annotation.hostname,
+                        annotation.description,
+                    ] {
+                        csv.push_str(&format!(",{}", csv_field(&field.unwrap_or_default())));
+                    }
+                }
+                if fingerprinted {
+                    let (name, version) = match record.service {
+                        Some(service) => (service.name, service.version.unwrap_or_default()),
+                        None => (String::new(), String::new()),
+                    };
+                    csv.push_str(&format!(",{},{}", csv_field(&name), csv_field(&version)));
+                }
+                if tls_checked {
+                    let encryption = record.encryption.map(|encryption| encryption.to_string());
+                    csv.push_str(&format!(",{}", encryption.unwrap_or_default()));
+                    // The soonest expiry in the chain, with the certificate it's for
+                    match record
+                        .certificates
+                        .iter()
+                        .min_by_key(|certificate| certificate.days_left)
+                    {
+                        Some(certificate) => csv.push_str(&format!(
+                            ",{},{},{}",
+                            csv_field(&certificate.subject),
+                            csv_field(&certificate.issuer),
+                            certificate.days_left
+                        )),
+                        None => csv.push_str(",,,"),
+                    }
+                    let versions: Vec<String> = record
+                        .tls_versions
+                        .into_iter()
+                        .map(|support| support.version)
+                        .collect();
+                    csv.push_str(&format!(",{}", versions.join(";")));
+                }
+                if web_probed {
+                    match record.http {
+                        Some(http) => csv.push_str(&format!(
+                            ",{},{},{},{}",
+                            http.status,
+                            csv_field(&http.server.unwrap_or_default()),
+                            csv_field(&http.location.unwrap_or_default()),
+                            csv_field(&http.title.unwrap_or_default())
+                        )),
+                        None => csv.push_str(",,,,"),
+                    }
+                }
+                if ssh_probed {
+                    match record.ssh {
+                        Some(ssh) => csv.push_str(&format!(
+                            ",{},{},{}",
+                            csv_field(&ssh.identification),
+                            ssh.kex_algorithms.join(";"),
+                            ssh.host_key_algorithms.join(";")
+                        )),
+                        None => csv.push_str(",,,"),
+                    }
+                }
+                if dns_probed {
+                    match record.dns {
+                        Some(dns) => csv.push_str(&format!(
+                            ",{},{},{},{},{}",
+                            dns.udp,
+                            dns.tcp,
+                            dns.rcode,
+                            dns.recursion_available,
+                            csv_field(&dns.answers.join(";"))
+                        )),
+                        None => csv.push_str(",,,,,"),
+                    }
+                }
+                if websocket_probed {
+                    match record.websocket {
+                        Some(websocket) => csv.push_str(&format!(
+                            ",{},{},{}",
+                            csv_field(&websocket.path),
+                            websocket.status,
+                            websocket.upgraded
+                        )),
+                        None => csv.push_str(",,,"),
+                    }
+                }
+                if quic_probed {
+                    match record.quic {
+                        Some(quic) => csv.push_str(&format!(
+                            ",{},{},{},{}",
+                            quic.http3(),
+                            csv_field(&quic.alpn.unwrap_or_default()),
+                            quic.versions.join(";"),
+                            csv_field(&quic.closed.unwrap_or_default())
+                        )),
+                        None => csv.push_str(",,,,"),
+                    }
+                }
+                if mail_probed {
+                    match record.starttls {
+                        Some(starttls) => csv.push_str(&format!(
+                            ",{},{},{},{}",
+                            starttls.protocol,
+                            csv_field(&starttls.greeting),
+                            starttls.offered,
+                            starttls.tls_version.unwrap_or_default()
+                        )),
+                        None => csv.push_str(",,,,"),
+                    }
+                }
+                if grpc_probed {
+                    match record.grpc {
+                        Some(grpc) => csv.push_str(&format!(
+                            ",{},{},{},{}",
+                            csv_field(&grpc.service),
+                            grpc.tls,
+                            grpc.status.unwrap_or_default(),
+                            csv_field(&grpc.problem.unwrap_or_default())
+                        )),
+                        None => csv.push_str(",,,,"),
+                    }
+                }
+                if ntp_probed {
+                    match record.ntp {
+                        Some(ntp) => csv.push_str(&format!(
+                            ",{},{},{},{:.3},{:.3}",
+                            ntp.stratum,
+                            csv_field(&ntp.reference),
+                            ntp.synchronized,
+                            ntp.offset_ms,
+                            ntp.delay_ms
+                        )),
+                        None => csv.push_str(",,,,,"),
+                    }
+                }
+                if snmp_probed {
+                    match record.snmp {
+                        Some(snmp) => csv.push_str(&format!(
+                            ",{},{}",
+                            csv_field(&snmp.community),
+                            csv_field(&snmp.description.unwrap_or_default())
+                        )),
+                        None => csv.push_str(",,"),
+                    }
+                }
+                if traced {
+                    match record.trace {
+                        Some(trace) => {
+                            let (distance, hop) = trace.last_hop().unwrap_or_default();
+                            let path = trace
+                                .hops
+                                .iter()
+                                .map(|hop| hop.as_deref().unwrap_or("*"))
+                                .collect::<Vec<_>>()
+                                .join(";");
+                            csv.push_str(&format!(
+                                ",{},{},{},{}",
+                                trace.reached, hop, distance, path
+                            ));
+                        }
+                        None => csv.push_str(",,,,"),
+                    }
+                }
+                if mtu_probed {
+                    match record.path_mtu {
+                        Some(path_mtu) => csv.push_str(&format!(
+                            ",{},{},{}",
+                            path_mtu.mtu,
+                            path_mtu.local_mtu,
+                            path_mtu.black_hole()
+                        )),
+                        None => csv.push_str(",,,"),
+                    }
+                }
+                if os_guessed {
+                    csv.push_str(&format!(
+                        ",{}",
+                        csv_field(&record.os_guess.unwrap_or_default())
+                    ));
+                }
+                if raced {
+                    match record.dual_stack {
+                        Some(dual_stack) => csv.push_str(&format!(
+                            ",{},{:.3},{},{:.3},{}",
+                            csv_field(&dual_stack.ipv4.outcome),
+                            dual_stack.ipv4.latency_ms,
+                            csv_field(&dual_stack.ipv6.outcome),
+                            dual_stack.ipv6.latency_ms,
+                            dual_stack.faster().unwrap_or_default()
+                        )),
+                        None => csv.push_str(",,,,,"),
+                    }
+                }
+                if retried {
+                    match record.attempts {
+                        Some(attempts) => csv.push_str(&format!(",{}", attempts)),
+                        None => csv.push(','),
+                    }
+                }
+                csv.push('\n');
+            }
+            csv
+        }
+        OutputFormat::Ndjson => results.iter().map(ndjson_line).collect(),
+        OutputFormat::Grepable => {
+            let mut hosts: BTreeMap<IpAddr, Vec<(u16, &'static str)>> = BTreeMap::new();
+            for scan_result in results {
+                hosts
+                    .entry(scan_result.ip.ip())
+                    .or_default()
+                    .push((scan_result.ip.port(), grepable_state(scan_result.status)));
+            }
+
+            let mut lines = String::new();
+            for (ip, mut ports) in hosts {
+                ports.sort();
+                let ports: Vec<String> = ports
+                    .iter()
+                    .map(|(port, state)| format!("{}/{}", port, state))
+                    .collect();
+                lines.push_str(&format!("Host: {} Ports: {}\n", ip, ports.join(", ")));
+            }
+            lines
+        }
+        OutputFormat::Html => report::render_html(metadata, results),
+    }
+}
+
+/// Quotes a free-text CSV field if it needs it.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Port states in the vocabulary other scanners' greppable output uses.
+fn grepable_state(status: ConnectionStatus) -> &'static str {
+    match status {
+        ConnectionStatus::Open => "open",
+        ConnectionStatus::Refused => "closed",
+        ConnectionStatus::Timeout => "filtered",
+        ConnectionStatus::Unreachable => "unreachable",
+        ConnectionStatus::Unconfirmed => "unconfirmed",
+        ConnectionStatus::OpenFiltered => "open|filtered",
+    }
+}
+
+fn ndjson_line(scan_result: &ScanResult) -> String {
+    serde_json::to_string(&ResultRecord::from_scan_result(scan_result)).unwrap_or_default() + "\n"
+}
+
+/// Destination for formats that write each result as soon as it lands.
+pub struct ResultStream {
+    writer: Box<dyn Write>,
+    path_name: String,
+}
+
+impl ResultStream {
+    pub fn open(output_file: Option<&Path>) -> ResultStream {
+        match output_file {
+            Some(path) => {
+                let path_name = path.display().to_string();
+                match File::create(path) {
+                    Ok(file) => ResultStream {
+                        writer: Box::new(file),
+                        path_name,
+                    },
+                    Err(_) => {
+                        error_handler(ErrorCodes::OUTPUT_WRITE_FAILURE, line!(), Some(&path_name))
+                    }
+                }
+            }
+            None => ResultStream {
+                writer: Box::new(io::stdout()),
+                path_name: String::from("stdout"),
+            },
+        }
+    }
+
+    pub fn write(&mut self, scan_result: &ScanResult) {
+        let line = ndjson_line(scan_result);
+        if self.writer.write_all(line.as_bytes()).is_err() || self.writer.flush().is_err() {
+            error_handler(
+                ErrorCodes::OUTPUT_WRITE_FAILURE,
+                line!(),
+                Some(&self.path_name),
+            );
+        }
+    }
+}
+
+pub fn write_results(
+    format: OutputFormat,
+    output_file: Option<&Path>,
+    metadata: &ScanMetadata,
+    results: &[ScanResult],
+) {
+    let rendered = render(format, metadata, results);
+
+    match output_file {
+        Some(path) => {
+            if write_whole(path, rendered.as_bytes()).is_err() {
+                error_handler(
+                    ErrorCodes::OUTPUT_WRITE_FAILURE,
+                    line!(),
+                    Some(&path.display().to_string()),
+                );
+            }
+        }
+        None => print!("{}", rendered),
+    }
+}
+
+/// Writes `contents` beside `path` and moves it into place, so anything
+/// reading the file, or a crash part way through, sees either the old
+/// contents or the new ones and never a mix.
+fn write_whole(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut staging = path.as_os_str().to_owned();
+    staging.push(".partial");
+    let staging = PathBuf::from(staging);
+    fs::write(&staging, contents)?;
+    fs::rename(&staging, path)
+}
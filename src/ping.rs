@@ -0,0 +1,296 @@
+use crate::{ErrorCodes, VerbosityLevel, error_handler, print_to_terminal};
+use std::collections::HashSet;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::{Arc, Mutex};
+use tokio::io::unix::AsyncFd;
+use tokio::time::{Duration, sleep};
+
+/// How long to wait for replies after each round of echo requests.
+const REPLY_WAIT: Duration = Duration::from_secs(1);
+/// Rounds of requests, the later ones only to hosts that haven't replied,
+/// so one lost packet doesn't hide a host.
+const ROUNDS: usize = 2;
+const PAYLOAD: &[u8] = b"conntest";
+
+/// An ICMP socket of one address family. Unprivileged ping sockets are
+/// used where the system allows them, raw sockets otherwise.
+pub struct IcmpSocket {
+    fd: AsyncFd<OwnedFd>,
+    raw: bool,
+    v6: bool,
+}
+
+impl IcmpSocket {
+    pub fn open(v6: bool) -> Option<IcmpSocket> {
+        let (domain, protocol) = if v6 {
+            (libc::AF_INET6, libc::IPPROTO_ICMPV6)
+        } else {
+            (libc::AF_INET, libc::IPPROTO_ICMP)
+        };
+        for (kind, raw) in [(libc::SOCK_DGRAM, false), (libc::SOCK_RAW, true)] {
+            let fd = unsafe {
+                libc::socket(
+                    domain,
+                    kind | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                    protocol,
+                )
+            };
+            if fd < 0 {
+                continue;
+            }
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            if let Ok(fd) = AsyncFd::new(fd) {
+                return Some(IcmpSocket { fd, raw, v6 });
+            }
+        }
+        None
+    }
+
+    /// Raw sockets see every echo reply the host gets, so ours are told
+    /// apart by this identifier. Ping sockets get one from the kernel.
+    fn identifier() -> u16 {
+        std::process::id() as u16
+    }
+
+    pub fn fd(&self) -> &AsyncFd<OwnedFd> {
+        &self.fd
+    }
+
+    fn echo_request(&self, sequence: u16, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![if self.v6 { 128 } else { 8 }, 0, 0, 0];
+        packet.extend_from_slice(&IcmpSocket::identifier().to_be_bytes());
+        packet.extend_from_slice(&sequence.to_be_bytes());
+        packet.extend_from_slice(payload);
+        // The kernel fills in the ICMPv6 checksum itself
+        if !self.v6 {
+            let checksum = checksum(&packet);
+            packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+        }
+        packet
+    }
+
+    /// Sends an echo request carrying `payload` to `ip`.
+    pub async fn send(&self, ip: IpAddr, sequence: u16, payload: &[u8]) -> io::Result<()> {
+        let packet = self.echo_request(sequence, payload);
+        let (address, length) = socket_address(ip);
+        loop {
+            let mut guard = self.fd.writable().await?;
+            let sent = guard.try_io(|fd| {
+                let sent = unsafe {
+                    libc::sendto(
+                        fd.as_raw_fd(),
+                        packet.as_ptr() as *const libc::c_void,
+                        packet.len(),
+                        0,
+                        &address as *const libc::sockaddr_storage as *const libc::sockaddr,
+                        length,
+                    )
+                };
+                if sent < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(())
+                }
+            });
+            match sent {
+                Ok(result) => return result,
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    /// Waits for the next echo reply meant for us and returns who sent it.
+    async fn receive(&self) -> io::Result<IpAddr> {
+        let mut buffer = [0u8; 1500];
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let received = guard.try_io(|fd| {
+                let mut address: libc::sockaddr_storage = unsafe { mem::zeroed() };
+                let mut length = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+                let read = unsafe {
+                    libc::recvfrom(
+                        fd.as_raw_fd(),
+                        buffer.as_mut_ptr() as *mut libc::c_void,
+                        buffer.len(),
+                        0,
+                        &mut address as *mut libc::sockaddr_storage as *mut libc::sockaddr,
+                        &mut length,
+                    )
+                };
+                if read < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok((read as usize, address))
+                }
+            });
+            let (read, address) = match received {
+                Ok(result) => result?,
+                Err(_would_block) => continue,
+            };
+            if let Some(ip) = from_socket_address(&address)
+                && self.reply_sequence(&buffer[..read]).is_some()
+            {
+                return Ok(ip);
+            }
+        }
+    }
+
+    /// The sequence number of `packet` if it's an echo reply meant for us.
+    pub fn reply_sequence(&self, packet: &[u8]) -> Option<u16> {
+        // Raw IPv4 sockets get the IP header too
+        let icmp = if self.raw && !self.v6 {
+            let header_length = (packet.first().copied().unwrap_or_default() & 0x0f) as usize * 4;
+            packet.get(header_length..).unwrap_or_default()
+        } else {
+            packet
+        };
+        let reply_type = if self.v6 { 129 } else { 0 };
+        match icmp {
+            [
+                kind,
+                _,
+                _,
+                _,
+                id_high,
+                id_low,
+                sequence_high,
+                sequence_low,
+                ..,
+            ] if *kind == reply_type
+                && (!self.raw
+                    || u16::from_be_bytes([*id_high, *id_low]) == IcmpSocket::identifier()) =>
+            {
+                Some(u16::from_be_bytes([*sequence_high, *sequence_low]))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The internet checksum of RFC 1071, as ICMP and TCP use it.
+pub fn checksum(packet: &[u8]) -> u16 {
+    let mut sum: u32 = packet
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or_default()]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+pub fn socket_address(ip: IpAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let length = match ip {
+        IpAddr::V4(ipv4) => {
+            let address = unsafe {
+                &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in)
+            };
+            address.sin_family = libc::AF_INET as libc::sa_family_t;
+            address.sin_addr.s_addr = u32::from_ne_bytes(ipv4.octets());
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        IpAddr::V6(ipv6) => {
+            let address = unsafe {
+                &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in6)
+            };
+            address.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            address.sin6_addr.s6_addr = ipv6.octets();
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, length as libc::socklen_t)
+}
+
+pub fn from_socket_address(storage: &libc::sockaddr_storage) -> Option<IpAddr> {
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let address =
+                unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in) };
+            Some(IpAddr::V4(Ipv4Addr::from(
+                address.sin_addr.s_addr.to_ne_bytes(),
+            )))
+        }
+        libc::AF_INET6 => {
+            let address = unsafe {
+                &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in6)
+            };
+            Some(IpAddr::V6(Ipv6Addr::from(address.sin6_addr.s6_addr)))
+        }
+        _ => None,
+    }
+}
+
+/// The ICMP sockets for a ping sweep. They're opened before privileges are
+/// dropped, since raw sockets need root or `CAP_NET_RAW`.
+pub struct Pinger {
+    v4: Option<Arc<IcmpSocket>>,
+    v6: Option<Arc<IcmpSocket>>,
+}
+
+impl Pinger {
+    pub fn open() -> Pinger {
+        let pinger = Pinger {
+            v4: IcmpSocket::open(false).map(Arc::new),
+            v6: IcmpSocket::open(true).map(Arc::new),
+        };
+        if pinger.v4.is_none() && pinger.v6.is_none() {
+            error_handler(ErrorCodes::PING_UNAVAILABLE, line!(), None);
+        }
+        pinger
+    }
+
+    /// Sends echo requests to every address and returns the ones that
+    /// replied. Addresses of a family no socket could be opened for are
+    /// counted as replying, so they're still scanned.
+    pub async fn sweep(&self, addresses: &[IpAddr]) -> HashSet<IpAddr> {
+        let alive: Arc<Mutex<HashSet<IpAddr>>> = Arc::default();
+        let mut listeners = Vec::new();
+        for socket in [&self.v4, &self.v6].into_iter().flatten() {
+            let socket = socket.clone();
+            let alive = alive.clone();
+            listeners.push(tokio::spawn(async move {
+                while let Ok(ip) = socket.receive().await {
+                    alive.lock().unwrap().insert(ip);
+                }
+            }));
+        }
+
+        for round in 0..ROUNDS {
+            if alive.lock().unwrap().len() == addresses.len() {
+                break;
+            }
+            for (index, ip) in addresses.iter().enumerate() {
+                if alive.lock().unwrap().contains(ip) {
+                    continue;
+                }
+                let socket = if ip.is_ipv4() { &self.v4 } else { &self.v6 };
+                let Some(socket) = socket else {
+                    alive.lock().unwrap().insert(*ip);
+                    continue;
+                };
+                let sequence = (round * addresses.len() + index) as u16;
+                if let Err(e) = socket.send(*ip, sequence, PAYLOAD).await {
+                    print_to_terminal(
+                        format!("Could not ping {}: {}", ip, e),
+                        VerbosityLevel::DEBUG,
+                    );
+                }
+            }
+            sleep(REPLY_WAIT).await;
+        }
+
+        for listener in listeners {
+            listener.abort();
+        }
+        let alive = alive.lock().unwrap();
+        addresses
+            .iter()
+            .filter(|ip| alive.contains(ip))
+            .copied()
+            .collect()
+    }
+}
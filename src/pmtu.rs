@@ -0,0 +1,333 @@
+use crate::ping::{IcmpSocket, from_socket_address};
+use crate::traceroute::{queued_error, set_option};
+use crate::{
+    ScanResult, ScanSettings, VerbosityLevel, interfaces, print_to_terminal, sandbox, usage,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::os::fd::AsRawFd;
+use std::sync::Arc;
+use tokio::io::Interest;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, timeout};
+
+/// How long each echo request gets for a reply, and how many are sent per
+/// size, so one lost packet isn't mistaken for one that was too big.
+const REPLY_WAIT: Duration = Duration::from_millis(500);
+const ATTEMPTS: u32 = 2;
+/// Paths below this usually lose more than a tunnel's worth of headers.
+pub const DEFAULT_MIN_MTU: u16 = 1400;
+/// The smallest packets every IPv4 and IPv6 path has to carry.
+const MIN_IPV4_MTU: u16 = 576;
+const MIN_IPV6_MTU: u16 = 1280;
+/// IP and ICMP header bytes in each echo request.
+const IPV4_OVERHEAD: u16 = 20 + 8;
+const IPV6_OVERHEAD: u16 = 40 + 8;
+const ICMP_DEST_UNREACH: u8 = 3;
+const ICMP_FRAG_NEEDED: u8 = 4;
+const ICMPV6_PACKET_TOO_BIG: u8 = 2;
+
+/// The largest packet that made it to a host and back for `--pmtu`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PathMtuSummary {
+    /// The largest packet, IP header included, that got an echo reply
+    pub mtu: u16,
+    /// The MTU of the route out of this machine, where the search started
+    pub local_mtu: u16,
+    /// Whether a router said larger packets were too big. Without that,
+    /// a path narrower than the local MTU silently drops them: an MTU
+    /// black hole
+    pub reported: bool,
+}
+
+impl PathMtuSummary {
+    pub fn black_hole(&self) -> bool {
+        self.mtu < self.local_mtu && !self.reported
+    }
+
+    /// The summary for a result line, e.g. `path MTU 1400 of 1500 locally,
+    /// larger packets silently dropped`.
+    pub fn describe(&self) -> String {
+        if self.mtu >= self.local_mtu {
+            return format!("path MTU {}, as locally", self.mtu);
+        }
+        let narrowing = if self.reported {
+            "reported by a router"
+        } else {
+            "larger packets silently dropped"
+        };
+        format!(
+            "path MTU {} of {} locally, {}",
+            self.mtu, self.local_mtu, narrowing
+        )
+    }
+}
+
+/// What sending an echo request of some size came to.
+enum Probe {
+    Replied,
+    /// The packet was refused for its size, with the MTU a router gave
+    TooBig(Option<u16>),
+    Silent,
+}
+
+/// The MTU of the link packets to `ip` leave through, or failing that the
+/// MTU the kernel would route them with, which can already be narrowed by
+/// what it learned about the path.
+fn local_mtu(ip: IpAddr) -> io::Result<u16> {
+    let local = if ip.is_ipv4() {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    } else {
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    };
+    usage::socket_opened();
+    let socket = UdpSocket::bind(SocketAddr::new(local, 0))?;
+    socket.connect(SocketAddr::new(ip, 9))?;
+    let (level, name) = if ip.is_ipv4() {
+        (libc::IPPROTO_IP, libc::IP_MTU)
+    } else {
+        (libc::IPPROTO_IPV6, libc::IPV6_MTU)
+    };
+    let mut mtu: libc::c_int = 0;
+    let mut length = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &mut mtu as *mut libc::c_int as *mut libc::c_void,
+            &mut length,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let route_mtu = mtu.clamp(0, u16::MAX as libc::c_int) as u16;
+    Ok(interfaces::link_mtu(ip, socket.local_addr()?.ip()).unwrap_or(route_mtu))
+}
+
+/// Sends echo requests of `size` bytes that mustn't be fragmented and waits
+/// for a reply, or for a router to say the packet was too big.
+async fn probe_size(
+    socket: &IcmpSocket,
+    ip: IpAddr,
+    size: u16,
+    sequence: &mut u16,
+) -> io::Result<Probe> {
+    let overhead = if ip.is_ipv4() {
+        IPV4_OVERHEAD
+    } else {
+        IPV6_OVERHEAD
+    };
+    let payload = vec![0u8; size.saturating_sub(overhead) as usize];
+    let mut buffer = vec![0u8; size as usize + 64];
+    for _ in 0..ATTEMPTS {
+        *sequence = sequence.wrapping_add(1);
+        match socket.send(ip, *sequence, &payload).await {
+            Err(e) if e.raw_os_error() == Some(libc::EMSGSIZE) => return Ok(Probe::TooBig(None)),
+            result => result?,
+        }
+        usage::sent(size as usize);
+        let answer = timeout(REPLY_WAIT, async {
+            loop {
+                let mut guard = socket
+                    .fd()
+                    .ready(Interest::READABLE | Interest::ERROR)
+                    .await?;
+                let raw = socket.fd().as_raw_fd();
+                if let Some(error) = queued_error(raw) {
+                    let too_big = match (ip.is_ipv4(), error.kind) {
+                        (true, ICMP_DEST_UNREACH) => error.code == ICMP_FRAG_NEEDED,
+                        (false, ICMPV6_PACKET_TOO_BIG) => true,
+                        _ => false,
+                    };
+                    if error.is_icmp() && too_big {
+                        return Ok(Probe::TooBig(u16::try_from(error.info).ok()));
+                    }
+                    continue;
+                }
+                let mut address: libc::sockaddr_storage = unsafe { mem::zeroed() };
+                let mut length = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+                let read = unsafe {
+                    libc::recvfrom(
+                        raw,
+                        buffer.as_mut_ptr() as *mut libc::c_void,
+                        buffer.len(),
+                        0,
+                        &mut address as *mut libc::sockaddr_storage as *mut libc::sockaddr,
+                        &mut length,
+                    )
+                };
+                if read < 0 {
+                    let e = io::Error::last_os_error();
+                    match e.kind() {
+                        ErrorKind::WouldBlock => guard.clear_ready(),
+                        // The error already read from the queue, reported
+                        // a second time
+                        _ if e.raw_os_error() == Some(libc::EMSGSIZE) => {}
+                        _ => return Err(e),
+                    }
+                    continue;
+                }
+                usage::received(read as usize);
+                if from_socket_address(&address) == Some(ip)
+                    && socket.reply_sequence(&buffer[..read as usize]) == Some(*sequence)
+                {
+                    return Ok(Probe::Replied);
+                }
+            }
+        })
+        .await;
+        match answer {
+            Ok(Ok(Probe::Silent)) | Err(_) => {}
+            Ok(answer) => return answer,
+        }
+    }
+    Ok(Probe::Silent)
+}
+
+/// Finds the largest echo request that reaches `ip` whole, searching
+/// between the smallest MTU every path carries and the local route's.
+async fn measure(ip: IpAddr) -> io::Result<PathMtuSummary> {
+    let local_mtu = local_mtu(ip)?;
+    let socket = IcmpSocket::open(ip.is_ipv6()).ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::PermissionDenied,
+            "no ICMP socket could be opened, which needs root, CAP_NET_RAW or ping sockets",
+        )
+    })?;
+    // Set the don't-fragment bit on every packet, whatever the kernel
+    // already believes about the path
+    let raw = socket.fd().as_raw_fd();
+    if ip.is_ipv4() {
+        set_option(
+            raw,
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            libc::IP_PMTUDISC_PROBE,
+        )?;
+        set_option(raw, libc::IPPROTO_IP, libc::IP_RECVERR, 1)?;
+    } else {
+        set_option(
+            raw,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_PROBE,
+        )?;
+        set_option(raw, libc::IPPROTO_IPV6, libc::IPV6_RECVERR, 1)?;
+    }
+
+    let floor = if ip.is_ipv4() {
+        MIN_IPV4_MTU
+    } else {
+        MIN_IPV6_MTU
+    };
+    let mut sequence = 0;
+    let mut reported = false;
+    if !matches!(
+        probe_size(&socket, ip, floor, &mut sequence).await?,
+        Probe::Replied
+    ) {
+        return Err(io::Error::new(
+            ErrorKind::TimedOut,
+            "no reply to echo requests",
+        ));
+    }
+    // `fits` always got a reply and `too_big` never did
+    let mut fits = floor;
+    let mut too_big = local_mtu.max(floor) + 1;
+    let mut next = too_big - 1;
+    while too_big - fits > 1 {
+        match probe_size(&socket, ip, next, &mut sequence).await? {
+            Probe::Replied => fits = next,
+            Probe::TooBig(mtu) => {
+                too_big = next;
+                // Routers that say how much fits save the search, though
+                // their word is checked like any other size
+                if let Some(mtu) = mtu.filter(|mtu| *mtu > fits && *mtu < too_big) {
+                    reported = true;
+                    next = mtu;
+                    continue;
+                }
+            }
+            Probe::Silent => too_big = next,
+        }
+        next = fits + (too_big - fits) / 2;
+    }
+    Ok(PathMtuSummary {
+        mtu: fits,
+        local_mtu,
+        reported,
+    })
+}
+
+/// Measures the path MTU to every host in `results` with echo requests that
+/// mustn't be fragmented, searching for the largest that gets a reply, and
+/// warns about paths narrower than `min_mtu`: packets past a narrow hop
+/// that drops them without a word only ever time out. At most
+/// `max_enrichment_probes` hosts are measured at once.
+pub async fn probe_path_mtu(
+    settings: &Arc<ScanSettings>,
+    results: &mut [ScanResult],
+    min_mtu: u16,
+) {
+    let limits = settings.config.limits;
+    let slots = Arc::new(Semaphore::new(limits.max_enrichment_probes.max(1)));
+    let hosts: BTreeSet<IpAddr> = results
+        .iter()
+        .map(|scan_result| scan_result.ip.ip())
+        .collect();
+    let mut set: JoinSet<(IpAddr, Result<io::Result<PathMtuSummary>, String>)> = JoinSet::new();
+    for ip in hosts {
+        let slots = slots.clone();
+        set.spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            let summary = sandbox::run_isolated(limits.max_probe_time, measure(ip)).await;
+            (ip, summary)
+        });
+    }
+    if set.is_empty() {
+        return;
+    }
+    print_to_terminal(
+        String::from("Measuring the path MTU to each host"),
+        VerbosityLevel::INFO,
+    );
+
+    let mut summaries: HashMap<IpAddr, PathMtuSummary> = HashMap::new();
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok((ip, Ok(Ok(summary)))) => {
+                let level = if summary.mtu < min_mtu {
+                    VerbosityLevel::WARN
+                } else {
+                    VerbosityLevel::INFO
+                };
+                print_to_terminal(format!("{} - {}", ip, summary.describe()), level);
+                summaries.insert(ip, summary);
+            }
+            Ok((ip, Ok(Err(e)))) => print_to_terminal(
+                format!("{} - path MTU unknown: {}", ip, e),
+                VerbosityLevel::WARN,
+            ),
+            Ok((ip, Err(problem))) => {
+                print_to_terminal(format!("{} - {}", ip, problem), VerbosityLevel::WARN)
+            }
+            Err(e) => {
+                print_to_terminal(
+                    format!("An error has occured: {}", e),
+                    VerbosityLevel::ERROR,
+                );
+            }
+        }
+    }
+    for scan_result in results.iter_mut() {
+        if let Some(summary) = summaries.get(&scan_result.ip.ip()) {
+            scan_result.path_mtu = Some(summary.clone());
+        }
+    }
+}
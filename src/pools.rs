@@ -0,0 +1,102 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::time::{Duration, Instant, sleep_until};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+/// Limits for one pool, from the `[pools]` section of the config file.
+/// Leaving either out means no limit.
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolLimits {
+    /// Probes in flight at once
+    pub concurrency: Option<usize>,
+    /// New probes started per second
+    pub rate: Option<u32>,
+}
+
+/// Each address family and protocol gets its own limits, so that probes
+/// which are slow to finish (UDP waiting out retransmits, or a v6 route that
+/// drops everything) can't use up the budget the fast ones need.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolsConfig {
+    pub tcp_v4: PoolLimits,
+    pub tcp_v6: PoolLimits,
+    pub udp_v4: PoolLimits,
+    pub udp_v6: PoolLimits,
+}
+
+struct Pool {
+    slots: Option<Semaphore>,
+    /// The gap between probe starts, and the earliest the next one may start
+    pacing: Option<(Duration, Mutex<Instant>)>,
+}
+
+impl Pool {
+    fn new(limits: PoolLimits) -> Pool {
+        Pool {
+            slots: limits
+                .concurrency
+                .map(|concurrency| Semaphore::new(concurrency.max(1))),
+            pacing: limits.rate.map(|rate| {
+                (
+                    Duration::from_secs(1) / rate.max(1),
+                    Mutex::new(Instant::now()),
+                )
+            }),
+        }
+    }
+
+    async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+        let permit = match &self.slots {
+            Some(slots) => slots.acquire().await.ok(),
+            None => None,
+        };
+
+        if let Some((gap, next_start)) = &self.pacing {
+            let start_at = match next_start.lock() {
+                Ok(mut next_start) => {
+                    let start_at = (*next_start).max(Instant::now());
+                    *next_start = start_at + *gap;
+                    start_at
+                }
+                Err(_) => Instant::now(),
+            };
+            sleep_until(start_at).await;
+        }
+        permit
+    }
+}
+
+#[derive(Default)]
+pub struct ProbePools {
+    pools: HashMap<(Protocol, bool), Pool>,
+}
+
+impl ProbePools {
+    pub fn new(config: &PoolsConfig) -> ProbePools {
+        let mut pools: HashMap<(Protocol, bool), Pool> = HashMap::new();
+        pools.insert((Protocol::Tcp, false), Pool::new(config.tcp_v4));
+        pools.insert((Protocol::Tcp, true), Pool::new(config.tcp_v6));
+        pools.insert((Protocol::Udp, false), Pool::new(config.udp_v4));
+        pools.insert((Protocol::Udp, true), Pool::new(config.udp_v6));
+        ProbePools { pools }
+    }
+
+    /// Waits until the pool for `protocol` and `ip`'s family has room for
+    /// another probe. The probe should hold on to the permit until it's done.
+    pub async fn acquire(&self, protocol: Protocol, ip: IpAddr) -> Option<SemaphorePermit<'_>> {
+        match self.pools.get(&(protocol, ip.is_ipv6())) {
+            Some(pool) => pool.acquire().await,
+            None => None,
+        }
+    }
+}
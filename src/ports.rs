@@ -0,0 +1,253 @@
+use crate::cli::ScanArgs;
+use crate::hints::{PortHints, ServiceProtocol};
+use crate::{ErrorCodes, error_handler};
+use std::collections::{HashMap, HashSet};
+
+/// Ports that can be given by the name of what usually runs on them, with
+/// the protocol probes should speak where they care.
+const SERVICES: &[(&str, u16, Option<ServiceProtocol>)] = &[
+    ("ftp", 21, None),
+    ("ssh", 22, Some(ServiceProtocol::Ssh)),
+    ("telnet", 23, None),
+    ("smtp", 25, Some(ServiceProtocol::Smtp)),
+    ("dns", 53, Some(ServiceProtocol::Dns)),
+    ("http", 80, Some(ServiceProtocol::Http)),
+    ("pop3", 110, Some(ServiceProtocol::Pop3)),
+    ("ntp", 123, Some(ServiceProtocol::Ntp)),
+    ("snmp", 161, Some(ServiceProtocol::Snmp)),
+    ("imap", 143, Some(ServiceProtocol::Imap)),
+    ("ldap", 389, None),
+    ("https", 443, Some(ServiceProtocol::Tls)),
+    ("smb", 445, None),
+    ("smtps", 465, Some(ServiceProtocol::Tls)),
+    ("submission", 587, Some(ServiceProtocol::Smtp)),
+    ("ldaps", 636, Some(ServiceProtocol::Tls)),
+    ("imaps", 993, Some(ServiceProtocol::Tls)),
+    ("pop3s", 995, Some(ServiceProtocol::Tls)),
+    ("mssql", 1433, None),
+    ("oracle", 1521, None),
+    ("mysql", 3306, None),
+    ("rdp", 3389, None),
+    ("postgres", 5432, None),
+    ("vnc", 5900, None),
+    ("redis", 6379, None),
+    ("http-alt", 8080, Some(ServiceProtocol::Http)),
+    ("https-alt", 8443, Some(ServiceProtocol::Tls)),
+    ("memcached", 11211, None),
+    ("mongodb", 27017, None),
+    ("grpc", 50051, Some(ServiceProtocol::Grpc)),
+];
+
+/// Named groups of ports, which can be used anywhere a port can.
+const GROUPS: &[(&str, &str)] = &[
+    (
+        "web",
+        "80=http,443=tls,8000=http,8008=http,8080=http,8443=tls,8888=http,9443=tls",
+    ),
+    ("mail", "smtp,pop3,imap,smtps,submission,imaps,pop3s,2525"),
+    (
+        "database",
+        "mssql,oracle,mysql,postgres,redis,memcached,mongodb",
+    ),
+    ("remote", "ssh,telnet,rdp,vnc"),
+    ("printer", "80=http,443=tls,515,631,9100"),
+];
+
+/// Every port a scan covers, in the order they were given and each once,
+/// with the protocols given for them. Ranges are inclusive.
+#[derive(Default)]
+pub struct PortSet {
+    ports: Vec<u16>,
+    seen: HashSet<u16>,
+    hints: HashMap<u16, ServiceProtocol>,
+}
+
+impl PortSet {
+    /// Resolves `--ports` and `--exclude-ports`. Both take a comma separated
+    /// list of ports, ranges like `8000-8100`, service names like `ssh` and
+    /// groups like `web`, each optionally followed by the protocol it speaks,
+    /// e.g. `8443=tls`. Returns the part that isn't any of those on failure.
+    pub fn parse(ports: &str, exclude: Option<&str>) -> Result<PortSet, String> {
+        let mut set = PortSet::default();
+        set.add(ports)?;
+        if let Some(exclude) = exclude {
+            let mut excluded = PortSet::default();
+            excluded.add(exclude)?;
+            set.ports.retain(|port| !excluded.seen.contains(port));
+            set.hints.retain(|port, _| !excluded.seen.contains(port));
+            set.seen.retain(|port| !excluded.seen.contains(port));
+        }
+        Ok(set)
+    }
+
+    /// The ports of a scan, which `validate_scan_args` has already checked,
+    /// or none when it has no `--ports`, as with a single ENDPOINT.
+    pub fn from_args(scan: &ScanArgs) -> PortSet {
+        let Some(ports) = scan.ports.as_deref() else {
+            return PortSet::default();
+        };
+        match PortSet::parse(ports, scan.exclude_ports.as_deref()) {
+            Ok(set) => set,
+            Err(part) => error_handler(ErrorCodes::INVALID_PORT_SPEC, line!(), Some(&part)),
+        }
+    }
+
+    fn add(&mut self, list: &str) -> Result<(), String> {
+        for part in list.split(',').map(str::trim) {
+            let (spec, protocol) = match part.split_once('=') {
+                Some((spec, name)) => match ServiceProtocol::parse(name.trim()) {
+                    Some(protocol) => (spec.trim(), Some(protocol)),
+                    None => return Err(part.to_string()),
+                },
+                None => (part, None),
+            };
+            if let Some((_, group)) = GROUPS.iter().find(|(name, _)| *name == spec) {
+                let mut members = PortSet::default();
+                members.add(group)?;
+                for port in members.ports {
+                    self.insert(port, protocol.or_else(|| members.hints.get(&port).copied()));
+                }
+            } else if let Some((_, port, usual)) = SERVICES.iter().find(|(name, ..)| *name == spec)
+            {
+                self.insert(*port, protocol.or(*usual));
+            } else if let Some((start, end)) = spec.split_once('-') {
+                let (Ok(start), Ok(end)) = (start.trim().parse::<u16>(), end.trim().parse::<u16>())
+                else {
+                    return Err(part.to_string());
+                };
+                if start == 0 || start > end {
+                    return Err(part.to_string());
+                }
+                for port in start..=end {
+                    self.insert(port, protocol);
+                }
+            } else {
+                match spec.parse::<u16>() {
+                    Ok(port) if port != 0 => self.insert(port, protocol),
+                    _ => return Err(part.to_string()),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, port: u16, protocol: Option<ServiceProtocol>) {
+        if self.seen.insert(port) {
+            self.ports.push(port);
+        }
+        if let Some(protocol) = protocol {
+            self.hints.insert(port, protocol);
+        }
+    }
+
+    pub fn ports(&self) -> &[u16] {
+        &self.ports
+    }
+
+    pub fn hints(&self) -> PortHints {
+        PortHints::new(self.hints.clone())
+    }
+
+    /// Prints the ports for `--print-ports`, one line per port or run of
+    /// consecutive ports, with their protocols.
+    pub fn print(&self) {
+        let mut index = 0;
+        while index < self.ports.len() {
+            let start = self.ports[index];
+            if let Some(protocol) = self.hints.get(&start) {
+                println!("{} ({})", start, protocol.name());
+                index += 1;
+                continue;
+            }
+            let mut end = start;
+            while let Some(next) = self.ports.get(index + 1)
+                && end.checked_add(1) == Some(*next)
+                && !self.hints.contains_key(next)
+            {
+                end = *next;
+                index += 1;
+            }
+            if start == end {
+                println!("{}", start);
+            } else {
+                println!("{}-{}", start, end);
+            }
+            index += 1;
+        }
+        println!("Total: {} ports", self.ports.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ports(list: &str) -> Vec<u16> {
+        PortSet::parse(list, None).unwrap().ports
+    }
+
+    #[test]
+    fn ranges_are_inclusive() {
+        assert_eq!(ports("20-23"), vec![20, 21, 22, 23]);
+        assert_eq!(ports("65535-65535"), vec![65535]);
+    }
+
+    #[test]
+    fn reversed_ranges_are_rejected() {
+        assert_eq!(
+            PortSet::parse("65535-1", None).err().as_deref(),
+            Some("65535-1")
+        );
+    }
+
+    #[test]
+    fn service_names_and_groups_resolve() {
+        let set = PortSet::parse("ssh,mail", None).unwrap();
+        assert_eq!(set.ports, vec![22, 25, 110, 143, 465, 587, 993, 995, 2525]);
+        assert_eq!(set.hints.get(&22), Some(&ServiceProtocol::Ssh));
+        assert_eq!(set.hints.get(&993), Some(&ServiceProtocol::Tls));
+        assert_eq!(set.hints.get(&2525), None);
+    }
+
+    #[test]
+    fn protocol_hints_apply_to_every_port_of_a_part() {
+        let set = PortSet::parse("8443=tls,9000-9001=http,ssh=tls", None).unwrap();
+        assert_eq!(set.hints.get(&8443), Some(&ServiceProtocol::Tls));
+        assert_eq!(set.hints.get(&9000), Some(&ServiceProtocol::Http));
+        assert_eq!(set.hints.get(&9001), Some(&ServiceProtocol::Http));
+        assert_eq!(set.hints.get(&22), Some(&ServiceProtocol::Tls));
+    }
+
+    #[test]
+    fn exclusions_remove_ports_and_their_hints() {
+        let set = PortSet::parse("web", Some("8000-8100,https")).unwrap();
+        assert_eq!(set.ports, vec![80, 8443, 8888, 9443]);
+        assert!(!set.hints.contains_key(&443));
+        assert!(!set.hints.contains_key(&8080));
+        assert_eq!(set.hints.get(&80), Some(&ServiceProtocol::Http));
+    }
+
+    #[test]
+    fn ports_are_deduplicated_in_the_order_given() {
+        assert_eq!(ports("443,80-82,http,22,443"), vec![443, 80, 81, 82, 22]);
+    }
+
+    #[test]
+    fn invalid_parts_are_returned() {
+        for part in ["0", "", "80=bogus", "70000", "1-0", "nope"] {
+            assert_eq!(
+                PortSet::parse(&format!("22,{}", part), None)
+                    .err()
+                    .as_deref(),
+                Some(part)
+            );
+        }
+    }
+
+    #[test]
+    fn no_ports_argument_gives_an_empty_set() {
+        let set = PortSet::from_args(&ScanArgs::default());
+        assert!(set.ports().is_empty());
+        assert!(set.hints.is_empty());
+    }
+}
@@ -0,0 +1,103 @@
+use crate::cli::ScanArgs;
+use crate::{ErrorCodes, VerbosityLevel, error_handler, print_to_terminal};
+use toml::Table;
+
+/// A scan shipped with the binary, written like a saved profile.
+struct Preset {
+    name: &'static str,
+    description: &'static str,
+    contents: &'static str,
+}
+
+const PRESETS: &[Preset] = &[
+    Preset {
+        name: "web-audit",
+        description: "Web servers and admin panels, with the HTTP and TLS probes, grouped by service",
+        contents: r#"
+ports = "web"
+http_probe = true
+detect_tls = true
+group_by = "service"
+sorted = true
+"#,
+    },
+    Preset {
+        name: "lan-inventory",
+        description: "Common services on the local network, finding hosts with ARP first, one line per host",
+        contents: r#"
+ports = "21,22=ssh,23,53,80=http,135,139,443=tls,445,3389,5900,8080=http"
+arp = true
+by_host = true
+open = true
+"#,
+    },
+    Preset {
+        name: "mail-exposure",
+        description: "SMTP, submission, POP3 and IMAP, with TLS checked on every open port and STARTTLS tried on the plaintext ones",
+        contents: r#"
+ports = "mail"
+detect_tls = true
+starttls_probe = true
+group_by = "service"
+sorted = true
+"#,
+    },
+    Preset {
+        name: "printer-sweep",
+        description: "LPD, IPP, raw printing and printer web interfaces on hosts that answer a ping",
+        contents: r#"
+ports = "printer"
+ping = true
+by_host = true
+open = true
+"#,
+    },
+];
+
+/// Fills in a scan from its `--preset`. Anything given on the command line
+/// wins over the preset, but flags only count as given when they differ
+/// from their defaults, so a preset's switches can't be turned back off.
+pub fn apply_preset(scan: ScanArgs) -> ScanArgs {
+    let Some(name) = scan.preset.clone() else {
+        return scan;
+    };
+    let preset = match PRESETS.iter().find(|preset| preset.name == name) {
+        Some(preset) => preset,
+        None => error_handler(ErrorCodes::PRESET_NOT_FOUND, line!(), Some(&name)),
+    };
+
+    let mut merged: Table = match toml::from_str(preset.contents) {
+        Ok(table) => table,
+        Err(_) => error_handler(ErrorCodes::PRESET_NOT_FOUND, line!(), Some(&name)),
+    };
+    let given = match Table::try_from(&scan) {
+        Ok(table) => table,
+        Err(_) => error_handler(ErrorCodes::PRESET_NOT_FOUND, line!(), Some(&name)),
+    };
+    let defaults = Table::try_from(ScanArgs::default()).unwrap_or_default();
+    for (key, value) in given {
+        if defaults.get(&key) != Some(&value) {
+            merged.insert(key, value);
+        }
+    }
+    merged.remove("preset");
+
+    match merged.try_into() {
+        Ok(applied) => {
+            print_to_terminal(format!("Using preset: {}", name), VerbosityLevel::DEBUG);
+            applied
+        }
+        Err(_) => error_handler(ErrorCodes::PRESET_NOT_FOUND, line!(), Some(&name)),
+    }
+}
+
+pub fn list_presets() {
+    let width = PRESETS
+        .iter()
+        .map(|preset| preset.name.len())
+        .max()
+        .unwrap_or_default();
+    for preset in PRESETS {
+        println!("{:width$}  {}", preset.name, preset.description);
+    }
+}
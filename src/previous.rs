@@ -0,0 +1,118 @@
+use crate::baseline;
+use crate::cli::ScanArgs;
+use crate::output::{self, OutputFormat, SavedReport, ScanMetadata};
+use crate::{ConnectionStatus, ScanResult, VerbosityLevel, print_to_terminal};
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// The results of the last run over the same targets and ports, so the next
+/// run can point out what drifted since.
+#[derive(Default)]
+pub struct PreviousScan {
+    /// Where this scan's results get saved for the next run. Unset when
+    /// comparing is turned off.
+    path: Option<PathBuf>,
+    statuses: BTreeMap<SocketAddr, String>,
+    finished_at: Option<String>,
+}
+
+/// Names the state file after the scan's targets, ports and protocol, so
+/// scans of different networks don't compare against each other. FNV-1a keeps the
+/// name stable across builds, unlike the standard library's hasher.
+fn state_file_name(scan: &ScanArgs) -> String {
+    let mut description = format!(
+        "{}\0{}",
+        scan.targets.join("\n"),
+        scan.ports.as_deref().unwrap_or_default()
+    );
+    // TCP scans keep the names they had before UDP scans existed
+    if scan.udp {
+        description.push_str("\0udp");
+    }
+    let hash = description
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+    format!("{:016x}.json", hash)
+}
+
+impl PreviousScan {
+    /// Reads the last results for the scan's targets and ports. Having none
+    /// yet, or an unreadable file, just means nothing is compared this time.
+    pub fn load(scan: &ScanArgs) -> PreviousScan {
+        let path = match dirs::data_dir() {
+            Some(data_dir) if !scan.no_compare => data_dir
+                .join("connection-tester")
+                .join("previous")
+                .join(state_file_name(scan)),
+            _ => return PreviousScan::default(),
+        };
+
+        match SavedReport::read(&path) {
+            Some(report) => PreviousScan {
+                statuses: baseline::statuses_by_endpoint(&report.results),
+                finished_at: Some(report.scan.finished_at),
+                path: Some(path),
+            },
+            None => PreviousScan {
+                path: Some(path),
+                ..PreviousScan::default()
+            },
+        }
+    }
+
+    /// How the endpoint's state differs from the last run, if it does.
+    /// Endpoints the last run didn't check have nothing to compare against.
+    pub fn change_for(&self, scan_result: &ScanResult) -> Option<String> {
+        let previous = self.statuses.get(&scan_result.ip)?;
+        let current = scan_result.status.to_string().to_lowercase();
+        if *previous == current {
+            return None;
+        }
+
+        let change = match scan_result.status {
+            ConnectionStatus::Open => "newly open",
+            ConnectionStatus::Timeout => "newly timing out",
+            _ if previous == "open" => "newly closed",
+            _ => "changed",
+        };
+        Some(format!("{}, was {}", change, previous))
+    }
+
+    /// Prints how many endpoints changed since the last run, then saves this
+    /// run's results for the next one.
+    pub fn finish(&self, metadata: &ScanMetadata, results: &[ScanResult]) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        if let Some(finished_at) = &self.finished_at {
+            let changed = results
+                .iter()
+                .filter(|scan_result| scan_result.change.is_some())
+                .count();
+            print_to_terminal(
+                format!(
+                    "{} endpoints changed since the previous scan at {}",
+                    changed, finished_at
+                ),
+                VerbosityLevel::INFO,
+            );
+        }
+
+        let rendered = output::render(OutputFormat::Json, metadata, results);
+        let saved = match path.parent() {
+            Some(parent) => fs::create_dir_all(parent).is_ok() && fs::write(path, rendered).is_ok(),
+            None => false,
+        };
+        if !saved {
+            print_to_terminal(
+                format!("Could not save this scan's results to {}", path.display()),
+                VerbosityLevel::WARN,
+            );
+        }
+    }
+}
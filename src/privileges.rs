@@ -0,0 +1,128 @@
+use crate::{ErrorCodes, VerbosityLevel, error_handler, print_to_terminal};
+use std::env;
+use std::ffi::{CStr, CString};
+
+/// Gives up root, or any capabilities the binary was granted, once
+/// everything that needs them has been set up and before any response from
+/// a scanned host is read. Whatever a hostile service sends is then parsed
+/// by an ordinary user.
+///
+/// Root switches to `run_as`, or to the user who ran `sudo`. Without either
+/// the scan carries on as root with a warning.
+pub fn drop_privileges(run_as: Option<&str>) {
+    // Kept for the rest of the process, including anything it runs
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0);
+    }
+
+    if unsafe { libc::geteuid() } != 0 {
+        if run_as.is_some() {
+            print_to_terminal(
+                String::from("Not running as root, so --run-as has nothing to do"),
+                VerbosityLevel::DEBUG,
+            );
+        }
+        clear_capabilities();
+        return;
+    }
+
+    let entry = match run_as {
+        Some(user) => {
+            let Ok(name) = CString::new(user) else {
+                error_handler(ErrorCodes::INVALID_VARIABLE, line!(), Some("run-as user"));
+            };
+            unsafe { libc::getpwnam(name.as_ptr()) }
+        }
+        None => match sudo_id("SUDO_UID") {
+            Some(uid) => unsafe { libc::getpwuid(uid) },
+            None => {
+                print_to_terminal(
+                    String::from(
+                        "Running as root, so responses are parsed as root. Use --run-as to drop privileges",
+                    ),
+                    VerbosityLevel::WARN,
+                );
+                return;
+            }
+        },
+    };
+    if entry.is_null() {
+        error_handler(ErrorCodes::INVALID_VARIABLE, line!(), Some("run-as user"));
+    }
+    // The entry points into static storage, so it's read before anything
+    // else can look a user up
+    let (name, uid, gid) = unsafe {
+        (
+            CStr::from_ptr((*entry).pw_name).to_owned(),
+            (*entry).pw_uid,
+            (*entry).pw_gid,
+        )
+    };
+
+    // Groups first, while there's still permission to change them. The
+    // user's own supplementary groups are kept, so sources like `docker`
+    // still work.
+    let dropped = unsafe {
+        libc::initgroups(name.as_ptr(), gid) == 0
+            && libc::setgid(gid) == 0
+            && libc::setuid(uid) == 0
+    };
+    // Getting root back has to fail, or nothing was really dropped
+    if !dropped || unsafe { libc::setuid(0) } == 0 {
+        error_handler(ErrorCodes::PRIVILEGE_DROP_FAILED, line!(), run_as);
+    }
+    print_to_terminal(
+        format!("Dropped root privileges, now running as uid {}", uid),
+        VerbosityLevel::DEBUG,
+    );
+}
+
+fn sudo_id(variable: &str) -> Option<u32> {
+    env::var(variable).ok()?.parse().ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn clear_capabilities() {}
+
+/// Empties every capability set, for a binary given capabilities like
+/// `cap_net_raw` instead of being run as root.
+#[cfg(target_os = "linux")]
+fn clear_capabilities() {
+    #[repr(C)]
+    struct CapabilityHeader {
+        version: u32,
+        pid: i32,
+    }
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct CapabilityData {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+    // _LINUX_CAPABILITY_VERSION_3, which takes two data structs
+    let mut header = CapabilityHeader {
+        version: 0x2008_0522,
+        pid: 0,
+    };
+    let data = [CapabilityData {
+        effective: 0,
+        permitted: 0,
+        inheritable: 0,
+    }; 2];
+
+    let cleared = unsafe {
+        libc::syscall(
+            libc::SYS_capset,
+            &mut header as *mut CapabilityHeader,
+            data.as_ptr(),
+        )
+    } == 0;
+    if !cleared {
+        print_to_terminal(
+            String::from("Could not clear capabilities"),
+            VerbosityLevel::DEBUG,
+        );
+    }
+}
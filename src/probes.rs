@@ -0,0 +1,248 @@
+use crate::hints::ServiceProtocol;
+use crate::tls::ClientIdentity;
+use crate::{
+    ConnectionStatus, ScanResult, ScanSettings, VerbosityLevel, http, print_to_terminal, sandbox,
+    tls, usage,
+};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, timeout};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A chain's label alongside its passed-step notes or failure reason.
+type ChainOutcome = (String, Result<Vec<String>, String>);
+
+/// A sequence of probes run against a host once its `port` is found open.
+/// Each step only runs if the one before it passed.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ProbeChain {
+    pub name: Option<String>,
+    pub port: u16,
+    pub steps: Vec<ProbeStep>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "probe", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ProbeStep {
+    /// Completes a TLS handshake. Later HTTP steps in the chain then go over TLS.
+    /// With `client_cert` and `client_key` (PEM files), the certificate is
+    /// presented to servers that ask for one
+    Tls {
+        client_cert: Option<PathBuf>,
+        client_key: Option<PathBuf>,
+    },
+    /// Sends a GET, passing on any response unless `expect_status` is set
+    Http {
+        #[serde(default = "default_http_path")]
+        path: String,
+        expect_status: Option<u16>,
+    },
+}
+
+fn default_http_path() -> String {
+    String::from("/")
+}
+
+impl ProbeChain {
+    fn label(&self) -> String {
+        self.name.clone().unwrap_or(format!("port {}", self.port))
+    }
+}
+
+/// Runs each step in turn, returning a note per passed step, or the reason
+/// the chain failed.
+async fn run_chain(
+    ip: IpAddr,
+    hostname: Option<String>,
+    chain: &ProbeChain,
+    protocol: Option<ServiceProtocol>,
+    max_bytes: usize,
+) -> Result<Vec<String>, String> {
+    let target = SocketAddr::new(ip, chain.port);
+    // A host given by name is asked for by name, in SNI and the Host header
+    let server_name = hostname.as_deref();
+    let host = match (&hostname, ip) {
+        (Some(hostname), _) => hostname.clone(),
+        (None, IpAddr::V4(_)) => ip.to_string(),
+        (None, IpAddr::V6(_)) => format!("[{}]", ip),
+    };
+    let mut notes: Vec<String> = Vec::new();
+    // A port known to speak TLS gets it without asking for a `tls` step
+    let mut use_tls = protocol == Some(ServiceProtocol::Tls);
+    let mut identity: Option<ClientIdentity> = None;
+
+    for step in &chain.steps {
+        match step {
+            ProbeStep::Tls {
+                client_cert,
+                client_key,
+            } => {
+                identity = match (client_cert, client_key) {
+                    (Some(client_cert), Some(client_key)) => {
+                        match ClientIdentity::load(client_cert, client_key) {
+                            Ok(identity_result) => Some(identity_result),
+                            Err(e) => {
+                                return Err(format!("client certificate unreadable: {}", e));
+                            }
+                        }
+                    }
+                    _ => None,
+                };
+                let handshake = timeout(PROBE_TIMEOUT, async {
+                    let mut stream = tls::handshake(target, server_name, identity.as_ref()).await?;
+                    tls::confirm_client_accepted(&mut stream).await
+                })
+                .await;
+
+                match handshake {
+                    Ok(Ok(())) => {
+                        use_tls = true;
+                        if identity.is_some() {
+                            notes.push(String::from("tls ok, client certificate accepted"));
+                        } else {
+                            notes.push(String::from("tls ok"));
+                        }
+                    }
+                    Ok(Err(e)) if tls::is_client_certificate_rejection(&e) => {
+                        return Err(match identity {
+                            Some(_) => format!("tls rejected the client certificate: {}", e),
+                            None => format!("tls requires a client certificate: {}", e),
+                        });
+                    }
+                    Ok(Err(e)) => return Err(format!("tls failed: {}", e)),
+                    Err(_) => return Err(String::from("tls timed out")),
+                }
+            }
+            ProbeStep::Http {
+                path,
+                expect_status,
+            } => {
+                let response = timeout(PROBE_TIMEOUT, async {
+                    if use_tls {
+                        http::get_limited(
+                            tls::handshake(target, server_name, identity.as_ref()).await?,
+                            &host,
+                            path,
+                            max_bytes,
+                        )
+                        .await
+                    } else {
+                        usage::socket_opened();
+                        http::get_limited(TcpStream::connect(target).await?, &host, path, max_bytes)
+                            .await
+                    }
+                })
+                .await
+                .unwrap_or_else(|_| Err(io::Error::from(io::ErrorKind::TimedOut)));
+
+                match response {
+                    Ok(response) => match expect_status {
+                        Some(expected) if *expected != response.status => {
+                            return Err(format!(
+                                "http {} returned {}, expected {}",
+                                path, response.status, expected
+                            ));
+                        }
+                        _ => notes.push(format!("http {} {}", path, response.status)),
+                    },
+                    Err(e) => return Err(format!("http {} failed: {}", path, e)),
+                }
+            }
+        }
+    }
+    Ok(notes)
+}
+
+/// Runs every chain whose port came back open and prints one verdict per
+/// host covering all of its chains. At most `max_enrichment_probes` chains
+/// run at once.
+pub async fn run_chains(settings: &Arc<ScanSettings>, results: &[ScanResult]) {
+    let chains = &settings.config.chains;
+    let limits = settings.config.limits;
+    if chains.is_empty() {
+        return;
+    }
+
+    let open: HashSet<SocketAddr> = results
+        .iter()
+        .filter(|scan_result| scan_result.status == ConnectionStatus::Open)
+        .map(|scan_result| scan_result.ip)
+        .collect();
+    let hosts: HashMap<IpAddr, Option<String>> = results
+        .iter()
+        .map(|scan_result| (scan_result.ip.ip(), scan_result.hostname.clone()))
+        .collect();
+
+    let slots = Arc::new(Semaphore::new(limits.max_enrichment_probes.max(1)));
+    let mut set: JoinSet<(IpAddr, String, Result<Vec<String>, String>)> = JoinSet::new();
+    for (ip, hostname) in hosts {
+        for chain in chains {
+            if !open.contains(&SocketAddr::new(ip, chain.port)) {
+                continue;
+            }
+            let chain = chain.clone();
+            let hostname = hostname.clone();
+            let protocol = settings.hints.protocol_for(chain.port);
+            let slots = slots.clone();
+            set.spawn(async move {
+                let label = chain.label();
+                // Waiting for a slot doesn't count against the time limit
+                let _slot = slots.acquire_owned().await;
+                let outcome = sandbox::run_isolated(limits.max_probe_time, async move {
+                    run_chain(ip, hostname, &chain, protocol, limits.max_probe_bytes).await
+                })
+                .await
+                .and_then(|outcome| outcome);
+                (ip, label, outcome)
+            });
+        }
+    }
+
+    print_to_terminal(String::from("Running probe chains"), VerbosityLevel::INFO);
+
+    let mut verdicts: BTreeMap<IpAddr, Vec<ChainOutcome>> = BTreeMap::new();
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok((ip, label, outcome)) => verdicts.entry(ip).or_default().push((label, outcome)),
+            Err(e) => {
+                print_to_terminal(
+                    format!("An error has occured: {}", e),
+                    VerbosityLevel::ERROR,
+                );
+            }
+        }
+    }
+
+    for (ip, mut outcomes) in verdicts {
+        outcomes.sort_by(|a, b| a.0.cmp(&b.0));
+        let healthy = outcomes.iter().all(|(_, outcome)| outcome.is_ok());
+        let details: Vec<String> = outcomes
+            .iter()
+            .map(|(label, outcome)| match outcome {
+                Ok(notes) => format!("{}: {}", label, notes.join(", ")),
+                Err(reason) => format!("{}: {}", label, reason),
+            })
+            .collect();
+
+        if healthy {
+            print_to_terminal(
+                format!("{} - Healthy ({})", ip, details.join("; ")),
+                VerbosityLevel::INFO,
+            );
+        } else {
+            print_to_terminal(
+                format!("{} - Unhealthy ({})", ip, details.join("; ")),
+                VerbosityLevel::ERROR,
+            );
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use crate::cli::ScanArgs;
+use crate::{ErrorCodes, VerbosityLevel, error_handler, print_to_terminal};
+use regex::Regex;
+use std::fs;
+use std::path::PathBuf;
+
+fn profile_dir() -> PathBuf {
+    match dirs::config_dir() {
+        Some(config_dir) => config_dir.join("connection-tester").join("profiles"),
+        None => error_handler(ErrorCodes::CONFIG_DIRECTORY_UNAVAILABLE, line!(), None),
+    }
+}
+
+fn profile_path(name: &str) -> PathBuf {
+    let profile_name_valid_pattern: Regex = Regex::new(r"^[A-Za-z0-9_-]+$").unwrap();
+    if !profile_name_valid_pattern.is_match(name) {
+        error_handler(ErrorCodes::INVALID_VARIABLE, line!(), Some("profile name"));
+    }
+    profile_dir().join(format!("{}.toml", name))
+}
+
+pub fn save_profile(name: &str, scan: &ScanArgs) {
+    let path = profile_path(name);
+    let contents = match toml::to_string_pretty(scan) {
+        Ok(contents_result) => contents_result,
+        Err(_) => error_handler(ErrorCodes::PROFILE_WRITE_FAILURE, line!(), Some(name)),
+    };
+
+    if fs::create_dir_all(profile_dir()).is_err() || fs::write(&path, contents).is_err() {
+        error_handler(ErrorCodes::PROFILE_WRITE_FAILURE, line!(), Some(name));
+    }
+
+    print_to_terminal(
+        format!("Saved profile {} to {}", name, path.display()),
+        VerbosityLevel::INFO,
+    );
+}
+
+pub fn load_profile(name: &str) -> ScanArgs {
+    let contents = match fs::read_to_string(profile_path(name)) {
+        Ok(contents_result) => contents_result,
+        Err(_) => error_handler(ErrorCodes::PROFILE_NOT_FOUND, line!(), Some(name)),
+    };
+
+    match toml::from_str(&contents) {
+        Ok(scan_result) => {
+            print_to_terminal(format!("Loaded profile: {}", name), VerbosityLevel::DEBUG);
+            scan_result
+        }
+        Err(_) => error_handler(ErrorCodes::PROFILE_INVALID, line!(), Some(name)),
+    }
+}
+
+pub fn list_profiles() {
+    let mut names: Vec<String> = match fs::read_dir(profile_dir()) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "toml")
+            })
+            .filter_map(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    if names.is_empty() {
+        print_to_terminal(String::from("No saved profiles"), VerbosityLevel::INFO);
+        return;
+    }
+
+    names.sort();
+    for name in names {
+        println!("{}", name);
+    }
+}
@@ -0,0 +1,49 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use std::sync::Mutex;
+
+/// The bar for the scan in progress, if `--progress` was given. Messages are
+/// printed through `suspend` so they land above the bar instead of through it.
+static PROGRESS: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Draws a bar on stderr counting finished endpoints out of `total`.
+pub fn start(total: usize) {
+    let bar = ProgressBar::new(total as u64);
+    let style = ProgressStyle::with_template(
+        "[{elapsed_precise}] {wide_bar} {pos}/{len} endpoints ({per_sec}, ETA {eta})",
+    )
+    .expect("the progress template is valid");
+    bar.set_style(style);
+
+    if let Ok(mut progress) = PROGRESS.lock() {
+        *progress = Some(bar);
+    }
+}
+
+pub fn advance() {
+    if let Ok(progress) = PROGRESS.lock()
+        && let Some(bar) = progress.as_ref()
+    {
+        bar.inc(1);
+    }
+}
+
+/// Clears the bar once the scan is done, so the summary lines follow
+/// straight on from the results.
+pub fn finish() {
+    if let Ok(mut progress) = PROGRESS.lock()
+        && let Some(bar) = progress.take()
+    {
+        bar.finish_and_clear();
+    }
+}
+
+/// Runs `print` with the bar hidden, redrawing it afterwards.
+pub fn suspend<F: FnOnce()>(print: F) {
+    match PROGRESS.lock() {
+        Ok(progress) => match progress.as_ref() {
+            Some(bar) => bar.suspend(print),
+            None => print(),
+        },
+        Err(_) => print(),
+    }
+}
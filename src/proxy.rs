@@ -0,0 +1,164 @@
+use crate::websocket::base64;
+use crate::{ErrorCodes, error_handler, usage};
+use serde::{Deserialize, Serialize};
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// The port proxies are reached on when the URL names none, Squid's.
+const DEFAULT_PORT: u16 = 3128;
+/// How much of the proxy's answer to CONNECT is read before giving up on
+/// it ever ending.
+const MAX_RESPONSE_HEAD: usize = 8192;
+
+/// An HTTP proxy that `--proxy` tunnels every connection through with
+/// CONNECT, for networks whose only way out is a proxy.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct HttpProxy {
+    /// The URL as given, credentials included
+    url: String,
+    /// `HOST:PORT`, resolved afresh on each connection
+    address: String,
+    /// `USER:PASSWORD`, sent with Basic authentication
+    credentials: Option<String>,
+}
+
+/// Decodes the `%XX` escapes URLs write reserved characters in credentials
+/// with, e.g. `%40` for `@`.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = (bytes[index] == b'%')
+            .then(|| value.get(index + 1..index + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+impl HttpProxy {
+    /// Parses `http://[USER:PASSWORD@]HOST[:PORT]`.
+    pub fn parse(value: &str) -> Result<HttpProxy, String> {
+        let expected = "expected http://[USER:PASSWORD@]HOST[:PORT]";
+        let rest = value
+            .strip_prefix("http://")
+            .ok_or_else(|| String::from(expected))?
+            .trim_end_matches('/');
+        let (credentials, host) = match rest.rsplit_once('@') {
+            Some((userinfo, host)) => {
+                let (user, password) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+                (
+                    Some(format!(
+                        "{}:{}",
+                        percent_decode(user),
+                        percent_decode(password)
+                    )),
+                    host,
+                )
+            }
+            None => (None, rest),
+        };
+        if host.is_empty() || host.contains('/') {
+            return Err(String::from(expected));
+        }
+        let has_port = match host.strip_prefix('[') {
+            Some(bracketed) => bracketed.contains("]:"),
+            None => host.contains(':'),
+        };
+        let address = if has_port {
+            host.to_string()
+        } else {
+            format!("{}:{}", host, DEFAULT_PORT)
+        };
+        Ok(HttpProxy {
+            url: value.to_string(),
+            address,
+            credentials,
+        })
+    }
+
+    /// Opens a tunnel to `target` through the proxy, failing the way a
+    /// direct connection would when the proxy can't make its own: refused
+    /// for a 502 or 503, timed out for a 504, and unreachable when policy
+    /// forbids it. A proxy that can't be reached, or turns the credentials
+    /// down, ends the scan, since no result through it would mean anything.
+    pub async fn connect(&self, target: SocketAddr) -> io::Result<TcpStream> {
+        let mut stream = match TcpStream::connect(&self.address).await {
+            Ok(stream) => stream,
+            Err(_) => error_handler(ErrorCodes::PROXY_UNREACHABLE, line!(), Some(&self.address)),
+        };
+        let mut request = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n", target, target);
+        if let Some(credentials) = &self.credentials {
+            request.push_str(&format!(
+                "Proxy-Authorization: Basic {}\r\n",
+                base64(credentials.as_bytes())
+            ));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+        usage::sent(request.len());
+
+        // A byte at a time, so nothing the target sends once the tunnel is
+        // up is read along with the proxy's answer
+        let mut head = Vec::new();
+        let mut byte = [0u8; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() >= MAX_RESPONSE_HEAD || stream.read(&mut byte).await? == 0 {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "the proxy's answer to CONNECT never ended",
+                ));
+            }
+            head.push(byte[0]);
+        }
+        usage::received(head.len());
+        let status = String::from_utf8_lossy(&head)
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok())
+            .unwrap_or_default();
+        match status {
+            200..=299 => Ok(stream),
+            407 => error_handler(
+                ErrorCodes::PROXY_AUTH_REJECTED,
+                line!(),
+                Some(&self.address),
+            ),
+            502 | 503 => Err(io::Error::from(ErrorKind::ConnectionRefused)),
+            504 => Err(io::Error::from(ErrorKind::TimedOut)),
+            403 => Err(io::Error::from(ErrorKind::HostUnreachable)),
+            status => Err(io::Error::other(format!(
+                "the proxy answered CONNECT with {}",
+                status
+            ))),
+        }
+    }
+}
+
+impl TryFrom<String> for HttpProxy {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<HttpProxy, String> {
+        HttpProxy::parse(&value)
+    }
+}
+
+impl From<HttpProxy> for String {
+    fn from(proxy: HttpProxy) -> String {
+        proxy.url
+    }
+}
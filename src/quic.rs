@@ -0,0 +1,745 @@
+use crate::encryption::Encryption;
+use crate::hints::ServiceProtocol;
+use crate::{
+    ConnectionStatus, ErrorCodes, ScanResult, ScanSettings, VerbosityLevel, error_handler,
+    print_to_terminal, sandbox, tls, usage,
+};
+use rustls::quic::{ClientConnection, DirectionalKeys, KeyChange, Keys, Version};
+use rustls::{AlertDescription, Side};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, Instant, timeout, timeout_at};
+
+/// How long the server gets to answer before the Initial is sent again,
+/// and how many times it's sent in all.
+const RESEND_AFTER: Duration = Duration::from_secs(1);
+const ATTEMPTS: u32 = 3;
+/// Clients must pad their first datagram this far, so servers can answer
+/// without amplifying spoofed traffic.
+const MIN_INITIAL_BYTES: usize = 1200;
+const MAX_DATAGRAM_BYTES: usize = 65535;
+const CONNECTION_ID_BYTES: usize = 8;
+const PACKET_NUMBER_BYTES: usize = 4;
+const QUIC_V1: u32 = 0x0000_0001;
+const INITIAL: u8 = 0;
+const HANDSHAKE: u8 = 2;
+const RETRY: u8 = 3;
+/// The application protocol HTTP/3 is negotiated as.
+const ALPN_H3: &[u8] = b"h3";
+
+/// What a QUIC handshake with the port found.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct QuicSummary {
+    /// The QUIC versions the server listed instead, when it doesn't speak
+    /// QUIC v1
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub versions: Vec<String>,
+    /// The application protocol the handshake agreed on, `h3` for HTTP/3
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alpn: Option<String>,
+    /// Why the server closed the handshake, e.g. because it doesn't serve h3
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub closed: Option<String>,
+}
+
+impl QuicSummary {
+    pub fn http3(&self) -> bool {
+        self.alpn.as_deref() == Some("h3")
+    }
+
+    /// The summary for a result line, e.g. `QUIC v1, HTTP/3` or `QUIC,
+    /// closed: TLS alert NoApplicationProtocol`.
+    pub fn describe(&self) -> String {
+        if self.http3() {
+            String::from("QUIC v1, HTTP/3")
+        } else if let Some(closed) = &self.closed {
+            format!("QUIC, closed: {}", closed)
+        } else if !self.versions.is_empty() {
+            format!("QUIC {}, not v1", self.versions.join(", "))
+        } else {
+            format!("QUIC v1, {}", self.alpn.as_deref().unwrap_or("no ALPN"))
+        }
+    }
+}
+
+fn random_bytes(count: usize) -> Vec<u8> {
+    let random = RandomState::new();
+    let mut bytes = Vec::with_capacity(count);
+    while bytes.len() < count {
+        let mut hasher = random.build_hasher();
+        hasher.write_usize(bytes.len());
+        bytes.extend_from_slice(&hasher.finish().to_be_bytes());
+    }
+    bytes.truncate(count);
+    bytes
+}
+
+fn push_varint(buffer: &mut Vec<u8>, value: u64) {
+    match value {
+        0..=0x3f => buffer.push(value as u8),
+        0x40..=0x3fff => buffer.extend_from_slice(&(0x4000 | value as u16).to_be_bytes()),
+        0x4000..=0x3fff_ffff => {
+            buffer.extend_from_slice(&(0x8000_0000 | value as u32).to_be_bytes())
+        }
+        _ => buffer.extend_from_slice(&(0xc000_0000_0000_0000 | value).to_be_bytes()),
+    }
+}
+
+fn read_varint(buffer: &[u8], offset: &mut usize) -> Option<u64> {
+    let first = *buffer.get(*offset)?;
+    let length = 1 << (first >> 6);
+    let bytes = buffer.get(*offset..*offset + length)?;
+    *offset += length;
+    Some(
+        bytes[1..]
+            .iter()
+            .fold((first & 0x3f) as u64, |value, byte| {
+                value << 8 | *byte as u64
+            }),
+    )
+}
+
+fn read_bytes<'a>(buffer: &'a [u8], offset: &mut usize, length: usize) -> Option<&'a [u8]> {
+    let bytes = buffer.get(*offset..*offset + length)?;
+    *offset += length;
+    Some(bytes)
+}
+
+/// The transport parameters a client has to send, and enough flow control
+/// credit for the server to finish its handshake.
+fn transport_parameters(source_id: &[u8]) -> Vec<u8> {
+    let mut parameters = Vec::new();
+    for (id, value) in [
+        // max_idle_timeout, initial_max_data, initial_max_stream_data_*
+        (0x01, 10_000),
+        (0x04, 1 << 20),
+        (0x05, 1 << 18),
+        (0x06, 1 << 18),
+        (0x07, 1 << 18),
+        // initial_max_streams_bidi, initial_max_streams_uni
+        (0x08, 16),
+        (0x09, 16),
+    ] {
+        let mut encoded = Vec::new();
+        push_varint(&mut encoded, value);
+        push_varint(&mut parameters, id);
+        push_varint(&mut parameters, encoded.len() as u64);
+        parameters.extend_from_slice(&encoded);
+    }
+    // initial_source_connection_id
+    push_varint(&mut parameters, 0x0f);
+    push_varint(&mut parameters, source_id.len() as u64);
+    parameters.extend_from_slice(source_id);
+    parameters
+}
+
+/// The connection IDs a packet is sent between.
+struct Path<'a> {
+    destination: &'a [u8],
+    source: &'a [u8],
+}
+
+/// Builds a long header packet of `kind` carrying `frames`, padded to at
+/// least `min_bytes`, and protects it with `keys`.
+fn seal(
+    kind: u8,
+    path: &Path,
+    token: &[u8],
+    packet_number: u32,
+    mut frames: Vec<u8>,
+    min_bytes: usize,
+    keys: &DirectionalKeys,
+) -> Option<Vec<u8>> {
+    let mut packet = vec![0xc0 | kind << 4 | (PACKET_NUMBER_BYTES as u8 - 1)];
+    packet.extend_from_slice(&QUIC_V1.to_be_bytes());
+    packet.push(path.destination.len() as u8);
+    packet.extend_from_slice(path.destination);
+    packet.push(path.source.len() as u8);
+    packet.extend_from_slice(path.source);
+    if kind == INITIAL {
+        push_varint(&mut packet, token.len() as u64);
+        packet.extend_from_slice(token);
+    }
+    let tag_length = keys.packet.tag_len();
+    // The length field takes two bytes
+    let unpadded = packet.len() + 2 + PACKET_NUMBER_BYTES + frames.len() + tag_length;
+    frames.resize(frames.len() + min_bytes.saturating_sub(unpadded), 0);
+    let length = PACKET_NUMBER_BYTES + frames.len() + tag_length;
+    packet.extend_from_slice(&(0x4000 | length as u16).to_be_bytes());
+    let number_offset = packet.len();
+    packet.extend_from_slice(&packet_number.to_be_bytes());
+
+    let tag = keys
+        .packet
+        .encrypt_in_place(packet_number as u64, &packet, &mut frames)
+        .ok()?;
+    packet.extend_from_slice(&frames);
+    packet.extend_from_slice(tag.as_ref());
+    let sample_start = number_offset + PACKET_NUMBER_BYTES;
+    let sample = packet
+        .get(sample_start..sample_start + keys.header.sample_len())?
+        .to_vec();
+    let (first, rest) = packet.split_at_mut(1);
+    keys.header
+        .encrypt_in_place(
+            &sample,
+            &mut first[0],
+            &mut rest[number_offset - 1..sample_start - 1],
+        )
+        .ok()?;
+    Some(packet)
+}
+
+/// Removes a packet's protection with `keys`, returning its frames.
+/// `number_offset` is where its packet number starts.
+fn open(mut packet: Vec<u8>, number_offset: usize, keys: &DirectionalKeys) -> Option<Vec<u8>> {
+    let sample_start = number_offset + PACKET_NUMBER_BYTES;
+    let sample = packet
+        .get(sample_start..sample_start + keys.header.sample_len())?
+        .to_vec();
+    let (first, rest) = packet.split_at_mut(1);
+    keys.header
+        .decrypt_in_place(
+            &sample,
+            &mut first[0],
+            &mut rest[number_offset - 1..sample_start - 1],
+        )
+        .ok()?;
+    let number_length = (packet[0] & 0x03) as usize + 1;
+    let payload_start = number_offset + number_length;
+    let packet_number = packet[number_offset..payload_start]
+        .iter()
+        .fold(0u64, |number, byte| number << 8 | *byte as u64);
+    let (header, payload) = packet.split_at_mut(payload_start);
+    let frames = keys
+        .packet
+        .decrypt_in_place(packet_number, header, payload)
+        .ok()?;
+    Some(frames.to_vec())
+}
+
+/// The bytes of one side of a crypto stream, put back in order.
+#[derive(Default)]
+struct CryptoStream {
+    delivered: u64,
+    received: BTreeMap<u64, Vec<u8>>,
+}
+
+impl CryptoStream {
+    fn add(&mut self, offset: u64, data: &[u8]) {
+        if offset + data.len() as u64 > self.delivered {
+            self.received.insert(offset, data.to_vec());
+        }
+    }
+
+    /// Whatever follows on from what was already delivered.
+    fn ready(&mut self) -> Vec<u8> {
+        let mut ready = Vec::new();
+        while let Some(entry) = self.received.first_entry()
+            && *entry.key() <= self.delivered
+        {
+            let (offset, data) = entry.remove_entry();
+            let end = offset + data.len() as u64;
+            if end > self.delivered {
+                ready.extend_from_slice(&data[(self.delivered - offset) as usize..]);
+                self.delivered = end;
+            }
+        }
+        ready
+    }
+}
+
+/// Why a CONNECTION_CLOSE frame closed the connection.
+fn close_reason(code: u64, reason: &[u8]) -> String {
+    let mut description = match code {
+        0x00 => String::from("no error"),
+        0x02 => String::from("connection refused"),
+        0x0a => String::from("protocol violation"),
+        0x100..=0x1ff => format!("TLS alert {:?}", AlertDescription::from(code as u8)),
+        other => format!("error 0x{:x}", other),
+    };
+    if !reason.is_empty() {
+        description.push_str(&format!(" ({})", String::from_utf8_lossy(reason)));
+    }
+    description
+}
+
+/// Sorts a packet's frames into `crypto`, returning why the server closed
+/// the connection if it did.
+fn read_frames(frames: &[u8], crypto: &mut CryptoStream) -> Option<Option<String>> {
+    let mut offset = 0;
+    while offset < frames.len() {
+        match read_varint(frames, &mut offset)? {
+            // PADDING, PING
+            0x00 | 0x01 => {}
+            // ACK, with or without ECN counts
+            kind @ (0x02 | 0x03) => {
+                read_varint(frames, &mut offset)?;
+                read_varint(frames, &mut offset)?;
+                let ranges = read_varint(frames, &mut offset)?;
+                read_varint(frames, &mut offset)?;
+                for _ in 0..ranges * 2 {
+                    read_varint(frames, &mut offset)?;
+                }
+                if kind == 0x03 {
+                    for _ in 0..3 {
+                        read_varint(frames, &mut offset)?;
+                    }
+                }
+            }
+            // CRYPTO
+            0x06 => {
+                let start = read_varint(frames, &mut offset)?;
+                let length = read_varint(frames, &mut offset)? as usize;
+                crypto.add(start, read_bytes(frames, &mut offset, length)?);
+            }
+            // CONNECTION_CLOSE, from the transport or the application
+            kind @ (0x1c | 0x1d) => {
+                let code = read_varint(frames, &mut offset)?;
+                if kind == 0x1c {
+                    read_varint(frames, &mut offset)?;
+                }
+                let length = read_varint(frames, &mut offset)? as usize;
+                let reason = read_bytes(frames, &mut offset, length)?;
+                return Some(Some(close_reason(code, reason)));
+            }
+            // Nothing else belongs in Initial or Handshake packets
+            _ => return None,
+        }
+    }
+    Some(None)
+}
+
+/// A long header packet as received, before its protection is removed.
+struct Received {
+    kind: u8,
+    version: u32,
+    source: Vec<u8>,
+    /// The Retry token, for Retry packets, or the supported versions listed
+    /// by a Version Negotiation
+    rest: Vec<u8>,
+    packet: Vec<u8>,
+    number_offset: usize,
+}
+
+/// Splits a datagram into the long header packets coalesced in it.
+fn split_datagram(datagram: &[u8]) -> Vec<Received> {
+    let mut packets = Vec::new();
+    let mut start = 0;
+    while let Some(packet) = split_packet(datagram, start) {
+        start += packet.packet.len();
+        packets.push(packet);
+    }
+    packets
+}
+
+fn split_packet(datagram: &[u8], start: usize) -> Option<Received> {
+    let mut offset = start;
+    let first = *datagram.get(offset)?;
+    // Short header packets only come once the handshake is over
+    if first & 0x80 == 0 {
+        return None;
+    }
+    offset += 1;
+    let version = u32::from_be_bytes(read_bytes(datagram, &mut offset, 4)?.try_into().ok()?);
+    let length = *datagram.get(offset)? as usize;
+    offset += 1 + length;
+    let length = *datagram.get(offset)? as usize;
+    let source = read_bytes(datagram, &mut offset, length + 1)?[1..].to_vec();
+    let kind = (first >> 4) & 0x03;
+    if version == 0 || kind == RETRY {
+        return Some(Received {
+            kind,
+            version,
+            source,
+            rest: datagram[offset..].to_vec(),
+            packet: datagram[start..].to_vec(),
+            number_offset: 0,
+        });
+    }
+    if kind == INITIAL {
+        let token_length = read_varint(datagram, &mut offset)? as usize;
+        offset += token_length;
+    }
+    let length = read_varint(datagram, &mut offset)? as usize;
+    let end = offset + length;
+    Some(Received {
+        kind,
+        version,
+        source,
+        rest: Vec::new(),
+        packet: datagram.get(start..end)?.to_vec(),
+        number_offset: offset - start,
+    })
+}
+
+fn version_name(version: u32) -> String {
+    match version {
+        QUIC_V1 => String::from("v1"),
+        0x6b33_43cf => String::from("v2"),
+        0xff00_0000..=0xff00_00ff => format!("draft-{}", version & 0xff),
+        _ => {
+            let bytes = version.to_be_bytes();
+            // Google's own QUIC versions read as text, e.g. Q046
+            if bytes.iter().all(|byte| byte.is_ascii_alphanumeric()) {
+                String::from_utf8_lossy(&bytes).to_string()
+            } else {
+                format!("0x{:08x}", version)
+            }
+        }
+    }
+}
+
+fn tls_error(e: rustls::Error) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, e)
+}
+
+/// One client's side of a QUIC handshake, taken as far as the server's
+/// EncryptedExtensions, which is where the application protocol is agreed.
+struct Handshake {
+    connection: ClientConnection,
+    hello: Vec<u8>,
+    source: Vec<u8>,
+    destination: Vec<u8>,
+    token: Vec<u8>,
+    initial_keys: Keys,
+    handshake_keys: Option<Keys>,
+    initial_crypto: CryptoStream,
+    handshake_crypto: CryptoStream,
+    /// Handshake packets that came before the keys to open them
+    early: Vec<Received>,
+    next_packet_number: u32,
+    retried: bool,
+}
+
+impl Handshake {
+    fn new(target: SocketAddr, server_name: Option<&str>) -> io::Result<Handshake> {
+        let source = random_bytes(CONNECTION_ID_BYTES);
+        let destination = random_bytes(CONNECTION_ID_BYTES);
+        let name = tls::server_name_for(target, server_name)?;
+        let mut connection = ClientConnection::new(
+            tls::quic_config(ALPN_H3),
+            Version::V1,
+            name,
+            transport_parameters(&source),
+        )
+        .map_err(tls_error)?;
+        let mut hello = Vec::new();
+        connection.write_hs(&mut hello);
+        Ok(Handshake {
+            connection,
+            hello,
+            initial_keys: initial_keys(&destination),
+            source,
+            destination,
+            token: Vec::new(),
+            handshake_keys: None,
+            initial_crypto: CryptoStream::default(),
+            handshake_crypto: CryptoStream::default(),
+            early: Vec::new(),
+            next_packet_number: 0,
+            retried: false,
+        })
+    }
+
+    /// The Initial packet carrying the ClientHello.
+    fn initial(&mut self) -> io::Result<Vec<u8>> {
+        let mut frames = vec![0x06, 0x00];
+        push_varint(&mut frames, self.hello.len() as u64);
+        frames.extend_from_slice(&self.hello);
+        let path = Path {
+            destination: &self.destination,
+            source: &self.source,
+        };
+        let packet_number = self.next_packet_number;
+        self.next_packet_number += 1;
+        seal(
+            INITIAL,
+            &path,
+            &self.token,
+            packet_number,
+            frames,
+            MIN_INITIAL_BYTES,
+            &self.initial_keys.local,
+        )
+        .ok_or_else(|| io::Error::other("couldn't protect the Initial packet"))
+    }
+
+    /// A CONNECTION_CLOSE for the server once it's said what it needed to,
+    /// so it doesn't hold the connection open until it times out.
+    fn close(&self) -> Option<Vec<u8>> {
+        let keys = self.handshake_keys.as_ref()?;
+        let path = Path {
+            destination: &self.destination,
+            source: &self.source,
+        };
+        seal(
+            HANDSHAKE,
+            &path,
+            &[],
+            0,
+            vec![0x1c, 0x00, 0x00, 0x00],
+            0,
+            &keys.local,
+        )
+    }
+
+    /// Takes in one datagram from the server. Returns the summary once
+    /// there's enough to go on, or an Initial to send again after a Retry.
+    fn receive(&mut self, datagram: &[u8]) -> io::Result<Step> {
+        for packet in split_datagram(datagram) {
+            if packet.version == 0 {
+                let versions: Vec<u32> = packet
+                    .rest
+                    .chunks_exact(4)
+                    .map(|version| {
+                        u32::from_be_bytes([version[0], version[1], version[2], version[3]])
+                    })
+                    .collect();
+                // A list with v1 in it isn't an answer to a v1 Initial
+                if versions.contains(&QUIC_V1) {
+                    continue;
+                }
+                return Ok(Step::Done(QuicSummary {
+                    versions: versions.into_iter().map(version_name).collect(),
+                    alpn: None,
+                    closed: None,
+                }));
+            }
+            if packet.version != QUIC_V1 {
+                continue;
+            }
+            match packet.kind {
+                RETRY if !self.retried && packet.rest.len() > 16 => {
+                    // The token, then a 16 byte integrity tag
+                    self.token = packet.rest[..packet.rest.len() - 16].to_vec();
+                    self.destination = packet.source.clone();
+                    self.initial_keys = initial_keys(&self.destination);
+                    self.retried = true;
+                    return Ok(Step::Send(self.initial()?));
+                }
+                INITIAL | HANDSHAKE => {
+                    if let Some(closed) = self.take_packet(packet)? {
+                        return Ok(Step::Done(QuicSummary {
+                            versions: Vec::new(),
+                            alpn: None,
+                            closed: Some(closed),
+                        }));
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let Some(alpn) = self.connection.alpn_protocol() {
+            return Ok(Step::Done(QuicSummary {
+                versions: Vec::new(),
+                alpn: Some(String::from_utf8_lossy(alpn).to_string()),
+                closed: None,
+            }));
+        }
+        if !self.connection.is_handshaking() {
+            return Ok(Step::Done(QuicSummary {
+                versions: Vec::new(),
+                alpn: None,
+                closed: None,
+            }));
+        }
+        Ok(Step::Wait)
+    }
+
+    /// Opens an Initial or Handshake packet and hands its crypto frames to
+    /// TLS, returning why the server closed the connection if it did.
+    fn take_packet(&mut self, packet: Received) -> io::Result<Option<String>> {
+        if packet.kind == INITIAL {
+            // The server's first Initial names the connection ID to use
+            self.destination = packet.source.clone();
+        }
+        let keys = match packet.kind {
+            INITIAL => &self.initial_keys.remote,
+            _ => match &self.handshake_keys {
+                Some(keys) => &keys.remote,
+                None => {
+                    self.early.push(packet);
+                    return Ok(None);
+                }
+            },
+        };
+        // Packets that don't open are dropped, as any QUIC endpoint would
+        let Some(frames) = open(packet.packet.clone(), packet.number_offset, keys) else {
+            return Ok(None);
+        };
+        let crypto = if packet.kind == INITIAL {
+            &mut self.initial_crypto
+        } else {
+            &mut self.handshake_crypto
+        };
+        let Some(closed) = read_frames(&frames, crypto) else {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "malformed QUIC frames",
+            ));
+        };
+        if closed.is_some() {
+            return Ok(closed);
+        }
+        let ready = crypto.ready();
+        if !ready.is_empty() {
+            self.connection.read_hs(&ready).map_err(tls_error)?;
+        }
+        let mut unused = Vec::new();
+        if let Some(KeyChange::Handshake { keys }) = self.connection.write_hs(&mut unused)
+            && self.handshake_keys.is_none()
+        {
+            self.handshake_keys = Some(keys);
+            for early in std::mem::take(&mut self.early) {
+                if let Some(closed) = self.take_packet(early)? {
+                    return Ok(Some(closed));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// What to do after a datagram from the server.
+enum Step {
+    Wait,
+    Send(Vec<u8>),
+    Done(QuicSummary),
+}
+
+fn initial_keys(destination: &[u8]) -> Keys {
+    let suite = rustls::crypto::ring::cipher_suite::TLS13_AES_128_GCM_SHA256
+        .tls13()
+        .and_then(|suite| suite.quic_suite())
+        .unwrap_or_else(|| error_handler(ErrorCodes::TLS_UNAVAILABLE, line!(), None));
+    suite.keys(destination, Side::Client, Version::V1)
+}
+
+/// Sends a QUIC v1 Initial to `target` offering HTTP/3 and reads the
+/// server's answer as far as the negotiated application protocol.
+async fn probe(target: SocketAddr, server_name: Option<String>) -> io::Result<QuicSummary> {
+    let local = if target.is_ipv4() {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    } else {
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(local, 0)).await?;
+    socket.connect(target).await?;
+    let mut handshake = Handshake::new(target, server_name.as_deref())?;
+    let mut buffer = vec![0u8; MAX_DATAGRAM_BYTES];
+    for _ in 0..ATTEMPTS {
+        let initial = handshake.initial()?;
+        socket.send(&initial).await?;
+        usage::sent(initial.len());
+        let resend_at = Instant::now() + RESEND_AFTER;
+        while let Ok(read) = timeout_at(resend_at, socket.recv(&mut buffer)).await {
+            let read = read?;
+            usage::received(read);
+            match handshake.receive(&buffer[..read])? {
+                Step::Wait => {}
+                Step::Send(packet) => {
+                    socket.send(&packet).await?;
+                    usage::sent(packet.len());
+                }
+                Step::Done(summary) => {
+                    if let Some(close) = handshake.close() {
+                        // The server finds out by itself if this is lost
+                        let _ = socket.send(&close).await;
+                        usage::sent(close.len());
+                    }
+                    return Ok(summary);
+                }
+            }
+        }
+    }
+    Err(io::Error::from(ErrorKind::TimedOut))
+}
+
+/// Whether the port is worth a QUIC handshake: one that speaks or is
+/// expected to speak TLS, since HTTP/3 is served on the same port number
+/// over UDP as HTTPS is over TCP.
+fn quic_port(settings: &ScanSettings, scan_result: &ScanResult) -> bool {
+    matches!(
+        scan_result.status,
+        ConnectionStatus::Open | ConnectionStatus::OpenFiltered
+    ) && (scan_result.encryption == Some(Encryption::Tls)
+        || settings.hints.protocol_for(scan_result.ip.port()) == Some(ServiceProtocol::Tls))
+}
+
+/// Tries a QUIC handshake offering HTTP/3 over UDP against every TLS port
+/// in `results`, 443 and 8443 included, and records whether the server
+/// speaks QUIC and serves HTTP/3. At most `max_enrichment_probes` handshakes
+/// are attempted at once.
+pub async fn probe_quic(settings: &Arc<ScanSettings>, results: &mut [ScanResult]) {
+    let limits = settings.config.limits;
+    let slots = Arc::new(Semaphore::new(limits.max_enrichment_probes.max(1)));
+    let mut set: JoinSet<(SocketAddr, Result<io::Result<QuicSummary>, String>)> = JoinSet::new();
+    for scan_result in results
+        .iter()
+        .filter(|scan_result| quic_port(settings, scan_result))
+    {
+        let target = scan_result.ip;
+        let server_name = scan_result.hostname.clone();
+        let slots = slots.clone();
+        set.spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            let summary = sandbox::run_isolated(limits.max_probe_time, async move {
+                let wait = RESEND_AFTER * ATTEMPTS + RESEND_AFTER;
+                match timeout(wait, probe(target, server_name)).await {
+                    Ok(summary) => summary,
+                    Err(_) => Err(io::Error::from(ErrorKind::TimedOut)),
+                }
+            })
+            .await;
+            (target, summary)
+        });
+    }
+    if set.is_empty() {
+        return;
+    }
+    print_to_terminal(
+        String::from("Trying QUIC handshakes for HTTP/3"),
+        VerbosityLevel::INFO,
+    );
+
+    let mut summaries: HashMap<SocketAddr, QuicSummary> = HashMap::new();
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok((target, Ok(Ok(summary)))) => {
+                print_to_terminal(
+                    format!("{}/udp - {}", target, summary.describe()),
+                    VerbosityLevel::INFO,
+                );
+                summaries.insert(target, summary);
+            }
+            // Most TLS ports have nothing listening on UDP
+            Ok((target, Ok(Err(e)))) => print_to_terminal(
+                format!("{}/udp - no QUIC answer: {}", target, e),
+                VerbosityLevel::DEBUG,
+            ),
+            Ok((target, Err(problem))) => {
+                print_to_terminal(format!("{} - {}", target, problem), VerbosityLevel::WARN)
+            }
+            Err(e) => {
+                print_to_terminal(
+                    format!("An error has occured: {}", e),
+                    VerbosityLevel::ERROR,
+                );
+            }
+        }
+    }
+    for scan_result in results.iter_mut() {
+        if let Some(summary) = summaries.remove(&scan_result.ip) {
+            scan_result.quic = Some(summary);
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use crate::cli::ScanArgs;
+use crate::{
+    ConnectionStatus, ScanSettings, check_target, print_scan_result, privileges, targets,
+    validate_scan_args,
+};
+use std::net::IpAddr;
+use std::process;
+
+/// The endpoint refused the connection, or accepted it without meeting its
+/// configured criteria.
+const EXIT_CLOSED: i32 = 1;
+/// Nothing answered at all: the connection timed out or the host was
+/// unreachable.
+const EXIT_UNREACHABLE: i32 = 2;
+
+/// Probes the one endpoint given as the only argument, like `nc -z`, prints
+/// its state and exits with a code scripts can branch on.
+pub async fn check_endpoint(endpoint: &str, scan: &ScanArgs) -> ! {
+    validate_scan_args(scan);
+    let settings = ScanSettings::from_args(scan);
+    let target = targets::resolve_endpoint(endpoint).await;
+    privileges::drop_privileges(scan.run_as.as_deref());
+
+    let mut scan_result = check_target(target, None, settings).await;
+    if let Some((host, _)) = targets::split_endpoint(endpoint)
+        && host.parse::<IpAddr>().is_err()
+    {
+        scan_result.hostname = Some(host);
+    }
+    print_scan_result(&scan_result);
+
+    process::exit(exit_code(scan_result.status))
+}
+
+fn exit_code(status: ConnectionStatus) -> i32 {
+    match status {
+        ConnectionStatus::Open => 0,
+        ConnectionStatus::Refused | ConnectionStatus::Unconfirmed => EXIT_CLOSED,
+        ConnectionStatus::Timeout
+        | ConnectionStatus::Unreachable
+        | ConnectionStatus::OpenFiltered => EXIT_UNREACHABLE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_follow_the_state() {
+        assert_eq!(exit_code(ConnectionStatus::Open), 0);
+        assert_eq!(exit_code(ConnectionStatus::Refused), 1);
+        assert_eq!(exit_code(ConnectionStatus::Unconfirmed), 1);
+        assert_eq!(exit_code(ConnectionStatus::Timeout), 2);
+        assert_eq!(exit_code(ConnectionStatus::Unreachable), 2);
+        assert_eq!(exit_code(ConnectionStatus::OpenFiltered), 2);
+    }
+}
@@ -0,0 +1,107 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use tokio::time::{Duration, Instant, sleep};
+
+const PER_UNIT: &[(&str, u32)] = &[("s", 1), ("m", 60), ("h", 60 * 60)];
+
+/// How many connection attempts `--rate` lets start, e.g. `200/s`, `50/m`
+/// or a bare `200` for per second.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Rate {
+    count: f64,
+    /// The length of the unit the count is per, in seconds
+    seconds: u32,
+}
+
+impl Rate {
+    pub fn parse(value: &str) -> Result<Rate, String> {
+        let (count, unit) = value.trim().split_once('/').unwrap_or((value.trim(), "s"));
+        let Some((_, seconds)) = PER_UNIT.iter().find(|(name, _)| *name == unit) else {
+            return Err(format!("{} is not s, m or h", unit));
+        };
+        match count.parse::<f64>() {
+            Ok(count) if count > 0.0 && count.is_finite() => Ok(Rate {
+                count,
+                seconds: *seconds,
+            }),
+            _ => Err(format!("{} is not a positive number", count)),
+        }
+    }
+
+    fn per_second(&self) -> f64 {
+        self.count / self.seconds as f64
+    }
+}
+
+impl TryFrom<String> for Rate {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Rate, String> {
+        Rate::parse(&value)
+    }
+}
+
+impl From<Rate> for String {
+    fn from(rate: Rate) -> String {
+        let unit = PER_UNIT
+            .iter()
+            .find(|(_, seconds)| *seconds == rate.seconds)
+            .map_or("s", |(name, _)| name);
+        format!("{}/{}", rate.count, unit)
+    }
+}
+
+/// Tokens on hand, which may go below zero when attempts have reserved ones
+/// that haven't been refilled yet, and when they were last topped up.
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// A token bucket every connection attempt takes a token from before it
+/// starts. Tokens refill at the `--rate`, and up to a tenth of a second's
+/// worth can build up while nothing is waiting, so bursts stay small enough
+/// not to trip an IDS or swamp a small device.
+pub struct RateLimiter {
+    per_second: f64,
+    capacity: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: Rate) -> RateLimiter {
+        let per_second = rate.per_second();
+        let capacity = (per_second / 10.0).max(1.0);
+        RateLimiter {
+            per_second,
+            capacity,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes a token, waiting until it's been refilled if the bucket is
+    /// empty. Waiters are served in the order they arrive.
+    pub async fn acquire(&self) {
+        let wait = match self.bucket.lock() {
+            Ok(mut bucket) => {
+                let now = Instant::now();
+                let refill = now.duration_since(bucket.refilled).as_secs_f64() * self.per_second;
+                bucket.tokens = (bucket.tokens + refill).min(self.capacity) - 1.0;
+                bucket.refilled = now;
+                if bucket.tokens < 0.0 {
+                    Duration::from_secs_f64(-bucket.tokens / self.per_second)
+                } else {
+                    Duration::ZERO
+                }
+            }
+            Err(_) => Duration::ZERO,
+        };
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+}
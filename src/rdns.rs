@@ -0,0 +1,73 @@
+use crate::{VerbosityLevel, print_to_terminal, usage};
+use hickory_resolver::TokioResolver;
+use hickory_resolver::proto::rr::RData;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// PTR lookups in flight at once. Each is a single small query, but a /16
+/// shouldn't turn into 65536 of them at the resolver together.
+const MAX_LOOKUPS: usize = 64;
+
+/// The first name `ip`'s PTR record points to, without the trailing dot.
+async fn lookup(resolver: &TokioResolver, ip: IpAddr) -> Option<String> {
+    usage::dns_query();
+    let lookup = resolver.reverse_lookup(ip).await.ok()?;
+    lookup
+        .answers()
+        .iter()
+        .find_map(|record| match &record.data {
+            RData::PTR(name) => Some(name.0.to_utf8().trim_end_matches('.').to_string()),
+            _ => None,
+        })
+}
+
+/// Looks up the PTR name of every address for `--reverse-dns`, returning
+/// the ones that have one. Addresses without a PTR record, or whose lookup
+/// fails, are left out.
+pub async fn reverse_lookup(addresses: &[IpAddr]) -> HashMap<IpAddr, String> {
+    let resolver = match TokioResolver::builder_tokio().and_then(|builder| builder.build()) {
+        Ok(resolver) => resolver,
+        Err(e) => {
+            print_to_terminal(
+                format!(
+                    "Could not read the system DNS configuration ({}), hosts won't be named",
+                    e
+                ),
+                VerbosityLevel::WARN,
+            );
+            return HashMap::new();
+        }
+    };
+    print_to_terminal(
+        format!("Looking up the names of {} hosts", addresses.len()),
+        VerbosityLevel::INFO,
+    );
+    let slots = Arc::new(Semaphore::new(MAX_LOOKUPS));
+    let mut set: JoinSet<(IpAddr, Option<String>)> = JoinSet::new();
+    for ip in addresses.iter().copied() {
+        let resolver = resolver.clone();
+        let slots = slots.clone();
+        set.spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            (ip, lookup(&resolver, ip).await)
+        });
+    }
+
+    let mut names = HashMap::new();
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok((ip, Some(name))) => {
+                names.insert(ip, name);
+            }
+            Ok((_, None)) => {}
+            Err(e) => print_to_terminal(
+                format!("An error has occured: {}", e),
+                VerbosityLevel::ERROR,
+            ),
+        }
+    }
+    names
+}
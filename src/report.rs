@@ -0,0 +1,440 @@
+use crate::output::ScanMetadata;
+use crate::{ConnectionStatus, ScanResult, grouping};
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+
+const STYLE: &str = "\
+body { font-family: -apple-system, 'Segoe UI', Helvetica, Arial, sans-serif; margin: 2em; color: #222; }
+h1 { margin-bottom: 0.2em; }
+.meta { color: #666; margin-bottom: 2em; }
+table { border-collapse: collapse; margin-bottom: 1.5em; min-width: 24em; }
+th, td { border: 1px solid #ccc; padding: 4px 10px; text-align: left; }
+th { background: #f3f3f3; }
+.chart { margin-bottom: 2em; }
+.bar-row { display: flex; align-items: center; margin: 4px 0; }
+.bar-label { width: 8em; }
+.bar { height: 1.2em; min-width: 2px; margin-right: 0.5em; }
+.labels { color: #666; font-size: 0.9em; }
+.annotation { color: #333; font-size: 0.9em; }
+.Open { background: #b7e1b5; }
+.Refused { background: #f5e3a3; }
+.Unconfirmed { background: #f7d1a8; }
+.Timeout, .Unreachable { background: #f0b2b2; }
+";
+
+pub fn escape_html(text: &str) -> String {
+    text.replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("\"", "&quot;")
+}
+
+/// Renders the scan as one standalone page, with no scripts or external
+/// assets, so it can be attached to a ticket or emailed as-is.
+pub fn render_html(metadata: &ScanMetadata, results: &[ScanResult]) -> String {
+    let hosts: BTreeSet<IpAddr> = results
+        .iter()
+        .map(|scan_result| scan_result.ip.ip())
+        .collect();
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Scan report</title>\n<style>\n{}</style>\n</head>\n<body>\n",
+        STYLE
+    );
+    html.push_str("<h1>Scan report</h1>\n");
+    html.push_str(&format!(
+        "<div class=\"meta\">Targets: {}<br>Ports: {}<br>Started {} &middot; finished {} &middot; {:.1}s</div>\n",
+        escape_html(&metadata.targets.join(", ")),
+        escape_html(&metadata.ports),
+        metadata.started_at,
+        metadata.finished_at,
+        metadata.duration_ms / 1000.0
+    ));
+    if metadata.label.is_some() || metadata.operator.is_some() {
+        let mut run = Vec::new();
+        if let Some(label) = &metadata.label {
+            run.push(format!("Label: {}", escape_html(label)));
+        }
+        if let Some(operator) = &metadata.operator {
+            run.push(format!("Operator: {}", escape_html(operator)));
+        }
+        html.push_str(&format!("<div class=\"meta\">{}</div>\n", run.join("<br>")));
+    }
+
+    html.push_str(&format!(
+        "<h2>Summary</h2>\n<p>{} hosts, {} ports checked</p>\n<div class=\"chart\">\n",
+        hosts.len(),
+        results.len()
+    ));
+    for status in ConnectionStatus::ALL {
+        let count = results
+            .iter()
+            .filter(|scan_result| scan_result.status == status)
+            .count();
+        if count == 0 {
+            continue;
+        }
+        // The widest bar takes 60% of the row, leaving room for the label and count.
+        let width = count as f64 / results.len() as f64 * 60.0;
+        html.push_str(&format!(
+            "<div class=\"bar-row\"><span class=\"bar-label\">{}</span><span class=\"bar {}\" style=\"width: {:.1}%\"></span>{}</div>\n",
+            status, status, width, count
+        ));
+    }
+    html.push_str("</div>\n");
+
+    let new_findings: Vec<&ScanResult> = results
+        .iter()
+        .filter(|scan_result| {
+            scan_result.status == ConnectionStatus::Open && scan_result.acknowledgment.is_none()
+        })
+        .collect();
+    let acknowledged: Vec<&ScanResult> = results
+        .iter()
+        .filter(|scan_result| scan_result.acknowledgment.is_some())
+        .collect();
+    if !acknowledged.is_empty() {
+        html.push_str(&format!(
+            "<h2>New findings</h2>\n<p>{} open endpoints not yet acknowledged</p>\n",
+            new_findings.len()
+        ));
+        push_endpoint_table(&mut html, new_findings);
+        html.push_str("<h2>Acknowledged</h2>\n");
+        push_endpoint_table(&mut html, acknowledged);
+    }
+    let groups = grouping::by_group(results);
+    if groups.is_empty() {
+        html.push_str("<h2>Hosts</h2>\n");
+        push_hosts(&mut html, results.iter().collect());
+    }
+    for (name, members) in groups {
+        let open = members
+            .iter()
+            .filter(|scan_result| scan_result.status == ConnectionStatus::Open)
+            .count();
+        html.push_str(&format!(
+            "<h2>{} <small>({} endpoints, {} open)</small></h2>\n",
+            escape_html(name),
+            members.len(),
+            open
+        ));
+        push_hosts(&mut html, members);
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Lists endpoints across hosts, with the acknowledgment note where there is one.
+fn push_endpoint_table(html: &mut String, mut results: Vec<&ScanResult>) {
+    if results.is_empty() {
+        return;
+    }
+    results.sort_by_key(|scan_result| scan_result.ip);
+    html.push_str("<table>\n<tr><th>Endpoint</th><th>Status</th><th>Note</th></tr>\n");
+    for scan_result in results {
+        html.push_str(&format!(
+            "<tr><td>{}</td><td class=\"{}\">{}</td><td>{}</td></tr>\n",
+            scan_result.ip,
+            scan_result.status,
+            scan_result.status,
+            escape_html(scan_result.acknowledgment.as_deref().unwrap_or_default())
+        ));
+    }
+    html.push_str("</table>\n");
+}
+
+/// A heading and a table of ports for every host among `results`.
+fn push_hosts(html: &mut String, results: Vec<&ScanResult>) {
+    let fingerprinted = results
+        .iter()
+        .any(|scan_result| scan_result.service.is_some());
+    let tls_checked = results
+        .iter()
+        .any(|scan_result| scan_result.encryption.is_some());
+    let web_probed = results.iter().any(|scan_result| scan_result.http.is_some());
+    let ssh_probed = results.iter().any(|scan_result| scan_result.ssh.is_some());
+    let dns_probed = results.iter().any(|scan_result| scan_result.dns.is_some());
+    let quic_probed = results.iter().any(|scan_result| scan_result.quic.is_some());
+    let websocket_probed = results
+        .iter()
+        .any(|scan_result| scan_result.websocket.is_some());
+    let ntp_probed = results.iter().any(|scan_result| scan_result.ntp.is_some());
+    let snmp_probed = results.iter().any(|scan_result| scan_result.snmp.is_some());
+    let traced = results
+        .iter()
+        .any(|scan_result| scan_result.trace.is_some());
+    let mtu_probed = results
+        .iter()
+        .any(|scan_result| scan_result.path_mtu.is_some());
+    let os_guessed = results
+        .iter()
+        .any(|scan_result| scan_result.os_guess.is_some());
+    let raced = results
+        .iter()
+        .any(|scan_result| scan_result.dual_stack.is_some());
+    let retried = results
+        .iter()
+        .any(|scan_result| scan_result.attempts.is_some());
+    let grpc_probed = results.iter().any(|scan_result| scan_result.grpc.is_some());
+    let mail_probed = results
+        .iter()
+        .any(|scan_result| scan_result.starttls.is_some());
+    let through_interfaces = results
+        .iter()
+        .any(|scan_result| scan_result.interface.is_some());
+    let mut hosts: BTreeMap<IpAddr, Vec<&ScanResult>> = BTreeMap::new();
+    for scan_result in results {
+        hosts
+            .entry(scan_result.ip.ip())
+            .or_default()
+            .push(scan_result);
+    }
+
+    for (ip, mut host_results) in hosts {
+        host_results.sort_by_key(|scan_result| scan_result.ip.port());
+        let open = host_results
+            .iter()
+            .filter(|scan_result| scan_result.status == ConnectionStatus::Open)
+            .count();
+
+        let host = match host_results
+            .first()
+            .and_then(|named| named.reverse_dns.as_deref())
+        {
+            Some(name) => format!("{} ({})", escape_html(name), ip),
+            None => ip.to_string(),
+        };
+        html.push_str(&format!(
+            "<h3>{} <small>({} open)</small></h3>\n",
+            host, open
+        ));
+        if let Some(labelled) = host_results.first()
+            && !labelled.labels.is_empty()
+        {
+            html.push_str(&format!(
+                "<div class=\"labels\">{}</div>\n",
+                escape_html(&labelled.labels.join(", "))
+            ));
+        }
+        if let Some(annotation) = host_results
+            .first()
+            .and_then(|annotated| annotated.annotation.as_ref())
+        {
+            html.push_str(&format!(
+                "<div class=\"annotation\">{}</div>\n",
+                escape_html(&annotation.describe())
+            ));
+        }
+        html.push_str("<table>\n<tr><th>Port</th><th>Status</th><th>Latency</th>");
+        if through_interfaces {
+            html.push_str("<th>Interface</th>");
+        }
+        if fingerprinted {
+            html.push_str("<th>Service</th>");
+        }
+        if tls_checked {
+            html.push_str("<th>Encryption</th>");
+        }
+        if web_probed {
+            html.push_str("<th>HTTP</th>");
+        }
+        if ssh_probed {
+            html.push_str("<th>SSH</th>");
+        }
+        if dns_probed {
+            html.push_str("<th>DNS</th>");
+        }
+        if websocket_probed {
+            html.push_str("<th>WebSocket</th>");
+        }
+        if quic_probed {
+            html.push_str("<th>QUIC</th>");
+        }
+        if mail_probed {
+            html.push_str("<th>STARTTLS</th>");
+        }
+        if grpc_probed {
+            html.push_str("<th>gRPC</th>");
+        }
+        if ntp_probed {
+            html.push_str("<th>NTP</th>");
+        }
+        if snmp_probed {
+            html.push_str("<th>SNMP</th>");
+        }
+        if traced {
+            html.push_str("<th>Traceroute</th>");
+        }
+        if mtu_probed {
+            html.push_str("<th>Path MTU</th>");
+        }
+        if os_guessed {
+            html.push_str("<th>OS guess</th>");
+        }
+        if raced {
+            html.push_str("<th>Dual stack</th>");
+        }
+        if retried {
+            html.push_str("<th>Attempts</th>");
+        }
+        html.push_str("</tr>\n");
+        for scan_result in host_results {
+            html.push_str(&format!(
+                "<tr><td>{}</td><td class=\"{}\">{}</td><td>{:.1} ms</td>",
+                scan_result.ip.port(),
+                scan_result.status,
+                scan_result.status,
+                scan_result.latency.as_secs_f64() * 1000.0
+            ));
+            if through_interfaces {
+                let interface = scan_result.interface.as_deref().unwrap_or_default();
+                html.push_str(&format!("<td>{}</td>", escape_html(interface)));
+            }
+            if fingerprinted {
+                let service = scan_result
+                    .service
+                    .as_ref()
+                    .map(|service| service.to_string())
+                    .unwrap_or_default();
+                html.push_str(&format!("<td>{}</td>", escape_html(&service)));
+            }
+            if tls_checked {
+                let encryption = scan_result
+                    .encryption
+                    .map(|encryption| encryption.to_string())
+                    .unwrap_or_default();
+                let certificate = scan_result
+                    .certificates
+                    .first()
+                    .map(|certificate| format!(", {}", certificate.describe()))
+                    .unwrap_or_default();
+                let versions: Vec<&str> = scan_result
+                    .tls_versions
+                    .iter()
+                    .map(|support| support.version.as_str())
+                    .collect();
+                let versions = if versions.is_empty() {
+                    String::new()
+                } else {
+                    format!(", accepts {}", versions.join(", "))
+                };
+                html.push_str(&format!(
+                    "<td>{}{}{}</td>",
+                    encryption,
+                    escape_html(&certificate),
+                    versions
+                ));
+            }
+            if web_probed {
+                let http = scan_result
+                    .http
+                    .as_ref()
+                    .map(|http| http.describe())
+                    .unwrap_or_default();
+                html.push_str(&format!("<td>{}</td>", escape_html(&http)));
+            }
+            if ssh_probed {
+                let ssh = scan_result
+                    .ssh
+                    .as_ref()
+                    .map(|ssh| ssh.identification.as_str())
+                    .unwrap_or_default();
+                html.push_str(&format!("<td>{}</td>", escape_html(ssh)));
+            }
+            if dns_probed {
+                let dns = scan_result
+                    .dns
+                    .as_ref()
+                    .map(|dns| dns.describe())
+                    .unwrap_or_default();
+                html.push_str(&format!("<td>{}</td>", escape_html(&dns)));
+            }
+            if websocket_probed {
+                let websocket = scan_result
+                    .websocket
+                    .as_ref()
+                    .map(|websocket| websocket.describe())
+                    .unwrap_or_default();
+                html.push_str(&format!("<td>{}</td>", escape_html(&websocket)));
+            }
+            if quic_probed {
+                let quic = scan_result
+                    .quic
+                    .as_ref()
+                    .map(|quic| quic.describe())
+                    .unwrap_or_default();
+                html.push_str(&format!("<td>{}</td>", escape_html(&quic)));
+            }
+            if mail_probed {
+                let starttls = scan_result
+                    .starttls
+                    .as_ref()
+                    .map(|starttls| starttls.describe())
+                    .unwrap_or_default();
+                html.push_str(&format!("<td>{}</td>", escape_html(&starttls)));
+            }
+            if grpc_probed {
+                let grpc = scan_result
+                    .grpc
+                    .as_ref()
+                    .map(|grpc| grpc.describe())
+                    .unwrap_or_default();
+                html.push_str(&format!("<td>{}</td>", escape_html(&grpc)));
+            }
+            if ntp_probed {
+                let ntp = scan_result
+                    .ntp
+                    .as_ref()
+                    .map(|ntp| ntp.describe())
+                    .unwrap_or_default();
+                html.push_str(&format!("<td>{}</td>", escape_html(&ntp)));
+            }
+            if snmp_probed {
+                let snmp = scan_result
+                    .snmp
+                    .as_ref()
+                    .map(|snmp| snmp.describe())
+                    .unwrap_or_default();
+                html.push_str(&format!("<td>{}</td>", escape_html(&snmp)));
+            }
+            if traced {
+                let trace = scan_result
+                    .trace
+                    .as_ref()
+                    .map(|trace| trace.describe())
+                    .unwrap_or_default();
+                html.push_str(&format!("<td>{}</td>", escape_html(&trace)));
+            }
+            if mtu_probed {
+                let path_mtu = scan_result
+                    .path_mtu
+                    .as_ref()
+                    .map(|path_mtu| path_mtu.describe())
+                    .unwrap_or_default();
+                html.push_str(&format!("<td>{}</td>", escape_html(&path_mtu)));
+            }
+            if os_guessed {
+                let os_guess = scan_result.os_guess.as_deref().unwrap_or_default();
+                html.push_str(&format!("<td>{}</td>", escape_html(os_guess)));
+            }
+            if raced {
+                let dual_stack = scan_result
+                    .dual_stack
+                    .as_ref()
+                    .map(|dual_stack| dual_stack.describe())
+                    .unwrap_or_default();
+                html.push_str(&format!("<td>{}</td>", escape_html(&dual_stack)));
+            }
+            if retried {
+                let attempts = scan_result
+                    .attempts
+                    .map(|attempts| attempts.to_string())
+                    .unwrap_or_default();
+                html.push_str(&format!("<td>{}</td>", attempts));
+            }
+            html.push_str("</tr>\n");
+        }
+        html.push_str("</table>\n");
+    }
+}
@@ -0,0 +1,127 @@
+use crate::baseline::{self, Deviation};
+use crate::cli::{RescanArgs, ScanArgs};
+use crate::diff::{self, DiffLine};
+use crate::output::{ResultRecord, SavedReport};
+use crate::{
+    ConnectionStatus, ErrorCodes, MESSAGES_TO_STDERR, ScanResult, ScanSettings, VerbosityLevel,
+    check_target, error_handler, print_to_terminal,
+};
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::Ordering;
+use tokio::task::JoinSet;
+
+/// Probes again only the endpoints of a results file whose status is one of
+/// `--statuses`, then writes the whole report back out with their new
+/// results merged in.
+pub async fn rescan(rescan_args: RescanArgs) {
+    if rescan_args.out.is_none() {
+        MESSAGES_TO_STDERR.store(true, Ordering::Relaxed);
+    }
+    let statuses: Vec<String> = rescan_args
+        .statuses
+        .iter()
+        .map(|status| status.to_lowercase())
+        .collect();
+    for status in &statuses {
+        if !ConnectionStatus::ALL
+            .iter()
+            .any(|known| known.to_string().to_lowercase() == *status)
+        {
+            error_handler(ErrorCodes::INVALID_VARIABLE, line!(), Some("rescan status"));
+        }
+    }
+
+    let mut report = match SavedReport::read(&rescan_args.results) {
+        Some(report_result) => report_result,
+        None => error_handler(
+            ErrorCodes::RESULTS_FILE_INVALID,
+            line!(),
+            Some(&rescan_args.results.display().to_string()),
+        ),
+    };
+    let mut pending: BTreeMap<SocketAddr, usize> = BTreeMap::new();
+    for (index, record) in report.results.iter().enumerate() {
+        if let Ok(ip) = record.target.parse::<IpAddr>()
+            && statuses.contains(&record.status)
+        {
+            pending.insert(SocketAddr::new(ip, record.port), index);
+        }
+    }
+
+    let scan = ScanArgs {
+        config: rescan_args.config.clone(),
+        ..Default::default()
+    };
+    let settings = ScanSettings::from_args(&scan);
+    let mut set: JoinSet<ScanResult> = JoinSet::new();
+    for endpoint in pending.keys() {
+        set.spawn(check_target(*endpoint, None, settings.clone()));
+    }
+    print_to_terminal(
+        format!("Rescanning {} endpoints", pending.len()),
+        VerbosityLevel::INFO,
+    );
+
+    let mut before: BTreeMap<SocketAddr, String> = BTreeMap::new();
+    let mut after: BTreeMap<SocketAddr, String> = BTreeMap::new();
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok(scan_result) => {
+                let Some(&index) = pending.get(&scan_result.ip) else {
+                    continue;
+                };
+                let fresh = ResultRecord::from_scan_result(&scan_result);
+                let record = &mut report.results[index];
+                before.insert(scan_result.ip, record.status.clone());
+                after.insert(scan_result.ip, fresh.status.clone());
+                // Only what the probe measured changes. Labels and notes
+                // from the original scan are kept.
+                record.status = fresh.status;
+                record.latency_ms = fresh.latency_ms;
+                record.timestamp = fresh.timestamp;
+            }
+            Err(e) => {
+                print_to_terminal(
+                    format!("An error has occured: {}", e),
+                    VerbosityLevel::ERROR,
+                );
+            }
+        }
+    }
+
+    // Every endpoint is in both, so only changes come back
+    let deviations = baseline::compare(&before, &after);
+    let lines: Vec<DiffLine> = deviations.iter().map(Deviation::diff_line).collect();
+    let width = diff::width(&lines);
+    for line in &lines {
+        line.emit(width);
+    }
+    print_to_terminal(
+        format!(
+            "Rescanned {} endpoints, {} changed",
+            after.len(),
+            deviations.len()
+        ),
+        VerbosityLevel::INFO,
+    );
+
+    let rendered = serde_json::to_string_pretty(&report).unwrap_or_default() + "\n";
+    match &rescan_args.out {
+        Some(out_path) => {
+            if fs::write(out_path, rendered).is_err() {
+                error_handler(
+                    ErrorCodes::OUTPUT_WRITE_FAILURE,
+                    line!(),
+                    Some(&out_path.display().to_string()),
+                );
+            }
+            print_to_terminal(
+                format!("Updated report written to {}", out_path.display()),
+                VerbosityLevel::INFO,
+            );
+        }
+        None => print!("{}", rendered),
+    }
+}
@@ -0,0 +1,41 @@
+use crate::units::format_duration;
+use std::any::Any;
+use std::future::Future;
+use tokio::time::{Duration, timeout};
+
+/// Runs a probe that reads from a scanned host in a task of its own, so a
+/// response that makes the parsing panic, or a service that keeps it busy
+/// forever, only costs that one probe instead of the whole scan. The limit
+/// comes from `max_probe_time` in the config's `[limits]`.
+pub async fn run_isolated<F, T>(time_limit: Duration, probe: F) -> Result<T, String>
+where
+    F: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    let mut handle = tokio::spawn(probe);
+    match timeout(time_limit, &mut handle).await {
+        Ok(Ok(output)) => Ok(output),
+        Ok(Err(e)) if e.is_panic() => Err(format!(
+            "probe crashed: {}",
+            panic_message(e.into_panic().as_ref())
+        )),
+        Ok(Err(_)) => Err(String::from("probe was cancelled")),
+        Err(_) => {
+            handle.abort();
+            Err(format!(
+                "probe ran past its {} limit",
+                format_duration(time_limit)
+            ))
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => String::from("unknown panic"),
+        },
+    }
+}
@@ -0,0 +1,117 @@
+use crate::cli::SimulateArgs;
+use crate::output::{ResultStream, SavedReport, ScanMetadata};
+use crate::{
+    ErrorCodes, ScanResult, VerbosityLevel, clock, configure_printing, error_handler, grouping,
+    hints, print_summary, print_to_terminal, publish_results, report_result, validate_scan_args,
+};
+use std::net::SocketAddr;
+use std::time::SystemTime;
+use tokio::time::{Duration, Instant, sleep_until};
+
+/// Feeds the results of an earlier scan to everything a scan would hand
+/// them to, spaced out as they originally came in, without probing
+/// anything. Receivers, dashboards and alert rules can be tried against a
+/// known scan this way.
+pub async fn simulate(simulate_args: SimulateArgs) {
+    let scan = &simulate_args.scan;
+    configure_printing(scan);
+    validate_scan_args(scan);
+    let report = match SavedReport::read(&simulate_args.results) {
+        Some(report_result) => report_result,
+        None => error_handler(
+            ErrorCodes::RESULTS_FILE_INVALID,
+            line!(),
+            Some(&simulate_args.results.display().to_string()),
+        ),
+    };
+
+    let mut replay: Vec<ScanResult> = report
+        .results
+        .iter()
+        .filter_map(|record| record.to_scan_result())
+        .collect();
+    if replay.len() < report.results.len() {
+        print_to_terminal(
+            format!(
+                "Skipping {} results with an address or status that can't be replayed",
+                report.results.len() - replay.len()
+            ),
+            VerbosityLevel::WARN,
+        );
+    }
+    // Older files have no monotonic times, so their timestamps are used
+    let arrival = |scan_result: &ScanResult| -> Duration {
+        if report
+            .results
+            .iter()
+            .all(|record| record.monotonic_ms.is_some())
+        {
+            scan_result.monotonic
+        } else {
+            scan_result
+                .timestamp
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+        }
+    };
+    replay.sort_by_key(arrival);
+    let first = replay.first().map(arrival).unwrap_or_default();
+    let offsets: Vec<Duration> = replay
+        .iter()
+        .map(|scan_result| arrival(scan_result).saturating_sub(first))
+        .collect();
+
+    print_to_terminal(
+        format!(
+            "Replaying {} results from {} at {}x speed",
+            replay.len(),
+            simulate_args.results.display(),
+            simulate_args.speed
+        ),
+        VerbosityLevel::INFO,
+    );
+    let mut result_stream = scan
+        .output
+        .is_streaming()
+        .then(|| ResultStream::open(scan.output_file.as_deref()));
+    let endpoints: Vec<SocketAddr> = replay.iter().map(|scan_result| scan_result.ip).collect();
+    let mut host_groups = scan
+        .by_host
+        .then(|| grouping::HostGroups::new(endpoints.iter().copied()));
+    let hints = hints::PortHints::parse(&report.scan.ports);
+
+    let started = Instant::now();
+    let started_at = SystemTime::now();
+    let mut results: Vec<ScanResult> = Vec::new();
+    for (mut scan_result, offset) in replay.into_iter().zip(offsets) {
+        sleep_until(started + offset.div_f64(simulate_args.speed)).await;
+        // Consumers see the results as if they'd just come in
+        scan_result.timestamp = SystemTime::now();
+        scan_result.monotonic = clock::monotonic();
+        if let Some(group_by) = &scan.group_by {
+            scan_result.group =
+                Some(group_by.group_for(scan_result.ip, &scan_result.labels, &hints));
+        }
+        report_result(&scan_result, &mut host_groups, &mut result_stream);
+        results.push(scan_result);
+    }
+
+    print_to_terminal(String::from("Replay has completed"), VerbosityLevel::INFO);
+    print_summary(&results, started.elapsed());
+    let finished_at = SystemTime::now();
+    let metadata = ScanMetadata {
+        started_at: humantime::format_rfc3339_millis(started_at).to_string(),
+        finished_at: humantime::format_rfc3339_millis(finished_at).to_string(),
+        duration_ms: started.elapsed().as_secs_f64() * 1000.0,
+        clock_adjustment_ms: None,
+        ..report.scan
+    };
+    publish_results(
+        scan,
+        &metadata,
+        started_at,
+        &results,
+        result_stream.is_some(),
+    )
+    .await;
+}
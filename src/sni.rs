@@ -0,0 +1,197 @@
+use crate::certificate::CertificateSummary;
+use crate::cli::{SniFormat, SniMatrixArgs};
+use crate::{ErrorCodes, VerbosityLevel, error_handler, print_to_terminal, targets, tls};
+use std::fs;
+use std::net::SocketAddr;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, timeout};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What one SNI name got back from the endpoint.
+struct SniRow {
+    /// `None` for the handshake sent without any SNI
+    name: Option<String>,
+    outcome: Result<(Vec<u8>, CertificateSummary), String>,
+}
+
+pub async fn run_sni_matrix(sni_args: SniMatrixArgs) {
+    let endpoint = targets::resolve_endpoint(&sni_args.endpoint).await;
+    print_to_terminal(
+        format!(
+            "Testing {} SNI names against {}",
+            sni_args.names.len(),
+            endpoint
+        ),
+        VerbosityLevel::INFO,
+    );
+
+    let mut names: Vec<Option<String>> = vec![None];
+    names.extend(sni_args.names.iter().cloned().map(Some));
+
+    let mut set: JoinSet<(usize, SniRow)> = JoinSet::new();
+    for (index, name) in names.into_iter().enumerate() {
+        set.spawn(async move {
+            let outcome = fetch_certificate(endpoint, name.as_deref()).await;
+            (index, SniRow { name, outcome })
+        });
+    }
+
+    let mut rows: Vec<(usize, SniRow)> = Vec::new();
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok(row) => rows.push(row),
+            Err(e) => print_to_terminal(
+                format!("An error has occured: {}", e),
+                VerbosityLevel::ERROR,
+            ),
+        }
+    }
+    rows.sort_by_key(|(index, _)| *index);
+    let rows: Vec<SniRow> = rows.into_iter().map(|(_, row)| row).collect();
+
+    let rendered = match sni_args.format {
+        SniFormat::Text => render_text(&rows),
+        SniFormat::Csv => render_csv(&rows),
+    };
+    match &sni_args.out {
+        Some(path) => {
+            let path_name = path.display().to_string();
+            if fs::write(path, rendered).is_err() {
+                error_handler(ErrorCodes::OUTPUT_WRITE_FAILURE, line!(), Some(&path_name));
+            }
+            print_to_terminal(
+                format!("SNI matrix written to {}", path_name),
+                VerbosityLevel::INFO,
+            );
+        }
+        None => print!("{}", rendered),
+    }
+}
+
+async fn fetch_certificate(
+    endpoint: SocketAddr,
+    name: Option<&str>,
+) -> Result<(Vec<u8>, CertificateSummary), String> {
+    let stream = match timeout(HANDSHAKE_TIMEOUT, tls::handshake(endpoint, name, None)).await {
+        Ok(Ok(stream_result)) => stream_result,
+        Ok(Err(e)) => return Err(format!("tls failed: {}", e)),
+        Err(_) => return Err(String::from("tls timed out")),
+    };
+    let Some(der) = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certificates| certificates.first())
+    else {
+        return Err(String::from("no certificate presented"));
+    };
+    match CertificateSummary::parse(der) {
+        Some(summary) => Ok((der.to_vec(), summary)),
+        None => Err(String::from("certificate could not be parsed")),
+    }
+}
+
+/// Numbers each distinct certificate in the order it first appears, so names
+/// served the same certificate share a number.
+fn certificate_ids(rows: &[SniRow]) -> Vec<Option<usize>> {
+    let mut seen: Vec<&Vec<u8>> = Vec::new();
+    rows.iter()
+        .map(|row| {
+            let (der, _) = row.outcome.as_ref().ok()?;
+            match seen.iter().position(|seen_der| *seen_der == der) {
+                Some(position) => Some(position + 1),
+                None => {
+                    seen.push(der);
+                    Some(seen.len())
+                }
+            }
+        })
+        .collect()
+}
+
+/// One row of cells per name: SNI, certificate number, whether it covers
+/// the name, subject, alternative names, issuer and expiry.
+fn table_cells(rows: &[SniRow]) -> Vec<[String; 7]> {
+    rows.iter()
+        .zip(certificate_ids(rows))
+        .map(|(row, id)| {
+            let sni = row.name.clone().unwrap_or(String::from("(none)"));
+            match &row.outcome {
+                Ok((_, summary)) => {
+                    let covers = match &row.name {
+                        Some(name) if summary.covers(name) => "yes",
+                        Some(_) => "no",
+                        None => "-",
+                    };
+                    [
+                        sni,
+                        format!("#{}", id.unwrap_or_default()),
+                        covers.to_string(),
+                        summary.subject.clone(),
+                        summary.names.join(" "),
+                        summary.issuer.clone(),
+                        humantime::format_rfc3339_seconds(summary.not_after).to_string(),
+                    ]
+                }
+                Err(reason) => [
+                    sni,
+                    String::from("-"),
+                    String::from("-"),
+                    reason.clone(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                ],
+            }
+        })
+        .collect()
+}
+
+const HEADERS: [&str; 7] = [
+    "SNI", "CERT", "MATCHES", "SUBJECT", "NAMES", "ISSUER", "EXPIRES",
+];
+
+fn render_text(rows: &[SniRow]) -> String {
+    let cells = table_cells(rows);
+    let mut widths: Vec<usize> = HEADERS.iter().map(|header| header.len()).collect();
+    for row in &cells {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let format_row = |row: Vec<&str>| -> String {
+        let padded: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        padded.join("  ").trim_end().to_string() + "\n"
+    };
+
+    let mut text = format_row(HEADERS.to_vec());
+    for row in &cells {
+        text.push_str(&format_row(row.iter().map(String::as_str).collect()));
+    }
+    text
+}
+
+fn render_csv(rows: &[SniRow]) -> String {
+    let mut csv = String::from("sni,certificate,matches,subject,names,issuer,expires\n");
+    for row in table_cells(rows) {
+        let escaped: Vec<String> = row
+            .iter()
+            .map(|cell| {
+                if cell.contains(",") || cell.contains("\"") {
+                    format!("\"{}\"", cell.replace("\"", "\"\""))
+                } else {
+                    cell.clone()
+                }
+            })
+            .collect();
+        csv.push_str(&escaped.join(","));
+        csv.push('\n');
+    }
+    csv
+}
@@ -0,0 +1,289 @@
+use crate::hints::ServiceProtocol;
+use crate::{
+    ConnectionStatus, ScanResult, ScanSettings, VerbosityLevel, print_to_terminal, sandbox, usage,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, timeout};
+
+/// How long an agent gets to answer each request, and how many are sent
+/// per community. Agents stay silent for communities they don't know, so
+/// every wrong guess costs the full wait.
+const REQUEST_WAIT: Duration = Duration::from_secs(1);
+const ATTEMPTS: u32 = 2;
+/// The community tried when none are given.
+pub const DEFAULT_COMMUNITY: &str = "public";
+/// sysDescr.0, which every agent serves and which names the device.
+const SYS_DESCR: &[u8] = &[0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00];
+const SNMP_V1: u8 = 0;
+
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const GET_REQUEST: u8 = 0xa0;
+const GET_RESPONSE: u8 = 0xa2;
+
+/// What asking an agent came to: the community it answered, or `None` if
+/// it answered none of them.
+type Answer = io::Result<Option<SnmpSummary>>;
+
+/// How an SNMP agent answered `--snmp-probe`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SnmpSummary {
+    /// The community string the agent answered to
+    pub community: String,
+    /// The device's sysDescr, e.g. `Cisco IOS Software, C2960 ...`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl SnmpSummary {
+    /// The summary for a result line, e.g. `answers community public:
+    /// Linux router 5.15.0`.
+    pub fn describe(&self) -> String {
+        match &self.description {
+            Some(description) => format!("answers community {}: {}", self.community, description),
+            None => format!("answers community {}", self.community),
+        }
+    }
+}
+
+/// Appends a BER element: its tag, its length, then `value`.
+fn push_tlv(message: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    message.push(tag);
+    match value.len() {
+        length @ 0..=0x7f => message.push(length as u8),
+        length @ 0x80..=0xff => message.extend_from_slice(&[0x81, length as u8]),
+        length => {
+            message.push(0x82);
+            message.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+    }
+    message.extend_from_slice(value);
+}
+
+/// Reads the BER element at `offset`, returning its tag and value and
+/// moving `offset` past it.
+fn read_tlv<'a>(message: &'a [u8], offset: &mut usize) -> Option<(u8, &'a [u8])> {
+    let tag = *message.get(*offset)?;
+    let first = *message.get(*offset + 1)? as usize;
+    let (length, header) = match first {
+        0..=0x7f => (first, 2),
+        0x81 => (*message.get(*offset + 2)? as usize, 3),
+        0x82 => (
+            u16::from_be_bytes(message.get(*offset + 2..*offset + 4)?.try_into().ok()?) as usize,
+            4,
+        ),
+        _ => return None,
+    };
+    let value = message.get(*offset + header..*offset + header + length)?;
+    *offset += header + length;
+    Some((tag, value))
+}
+
+fn read_integer(value: &[u8]) -> i64 {
+    value.iter().fold(
+        if value.first().is_some_and(|byte| byte & 0x80 != 0) {
+            -1
+        } else {
+            0
+        },
+        |integer, byte| integer << 8 | *byte as i64,
+    )
+}
+
+/// An SNMPv1 get of sysDescr.0 in `community`.
+fn get_request(community: &str, request_id: u32) -> Vec<u8> {
+    let mut variable = Vec::new();
+    push_tlv(&mut variable, OBJECT_IDENTIFIER, SYS_DESCR);
+    push_tlv(&mut variable, NULL, &[]);
+    let mut bindings = Vec::new();
+    push_tlv(&mut bindings, SEQUENCE, &variable);
+    let mut pdu = Vec::new();
+    push_tlv(&mut pdu, INTEGER, &request_id.to_be_bytes());
+    push_tlv(&mut pdu, INTEGER, &[0]);
+    push_tlv(&mut pdu, INTEGER, &[0]);
+    push_tlv(&mut pdu, SEQUENCE, &bindings);
+    let mut body = Vec::new();
+    push_tlv(&mut body, INTEGER, &[SNMP_V1]);
+    push_tlv(&mut body, OCTET_STRING, community.as_bytes());
+    push_tlv(&mut body, GET_REQUEST, &pdu);
+    let mut message = Vec::new();
+    push_tlv(&mut message, SEQUENCE, &body);
+    message
+}
+
+/// The sysDescr in the response to request `request_id`, or `None` inside
+/// if the agent answered with an error, as some do for views that leave
+/// it out. `None` outright means it isn't that response.
+fn parse_response(message: &[u8], request_id: u32) -> Option<Option<String>> {
+    let mut offset = 0;
+    let (SEQUENCE, body) = read_tlv(message, &mut offset)? else {
+        return None;
+    };
+    let mut offset = 0;
+    read_tlv(body, &mut offset)?;
+    read_tlv(body, &mut offset)?;
+    let (GET_RESPONSE, pdu) = read_tlv(body, &mut offset)? else {
+        return None;
+    };
+    let mut offset = 0;
+    let (INTEGER, id) = read_tlv(pdu, &mut offset)? else {
+        return None;
+    };
+    if read_integer(id) != request_id as i64 {
+        return None;
+    }
+    let (INTEGER, error_status) = read_tlv(pdu, &mut offset)? else {
+        return None;
+    };
+    if read_integer(error_status) != 0 {
+        return Some(None);
+    }
+    read_tlv(pdu, &mut offset)?;
+    let (SEQUENCE, bindings) = read_tlv(pdu, &mut offset)? else {
+        return None;
+    };
+    let description = (|| {
+        let (SEQUENCE, variable) = read_tlv(bindings, &mut 0)? else {
+            return None;
+        };
+        let mut offset = 0;
+        read_tlv(variable, &mut offset)?;
+        let (OCTET_STRING, value) = read_tlv(variable, &mut offset)? else {
+            return None;
+        };
+        Some(String::from_utf8_lossy(value).trim().to_string())
+    })();
+    Some(description)
+}
+
+/// Asks for sysDescr in each of `communities` in turn, returning the first
+/// the agent answers to, or `None` if it answers to none of them.
+async fn probe(target: SocketAddr, communities: Arc<Vec<String>>) -> Answer {
+    let local = if target.is_ipv4() {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    } else {
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(local, 0)).await?;
+    socket.connect(target).await?;
+    let base_id = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.subsec_nanos() & 0x3fff_ffff)
+        .unwrap_or(0x1337);
+    let mut buffer = [0u8; 1500];
+    for (index, community) in communities.iter().enumerate() {
+        let request_id = base_id + index as u32;
+        let request = get_request(community, request_id);
+        for _ in 0..ATTEMPTS {
+            socket.send(&request).await?;
+            usage::sent(request.len());
+            let reply = timeout(REQUEST_WAIT, async {
+                // Stray datagrams, and late answers to earlier requests,
+                // are skipped until the wait runs out
+                loop {
+                    let read = socket.recv(&mut buffer).await?;
+                    usage::received(read);
+                    if let Some(description) = parse_response(&buffer[..read], request_id) {
+                        return Ok::<_, io::Error>(description);
+                    }
+                }
+            })
+            .await;
+            if let Ok(description) = reply {
+                return Ok(Some(SnmpSummary {
+                    community: community.clone(),
+                    description: description?,
+                }));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Asks every port in `results` that's hinted as SNMP, 161 included, and
+/// open or open|filtered in a `--udp` scan for its sysDescr with each of
+/// `communities`, and warns about every agent that answers one, default
+/// communities being readable by anyone who guesses them. At most
+/// `max_enrichment_probes` agents are asked at once.
+pub async fn probe_snmp(
+    settings: &Arc<ScanSettings>,
+    results: &mut [ScanResult],
+    communities: Vec<String>,
+) {
+    let limits = settings.config.limits;
+    let slots = Arc::new(Semaphore::new(limits.max_enrichment_probes.max(1)));
+    let communities = Arc::new(communities);
+    let mut set: JoinSet<(SocketAddr, Result<Answer, String>)> = JoinSet::new();
+    for scan_result in results.iter().filter(|scan_result| {
+        matches!(
+            scan_result.status,
+            ConnectionStatus::Open | ConnectionStatus::OpenFiltered
+        ) && settings.hints.protocol_for(scan_result.ip.port()) == Some(ServiceProtocol::Snmp)
+    }) {
+        let target = scan_result.ip;
+        let communities = communities.clone();
+        let slots = slots.clone();
+        set.spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            let summary =
+                sandbox::run_isolated(limits.max_probe_time, probe(target, communities)).await;
+            (target, summary)
+        });
+    }
+    if set.is_empty() {
+        return;
+    }
+    print_to_terminal(
+        format!(
+            "Asking SNMP agents for sysDescr with community {}",
+            communities.join(", ")
+        ),
+        VerbosityLevel::INFO,
+    );
+
+    let mut summaries: HashMap<SocketAddr, SnmpSummary> = HashMap::new();
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok((target, Ok(Ok(Some(summary))))) => {
+                print_to_terminal(
+                    format!("{} - {}", target, summary.describe()),
+                    VerbosityLevel::WARN,
+                );
+                summaries.insert(target, summary);
+            }
+            Ok((target, Ok(Ok(None)))) => print_to_terminal(
+                format!("{} - answers none of the communities", target),
+                VerbosityLevel::INFO,
+            ),
+            Ok((target, Ok(Err(e)))) => print_to_terminal(
+                format!("{} - SNMP probe failed: {}", target, e),
+                VerbosityLevel::WARN,
+            ),
+            Ok((target, Err(problem))) => {
+                print_to_terminal(format!("{} - {}", target, problem), VerbosityLevel::WARN)
+            }
+            Err(e) => {
+                print_to_terminal(
+                    format!("An error has occured: {}", e),
+                    VerbosityLevel::ERROR,
+                );
+            }
+        }
+    }
+    for scan_result in results.iter_mut() {
+        if let Some(summary) = summaries.remove(&scan_result.ip) {
+            scan_result.snmp = Some(summary);
+        }
+    }
+}
@@ -0,0 +1,362 @@
+use crate::{ErrorCodes, VerbosityLevel, error_handler, http, print_to_terminal};
+use std::env;
+use std::fs;
+use std::future::Future;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::pin::Pin;
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, timeout};
+
+/// How long an inventory gets to answer before the scan gives up on it.
+const SOURCE_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_CONSUL_ADDRESS: &str = "127.0.0.1:8500";
+
+pub type SourceFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<String>, String>> + Send + 'a>>;
+
+/// Somewhere targets come from other than the command line itself. Each
+/// source gives back target strings, which are then expanded like any
+/// others, so they can be addresses, CIDRs, hostnames or brace patterns.
+///
+/// Adding an inventory means implementing this and recognising its prefix
+/// in `source_for`; nothing in the scan itself needs to change.
+pub trait TargetSource: Send + Sync {
+    /// What the source is, for messages, e.g. `consul service web`
+    fn describe(&self) -> String;
+    fn fetch(&self) -> SourceFuture<'_>;
+}
+
+/// A target given directly, such as an address, CIDR or hostname.
+pub struct Inline(pub String);
+
+impl TargetSource for Inline {
+    fn describe(&self) -> String {
+        self.0.clone()
+    }
+
+    fn fetch(&self) -> SourceFuture<'_> {
+        Box::pin(async move { Ok(vec![self.0.clone()]) })
+    }
+}
+
+/// `file:PATH`, one target per line. Blank lines and `#` comments are skipped.
+pub struct TargetFile(pub PathBuf);
+
+impl TargetSource for TargetFile {
+    fn describe(&self) -> String {
+        format!("file {}", self.0.display())
+    }
+
+    fn fetch(&self) -> SourceFuture<'_> {
+        Box::pin(async move {
+            fs::read_to_string(&self.0)
+                .map(|contents| target_lines(&contents))
+                .map_err(|e| e.to_string())
+        })
+    }
+}
+
+/// `-`, one target per line on stdin.
+pub struct Stdin;
+
+impl TargetSource for Stdin {
+    fn describe(&self) -> String {
+        String::from("stdin")
+    }
+
+    fn fetch(&self) -> SourceFuture<'_> {
+        Box::pin(async move {
+            let contents = tokio::task::spawn_blocking(|| {
+                let mut contents = String::new();
+                io::stdin().read_to_string(&mut contents).map(|_| contents)
+            })
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.to_string())?;
+            Ok(target_lines(&contents))
+        })
+    }
+}
+
+/// `consul:SERVICE`, the addresses of every instance of a service in the
+/// Consul catalog. The agent is found through `CONSUL_HTTP_ADDR`.
+pub struct Consul {
+    pub service: String,
+}
+
+impl TargetSource for Consul {
+    fn describe(&self) -> String {
+        format!("consul service {}", self.service)
+    }
+
+    fn fetch(&self) -> SourceFuture<'_> {
+        Box::pin(async move {
+            let address = env::var("CONSUL_HTTP_ADDR")
+                .unwrap_or_else(|_| String::from(DEFAULT_CONSUL_ADDRESS));
+            let address = address.trim_start_matches("http://").to_string();
+            let path = format!("/v1/catalog/service/{}", self.service);
+
+            let stream = TcpStream::connect(&address)
+                .await
+                .map_err(|e| format!("could not reach consul at {}: {}", address, e))?;
+            let response = http::get(stream, &address, &path)
+                .await
+                .map_err(|e| e.to_string())?;
+            if response.status != 200 {
+                return Err(format!("consul answered with status {}", response.status));
+            }
+
+            let instances: Vec<serde_json::Value> =
+                serde_json::from_slice(&response.body).map_err(|e| e.to_string())?;
+            Ok(instances
+                .iter()
+                .filter_map(|instance| {
+                    // ServiceAddress is empty when the service uses its node's address
+                    ["ServiceAddress", "Address"]
+                        .iter()
+                        .filter_map(|field| instance[field].as_str())
+                        .find(|address| !address.is_empty())
+                        .map(String::from)
+                })
+                .collect())
+        })
+    }
+}
+
+/// `docker:` for every running container, or `docker:NAME` for one, taken
+/// from the local `docker` CLI.
+pub struct Docker {
+    pub container: Option<String>,
+}
+
+impl TargetSource for Docker {
+    fn describe(&self) -> String {
+        match &self.container {
+            Some(container) => format!("docker container {}", container),
+            None => String::from("docker containers"),
+        }
+    }
+
+    fn fetch(&self) -> SourceFuture<'_> {
+        Box::pin(async move {
+            let containers = match &self.container {
+                Some(container) => vec![container.clone()],
+                None => run_command("docker", &["ps", "--quiet"]).await?,
+            };
+            if containers.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            let mut args = vec![
+                "inspect",
+                "--format",
+                "{{range .NetworkSettings.Networks}}{{.IPAddress}} {{end}}",
+            ];
+            args.extend(containers.iter().map(String::as_str));
+            run_command("docker", &args).await
+        })
+    }
+}
+
+/// `k8s:[NAMESPACE/]SERVICE`, the pod addresses behind a Kubernetes service,
+/// through `kubectl` and its current context.
+pub struct Kubernetes {
+    pub namespace: Option<String>,
+    pub service: String,
+}
+
+impl TargetSource for Kubernetes {
+    fn describe(&self) -> String {
+        match &self.namespace {
+            Some(namespace) => format!("kubernetes service {}/{}", namespace, self.service),
+            None => format!("kubernetes service {}", self.service),
+        }
+    }
+
+    fn fetch(&self) -> SourceFuture<'_> {
+        Box::pin(async move {
+            let mut args = vec![
+                "get",
+                "endpoints",
+                self.service.as_str(),
+                "--output",
+                "jsonpath={.subsets[*].addresses[*].ip}",
+            ];
+            if let Some(namespace) = &self.namespace {
+                args.extend(["--namespace", namespace.as_str()]);
+            }
+            run_command("kubectl", &args).await
+        })
+    }
+}
+
+/// `aws:` for every running EC2 instance, or `aws:tag:KEY=VALUE` for the
+/// ones with that tag, by private address through the `aws` CLI and its
+/// usual credentials and region.
+pub struct Aws {
+    pub tag: Option<(String, String)>,
+}
+
+impl TargetSource for Aws {
+    fn describe(&self) -> String {
+        match &self.tag {
+            Some((key, value)) => format!("aws instances tagged {}={}", key, value),
+            None => String::from("aws instances"),
+        }
+    }
+
+    fn fetch(&self) -> SourceFuture<'_> {
+        Box::pin(async move {
+            let mut filters = vec![String::from("Name=instance-state-name,Values=running")];
+            if let Some((key, value)) = &self.tag {
+                filters.push(format!("Name=tag:{},Values={}", key, value));
+            }
+            let mut args = vec![
+                "ec2",
+                "describe-instances",
+                "--query",
+                "Reservations[].Instances[].PrivateIpAddress",
+                "--output",
+                "text",
+                "--filters",
+            ];
+            args.extend(filters.iter().map(String::as_str));
+            run_command("aws", &args).await
+        })
+    }
+}
+
+fn target_lines(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(|line| line.split("#").next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Runs an inventory CLI and splits whatever it prints into targets.
+async fn run_command(program: &str, args: &[&str]) -> Result<Vec<String>, String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| format!("could not run {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .filter(|target| *target != "None")
+        .map(String::from)
+        .collect())
+}
+
+/// Picks the source a target names by its prefix. Plain targets are `Inline`.
+pub fn source_for(target: &str) -> Box<dyn TargetSource> {
+    let target = target.trim();
+    if target == "-" {
+        return Box::new(Stdin);
+    }
+    let Some((kind, rest)) = target.split_once(":") else {
+        return Box::new(Inline(target.to_string()));
+    };
+
+    match kind {
+        "file" => Box::new(TargetFile(PathBuf::from(rest))),
+        "consul" => Box::new(Consul {
+            service: rest.to_string(),
+        }),
+        "docker" => Box::new(Docker {
+            container: (!rest.is_empty()).then(|| rest.to_string()),
+        }),
+        "k8s" => match rest.split_once("/") {
+            Some((namespace, service)) => Box::new(Kubernetes {
+                namespace: Some(namespace.to_string()),
+                service: service.to_string(),
+            }),
+            None => Box::new(Kubernetes {
+                namespace: None,
+                service: rest.to_string(),
+            }),
+        },
+        "aws" => match rest
+            .strip_prefix("tag:")
+            .and_then(|tag| tag.split_once("="))
+        {
+            Some((key, value)) => Box::new(Aws {
+                tag: Some((key.to_string(), value.to_string())),
+            }),
+            None if rest.is_empty() => Box::new(Aws { tag: None }),
+            None => error_handler(ErrorCodes::INVALID_VARIABLE, line!(), Some("aws target")),
+        },
+        _ => Box::new(Inline(target.to_string())),
+    }
+}
+
+/// Whether a target is read from somewhere else rather than given directly.
+pub fn is_external(target: &str) -> bool {
+    let target = target.trim();
+    target == "-"
+        || target
+            .split_once(":")
+            .is_some_and(|(kind, _)| matches!(kind, "file" | "consul" | "docker" | "k8s" | "aws"))
+}
+
+/// Replaces every external source in the target list with the targets it
+/// gives, fetching them all at once. A source that can't be read stops the
+/// scan rather than quietly scanning less than was asked for.
+pub async fn gather_targets(targets: &[String]) -> Vec<String> {
+    let mut fetches: JoinSet<(usize, String, Result<Vec<String>, String>)> = JoinSet::new();
+    for (position, target) in targets.iter().enumerate() {
+        let source = source_for(target);
+        fetches.spawn(async move {
+            let description = source.describe();
+            let fetched = match timeout(SOURCE_TIMEOUT, source.fetch()).await {
+                Ok(fetched) => fetched,
+                Err(_) => Err(String::from("timed out")),
+            };
+            (position, description, fetched)
+        });
+    }
+
+    let mut gathered: Vec<(usize, Vec<String>)> = Vec::new();
+    while let Some(res) = fetches.join_next().await {
+        match res {
+            Ok((position, description, Ok(fetched))) => {
+                if is_external(&targets[position]) {
+                    print_to_terminal(
+                        format!("Read {} targets from {}", fetched.len(), description),
+                        VerbosityLevel::DEBUG,
+                    );
+                }
+                gathered.push((position, fetched));
+            }
+            Ok((_, description, Err(problem))) => {
+                print_to_terminal(problem, VerbosityLevel::ERROR);
+                error_handler(
+                    ErrorCodes::TARGET_SOURCE_FAILED,
+                    line!(),
+                    Some(&description),
+                );
+            }
+            Err(e) => print_to_terminal(
+                format!("An error has occured: {}", e),
+                VerbosityLevel::ERROR,
+            ),
+        }
+    }
+
+    // Keep the order the targets were given in
+    gathered.sort_by_key(|(position, _)| *position);
+    gathered
+        .into_iter()
+        .flat_map(|(_, fetched)| fetched)
+        .collect()
+}
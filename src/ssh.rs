@@ -0,0 +1,276 @@
+use crate::hints::ServiceProtocol;
+use crate::{
+    ConnectionStatus, ScanResult, ScanSettings, VerbosityLevel, print_to_terminal, sandbox, usage,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, timeout};
+
+/// How long the server gets to identify itself and send its KEXINIT.
+const EXCHANGE_WAIT: Duration = Duration::from_secs(5);
+/// What the probe identifies itself as, for servers that wait to hear from
+/// the client before sending their key exchange offer.
+const IDENTIFICATION: &[u8] = concat!(
+    "SSH-2.0-connection-tester_",
+    env!("CARGO_PKG_VERSION"),
+    "\r\n"
+)
+.as_bytes();
+/// Servers may send lines of text before the identification string, and
+/// the string itself can be at most 255 bytes.
+const MAX_PREAMBLE_LINES: usize = 16;
+const MAX_LINE_BYTES: usize = 255;
+/// The KEXINIT is small; anything claiming to be much larger isn't one.
+const MAX_PACKET_BYTES: usize = 64 * 1024;
+const SSH_MSG_KEXINIT: u8 = 20;
+
+/// Algorithms broken or deprecated enough to point out: key exchanges over
+/// 1024-bit groups or SHA-1, and DSA host keys.
+const WEAK_ALGORITHMS: &[&str] = &[
+    "diffie-hellman-group1-sha1",
+    "diffie-hellman-group14-sha1",
+    "diffie-hellman-group-exchange-sha1",
+    "gss-group1-sha1-",
+    "ssh-dss",
+];
+
+/// What an SSH server said about itself.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SshSummary {
+    /// The identification string, e.g. `SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13`
+    pub identification: String,
+    /// The software and version part of it, e.g. `OpenSSH_9.6p1`
+    pub software: String,
+    /// With `--ssh-algorithms`, the key exchange algorithms the server offers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kex_algorithms: Vec<String>,
+    /// With `--ssh-algorithms`, the host key algorithms the server offers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub host_key_algorithms: Vec<String>,
+}
+
+impl SshSummary {
+    /// The offered algorithms worth warning about.
+    pub fn weak_algorithms(&self) -> Vec<&str> {
+        self.kex_algorithms
+            .iter()
+            .chain(&self.host_key_algorithms)
+            .filter(|algorithm| {
+                WEAK_ALGORITHMS
+                    .iter()
+                    .any(|weak| algorithm.starts_with(weak))
+            })
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+/// Reads an SSH `name-list`: a length, then that many bytes of comma
+/// separated names.
+fn name_list(payload: &[u8], offset: &mut usize) -> Option<Vec<String>> {
+    let length = u32::from_be_bytes(payload.get(*offset..*offset + 4)?.try_into().ok()?) as usize;
+    let names = payload.get(*offset + 4..*offset + 4 + length)?;
+    *offset += 4 + length;
+    Some(
+        String::from_utf8_lossy(names)
+            .split(',')
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect(),
+    )
+}
+
+/// The key exchange and host key algorithms in a KEXINIT payload, which
+/// starts with the message number and a 16 byte cookie.
+fn parse_kexinit(payload: &[u8]) -> Option<(Vec<String>, Vec<String>)> {
+    if payload.first() != Some(&SSH_MSG_KEXINIT) {
+        return None;
+    }
+    let mut offset = 17;
+    let kex_algorithms = name_list(payload, &mut offset)?;
+    let host_key_algorithms = name_list(payload, &mut offset)?;
+    Some((kex_algorithms, host_key_algorithms))
+}
+
+/// Sends our identification and reads the KEXINIT that follows, returning
+/// the key exchange and host key algorithms it offers. Nothing past the
+/// offer is sent, so no key exchange takes place.
+async fn read_kexinit(reader: &mut BufReader<TcpStream>) -> io::Result<(Vec<String>, Vec<String>)> {
+    reader.get_mut().write_all(IDENTIFICATION).await?;
+    usage::sent(IDENTIFICATION.len());
+    let mut length = [0u8; 4];
+    reader.read_exact(&mut length).await?;
+    let length = u32::from_be_bytes(length) as usize;
+    if !(2..=MAX_PACKET_BYTES).contains(&length) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not an SSH packet",
+        ));
+    }
+    let mut packet = vec![0u8; length];
+    reader.read_exact(&mut packet).await?;
+    usage::received(4 + length);
+    // The padding length, then the payload, then the padding
+    let padding = packet[0] as usize;
+    let payload = packet
+        .get(1..length.saturating_sub(padding))
+        .unwrap_or_default();
+    parse_kexinit(payload).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the server's first packet wasn't a KEXINIT",
+        )
+    })
+}
+
+/// Reads the server's identification string and, with `algorithms`, the
+/// algorithms it offers. A server that identifies itself but can't be got
+/// to offer algorithms still gets its identification recorded.
+async fn probe(target: SocketAddr, algorithms: bool) -> io::Result<SshSummary> {
+    usage::socket_opened();
+    let stream = TcpStream::connect(target).await?;
+    let mut reader = BufReader::new(stream);
+    let mut identification = None;
+    for _ in 0..MAX_PREAMBLE_LINES {
+        let mut line = Vec::new();
+        let read = (&mut reader)
+            .take(MAX_LINE_BYTES as u64)
+            .read_until(b'\n', &mut line)
+            .await?;
+        if read == 0 {
+            break;
+        }
+        usage::received(read);
+        let line = String::from_utf8_lossy(&line).trim_end().to_string();
+        if line.starts_with("SSH-") {
+            identification = Some(line);
+            break;
+        }
+    }
+    let Some(identification) = identification else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no SSH identification string",
+        ));
+    };
+    // SSH-protoversion-softwareversion SP comments
+    let software = identification
+        .splitn(3, '-')
+        .nth(2)
+        .and_then(|rest| rest.split_whitespace().next())
+        .unwrap_or_default()
+        .to_string();
+    let mut summary = SshSummary {
+        identification,
+        software,
+        kex_algorithms: Vec::new(),
+        host_key_algorithms: Vec::new(),
+    };
+    if algorithms {
+        match read_kexinit(&mut reader).await {
+            Ok((kex_algorithms, host_key_algorithms)) => {
+                summary.kex_algorithms = kex_algorithms;
+                summary.host_key_algorithms = host_key_algorithms;
+            }
+            Err(e) => print_to_terminal(
+                format!("{} - algorithms not offered: {}", target, e),
+                VerbosityLevel::WARN,
+            ),
+        }
+    }
+    Ok(summary)
+}
+
+/// Reads the identification string of every open port in `results` that's
+/// hinted as SSH, port 22 included, or found running SSH by
+/// `--detect-services`, and with `algorithms` the key exchange and host key
+/// algorithms it offers. Versions are printed for patch audits and weak
+/// algorithms warned about. At most `max_enrichment_probes` endpoints are
+/// probed at once.
+pub async fn probe_ssh(settings: &Arc<ScanSettings>, results: &mut [ScanResult], algorithms: bool) {
+    let limits = settings.config.limits;
+    let slots = Arc::new(Semaphore::new(limits.max_enrichment_probes.max(1)));
+    let mut set: JoinSet<(SocketAddr, Result<io::Result<SshSummary>, String>)> = JoinSet::new();
+    for scan_result in results.iter().filter(|scan_result| {
+        scan_result.status == ConnectionStatus::Open
+            && (settings.hints.protocol_for(scan_result.ip.port()) == Some(ServiceProtocol::Ssh)
+                || scan_result
+                    .service
+                    .as_ref()
+                    .is_some_and(|service| service.name == "ssh"))
+    }) {
+        let target = scan_result.ip;
+        let slots = slots.clone();
+        set.spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            let summary = sandbox::run_isolated(limits.max_probe_time, async move {
+                match timeout(EXCHANGE_WAIT, probe(target, algorithms)).await {
+                    Ok(summary) => summary,
+                    Err(_) => Err(io::Error::from(io::ErrorKind::TimedOut)),
+                }
+            })
+            .await;
+            (target, summary)
+        });
+    }
+    if set.is_empty() {
+        return;
+    }
+    print_to_terminal(String::from("Probing SSH servers"), VerbosityLevel::INFO);
+
+    let mut summaries: HashMap<SocketAddr, SshSummary> = HashMap::new();
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok((target, Ok(Ok(summary)))) => {
+                print_to_terminal(
+                    format!("{} - {}", target, summary.identification),
+                    VerbosityLevel::INFO,
+                );
+                if !summary.kex_algorithms.is_empty() {
+                    print_to_terminal(
+                        format!(
+                            "{} - key exchange {}; host keys {}",
+                            target,
+                            summary.kex_algorithms.join(", "),
+                            summary.host_key_algorithms.join(", ")
+                        ),
+                        VerbosityLevel::INFO,
+                    );
+                }
+                let weak = summary.weak_algorithms();
+                if !weak.is_empty() {
+                    print_to_terminal(
+                        format!("{} - offers weak {}", target, weak.join(", ")),
+                        VerbosityLevel::WARN,
+                    );
+                }
+                summaries.insert(target, summary);
+            }
+            Ok((target, Ok(Err(e)))) => print_to_terminal(
+                format!("{} - no SSH server: {}", target, e),
+                VerbosityLevel::WARN,
+            ),
+            Ok((target, Err(problem))) => {
+                print_to_terminal(format!("{} - {}", target, problem), VerbosityLevel::WARN)
+            }
+            Err(e) => {
+                print_to_terminal(
+                    format!("An error has occured: {}", e),
+                    VerbosityLevel::ERROR,
+                );
+            }
+        }
+    }
+    for scan_result in results.iter_mut() {
+        if let Some(summary) = summaries.remove(&scan_result.ip) {
+            scan_result.ssh = Some(summary);
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use crate::hints::ServiceProtocol;
+use crate::{
+    ConnectionStatus, ScanResult, ScanSettings, VerbosityLevel, print_to_terminal, sandbox, tls,
+    usage,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, timeout};
+
+/// How long a mail server gets to greet, list its capabilities and agree
+/// to STARTTLS.
+const DIALOGUE_WAIT: Duration = Duration::from_secs(10);
+/// Mail protocol lines are short; capability lists run to a few dozen.
+const MAX_LINE_BYTES: usize = 1024;
+const MAX_REPLY_LINES: usize = 64;
+
+/// How a mail server answered `--starttls-probe`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StarttlsSummary {
+    /// The protocol spoken, `smtp`, `imap` or `pop3`
+    pub protocol: String,
+    /// The server's greeting, e.g. `220 mx.example.com ESMTP Postfix`
+    pub greeting: String,
+    /// Whether STARTTLS, or STLS for POP3, was among the capabilities
+    pub offered: bool,
+    /// The TLS version negotiated once the server agreed to upgrade
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_version: Option<String>,
+}
+
+impl StarttlsSummary {
+    /// The summary for a result line, e.g. `SMTP, STARTTLS to TLS 1.3`.
+    pub fn describe(&self) -> String {
+        let protocol = self.protocol.to_uppercase();
+        match (&self.tls_version, self.offered) {
+            (Some(version), _) => format!("{}, STARTTLS to {}", protocol, version),
+            (None, true) => format!("{}, STARTTLS offered but failed", protocol),
+            (None, false) => format!("{}, no STARTTLS", protocol),
+        }
+    }
+}
+
+async fn read_line(reader: &mut BufReader<TcpStream>) -> io::Result<String> {
+    let mut line = Vec::new();
+    let read = (&mut *reader)
+        .take(MAX_LINE_BYTES as u64)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if read == 0 {
+        return Err(io::Error::from(ErrorKind::UnexpectedEof));
+    }
+    usage::received(read);
+    Ok(String::from_utf8_lossy(&line).trim_end().to_string())
+}
+
+async fn send(reader: &mut BufReader<TcpStream>, command: &str) -> io::Result<()> {
+    let command = format!("{}\r\n", command);
+    reader.get_mut().write_all(command.as_bytes()).await?;
+    usage::sent(command.len());
+    Ok(())
+}
+
+/// Reads lines until `last` says the reply is over, returning them all.
+async fn read_reply(
+    reader: &mut BufReader<TcpStream>,
+    last: impl Fn(&str) -> bool,
+) -> io::Result<Vec<String>> {
+    let mut lines = Vec::new();
+    for _ in 0..MAX_REPLY_LINES {
+        let line = read_line(reader).await?;
+        let done = last(&line);
+        lines.push(line);
+        if done {
+            return Ok(lines);
+        }
+    }
+    Err(io::Error::new(ErrorKind::InvalidData, "reply too long"))
+}
+
+/// An SMTP reply ends with the line that has a space after its code.
+fn smtp_last(line: &str) -> bool {
+    line.as_bytes().get(3) != Some(&b'-')
+}
+
+fn unexpected(reply: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("unexpected reply {:?}", reply),
+    )
+}
+
+/// Holds the protocol's dialogue up to the point of asking for TLS, then
+/// asks for it if offered. Returns the greeting, and whether the server
+/// offered and then agreed to STARTTLS.
+async fn negotiate(
+    reader: &mut BufReader<TcpStream>,
+    protocol: ServiceProtocol,
+) -> io::Result<(String, bool, bool)> {
+    match protocol {
+        ServiceProtocol::Smtp => {
+            let greeting = read_reply(reader, smtp_last).await?;
+            let greeting = greeting.join(" ");
+            if !greeting.starts_with("220") {
+                return Err(unexpected(&greeting));
+            }
+            send(reader, "EHLO connection-tester").await?;
+            let capabilities = read_reply(reader, smtp_last).await?;
+            let offered = capabilities.iter().any(|line| {
+                line.starts_with("250")
+                    && line
+                        .get(4..)
+                        .is_some_and(|capability| capability.eq_ignore_ascii_case("STARTTLS"))
+            });
+            if !offered {
+                return Ok((greeting, false, false));
+            }
+            send(reader, "STARTTLS").await?;
+            let reply = read_reply(reader, smtp_last).await?;
+            Ok((
+                greeting,
+                true,
+                reply.last().is_some_and(|line| line.starts_with("220")),
+            ))
+        }
+        ServiceProtocol::Imap => {
+            let greeting = read_line(reader).await?;
+            if !greeting.starts_with("* OK") && !greeting.starts_with("* PREAUTH") {
+                return Err(unexpected(&greeting));
+            }
+            send(reader, "a1 CAPABILITY").await?;
+            let capabilities = read_reply(reader, |line| line.starts_with("a1 ")).await?;
+            let offered = capabilities.iter().any(|line| {
+                line.starts_with("* CAPABILITY")
+                    && line
+                        .split_whitespace()
+                        .any(|capability| capability.eq_ignore_ascii_case("STARTTLS"))
+            });
+            if !offered {
+                return Ok((greeting, false, false));
+            }
+            send(reader, "a2 STARTTLS").await?;
+            let reply = read_reply(reader, |line| line.starts_with("a2 ")).await?;
+            Ok((
+                greeting,
+                true,
+                reply.last().is_some_and(|line| line.starts_with("a2 OK")),
+            ))
+        }
+        _ => {
+            let greeting = read_line(reader).await?;
+            if !greeting.starts_with("+OK") {
+                return Err(unexpected(&greeting));
+            }
+            send(reader, "CAPA").await?;
+            let first = read_line(reader).await?;
+            // Servers too old to know CAPA don't know STLS either
+            if !first.starts_with("+OK") {
+                return Ok((greeting, false, false));
+            }
+            let capabilities = read_reply(reader, |line| line == ".").await?;
+            let offered = capabilities
+                .iter()
+                .any(|line| line.eq_ignore_ascii_case("STLS"));
+            if !offered {
+                return Ok((greeting, false, false));
+            }
+            send(reader, "STLS").await?;
+            let reply = read_line(reader).await?;
+            Ok((greeting, true, reply.starts_with("+OK")))
+        }
+    }
+}
+
+/// Greets the server, looks for STARTTLS among its capabilities and, if
+/// it's there, upgrades the connection to see that it works.
+async fn probe(
+    target: SocketAddr,
+    server_name: Option<String>,
+    protocol: ServiceProtocol,
+) -> io::Result<StarttlsSummary> {
+    usage::socket_opened();
+    let stream = TcpStream::connect(target).await?;
+    let mut reader = BufReader::new(stream);
+    let (greeting, offered, agreed) = negotiate(&mut reader, protocol).await?;
+    let mut summary = StarttlsSummary {
+        protocol: protocol.name().to_string(),
+        greeting,
+        offered,
+        tls_version: None,
+    };
+    if !agreed {
+        return Ok(summary);
+    }
+    // Anything sent ahead of the handshake would be taken as plaintext
+    // slipped into the TLS session
+    if !reader.buffer().is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "data sent ahead of the TLS handshake",
+        ));
+    }
+    let name = tls::server_name_for(target, server_name.as_deref())?;
+    match tls::upgrade(reader.into_inner(), name, None).await {
+        Ok(stream) => {
+            summary.tls_version = stream
+                .get_ref()
+                .1
+                .protocol_version()
+                .and_then(|version| version.as_str())
+                .map(|version| version.replace("TLSv1_", "TLS 1."));
+        }
+        Err(e) => print_to_terminal(
+            format!("{} - STARTTLS handshake failed: {}", target, e),
+            VerbosityLevel::WARN,
+        ),
+    }
+    Ok(summary)
+}
+
+/// The mail protocol a port speaks, going by hints, well-known ports and
+/// what `--detect-services` found.
+fn mail_protocol(settings: &ScanSettings, scan_result: &ScanResult) -> Option<ServiceProtocol> {
+    let hinted = settings
+        .hints
+        .protocol_for(scan_result.ip.port())
+        .filter(|protocol| {
+            matches!(
+                protocol,
+                ServiceProtocol::Smtp | ServiceProtocol::Imap | ServiceProtocol::Pop3
+            )
+        });
+    hinted.or_else(|| match scan_result.service.as_ref()?.name.as_str() {
+        "smtp" => Some(ServiceProtocol::Smtp),
+        "imap" => Some(ServiceProtocol::Imap),
+        "pop3" => Some(ServiceProtocol::Pop3),
+        _ => None,
+    })
+}
+
+/// Checks every open SMTP, IMAP and POP3 port in `results` for STARTTLS,
+/// upgrading where it's offered, and warns about servers that don't offer
+/// it or fail the upgrade. At most `max_enrichment_probes` servers are
+/// checked at once.
+pub async fn probe_starttls(settings: &Arc<ScanSettings>, results: &mut [ScanResult]) {
+    let limits = settings.config.limits;
+    let slots = Arc::new(Semaphore::new(limits.max_enrichment_probes.max(1)));
+    let mut set: JoinSet<(SocketAddr, Result<io::Result<StarttlsSummary>, String>)> =
+        JoinSet::new();
+    for scan_result in results
+        .iter()
+        .filter(|scan_result| scan_result.status == ConnectionStatus::Open)
+    {
+        let Some(protocol) = mail_protocol(settings, scan_result) else {
+            continue;
+        };
+        let target = scan_result.ip;
+        let server_name = scan_result.hostname.clone();
+        let slots = slots.clone();
+        set.spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            let summary = sandbox::run_isolated(limits.max_probe_time, async move {
+                match timeout(DIALOGUE_WAIT, probe(target, server_name, protocol)).await {
+                    Ok(summary) => summary,
+                    Err(_) => Err(io::Error::from(ErrorKind::TimedOut)),
+                }
+            })
+            .await;
+            (target, summary)
+        });
+    }
+    if set.is_empty() {
+        return;
+    }
+    print_to_terminal(
+        String::from("Checking mail servers for STARTTLS"),
+        VerbosityLevel::INFO,
+    );
+
+    let mut summaries: HashMap<SocketAddr, StarttlsSummary> = HashMap::new();
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok((target, Ok(Ok(summary)))) => {
+                let level = if summary.tls_version.is_some() {
+                    VerbosityLevel::INFO
+                } else {
+                    VerbosityLevel::WARN
+                };
+                print_to_terminal(format!("{} - {}", target, summary.describe()), level);
+                summaries.insert(target, summary);
+            }
+            Ok((target, Ok(Err(e)))) => print_to_terminal(
+                format!("{} - STARTTLS check failed: {}", target, e),
+                VerbosityLevel::WARN,
+            ),
+            Ok((target, Err(problem))) => {
+                print_to_terminal(format!("{} - {}", target, problem), VerbosityLevel::WARN)
+            }
+            Err(e) => {
+                print_to_terminal(
+                    format!("An error has occured: {}", e),
+                    VerbosityLevel::ERROR,
+                );
+            }
+        }
+    }
+    for scan_result in results.iter_mut() {
+        if let Some(summary) = summaries.remove(&scan_result.ip) {
+            scan_result.starttls = Some(summary);
+        }
+    }
+}
@@ -0,0 +1,434 @@
+use crate::cli::ScanType;
+use crate::ping::{checksum, from_socket_address, socket_address};
+use crate::traceroute::set_option;
+use crate::{ConnectionStatus, ErrorCodes, error_handler, os_guess, usage};
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::unix::AsyncFd;
+use tokio::sync::oneshot;
+use tokio::time::{Duration, Instant, timeout};
+
+const FLAG_FIN: u8 = 0x01;
+const FLAG_SYN: u8 = 0x02;
+const FLAG_RST: u8 = 0x04;
+const FLAG_PSH: u8 = 0x08;
+const FLAG_ACK: u8 = 0x10;
+const FLAG_URG: u8 = 0x20;
+/// Local ports the probes are sent from, kept clear of the usual ephemeral
+/// range so replies don't land on a real connection
+const LOCAL_PORTS: std::ops::Range<u16> = 20000..32000;
+
+/// What a probe waits on: the target, the local port its segment went out
+/// from, and the acknowledgment number a reply to it carries.
+type Pending = Mutex<HashMap<(SocketAddr, u16), (u32, oneshot::Sender<Reply>)>>;
+
+/// The parts of a SYN/ACK or RST a probe looks at.
+struct Reply {
+    flags: u8,
+    /// The TTL, or hop limit, the reply arrived with
+    ttl: Option<u8>,
+    window: u16,
+}
+
+/// A raw TCP socket of one address family. Every TCP segment the host
+/// receives is delivered to it, so replies are matched up with probes by
+/// address, port and acknowledgment number.
+struct RawTcpSocket {
+    fd: AsyncFd<OwnedFd>,
+    v6: bool,
+}
+
+impl RawTcpSocket {
+    fn open(v6: bool) -> Option<RawTcpSocket> {
+        let domain = if v6 { libc::AF_INET6 } else { libc::AF_INET };
+        let fd = unsafe {
+            libc::socket(
+                domain,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::IPPROTO_TCP,
+            )
+        };
+        if fd < 0 {
+            return None;
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        // Raw IPv6 sockets get no header, so the hop limit has to be asked
+        // for alongside each segment
+        if v6 {
+            set_option(
+                fd.as_raw_fd(),
+                libc::IPPROTO_IPV6,
+                libc::IPV6_RECVHOPLIMIT,
+                1,
+            )
+            .ok()?;
+        }
+        AsyncFd::new(fd).ok().map(|fd| RawTcpSocket { fd, v6 })
+    }
+
+    /// Sends `segment` to `target` from `source`.
+    async fn send(&self, target: IpAddr, source: IpAddr, segment: &[u8]) -> io::Result<()> {
+        loop {
+            let mut guard = self.fd.writable().await?;
+            match guard.try_io(|fd| send_segment(fd.as_raw_fd(), target, source, segment)) {
+                Ok(result) => return result,
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    /// Waits for the next segment and returns who sent it, where in
+    /// `buffer` the segment lies without the IP header, and the TTL it
+    /// arrived with.
+    async fn receive(&self, buffer: &mut [u8]) -> io::Result<(IpAddr, usize, usize, Option<u8>)> {
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let mut hop_limit = None;
+            let received = guard.try_io(|fd| {
+                let mut address: libc::sockaddr_storage = unsafe { mem::zeroed() };
+                let mut iov = libc::iovec {
+                    iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+                    iov_len: buffer.len(),
+                };
+                let mut control = [0u64; 8];
+                let mut message: libc::msghdr = unsafe { mem::zeroed() };
+                message.msg_name = &mut address as *mut libc::sockaddr_storage as *mut libc::c_void;
+                message.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+                message.msg_iov = &mut iov;
+                message.msg_iovlen = 1;
+                message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+                message.msg_controllen = mem::size_of_val(&control);
+                let read = unsafe { libc::recvmsg(fd.as_raw_fd(), &mut message, 0) };
+                if read < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                unsafe {
+                    let mut header = libc::CMSG_FIRSTHDR(&message);
+                    while !header.is_null() {
+                        if (*header).cmsg_level == libc::IPPROTO_IPV6
+                            && (*header).cmsg_type == libc::IPV6_HOPLIMIT
+                        {
+                            let limit = std::ptr::read_unaligned(
+                                libc::CMSG_DATA(header) as *const libc::c_int
+                            );
+                            hop_limit = u8::try_from(limit).ok();
+                        }
+                        header = libc::CMSG_NXTHDR(&message, header);
+                    }
+                }
+                Ok((read as usize, address))
+            });
+            let (read, address) = match received {
+                Ok(result) => result?,
+                Err(_would_block) => continue,
+            };
+            let Some(ip) = from_socket_address(&address) else {
+                continue;
+            };
+            // Raw IPv4 sockets get the IP header too
+            let (start, ttl) = if self.v6 {
+                (0, hop_limit)
+            } else {
+                ((buffer[0] & 0x0f) as usize * 4, Some(buffer[8]))
+            };
+            if read >= start + 20 {
+                return Ok((ip, start, read, ttl));
+            }
+        }
+    }
+
+    /// Hands every SYN/ACK or RST to the probe it answers.
+    async fn listen(self: Arc<RawTcpSocket>, pending: Arc<Pending>) {
+        let mut buffer = [0u8; 1500];
+        while let Ok((ip, start, end, ttl)) = self.receive(&mut buffer).await {
+            let segment = &buffer[start..end];
+            let remote_port = u16::from_be_bytes([segment[0], segment[1]]);
+            let local_port = u16::from_be_bytes([segment[2], segment[3]]);
+            let acknowledged =
+                u32::from_be_bytes([segment[8], segment[9], segment[10], segment[11]]);
+            let flags = segment[13];
+            let window = u16::from_be_bytes([segment[14], segment[15]]);
+            if flags & (FLAG_ACK | FLAG_RST) == 0 {
+                continue;
+            }
+            let key = (SocketAddr::new(ip, remote_port), local_port);
+            let mut pending = pending.lock().unwrap();
+            if pending
+                .get(&key)
+                .is_some_and(|(expected, _)| acknowledged == *expected)
+                && let Some((_, reply)) = pending.remove(&key)
+            {
+                usage::received(segment.len());
+                let _ = reply.send(Reply { flags, ttl, window });
+            }
+        }
+    }
+}
+
+/// Sends one segment, with the source set explicitly since the segment's
+/// checksum was computed over it.
+fn send_segment(fd: RawFd, target: IpAddr, source: IpAddr, segment: &[u8]) -> io::Result<()> {
+    let (mut address, length) = socket_address(target);
+    let mut iov = libc::iovec {
+        iov_base: segment.as_ptr() as *mut libc::c_void,
+        iov_len: segment.len(),
+    };
+    // Large enough for either family's packet info
+    let mut control = [0u64; 8];
+    let mut message: libc::msghdr = unsafe { mem::zeroed() };
+    message.msg_name = &mut address as *mut libc::sockaddr_storage as *mut libc::c_void;
+    message.msg_namelen = length;
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    unsafe {
+        match source {
+            IpAddr::V4(ipv4) => {
+                let info = libc::in_pktinfo {
+                    ipi_ifindex: 0,
+                    ipi_spec_dst: libc::in_addr {
+                        s_addr: u32::from_ne_bytes(ipv4.octets()),
+                    },
+                    ipi_addr: libc::in_addr { s_addr: 0 },
+                };
+                message.msg_controllen =
+                    libc::CMSG_SPACE(mem::size_of::<libc::in_pktinfo>() as u32) as usize;
+                let header = libc::CMSG_FIRSTHDR(&message);
+                (*header).cmsg_level = libc::IPPROTO_IP;
+                (*header).cmsg_type = libc::IP_PKTINFO;
+                (*header).cmsg_len =
+                    libc::CMSG_LEN(mem::size_of::<libc::in_pktinfo>() as u32) as usize;
+                std::ptr::write_unaligned(libc::CMSG_DATA(header) as *mut libc::in_pktinfo, info);
+            }
+            IpAddr::V6(ipv6) => {
+                let info = libc::in6_pktinfo {
+                    ipi6_addr: libc::in6_addr {
+                        s6_addr: ipv6.octets(),
+                    },
+                    ipi6_ifindex: 0,
+                };
+                message.msg_controllen =
+                    libc::CMSG_SPACE(mem::size_of::<libc::in6_pktinfo>() as u32) as usize;
+                let header = libc::CMSG_FIRSTHDR(&message);
+                (*header).cmsg_level = libc::IPPROTO_IPV6;
+                (*header).cmsg_type = libc::IPV6_PKTINFO;
+                (*header).cmsg_len =
+                    libc::CMSG_LEN(mem::size_of::<libc::in6_pktinfo>() as u32) as usize;
+                std::ptr::write_unaligned(libc::CMSG_DATA(header) as *mut libc::in6_pktinfo, info);
+            }
+        }
+    }
+    if unsafe { libc::sendmsg(fd, &message, 0) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// The flags a probe of `kind` is sent with.
+fn probe_flags(kind: ScanType) -> u8 {
+    match kind {
+        ScanType::Connect | ScanType::Syn => FLAG_SYN,
+        ScanType::Fin => FLAG_FIN,
+        ScanType::Null => 0,
+        ScanType::Xmas => FLAG_FIN | FLAG_PSH | FLAG_URG,
+    }
+}
+
+/// A TCP segment with `flags` set, checksummed over the pseudo header of
+/// `source` and `target`. SYNs carry an MSS option as a real one would.
+fn probe_segment(
+    source: IpAddr,
+    target: SocketAddr,
+    local_port: u16,
+    sequence: u32,
+    flags: u8,
+) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(24);
+    segment.extend_from_slice(&local_port.to_be_bytes());
+    segment.extend_from_slice(&target.port().to_be_bytes());
+    segment.extend_from_slice(&sequence.to_be_bytes());
+    segment.extend_from_slice(&0u32.to_be_bytes());
+    if flags & FLAG_SYN != 0 {
+        // Data offset of six words, for the one option
+        segment.extend_from_slice(&[6 << 4, flags]);
+    } else {
+        segment.extend_from_slice(&[5 << 4, flags]);
+    }
+    segment.extend_from_slice(&64240u16.to_be_bytes());
+    segment.extend_from_slice(&[0, 0, 0, 0]);
+    if flags & FLAG_SYN != 0 {
+        segment.extend_from_slice(&[2, 4, 0x05, 0xb4]);
+    }
+
+    let mut pseudo = match (source, target.ip()) {
+        (IpAddr::V4(from), IpAddr::V4(to)) => {
+            let mut pseudo = from.octets().to_vec();
+            pseudo.extend_from_slice(&to.octets());
+            pseudo.extend_from_slice(&[0, libc::IPPROTO_TCP as u8]);
+            pseudo.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+            pseudo
+        }
+        (from, to) => {
+            let mut pseudo = to_ipv6(from).octets().to_vec();
+            pseudo.extend_from_slice(&to_ipv6(to).octets());
+            pseudo.extend_from_slice(&(segment.len() as u32).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, libc::IPPROTO_TCP as u8]);
+            pseudo
+        }
+    };
+    pseudo.extend_from_slice(&segment);
+    let checksum = checksum(&pseudo);
+    segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+    segment
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ipv4) => ipv4.to_ipv6_mapped(),
+        IpAddr::V6(ipv6) => ipv6,
+    }
+}
+
+/// The address the kernel would send from to reach `target`, found by
+/// connecting a UDP socket, which sends nothing.
+fn local_address(target: SocketAddr, source: Option<IpAddr>) -> io::Result<IpAddr> {
+    let local = source.unwrap_or(if target.is_ipv4() {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    } else {
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    });
+    let socket = UdpSocket::bind(SocketAddr::new(local, 0))?;
+    socket.connect(target)?;
+    Ok(socket.local_addr()?.ip())
+}
+
+/// The raw sockets for a SYN scan, or the FIN, NULL or Xmas scans of
+/// `--scan-type`. They're opened before privileges are dropped, since raw
+/// sockets need root or `CAP_NET_RAW`.
+pub struct SynScanner {
+    kind: ScanType,
+    v4: Option<Arc<RawTcpSocket>>,
+    v6: Option<Arc<RawTcpSocket>>,
+    pending: Arc<Pending>,
+    next_port: AtomicU16,
+    random: RandomState,
+}
+
+impl SynScanner {
+    pub fn open(kind: ScanType) -> SynScanner {
+        let scanner = SynScanner {
+            kind,
+            v4: RawTcpSocket::open(false).map(Arc::new),
+            v6: RawTcpSocket::open(true).map(Arc::new),
+            pending: Arc::default(),
+            next_port: AtomicU16::new(LOCAL_PORTS.start),
+            random: RandomState::new(),
+        };
+        if scanner.v4.is_none() && scanner.v6.is_none() {
+            error_handler(ErrorCodes::SYN_UNAVAILABLE, line!(), None);
+        }
+        for socket in [&scanner.v4, &scanner.v6].into_iter().flatten() {
+            tokio::spawn(socket.clone().listen(scanner.pending.clone()));
+        }
+        scanner
+    }
+
+    /// A local port not in use by another probe of `target`, and a
+    /// sequence number that can't be guessed to forge a reply.
+    fn allocate(&self, target: SocketAddr) -> (u16, u32) {
+        let span = LOCAL_PORTS.end - LOCAL_PORTS.start;
+        let pending = self.pending.lock().unwrap();
+        let port = loop {
+            let port = LOCAL_PORTS.start + self.next_port.fetch_add(1, Ordering::Relaxed) % span;
+            if !pending.contains_key(&(target, port)) {
+                break port;
+            }
+        };
+        let mut hasher = self.random.build_hasher();
+        hasher.write(target.to_string().as_bytes());
+        hasher.write_u16(port);
+        (port, hasher.finish() as u32)
+    }
+
+    /// Sends a SYN and waits for the answer. A SYN/ACK means open and an
+    /// RST refused. The handshake is never completed: the kernel, knowing
+    /// nothing of the probe, resets the connection itself. Silence, which
+    /// includes an ICMP unreachable since raw TCP sockets don't see those,
+    /// is a timeout. One retransmission is sent halfway through `wait`.
+    /// Whatever answered also gets its OS family guessed from the reply.
+    ///
+    /// FIN, NULL and Xmas probes lean on RFC 793 instead: a closed port
+    /// answers a segment outside any connection with an RST, and an open
+    /// one drops it. Silence is open|filtered, as a firewall dropping the
+    /// probe looks the same.
+    pub async fn probe(
+        &self,
+        target: SocketAddr,
+        source: Option<IpAddr>,
+        wait: Duration,
+    ) -> (ConnectionStatus, Duration, Option<String>) {
+        let socket = if target.is_ipv4() { &self.v4 } else { &self.v6 };
+        let Some(socket) = socket else {
+            return (ConnectionStatus::Unreachable, Duration::ZERO, None);
+        };
+        let source = match local_address(target, source) {
+            Ok(source) => source,
+            Err(_) => return (ConnectionStatus::Unreachable, Duration::ZERO, None),
+        };
+
+        let (local_port, sequence) = self.allocate(target);
+        let (reply, mut replied) = oneshot::channel();
+        let key = (target, local_port);
+        let flags = probe_flags(self.kind);
+        // SYN and FIN each take up a sequence number, so a reply
+        // acknowledges one past them
+        let expected = sequence.wrapping_add((flags & (FLAG_SYN | FLAG_FIN) != 0) as u32);
+        self.pending.lock().unwrap().insert(key, (expected, reply));
+        let segment = probe_segment(source, target, local_port, sequence, flags);
+
+        let started = Instant::now();
+        let mut status = if flags & FLAG_SYN != 0 {
+            ConnectionStatus::Timeout
+        } else {
+            ConnectionStatus::OpenFiltered
+        };
+        let mut os_guess = None;
+        for _attempt in 0..2 {
+            if let Err(e) = socket.send(target.ip(), source, &segment).await {
+                if matches!(
+                    e.kind(),
+                    ErrorKind::HostUnreachable | ErrorKind::NetworkUnreachable
+                ) {
+                    status = ConnectionStatus::Unreachable;
+                }
+                break;
+            }
+            usage::sent(segment.len());
+            if let Ok(Ok(reply)) = timeout(wait / 2, &mut replied).await {
+                let reset = reply.flags & FLAG_RST != 0;
+                status = if reset {
+                    ConnectionStatus::Refused
+                } else if reply.flags & FLAG_SYN != 0 {
+                    ConnectionStatus::Open
+                } else {
+                    ConnectionStatus::Timeout
+                };
+                os_guess = reply
+                    .ttl
+                    .and_then(|ttl| os_guess::guess(ttl, (!reset).then_some(reply.window)));
+                break;
+            }
+        }
+        let latency = started.elapsed();
+        self.pending.lock().unwrap().remove(&key);
+        (status, latency, os_guess)
+    }
+}
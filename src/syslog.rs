@@ -0,0 +1,156 @@
+use crate::logging::RESULTS_TARGET;
+use clap::ValueEnum;
+use std::fmt;
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use std::time::SystemTime;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// Where the local syslog daemon listens on Linux and on macOS.
+pub const SYSLOG_SOCKETS: [&str; 2] = ["/dev/log", "/var/run/syslog"];
+/// Structured data IDs need an enterprise number; this is the one set aside
+/// for documentation and examples.
+const STRUCTURED_DATA_ID: &str = "result@32473";
+const APP_NAME: &str = "connection-tester";
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum Facility {
+    User,
+    Daemon,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl Facility {
+    fn code(self) -> u8 {
+        match self {
+            Facility::User => 1,
+            Facility::Daemon => 3,
+            Facility::Local0 => 16,
+            Facility::Local1 => 17,
+            Facility::Local2 => 18,
+            Facility::Local3 => 19,
+            Facility::Local4 => 20,
+            Facility::Local5 => 21,
+            Facility::Local6 => 22,
+            Facility::Local7 => 23,
+        }
+    }
+}
+
+/// Sends results and errors to the local syslog daemon as RFC 5424
+/// messages. A result's address, port and status go in its structured data,
+/// so the log pipeline doesn't have to parse them out of the text.
+pub struct SyslogLayer {
+    socket: UnixDatagram,
+    facility: Facility,
+    hostname: String,
+}
+
+impl SyslogLayer {
+    /// Connects to the first syslog socket that's there, if any is.
+    pub fn connect(facility: Facility) -> Option<SyslogLayer> {
+        let socket = UnixDatagram::unbound().ok()?;
+        if !SYSLOG_SOCKETS
+            .iter()
+            .any(|path| socket.connect(path).is_ok())
+        {
+            return None;
+        }
+
+        Some(SyslogLayer {
+            socket,
+            facility,
+            hostname: hostname(),
+        })
+    }
+}
+
+impl<S: Subscriber> Layer<S> for SyslogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let is_result = metadata.target() == RESULTS_TARGET;
+        let level = *metadata.level();
+        if !is_result && level != Level::ERROR {
+            return;
+        }
+
+        let mut fields = FieldVisitor::default();
+        event.record(&mut fields);
+        let severity = match level {
+            Level::ERROR => 3,
+            Level::WARN => 4,
+            Level::INFO => 6,
+            _ => 7,
+        };
+        let structured_data = if fields.params.is_empty() {
+            String::from("-")
+        } else {
+            format!("[{}{}]", STRUCTURED_DATA_ID, fields.params)
+        };
+
+        let message = format!(
+            "<{}>1 {} {} {} {} {} {} {}",
+            self.facility.code() * 8 + severity,
+            humantime::format_rfc3339_micros(SystemTime::now()),
+            self.hostname,
+            APP_NAME,
+            process::id(),
+            if is_result { "result" } else { "error" },
+            structured_data,
+            fields.message
+        );
+        // Losing a log line shouldn't stop the scan
+        let _ = self.socket.send(message.as_bytes());
+    }
+}
+
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    /// Every other field, already written as ` name="value"` parameters
+    params: String,
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+            return;
+        }
+        let value = value
+            .replace("\\", "\\\\")
+            .replace("\"", "\\\"")
+            .replace("]", "\\]");
+        self.params
+            .push_str(&format!(" {}=\"{}\"", field.name(), value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value));
+    }
+}
+
+fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    // gethostname writes at most the buffer's length into it
+    if unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) } != 0 {
+        return String::from("-");
+    }
+    let end = buffer
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(buffer.len());
+    match String::from_utf8_lossy(&buffer[..end]).trim() {
+        "" => String::from("-"),
+        name => name.to_string(),
+    }
+}
@@ -0,0 +1,445 @@
+use crate::dns_cache::{self, DnsCache};
+use crate::sources;
+use crate::{
+    ErrorCodes, VerbosityLevel, build_valid_network_configuration, error_handler, print_to_terminal,
+};
+use cidr::IpCidr;
+use hickory_resolver::TokioResolver;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::LazyLock;
+use std::time::SystemTime;
+use tokio::net::lookup_host;
+use tokio::task::JoinSet;
+
+/// The widest IPv6 network that can be scanned, 65536 addresses. Wider ones
+/// would take longer to walk address by address than anyone would wait.
+const MIN_IPV6_PREFIX: u8 = 112;
+/// The most names a single brace range like `{1..100}` may stand for.
+const MAX_BRACE_RANGE: u64 = 65536;
+/// The most names a whole brace pattern may stand for, with its groups
+/// multiplied out.
+const MAX_BRACE_EXPANSIONS: usize = 65536;
+
+static HOSTNAME_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[A-Za-z0-9.-]*[A-Za-z][A-Za-z0-9.-]*$").unwrap());
+
+pub enum Target {
+    Network(IpCidr),
+    Hostname(String),
+}
+
+/// Expands shell-style brace patterns into every name they describe.
+///
+/// `web{01..03}.example.com` gives `web01`, `web02` and `web03`, keeping the
+/// zero padding of the range. `db-{a,b}.internal` gives one name per
+/// alternative. Several groups multiply out, but groups can't be nested.
+pub fn expand_braces(pattern: &str) -> Vec<String> {
+    let Some(open) = pattern.find("{") else {
+        if pattern.contains("}") {
+            error_handler(
+                ErrorCodes::INVALID_VARIABLE,
+                line!(),
+                Some("target pattern"),
+            );
+        }
+        return vec![pattern.to_string()];
+    };
+    let close = match pattern[open..].find("}") {
+        Some(close_offset) => open + close_offset,
+        None => error_handler(
+            ErrorCodes::INVALID_VARIABLE,
+            line!(),
+            Some("target pattern"),
+        ),
+    };
+
+    let prefix = &pattern[..open];
+    if prefix.contains("}") {
+        error_handler(
+            ErrorCodes::INVALID_VARIABLE,
+            line!(),
+            Some("target pattern"),
+        );
+    }
+
+    let alternatives = expand_brace_group(&pattern[open + 1..close]);
+    let suffixes = expand_braces(&pattern[close + 1..]);
+    if alternatives
+        .len()
+        .checked_mul(suffixes.len())
+        .is_none_or(|expansions| expansions > MAX_BRACE_EXPANSIONS)
+    {
+        error_handler(
+            ErrorCodes::INVALID_VARIABLE,
+            line!(),
+            Some("target pattern"),
+        );
+    }
+
+    let mut expanded: Vec<String> = Vec::new();
+    for alternative in &alternatives {
+        for suffix in &suffixes {
+            expanded.push(format!("{}{}{}", prefix, alternative, suffix));
+        }
+    }
+    expanded
+}
+
+fn expand_brace_group(group: &str) -> Vec<String> {
+    if let Some((start, end)) = group.split_once("..") {
+        if let (Ok(start_number), Ok(end_number)) = (start.parse::<u64>(), end.parse::<u64>()) {
+            if start_number.abs_diff(end_number) >= MAX_BRACE_RANGE {
+                error_handler(
+                    ErrorCodes::INVALID_VARIABLE,
+                    line!(),
+                    Some("target pattern"),
+                );
+            }
+            let padded = (start.len() > 1 && start.starts_with("0"))
+                || (end.len() > 1 && end.starts_with("0"));
+            let width = if padded {
+                start.len().max(end.len())
+            } else {
+                0
+            };
+            let numbers: Vec<u64> = if start_number <= end_number {
+                (start_number..=end_number).collect()
+            } else {
+                (end_number..=start_number).rev().collect()
+            };
+            return numbers
+                .iter()
+                .map(|number| format!("{:0width$}", number, width = width))
+                .collect();
+        }
+
+        let mut start_chars = start.chars();
+        let mut end_chars = end.chars();
+        if let (Some(start_char), None, Some(end_char), None) = (
+            start_chars.next(),
+            start_chars.next(),
+            end_chars.next(),
+            end_chars.next(),
+        ) && start_char.is_ascii_alphabetic()
+            && end_char.is_ascii_alphabetic()
+        {
+            let letters: Vec<char> = if start_char <= end_char {
+                (start_char..=end_char).collect()
+            } else {
+                (end_char..=start_char).rev().collect()
+            };
+            return letters.iter().map(|letter| letter.to_string()).collect();
+        }
+    } else if group.contains(",") {
+        return group.split(",").map(String::from).collect();
+    }
+
+    error_handler(
+        ErrorCodes::INVALID_VARIABLE,
+        line!(),
+        Some("target pattern"),
+    );
+}
+
+/// Parses one target. Internationalized hostnames are converted to their
+/// punycode form here, which is what gets resolved and kept from then on.
+pub fn parse_target(target: &str) -> Target {
+    if IpCidr::from_str(target.trim()).is_err()
+        && let Some(hostname) = ascii_hostname(target)
+    {
+        return Target::Hostname(hostname);
+    }
+
+    let network = match target.split_once("/") {
+        Some((network_id, network_cidr)) => {
+            build_valid_network_configuration(network_id.to_string(), network_cidr.to_string())
+        }
+        None => build_valid_network_configuration(target.to_string(), String::new()),
+    };
+    if let IpCidr::V6(v6_network) = network
+        && v6_network.network_length() < MIN_IPV6_PREFIX
+    {
+        error_handler(ErrorCodes::NETWORK_TOO_LARGE, line!(), Some(target.trim()));
+    }
+    Target::Network(network)
+}
+
+/// The Unicode form of an internationalized hostname, if `hostname` is one.
+pub fn unicode_form(hostname: &str) -> Option<String> {
+    if !hostname
+        .split(".")
+        .any(|label| label.to_ascii_lowercase().starts_with("xn--"))
+    {
+        return None;
+    }
+    match idna::domain_to_unicode(hostname) {
+        (unicode, Ok(())) => Some(unicode),
+        _ => None,
+    }
+}
+
+/// A hostname as it's shown to the user, with an internationalized name in
+/// both forms, e.g. `bücher.example (xn--bcher-kva.example)`.
+pub fn display_hostname(hostname: &str) -> String {
+    match unicode_form(hostname) {
+        Some(unicode) => format!("{} ({})", unicode, hostname),
+        None => hostname.to_string(),
+    }
+}
+
+/// `target` as a hostname in its punycode form, if it is a valid one.
+fn ascii_hostname(target: &str) -> Option<String> {
+    let hostname = if target.trim().is_ascii() {
+        Some(target.trim().to_string())
+    } else {
+        idna::domain_to_ascii(target.trim()).ok()
+    }?;
+    HOSTNAME_PATTERN.is_match(&hostname).then_some(hostname)
+}
+
+/// Splits a `HOST:PORT` or `[V6]:PORT` endpoint, with an internationalized
+/// hostname converted to its punycode form as targets are.
+pub fn split_endpoint(endpoint: &str) -> Option<(String, u16)> {
+    if let Ok(address) = endpoint.trim().parse::<SocketAddr>() {
+        return (address.port() != 0).then(|| (address.ip().to_string(), address.port()));
+    }
+    let (host, port) = endpoint.trim().rsplit_once(":")?;
+    let port = port.parse::<u16>().ok().filter(|port| *port != 0)?;
+    // Addresses with valid ports have already parsed, and a bare IPv6
+    // address needs brackets to take one
+    if host.parse::<IpAddr>().is_ok() {
+        return None;
+    }
+    Some((ascii_hostname(host)?, port))
+}
+
+/// Resolves a single `HOST:PORT` endpoint to the first address it names.
+pub async fn resolve_endpoint(endpoint: &str) -> SocketAddr {
+    let Some((host, port)) = split_endpoint(endpoint) else {
+        error_handler(ErrorCodes::ENDPOINT_INVALID, line!(), Some(endpoint));
+    };
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return SocketAddr::new(ip, port);
+    }
+    match lookup_host((host.as_str(), port)).await {
+        Ok(mut addresses) => match addresses.next() {
+            Some(address) => address,
+            None => error_handler(ErrorCodes::ENDPOINT_INVALID, line!(), Some(endpoint)),
+        },
+        Err(_) => error_handler(ErrorCodes::ENDPOINT_INVALID, line!(), Some(endpoint)),
+    }
+}
+
+/// Separates the hostnames in the user's target list from everything else,
+/// after expanding brace patterns. Hostnames are deduplicated.
+pub fn split_hostnames(targets: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut others: Vec<String> = Vec::new();
+    let mut hostnames: Vec<String> = Vec::new();
+
+    for pattern in targets {
+        for target in expand_braces(pattern) {
+            match parse_target(&target) {
+                Target::Hostname(hostname) => {
+                    if !hostnames.contains(&hostname) {
+                        hostnames.push(hostname);
+                    }
+                }
+                Target::Network(_) => others.push(target),
+            }
+        }
+    }
+    (others, hostnames)
+}
+
+/// The addresses a scan covers, with networks kept as networks rather than
+/// expanded, so that even a /8 costs next to no memory until its addresses
+/// are handed out one by one.
+#[derive(Default)]
+pub struct AddressSet {
+    networks: Vec<IpCidr>,
+    /// Addresses given on their own or resolved from hostnames
+    addresses: Vec<IpAddr>,
+}
+
+impl AddressSet {
+    pub fn from_addresses(addresses: Vec<IpAddr>) -> AddressSet {
+        AddressSet {
+            networks: Vec::new(),
+            addresses,
+        }
+    }
+
+    /// Every address once, in the order the networks were given and then
+    /// the other addresses, each only where nothing before it covered it.
+    pub fn iter(&self) -> impl Iterator<Item = IpAddr> + '_ {
+        let networks = self
+            .networks
+            .iter()
+            .enumerate()
+            .flat_map(move |(index, network)| {
+                network.iter().map(|ip| ip.address()).filter(move |ip| {
+                    !self.networks[..index]
+                        .iter()
+                        .any(|earlier| earlier.contains(ip))
+                })
+            });
+        let addresses = self
+            .addresses
+            .iter()
+            .copied()
+            .filter(|ip| !self.networks.iter().any(|network| network.contains(ip)));
+        networks.chain(addresses)
+    }
+
+    fn push(&mut self, ip: IpAddr, seen: &mut HashSet<IpAddr>) {
+        if seen.insert(ip) {
+            self.addresses.push(ip);
+        }
+    }
+}
+
+/// Turns the user's target list into the addresses to scan, reading target
+/// sources, expanding brace patterns and resolving hostnames along the way,
+/// along with the hostname each address was resolved from, the first one
+/// given if several resolve to it. Networks are left unexpanded until
+/// they're iterated over.
+pub async fn resolve_named_targets(
+    targets: &[String],
+    use_dns_cache: bool,
+) -> (AddressSet, HashMap<IpAddr, String>) {
+    let targets = sources::gather_targets(targets).await;
+    let mut addresses = AddressSet::default();
+    let mut names: HashMap<IpAddr, String> = HashMap::new();
+    let mut seen: HashSet<IpAddr> = HashSet::new();
+    let mut lookups: JoinSet<(String, Vec<IpAddr>, Option<SystemTime>)> = JoinSet::new();
+    let mut dns_cache = DnsCache::load(use_dns_cache);
+    let mut resolver: Option<Option<TokioResolver>> = None;
+
+    for pattern in &targets {
+        for target in expand_braces(pattern) {
+            match parse_target(&target) {
+                // Single addresses are kept apart, so long lists of them
+                // aren't each checked against one another
+                Target::Network(network) if network.is_host_address() => {
+                    addresses.push(network.first_address(), &mut seen);
+                }
+                Target::Network(network) => {
+                    if !addresses.networks.contains(&network) {
+                        addresses.networks.push(network);
+                    }
+                }
+                Target::Hostname(hostname) => {
+                    if let Some(cached) = dns_cache.get(&hostname) {
+                        print_to_terminal(
+                            format!("Using cached addresses for {}", display_hostname(&hostname)),
+                            VerbosityLevel::DEBUG,
+                        );
+                        for ip in cached {
+                            names.entry(ip).or_insert_with(|| hostname.clone());
+                            addresses.push(ip, &mut seen);
+                        }
+                        continue;
+                    }
+
+                    let resolver = resolver
+                        .get_or_insert_with(dns_cache::build_resolver)
+                        .clone();
+                    lookups.spawn(async move {
+                        let (resolved, expires_at) = dns_cache::resolve(resolver, &hostname).await;
+                        (hostname, resolved, expires_at)
+                    });
+                }
+            }
+        }
+    }
+
+    while let Some(res) = lookups.join_next().await {
+        match res {
+            Ok((hostname, resolved, _)) if resolved.is_empty() => print_to_terminal(
+                format!(
+                    "Could not resolve {}, skipping it",
+                    display_hostname(&hostname)
+                ),
+                VerbosityLevel::WARN,
+            ),
+            Ok((hostname, resolved, expires_at)) => {
+                if let Some(expires_at) = expires_at {
+                    dns_cache.insert(hostname.clone(), resolved.clone(), expires_at);
+                }
+                for ip in resolved {
+                    print_to_terminal(
+                        format!("Resolved {} to {}", display_hostname(&hostname), ip),
+                        VerbosityLevel::DEBUG,
+                    );
+                    names.entry(ip).or_insert_with(|| hostname.clone());
+                    addresses.push(ip, &mut seen);
+                }
+            }
+            Err(e) => print_to_terminal(
+                format!("An error has occured: {}", e),
+                VerbosityLevel::ERROR,
+            ),
+        }
+    }
+
+    dns_cache.save();
+    (addresses, names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoints_split_into_host_and_port() {
+        assert_eq!(
+            split_endpoint("example.com:443"),
+            Some((String::from("example.com"), 443))
+        );
+        assert_eq!(
+            split_endpoint("127.0.0.1:22"),
+            Some((String::from("127.0.0.1"), 22))
+        );
+        assert_eq!(
+            split_endpoint("[2001:db8::1]:8443"),
+            Some((String::from("2001:db8::1"), 8443))
+        );
+    }
+
+    #[test]
+    fn endpoint_hostnames_are_converted_to_punycode() {
+        assert_eq!(
+            split_endpoint("bücher.example:80"),
+            Some((String::from("xn--bcher-kva.example"), 80))
+        );
+    }
+
+    #[test]
+    fn invalid_endpoints_are_rejected() {
+        for endpoint in [
+            "example.com",
+            "example.com:0",
+            "example.com:http",
+            "2001:db8::1:80",
+            "bad_host:80",
+            ":80",
+        ] {
+            assert_eq!(split_endpoint(endpoint), None, "{}", endpoint);
+        }
+    }
+
+    #[tokio::test]
+    async fn address_endpoints_resolve_without_lookups() {
+        assert_eq!(
+            resolve_endpoint("[::1]:22").await,
+            "[::1]:22".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            resolve_endpoint("127.0.0.1:80").await,
+            "127.0.0.1:80".parse::<SocketAddr>().unwrap()
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use crate::units;
+use cidr::IpCidr;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// What a `--timeout-for` override applies to.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Scope {
+    Port(u16),
+    Network(IpCidr),
+}
+
+/// A timeout for the endpoints on one port or in one network, given to
+/// `--timeout-for` as `443=5s` or `10.0.0.0/24=1s`.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeoutOverride {
+    scope: Scope,
+    timeout: Duration,
+}
+
+impl TimeoutOverride {
+    pub fn parse(value: &str) -> Result<TimeoutOverride, String> {
+        let (scope, timeout) = value
+            .split_once('=')
+            .ok_or_else(|| String::from("expected PORT=DURATION or CIDR=DURATION, e.g. 443=5s"))?;
+        let scope = scope.trim();
+        let scope = match scope.parse::<u16>() {
+            Ok(port) if port > 0 => Scope::Port(port),
+            _ => match IpCidr::from_str(scope) {
+                Ok(network) => Scope::Network(network),
+                Err(_) => return Err(format!("{} is neither a port nor a CIDR", scope)),
+            },
+        };
+        Ok(TimeoutOverride {
+            scope,
+            timeout: units::parse_duration(timeout)?,
+        })
+    }
+
+    fn applies_to(&self, target: SocketAddr) -> bool {
+        match self.scope {
+            Scope::Port(port) => target.port() == port,
+            Scope::Network(network) => network.contains(&target.ip()),
+        }
+    }
+}
+
+impl TryFrom<String> for TimeoutOverride {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<TimeoutOverride, String> {
+        TimeoutOverride::parse(&value)
+    }
+}
+
+impl From<TimeoutOverride> for String {
+    fn from(timeout_override: TimeoutOverride) -> String {
+        let scope = match timeout_override.scope {
+            Scope::Port(port) => port.to_string(),
+            Scope::Network(network) => network.to_string(),
+        };
+        format!(
+            "{}={}",
+            scope,
+            units::format_duration(timeout_override.timeout)
+        )
+    }
+}
+
+/// The timeout for `target`: the longest of the overrides that apply to it,
+/// so a slow service on a slow network gets the more patient of the two, or
+/// `default` when none do.
+pub fn timeout_for(
+    overrides: &[TimeoutOverride],
+    target: SocketAddr,
+    default: Duration,
+) -> Duration {
+    overrides
+        .iter()
+        .filter(|timeout_override| timeout_override.applies_to(target))
+        .map(|timeout_override| timeout_override.timeout)
+        .max()
+        .unwrap_or(default)
+}
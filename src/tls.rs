@@ -0,0 +1,235 @@
+use crate::{ErrorCodes, error_handler, usage};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, ring, verify_tls12_signature, verify_tls13_signature};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{AlertDescription, ClientConfig, DigitallySignedStruct, SignatureScheme};
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::client::TlsStream;
+
+/// How long to wait after the handshake for the server to object to the
+/// client certificate. TLS 1.3 servers only do so once the client believes
+/// the handshake is already over.
+const CERTIFICATE_VERDICT_WAIT: Duration = Duration::from_millis(500);
+
+/// Accepts whatever certificate the server presents.
+///
+/// Probes only need to know whether a port speaks TLS, and the hosts being
+/// scanned are often addressed by IP or use internal CAs, so verifying the
+/// chain would turn most answers into failures. Signatures are still checked
+/// so the handshake itself is genuine.
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// A client certificate chain and its private key, presented to servers
+/// that ask for one.
+pub struct ClientIdentity {
+    certificates: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+}
+
+impl ClientIdentity {
+    /// Reads a PEM certificate chain and a PEM private key.
+    pub fn load(certificate_path: &Path, key_path: &Path) -> io::Result<ClientIdentity> {
+        let certificates = CertificateDer::pem_file_iter(certificate_path)
+            .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if certificates.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no certificates found",
+            ));
+        }
+        let key = PrivateKeyDer::from_pem_file(key_path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(ClientIdentity { certificates, key })
+    }
+}
+
+fn client_config(identity: Option<&ClientIdentity>) -> io::Result<Arc<ClientConfig>> {
+    let provider = Arc::new(ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .unwrap_or_else(|_| error_handler(ErrorCodes::TLS_UNAVAILABLE, line!(), None))
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)));
+    let config = match identity {
+        Some(identity) => builder
+            .with_client_auth_cert(identity.certificates.clone(), identity.key.clone_key())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+        None => builder.with_no_client_auth(),
+    };
+    Ok(Arc::new(config))
+}
+
+/// A TLS 1.3 configuration offering `alpn`, for QUIC handshakes, which
+/// take certificates on trust as every other probe does.
+pub fn quic_config(alpn: &[u8]) -> Arc<ClientConfig> {
+    let provider = Arc::new(ring::default_provider());
+    let mut config = ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap_or_else(|_| error_handler(ErrorCodes::TLS_UNAVAILABLE, line!(), None))
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+        .with_no_client_auth();
+    config.alpn_protocols = vec![alpn.to_vec()];
+    Arc::new(config)
+}
+
+/// Connects to `target` and completes a TLS handshake, presenting
+/// `identity` if the server asks for a client certificate. Without a
+/// `server_name` no SNI is sent.
+pub async fn handshake(
+    target: SocketAddr,
+    server_name: Option<&str>,
+    identity: Option<&ClientIdentity>,
+) -> io::Result<TlsStream<TcpStream>> {
+    let name = server_name_for(target, server_name)?;
+
+    usage::socket_opened();
+    let stream = TcpStream::connect(target).await?;
+    upgrade(stream, name, identity).await
+}
+
+/// Connects to `target` and completes a TLS handshake offering `alpn`, e.g.
+/// `h2` for gRPC. Without a `server_name` no SNI is sent.
+pub async fn handshake_offering(
+    target: SocketAddr,
+    server_name: Option<&str>,
+    alpn: &[u8],
+) -> io::Result<TlsStream<TcpStream>> {
+    let name = server_name_for(target, server_name)?;
+    let mut config = ClientConfig::clone(&*client_config(None)?);
+    config.alpn_protocols = vec![alpn.to_vec()];
+
+    usage::socket_opened();
+    let stream = TcpStream::connect(target).await?;
+    TlsConnector::from(Arc::new(config))
+        .connect(name, stream)
+        .await
+}
+
+/// Completes a TLS handshake over a connection already made, e.g. once a
+/// mail server has agreed to STARTTLS.
+pub async fn upgrade(
+    stream: TcpStream,
+    name: ServerName<'static>,
+    identity: Option<&ClientIdentity>,
+) -> io::Result<TlsStream<TcpStream>> {
+    TlsConnector::from(client_config(identity)?)
+        .connect(name, stream)
+        .await
+}
+
+/// The name a handshake with `target` goes by: `server_name` as SNI, or the
+/// address itself.
+pub fn server_name_for(
+    target: SocketAddr,
+    server_name: Option<&str>,
+) -> io::Result<ServerName<'static>> {
+    match server_name {
+        Some(server_name) => ServerName::try_from(server_name.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)),
+        None => Ok(ServerName::IpAddress(target.ip().into())),
+    }
+}
+
+/// Waits briefly after a handshake for the server to reject the client's
+/// certificate, or its lack of one. Silence or application data both mean
+/// the server is willing to carry on.
+pub async fn confirm_client_accepted(stream: &mut TlsStream<TcpStream>) -> io::Result<()> {
+    let mut buffer = [0u8; 1];
+    match timeout(CERTIFICATE_VERDICT_WAIT, stream.read(&mut buffer)).await {
+        Ok(Err(e)) => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Whether a TLS failure was the server refusing the client's certificate,
+/// or refusing to continue without one.
+pub fn is_client_certificate_rejection(error: &io::Error) -> bool {
+    let Some(rustls::Error::AlertReceived(alert)) = error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+    else {
+        return false;
+    };
+    matches!(
+        alert,
+        AlertDescription::CertificateRequired
+            | AlertDescription::BadCertificate
+            | AlertDescription::UnknownCA
+            | AlertDescription::CertificateUnknown
+            | AlertDescription::AccessDenied
+            | AlertDescription::HandshakeFailure
+    )
+}
+
+/// Whether a failed handshake still got far enough to show the server
+/// speaks TLS, e.g. it sent an alert or a certificate the handshake then
+/// tripped over, rather than bytes that aren't TLS at all.
+pub fn is_tls_failure(error: &io::Error) -> bool {
+    let Some(tls_error) = error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+    else {
+        return false;
+    };
+    !matches!(
+        tls_error,
+        rustls::Error::InvalidMessage(_) | rustls::Error::InappropriateMessage { .. }
+    )
+}
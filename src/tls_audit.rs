@@ -0,0 +1,395 @@
+use crate::encryption::Encryption;
+use crate::{
+    ConnectionStatus, ScanResult, ScanSettings, VerbosityLevel, print_to_terminal, sandbox, usage,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, timeout};
+
+/// How long each handshake gets to come back with a ServerHello.
+const HELLO_WAIT: Duration = Duration::from_secs(5);
+const TLS_1_3: u16 = 0x0304;
+const VERSIONS: &[(u16, &str)] = &[
+    (0x0301, "TLS 1.0"),
+    (0x0302, "TLS 1.1"),
+    (0x0303, "TLS 1.2"),
+    (TLS_1_3, "TLS 1.3"),
+];
+
+const TLS_1_3_SUITES: &[(u16, &str)] = &[
+    (0x1301, "TLS_AES_128_GCM_SHA256"),
+    (0x1302, "TLS_AES_256_GCM_SHA384"),
+    (0x1303, "TLS_CHACHA20_POLY1305_SHA256"),
+    (0x1304, "TLS_AES_128_CCM_SHA256"),
+    (0x1305, "TLS_AES_128_CCM_8_SHA256"),
+];
+
+/// The suites offered to TLS 1.2 and older, weak ones included, since
+/// finding those is the point of an audit.
+const LEGACY_SUITES: &[(u16, &str)] = &[
+    (0xc02b, "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256"),
+    (0xc02c, "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384"),
+    (0xc02f, "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256"),
+    (0xc030, "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384"),
+    (0xcca9, "TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256"),
+    (0xcca8, "TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256"),
+    (0x009e, "TLS_DHE_RSA_WITH_AES_128_GCM_SHA256"),
+    (0x009f, "TLS_DHE_RSA_WITH_AES_256_GCM_SHA384"),
+    (0xc023, "TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA256"),
+    (0xc024, "TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA384"),
+    (0xc027, "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256"),
+    (0xc028, "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA384"),
+    (0xc009, "TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA"),
+    (0xc00a, "TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA"),
+    (0xc013, "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA"),
+    (0xc014, "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA"),
+    (0x0067, "TLS_DHE_RSA_WITH_AES_128_CBC_SHA256"),
+    (0x006b, "TLS_DHE_RSA_WITH_AES_256_CBC_SHA256"),
+    (0x0033, "TLS_DHE_RSA_WITH_AES_128_CBC_SHA"),
+    (0x0039, "TLS_DHE_RSA_WITH_AES_256_CBC_SHA"),
+    (0x009c, "TLS_RSA_WITH_AES_128_GCM_SHA256"),
+    (0x009d, "TLS_RSA_WITH_AES_256_GCM_SHA384"),
+    (0x003c, "TLS_RSA_WITH_AES_128_CBC_SHA256"),
+    (0x003d, "TLS_RSA_WITH_AES_256_CBC_SHA256"),
+    (0x002f, "TLS_RSA_WITH_AES_128_CBC_SHA"),
+    (0x0035, "TLS_RSA_WITH_AES_256_CBC_SHA"),
+    (0xc012, "TLS_ECDHE_RSA_WITH_3DES_EDE_CBC_SHA"),
+    (0x000a, "TLS_RSA_WITH_3DES_EDE_CBC_SHA"),
+    (0xc011, "TLS_ECDHE_RSA_WITH_RC4_128_SHA"),
+    (0x0005, "TLS_RSA_WITH_RC4_128_SHA"),
+    (0x0004, "TLS_RSA_WITH_RC4_128_MD5"),
+    (0x0009, "TLS_RSA_WITH_DES_CBC_SHA"),
+    (0x0003, "TLS_RSA_EXPORT_WITH_RC4_40_MD5"),
+    (0x0034, "TLS_DH_anon_WITH_AES_128_CBC_SHA"),
+    (0x0018, "TLS_DH_anon_WITH_RC4_128_MD5"),
+    (0x0002, "TLS_RSA_WITH_NULL_SHA"),
+    (0x0001, "TLS_RSA_WITH_NULL_MD5"),
+];
+
+/// A protocol version an endpoint accepted, and with `--tls-audit-ciphers`
+/// the suites it accepted with it, in the server's order of preference.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct VersionSupport {
+    pub version: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cipher_suites: Vec<String>,
+}
+
+/// Versions and suites below what a hardening baseline would allow.
+fn is_weak_version(version: &str) -> bool {
+    version == "TLS 1.0" || version == "TLS 1.1"
+}
+
+fn is_weak_suite(suite: &str) -> bool {
+    ["RC4", "3DES", "_DES_", "NULL", "EXPORT", "anon", "MD5"]
+        .iter()
+        .any(|weak| suite.contains(weak))
+}
+
+fn suite_name(suite: u16) -> String {
+    TLS_1_3_SUITES
+        .iter()
+        .chain(LEGACY_SUITES)
+        .find(|(id, _)| *id == suite)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_else(|| format!("0x{:04x}", suite))
+}
+
+/// Bytes for the hello's random fields. They only need to differ between
+/// handshakes, not to be secret, as no handshake is ever completed.
+fn random_bytes(count: usize) -> Vec<u8> {
+    let random = RandomState::new();
+    let mut bytes = Vec::with_capacity(count);
+    while bytes.len() < count {
+        let mut hasher = random.build_hasher();
+        hasher.write_usize(bytes.len());
+        bytes.extend_from_slice(&hasher.finish().to_be_bytes());
+    }
+    bytes.truncate(count);
+    bytes
+}
+
+fn push_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_extension(buffer: &mut Vec<u8>, kind: u16, data: &[u8]) {
+    push_u16(buffer, kind);
+    push_u16(buffer, data.len() as u16);
+    buffer.extend_from_slice(data);
+}
+
+/// A ClientHello record offering only `version` and `suites`, naming
+/// `server_name` in SNI if given. TLS 1.3 is offered the way it has to be,
+/// as TLS 1.2 with a supported_versions extension and an X25519 key share.
+fn client_hello(version: u16, suites: &[u16], server_name: Option<&str>) -> Vec<u8> {
+    let mut extensions = Vec::new();
+    if let Some(server_name) = server_name {
+        // server_name: a list holding one host_name
+        let mut names = Vec::new();
+        push_u16(&mut names, server_name.len() as u16 + 3);
+        names.push(0);
+        push_u16(&mut names, server_name.len() as u16);
+        names.extend_from_slice(server_name.as_bytes());
+        push_extension(&mut extensions, 0x0000, &names);
+    }
+    // supported_groups: x25519, secp256r1, secp384r1
+    push_extension(&mut extensions, 0x000a, &[0, 6, 0, 0x1d, 0, 0x17, 0, 0x18]);
+    // ec_point_formats: uncompressed
+    push_extension(&mut extensions, 0x000b, &[1, 0]);
+    let mut algorithms = Vec::new();
+    for algorithm in [
+        0x0403u16, 0x0503, 0x0603, 0x0804, 0x0805, 0x0806, 0x0401, 0x0501, 0x0601, 0x0203, 0x0201,
+    ] {
+        push_u16(&mut algorithms, algorithm);
+    }
+    let mut signature_algorithms = Vec::new();
+    push_u16(&mut signature_algorithms, algorithms.len() as u16);
+    signature_algorithms.extend_from_slice(&algorithms);
+    push_extension(&mut extensions, 0x000d, &signature_algorithms);
+    // renegotiation_info, which some older servers insist on
+    push_extension(&mut extensions, 0xff01, &[0]);
+    if version == TLS_1_3 {
+        push_extension(&mut extensions, 0x002b, &[2, 0x03, 0x04]);
+        let mut key_share = Vec::new();
+        push_u16(&mut key_share, 36);
+        push_u16(&mut key_share, 0x001d);
+        push_u16(&mut key_share, 32);
+        key_share.extend_from_slice(&random_bytes(32));
+        push_extension(&mut extensions, 0x0033, &key_share);
+    }
+
+    let mut hello = Vec::new();
+    push_u16(&mut hello, version.min(0x0303));
+    hello.extend_from_slice(&random_bytes(32));
+    hello.push(32);
+    hello.extend_from_slice(&random_bytes(32));
+    push_u16(&mut hello, (suites.len() * 2) as u16);
+    for suite in suites {
+        push_u16(&mut hello, *suite);
+    }
+    hello.extend_from_slice(&[1, 0]);
+    push_u16(&mut hello, extensions.len() as u16);
+    hello.extend_from_slice(&extensions);
+
+    let mut handshake = vec![1];
+    handshake.extend_from_slice(&(hello.len() as u32).to_be_bytes()[1..]);
+    handshake.extend_from_slice(&hello);
+    let mut record = vec![22];
+    push_u16(&mut record, 0x0301);
+    push_u16(&mut record, handshake.len() as u16);
+    record.extend_from_slice(&handshake);
+    record
+}
+
+/// The version and suite a ServerHello picked. A TLS 1.3 server names its
+/// version in the supported_versions extension rather than the header.
+fn parse_server_hello(hello: &[u8]) -> Option<(u16, u16)> {
+    let mut version = u16::from_be_bytes([*hello.first()?, *hello.get(1)?]);
+    let session_id_length = *hello.get(34)? as usize;
+    let mut offset = 35 + session_id_length;
+    let suite = u16::from_be_bytes([*hello.get(offset)?, *hello.get(offset + 1)?]);
+    offset += 3;
+    if let Some(length) = hello.get(offset..offset + 2) {
+        let end =
+            (offset + 2 + u16::from_be_bytes([length[0], length[1]]) as usize).min(hello.len());
+        offset += 2;
+        while offset + 4 <= end {
+            let kind = u16::from_be_bytes([hello[offset], hello[offset + 1]]);
+            let length = u16::from_be_bytes([hello[offset + 2], hello[offset + 3]]) as usize;
+            if kind == 0x002b && length == 2 && offset + 6 <= end {
+                version = u16::from_be_bytes([hello[offset + 4], hello[offset + 5]]);
+            }
+            offset += 4 + length;
+        }
+    }
+    Some((version, suite))
+}
+
+/// Offers `version` with `suites` and returns the suite the server picked,
+/// or `None` if it refused the offer or answered with another version.
+async fn offer(
+    target: SocketAddr,
+    version: u16,
+    suites: &[u16],
+    server_name: Option<&str>,
+) -> io::Result<Option<u16>> {
+    usage::socket_opened();
+    let mut stream = TcpStream::connect(target).await?;
+    let hello = client_hello(version, suites, server_name);
+    stream.write_all(&hello).await?;
+    usage::sent(hello.len());
+
+    // The ServerHello may be split across records
+    let mut handshake: Vec<u8> = Vec::new();
+    loop {
+        let mut header = [0u8; 5];
+        if stream.read_exact(&mut header).await.is_err() {
+            return Ok(None);
+        }
+        let length = u16::from_be_bytes([header[3], header[4]]) as usize;
+        if header[0] != 22 || length == 0 {
+            // An alert, or not TLS at all
+            return Ok(None);
+        }
+        let mut body = vec![0u8; length];
+        stream.read_exact(&mut body).await?;
+        usage::received(header.len() + length);
+        handshake.extend_from_slice(&body);
+
+        if handshake.len() >= 4 {
+            if handshake[0] != 2 {
+                return Ok(None);
+            }
+            let hello_length = u32::from_be_bytes([0, handshake[1], handshake[2], handshake[3]]);
+            let hello_end = 4 + hello_length as usize;
+            if handshake.len() >= hello_end {
+                return Ok(parse_server_hello(&handshake[4..hello_end])
+                    .filter(|(chosen, _)| *chosen == version)
+                    .map(|(_, suite)| suite));
+            }
+        }
+        if handshake.len() > u16::MAX as usize {
+            return Ok(None);
+        }
+    }
+}
+
+async fn offer_in_time(
+    target: SocketAddr,
+    version: u16,
+    suites: &[u16],
+    server_name: Option<&str>,
+) -> Option<u16> {
+    match timeout(HELLO_WAIT, offer(target, version, suites, server_name)).await {
+        Ok(Ok(suite)) => suite,
+        _ => None,
+    }
+}
+
+/// Tries each version with every suite it could use. With `enumerate`, the
+/// suite the server picks is taken out of the offer and the rest offered
+/// again, until the server refuses what's left.
+async fn audit(
+    target: SocketAddr,
+    server_name: Option<String>,
+    enumerate: bool,
+) -> Vec<VersionSupport> {
+    let mut accepted = Vec::new();
+    for (version, name) in VERSIONS {
+        let suites = if *version == TLS_1_3 {
+            TLS_1_3_SUITES
+        } else {
+            LEGACY_SUITES
+        };
+        let mut remaining: Vec<u16> = suites.iter().map(|(id, _)| *id).collect();
+        let mut picked: Vec<String> = Vec::new();
+        while let Some(suite) =
+            offer_in_time(target, *version, &remaining, server_name.as_deref()).await
+        {
+            picked.push(suite_name(suite));
+            remaining.retain(|offered| *offered != suite);
+            if !enumerate || remaining.is_empty() {
+                break;
+            }
+        }
+        if !picked.is_empty() {
+            accepted.push(VersionSupport {
+                version: name.to_string(),
+                cipher_suites: if enumerate { picked } else { Vec::new() },
+            });
+        }
+    }
+    accepted
+}
+
+/// Works out which TLS versions, and with `enumerate` which cipher suites,
+/// every TLS port in `results` accepts, and records them on the result.
+/// Deprecated versions and weak suites are warned about. At most
+/// `max_enrichment_probes` endpoints are audited at once.
+pub async fn audit_tls(settings: &Arc<ScanSettings>, results: &mut [ScanResult], enumerate: bool) {
+    let limits = settings.config.limits;
+    let slots = Arc::new(Semaphore::new(limits.max_enrichment_probes.max(1)));
+    let mut set: JoinSet<(SocketAddr, Result<Vec<VersionSupport>, String>)> = JoinSet::new();
+    for scan_result in results.iter().filter(|scan_result| {
+        scan_result.status == ConnectionStatus::Open
+            && scan_result.encryption == Some(Encryption::Tls)
+    }) {
+        let target = scan_result.ip;
+        let server_name = scan_result.hostname.clone();
+        let slots = slots.clone();
+        set.spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            let versions =
+                sandbox::run_isolated(limits.max_probe_time, audit(target, server_name, enumerate))
+                    .await;
+            (target, versions)
+        });
+    }
+    if set.is_empty() {
+        return;
+    }
+    print_to_terminal(String::from("Auditing TLS versions"), VerbosityLevel::INFO);
+
+    let mut audited: HashMap<SocketAddr, Vec<VersionSupport>> = HashMap::new();
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok((target, Ok(versions))) => {
+                let names: Vec<&str> = versions
+                    .iter()
+                    .map(|support| support.version.as_str())
+                    .collect();
+                if names.is_empty() {
+                    print_to_terminal(
+                        format!("{} - accepted none of the TLS versions offered", target),
+                        VerbosityLevel::WARN,
+                    );
+                } else {
+                    let level = if names.iter().any(|name| is_weak_version(name)) {
+                        VerbosityLevel::WARN
+                    } else {
+                        VerbosityLevel::INFO
+                    };
+                    print_to_terminal(format!("{} - accepts {}", target, names.join(", ")), level);
+                }
+                for support in &versions {
+                    for suite in &support.cipher_suites {
+                        let level = if is_weak_suite(suite) {
+                            VerbosityLevel::WARN
+                        } else {
+                            VerbosityLevel::INFO
+                        };
+                        print_to_terminal(
+                            format!("{} - {} {}", target, support.version, suite),
+                            level,
+                        );
+                    }
+                }
+                audited.insert(target, versions);
+            }
+            Ok((target, Err(problem))) => {
+                print_to_terminal(format!("{} - {}", target, problem), VerbosityLevel::WARN)
+            }
+            Err(e) => {
+                print_to_terminal(
+                    format!("An error has occured: {}", e),
+                    VerbosityLevel::ERROR,
+                );
+            }
+        }
+    }
+    for scan_result in results.iter_mut() {
+        if let Some(versions) = audited.remove(&scan_result.ip) {
+            scan_result.tls_versions = versions;
+        }
+    }
+}
@@ -0,0 +1,429 @@
+use crate::ping::{from_socket_address, socket_address};
+use crate::{
+    ConnectionStatus, ScanResult, ScanSettings, VerbosityLevel, print_to_terminal, sandbox, usage,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::net::SocketAddr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::Arc;
+use tokio::io::Interest;
+use tokio::io::unix::AsyncFd;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, timeout};
+
+/// How long each hop gets to answer. The kernel would resend the SYN after
+/// a second, and by then any router on the path has long said its piece.
+const HOP_WAIT: Duration = Duration::from_secs(1);
+const MAX_HOPS: u8 = 30;
+/// Hops in a row that can stay silent before the path is taken to end
+/// there, so a dead path doesn't cost a wait for every hop up to the limit.
+const MAX_SILENT_HOPS: usize = 4;
+const SO_EE_ORIGIN_ICMP6: u8 = 3;
+const ICMP_DEST_UNREACH: u8 = 3;
+const ICMP_TIME_EXCEEDED: u8 = 11;
+const ICMPV6_DEST_UNREACH: u8 = 1;
+const ICMPV6_TIME_EXCEEDED: u8 = 3;
+
+/// Where a TCP traceroute toward a port that didn't answer got to.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TraceSummary {
+    /// What answered at each distance, the first being one hop away, with
+    /// `None` where nothing did
+    pub hops: Vec<Option<String>>,
+    /// Whether the target itself answered, which means it can be reached
+    /// after all
+    pub reached: bool,
+    /// Why the last hop turned the probe away instead of passing it on,
+    /// e.g. `administratively prohibited`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejected: Option<String>,
+}
+
+impl TraceSummary {
+    /// The furthest hop that answered and its distance.
+    pub fn last_hop(&self) -> Option<(usize, &str)> {
+        self.hops
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(index, hop)| Some((index + 1, hop.as_deref()?)))
+    }
+
+    /// The summary for a result line, e.g. `last answer from 192.0.2.1 at
+    /// hop 3: 10.0.0.1, *, 192.0.2.1`.
+    pub fn describe(&self) -> String {
+        let path = self
+            .hops
+            .iter()
+            .map(|hop| hop.as_deref().unwrap_or("*"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        match (self.reached, &self.rejected, self.last_hop()) {
+            (true, _, _) => format!("reached in {} hops: {}", self.hops.len(), path),
+            (false, Some(reason), Some((distance, hop))) => format!(
+                "rejected by {} at hop {} ({}): {}",
+                hop, distance, reason, path
+            ),
+            (false, _, Some((distance, hop))) => {
+                format!("last answer from {} at hop {}: {}", hop, distance, path)
+            }
+            (false, _, None) => format!("no hop answered within {} hops", self.hops.len()),
+        }
+    }
+}
+
+/// What a SYN sent with a limited TTL came to.
+enum HopAnswer {
+    /// The target answered, with a SYN/ACK or an RST
+    Target(String),
+    /// A router on the way said the TTL ran out
+    TimeExceeded(String),
+    /// A router on the way said the target can't be reached, and why
+    Unreachable(String, String),
+    Silent,
+}
+
+fn unreachable_reason(v6: bool, code: u8) -> String {
+    let reason = match (v6, code) {
+        (false, 0) | (true, 0) => "no route",
+        (false, 1) | (true, 3) => "host unreachable",
+        (false, 3) | (true, 4) => "port unreachable",
+        (false, 9 | 10 | 13) | (true, 1) => "administratively prohibited",
+        _ => "unreachable",
+    };
+    reason.to_string()
+}
+
+pub fn set_option(
+    fd: RawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Starts a non-blocking connection to `target` whose packets expire after
+/// `ttl` hops, with ICMP errors kept for reading back.
+fn start_connect(target: SocketAddr, ttl: u8) -> io::Result<OwnedFd> {
+    let v6 = target.is_ipv6();
+    let domain = if v6 { libc::AF_INET6 } else { libc::AF_INET };
+    let fd = unsafe {
+        libc::socket(
+            domain,
+            libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            0,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    if v6 {
+        set_option(
+            fd.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_UNICAST_HOPS,
+            ttl as libc::c_int,
+        )?;
+        set_option(fd.as_raw_fd(), libc::IPPROTO_IPV6, libc::IPV6_RECVERR, 1)?;
+    } else {
+        set_option(
+            fd.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_TTL,
+            ttl as libc::c_int,
+        )?;
+        set_option(fd.as_raw_fd(), libc::IPPROTO_IP, libc::IP_RECVERR, 1)?;
+    }
+    let (mut address, length) = socket_address(target.ip());
+    let port = target.port().to_be();
+    unsafe {
+        if v6 {
+            (*(&mut address as *mut libc::sockaddr_storage as *mut libc::sockaddr_in6)).sin6_port =
+                port;
+        } else {
+            (*(&mut address as *mut libc::sockaddr_storage as *mut libc::sockaddr_in)).sin_port =
+                port;
+        }
+    }
+    let result = unsafe {
+        libc::connect(
+            fd.as_raw_fd(),
+            &address as *const libc::sockaddr_storage as *const libc::sockaddr,
+            length,
+        )
+    };
+    if result < 0 {
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() != Some(libc::EINPROGRESS) {
+            return Err(e);
+        }
+    }
+    Ok(fd)
+}
+
+/// An error the kernel queued on a socket with `IP_RECVERR` set.
+pub struct QueuedError {
+    /// Where it came from, e.g. `SO_EE_ORIGIN_ICMP` for an ICMP message
+    pub origin: u8,
+    /// The ICMP type and code
+    pub kind: u8,
+    pub code: u8,
+    /// Extra detail the message carried, e.g. the next hop's MTU when a
+    /// packet was too big
+    pub info: u32,
+    /// The router or host that sent it
+    pub from: String,
+}
+
+impl QueuedError {
+    pub fn is_icmp(&self) -> bool {
+        self.origin == libc::SO_EE_ORIGIN_ICMP || self.origin == SO_EE_ORIGIN_ICMP6
+    }
+}
+
+/// The error queued on the socket, if there is one.
+pub fn queued_error(fd: RawFd) -> Option<QueuedError> {
+    let mut offender: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut data = [0u8; 64];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    let mut control = [0u64; 32];
+    let mut message: libc::msghdr = unsafe { mem::zeroed() };
+    message.msg_name = &mut offender as *mut libc::sockaddr_storage as *mut libc::c_void;
+    message.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    message.msg_controllen = mem::size_of_val(&control);
+    if unsafe { libc::recvmsg(fd, &mut message, libc::MSG_ERRQUEUE) } < 0 {
+        return None;
+    }
+    unsafe {
+        let mut header = libc::CMSG_FIRSTHDR(&message);
+        while !header.is_null() {
+            let level = (*header).cmsg_level;
+            let kind = (*header).cmsg_type;
+            if (level == libc::IPPROTO_IP && kind == libc::IP_RECVERR)
+                || (level == libc::IPPROTO_IPV6 && kind == libc::IPV6_RECVERR)
+            {
+                let error = libc::CMSG_DATA(header) as *const libc::sock_extended_err;
+                let extended = std::ptr::read_unaligned(error);
+                let offender = libc::SO_EE_OFFENDER(error) as *const libc::sockaddr;
+                let mut storage: libc::sockaddr_storage = mem::zeroed();
+                let size = if (*offender).sa_family as libc::c_int == libc::AF_INET6 {
+                    mem::size_of::<libc::sockaddr_in6>()
+                } else {
+                    mem::size_of::<libc::sockaddr_in>()
+                };
+                std::ptr::copy_nonoverlapping(
+                    offender as *const u8,
+                    &mut storage as *mut libc::sockaddr_storage as *mut u8,
+                    size,
+                );
+                let from = from_socket_address(&storage)?;
+                return Some(QueuedError {
+                    origin: extended.ee_origin,
+                    kind: extended.ee_type,
+                    code: extended.ee_code,
+                    info: extended.ee_info,
+                    from: from.to_string(),
+                });
+            }
+            header = libc::CMSG_NXTHDR(&message, header);
+        }
+    }
+    None
+}
+
+fn socket_error(fd: RawFd) -> io::Result<i32> {
+    let mut error: libc::c_int = 0;
+    let mut length = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_ERROR,
+            &mut error as *mut libc::c_int as *mut libc::c_void,
+            &mut length,
+        )
+    };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(error)
+    }
+}
+
+/// Sends a SYN that expires after `ttl` hops and waits to hear what came of
+/// it, from the target or a router on the way.
+async fn probe_hop(target: SocketAddr, ttl: u8) -> io::Result<HopAnswer> {
+    usage::socket_opened();
+    let fd = AsyncFd::new(start_connect(target, ttl)?)?;
+    let v6 = target.is_ipv6();
+    let answer = timeout(HOP_WAIT, async {
+        loop {
+            let mut guard = fd.ready(Interest::WRITABLE | Interest::ERROR).await?;
+            let raw = fd.as_raw_fd();
+            if let Some(error) = queued_error(raw) {
+                if !error.is_icmp() {
+                    continue;
+                }
+                return Ok(match (v6, error.kind) {
+                    (false, ICMP_TIME_EXCEEDED) | (true, ICMPV6_TIME_EXCEEDED) => {
+                        HopAnswer::TimeExceeded(error.from)
+                    }
+                    (false, ICMP_DEST_UNREACH) | (true, ICMPV6_DEST_UNREACH) => {
+                        HopAnswer::Unreachable(error.from, unreachable_reason(v6, error.code))
+                    }
+                    _ => HopAnswer::Silent,
+                });
+            }
+            match socket_error(raw)? {
+                0 if guard.ready().is_writable() => {
+                    return Ok(HopAnswer::Target(target.ip().to_string()));
+                }
+                0 => {}
+                libc::ECONNREFUSED => return Ok(HopAnswer::Target(target.ip().to_string())),
+                error => return Err(io::Error::from_raw_os_error(error)),
+            }
+            guard.clear_ready();
+        }
+    })
+    .await;
+    match answer {
+        Ok(answer) => answer,
+        Err(_) => Ok(HopAnswer::Silent),
+    }
+}
+
+/// Traces the path to `target` a hop at a time with SYNs to its port, until
+/// the target answers, a router turns the probe away, or the path goes
+/// quiet.
+async fn trace(target: SocketAddr) -> io::Result<TraceSummary> {
+    let mut summary = TraceSummary {
+        hops: Vec::new(),
+        reached: false,
+        rejected: None,
+    };
+    let mut silent = 0;
+    for ttl in 1..=MAX_HOPS {
+        match probe_hop(target, ttl).await? {
+            HopAnswer::Target(address) => {
+                summary.hops.push(Some(address));
+                summary.reached = true;
+                break;
+            }
+            HopAnswer::TimeExceeded(address) => {
+                summary.hops.push(Some(address));
+                silent = 0;
+            }
+            HopAnswer::Unreachable(address, reason) => {
+                summary.hops.push(Some(address));
+                summary.rejected = Some(reason);
+                break;
+            }
+            HopAnswer::Silent => {
+                summary.hops.push(None);
+                silent += 1;
+                if silent == MAX_SILENT_HOPS {
+                    break;
+                }
+            }
+        }
+    }
+    // The silent hops past the last answer only show where the trace gave up
+    if !summary.reached && summary.rejected.is_none() {
+        let answered = summary.last_hop().map_or(0, |(distance, _)| distance);
+        summary.hops.truncate(answered.max(1));
+    }
+    Ok(summary)
+}
+
+/// Traces the path to every port in `results` that timed out or was
+/// unreachable, sending SYNs to the port with TTLs counting up
+/// from one, and records the last hop that answered: where in the path
+/// connectivity breaks. At most `max_enrichment_probes` paths are traced at
+/// once.
+pub async fn trace_routes(settings: &Arc<ScanSettings>, results: &mut [ScanResult]) {
+    let limits = settings.config.limits;
+    let slots = Arc::new(Semaphore::new(limits.max_enrichment_probes.max(1)));
+    let mut set: JoinSet<(SocketAddr, Result<io::Result<TraceSummary>, String>)> = JoinSet::new();
+    for scan_result in results.iter().filter(|scan_result| {
+        matches!(
+            scan_result.status,
+            ConnectionStatus::Timeout | ConnectionStatus::Unreachable
+        )
+    }) {
+        let target = scan_result.ip;
+        let slots = slots.clone();
+        set.spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            let summary = sandbox::run_isolated(limits.max_probe_time, trace(target)).await;
+            (target, summary)
+        });
+    }
+    if set.is_empty() {
+        return;
+    }
+    print_to_terminal(
+        String::from("Tracing the path to ports that didn't answer"),
+        VerbosityLevel::INFO,
+    );
+
+    let mut summaries: HashMap<SocketAddr, TraceSummary> = HashMap::new();
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok((target, Ok(Ok(summary)))) => {
+                print_to_terminal(
+                    format!("{} - {}", target, summary.describe()),
+                    VerbosityLevel::INFO,
+                );
+                summaries.insert(target, summary);
+            }
+            Ok((target, Ok(Err(e)))) => print_to_terminal(
+                format!("{} - traceroute failed: {}", target, e),
+                match e.kind() {
+                    ErrorKind::HostUnreachable | ErrorKind::NetworkUnreachable => {
+                        VerbosityLevel::INFO
+                    }
+                    _ => VerbosityLevel::WARN,
+                },
+            ),
+            Ok((target, Err(problem))) => {
+                print_to_terminal(format!("{} - {}", target, problem), VerbosityLevel::WARN)
+            }
+            Err(e) => {
+                print_to_terminal(
+                    format!("An error has occured: {}", e),
+                    VerbosityLevel::ERROR,
+                );
+            }
+        }
+    }
+    for scan_result in results.iter_mut() {
+        if let Some(summary) = summaries.remove(&scan_result.ip) {
+            scan_result.trace = Some(summary);
+        }
+    }
+}
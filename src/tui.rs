@@ -0,0 +1,277 @@
+use crate::acks::Acknowledgments;
+use crate::labels::LabelSet;
+use crate::{
+    ConnectionStatus, ErrorCodes, ScanResult, ScanSettings, check_target, error_handler,
+    format_latency,
+};
+use ratatui::Frame;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, List, ListItem, Paragraph};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, IsTerminal};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, Instant, interval};
+
+/// Probes are handed out a batch at a time so that pausing takes effect
+/// quickly instead of after every endpoint has already been started.
+const MAX_IN_FLIGHT: usize = 512;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, PartialEq)]
+enum Filter {
+    All,
+    Open,
+    NotOpen,
+}
+
+impl Filter {
+    fn next(self) -> Filter {
+        match self {
+            Filter::All => Filter::Open,
+            Filter::Open => Filter::NotOpen,
+            Filter::NotOpen => Filter::All,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Filter::All => "all",
+            Filter::Open => "open",
+            Filter::NotOpen => "not open",
+        }
+    }
+
+    fn allows(self, status: ConnectionStatus) -> bool {
+        match self {
+            Filter::All => true,
+            Filter::Open => status == ConnectionStatus::Open,
+            Filter::NotOpen => status != ConnectionStatus::Open,
+        }
+    }
+}
+
+struct Dashboard {
+    total: usize,
+    results: Vec<ScanResult>,
+    /// Open ports and finished ports per host
+    hosts: BTreeMap<IpAddr, (usize, usize)>,
+    started: Instant,
+    paused: bool,
+    finished: bool,
+    filter: Filter,
+}
+
+enum KeyAction {
+    Continue,
+    Quit,
+}
+
+/// Runs the scan behind a full-screen dashboard until it finishes and the
+/// user quits, returning whatever results came in. Quitting early abandons
+/// the endpoints that haven't been probed yet.
+pub async fn run_tui(
+    scan_targets: Vec<SocketAddr>,
+    settings: Arc<ScanSettings>,
+    label_set: &LabelSet,
+    acknowledgments: &Acknowledgments,
+) -> Vec<ScanResult> {
+    if !io::stdout().is_terminal() {
+        error_handler(ErrorCodes::TERMINAL_UNAVAILABLE, line!(), None);
+    }
+    let mut terminal = match ratatui::try_init() {
+        Ok(terminal_result) => terminal_result,
+        Err(_) => error_handler(ErrorCodes::TERMINAL_UNAVAILABLE, line!(), None),
+    };
+
+    let mut dashboard = Dashboard {
+        total: scan_targets.len(),
+        results: Vec::new(),
+        hosts: BTreeMap::new(),
+        started: Instant::now(),
+        paused: false,
+        finished: false,
+        filter: Filter::All,
+    };
+    let mut pending: VecDeque<SocketAddr> = scan_targets.into();
+    let mut set: JoinSet<ScanResult> = JoinSet::new();
+    let mut redraw = interval(REDRAW_INTERVAL);
+
+    loop {
+        while !dashboard.paused
+            && set.len() < MAX_IN_FLIGHT
+            && let Some(target) = pending.pop_front()
+        {
+            set.spawn(check_target(target, None, settings.clone()));
+        }
+        dashboard.finished = pending.is_empty() && set.is_empty();
+
+        tokio::select! {
+            Some(res) = set.join_next(), if !set.is_empty() => {
+                if let Ok(mut scan_result) = res {
+                    scan_result.labels = label_set.labels_for(scan_result.ip.ip());
+                    scan_result.annotation = settings.annotations.annotation_for(scan_result.ip.ip());
+                    scan_result.acknowledgment = acknowledgments.note_for(scan_result.ip);
+                    dashboard.record(scan_result);
+                }
+            }
+            _ = redraw.tick() => {
+                let _ = terminal.draw(|frame| dashboard.render(frame));
+                if let KeyAction::Quit = handle_keys(&mut dashboard) {
+                    break;
+                }
+            }
+        }
+    }
+
+    set.abort_all();
+    ratatui::restore();
+    dashboard.results
+}
+
+/// Applies every key press waiting since the last redraw.
+fn handle_keys(dashboard: &mut Dashboard) -> KeyAction {
+    while let Ok(true) = event::poll(Duration::ZERO) {
+        let Ok(Event::Key(key)) = event::read() else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return KeyAction::Quit,
+            KeyCode::Char('p') | KeyCode::Char(' ') => dashboard.paused = !dashboard.paused,
+            KeyCode::Char('f') => dashboard.filter = dashboard.filter.next(),
+            _ => {}
+        }
+    }
+    KeyAction::Continue
+}
+
+fn status_color(status: ConnectionStatus) -> Color {
+    match status {
+        ConnectionStatus::Open => Color::Green,
+        ConnectionStatus::Refused
+        | ConnectionStatus::Unconfirmed
+        | ConnectionStatus::OpenFiltered => Color::Yellow,
+        ConnectionStatus::Timeout | ConnectionStatus::Unreachable => Color::Red,
+    }
+}
+
+impl Dashboard {
+    fn record(&mut self, scan_result: ScanResult) {
+        let host = self.hosts.entry(scan_result.ip.ip()).or_default();
+        if scan_result.status == ConnectionStatus::Open {
+            host.0 += 1;
+        }
+        host.1 += 1;
+        self.results.push(scan_result);
+    }
+
+    fn render(&self, frame: &mut Frame) {
+        let [progress_area, body_area, help_area] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(5),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [hosts_area, log_area] =
+            Layout::horizontal([Constraint::Percentage(35), Constraint::Percentage(65)])
+                .areas(body_area);
+
+        let done = self.results.len();
+        let elapsed = self.started.elapsed().as_secs_f64().max(0.001);
+        let state = if self.finished {
+            " - done"
+        } else if self.paused {
+            " - paused"
+        } else {
+            ""
+        };
+        let ratio = if self.total == 0 {
+            1.0
+        } else {
+            done as f64 / self.total as f64
+        };
+        let gauge = Gauge::default()
+            .block(Block::bordered().title(" Progress "))
+            .gauge_style(Style::default().fg(Color::Cyan))
+            .ratio(ratio)
+            .label(format!(
+                "{}/{} endpoints, {:.0}/s{}",
+                done,
+                self.total,
+                done as f64 / elapsed,
+                state
+            ));
+        frame.render_widget(gauge, progress_area);
+
+        let hosts: Vec<ListItem> = self
+            .hosts
+            .iter()
+            .map(|(ip, (open, finished))| {
+                let style = if *open > 0 {
+                    Style::default().fg(Color::Green)
+                } else {
+                    Style::default()
+                };
+                ListItem::new(format!("{}  {} open / {}", ip, open, finished)).style(style)
+            })
+            .collect();
+        frame.render_widget(
+            List::new(hosts)
+                .block(Block::bordered().title(format!(" Hosts ({}) ", self.hosts.len()))),
+            hosts_area,
+        );
+
+        // Newest results at the bottom, as in the plain line output
+        let visible_rows = log_area.height.saturating_sub(2) as usize;
+        let mut lines: Vec<Line> = self
+            .results
+            .iter()
+            .rev()
+            .filter(|scan_result| self.filter.allows(scan_result.status))
+            .take(visible_rows)
+            .map(|scan_result| {
+                let labels = if scan_result.labels.is_empty() {
+                    String::new()
+                } else {
+                    format!(" [{}]", scan_result.labels.join(", "))
+                };
+                let latency = if scan_result.status == ConnectionStatus::Open {
+                    format!(" ({})", format_latency(scan_result.latency))
+                } else {
+                    String::new()
+                };
+                Line::styled(
+                    format!(
+                        "{} - {}{}{}",
+                        scan_result.ip, scan_result.status, latency, labels
+                    ),
+                    Style::default().fg(status_color(scan_result.status)),
+                )
+            })
+            .collect();
+        lines.reverse();
+        frame.render_widget(
+            Paragraph::new(lines).block(
+                Block::bordered().title(format!(" Results (showing {}) ", self.filter.label())),
+            ),
+            log_area,
+        );
+
+        let pause_hint = if self.paused { "resume" } else { "pause" };
+        frame.render_widget(
+            Paragraph::new(format!(
+                " q quit   p {}   f filter: {}",
+                pause_hint,
+                self.filter.label()
+            )),
+            help_area,
+        );
+    }
+}
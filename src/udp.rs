@@ -0,0 +1,90 @@
+use crate::{ConnectionStatus, usage};
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+use tokio::time::{Duration, timeout};
+
+/// A DNS query for the root's NS records, which any resolver or
+/// authoritative server answers.
+const DNS_QUERY: &[u8] = &[
+    0x13, 0x37, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00,
+    0x01,
+];
+
+/// An NTP client request, version 3.
+const NTP_REQUEST: &[u8] = &{
+    let mut request = [0u8; 48];
+    request[0] = 0x1b;
+    request
+};
+
+/// An SNMPv1 get of sysDescr.0 in the `public` community.
+const SNMP_GET: &[u8] = &[
+    0x30, 0x26, 0x02, 0x01, 0x00, 0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c', 0xa0, 0x19, 0x02,
+    0x01, 0x01, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x30, 0x0e, 0x30, 0x0c, 0x06, 0x08, 0x2b, 0x06,
+    0x01, 0x02, 0x01, 0x01, 0x01, 0x00, 0x05, 0x00,
+];
+
+/// Most UDP services ignore datagrams they can't parse, so well-known ports
+/// get a request they'll answer. Anything else gets an empty datagram.
+fn payload_for(port: u16) -> &'static [u8] {
+    match port {
+        53 => DNS_QUERY,
+        123 => NTP_REQUEST,
+        161 => SNMP_GET,
+        _ => &[],
+    }
+}
+
+/// Sends a probe datagram and waits for the answer. A reply means open, an
+/// ICMP port unreachable is reported as refused, and silence can't be told
+/// apart from a firewall dropping the datagram, so it's open|filtered.
+pub async fn probe(target: SocketAddr, source: Option<IpAddr>, wait: Duration) -> ConnectionStatus {
+    match timeout(wait, exchange(target, source)).await {
+        Err(_) => ConnectionStatus::OpenFiltered,
+        Ok(Ok(())) => ConnectionStatus::Open,
+        Ok(Err(e)) => match e.kind() {
+            ErrorKind::ConnectionRefused => ConnectionStatus::Refused,
+            ErrorKind::HostUnreachable | ErrorKind::NetworkUnreachable => {
+                ConnectionStatus::Unreachable
+            }
+            _ => ConnectionStatus::OpenFiltered,
+        },
+    }
+}
+
+async fn exchange(target: SocketAddr, source: Option<IpAddr>) -> io::Result<()> {
+    let local = source.unwrap_or(if target.is_ipv4() {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    } else {
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    });
+    let socket = UdpSocket::bind(SocketAddr::new(local, 0)).await?;
+    // Connecting the socket is what lets the kernel hand ICMP errors for the
+    // destination back to it
+    socket.connect(target).await?;
+
+    let payload = payload_for(target.port());
+    socket.send(payload).await?;
+    usage::sent(payload.len());
+    let mut buffer = [0u8; 1500];
+    loop {
+        // An ICMP error only shows up as error readiness, which a plain
+        // `recv` never wakes for
+        let ready = socket.ready(Interest::READABLE | Interest::ERROR).await?;
+        if ready.is_error()
+            && let Some(e) = socket.take_error()?
+        {
+            return Err(e);
+        }
+        match socket.try_recv(&mut buffer) {
+            Ok(read) => {
+                usage::received(read);
+                return Ok(());
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serializer};
+use std::time::Duration;
+
+const DURATION_UNITS: &[(&[&str], Duration)] = &[
+    (&["ns"], Duration::from_nanos(1)),
+    (&["us", "µs"], Duration::from_micros(1)),
+    (&["ms"], Duration::from_millis(1)),
+    (&["s", "sec", "secs"], Duration::from_secs(1)),
+    (&["m", "min", "mins"], Duration::from_secs(60)),
+    (&["h", "hr", "hrs"], Duration::from_secs(60 * 60)),
+    (&["d", "day", "days"], Duration::from_secs(24 * 60 * 60)),
+];
+
+/// Sizes count in 1024s, so `4kb` and `4kib` are both 4096 bytes.
+const SIZE_UNITS: &[(&[&str], u64)] = &[
+    (&["", "b"], 1),
+    (&["k", "kb", "kib"], 1 << 10),
+    (&["m", "mb", "mib"], 1 << 20),
+    (&["g", "gb", "gib"], 1 << 30),
+];
+
+/// Splits `1h30m` into `[("1", "h"), ("30", "m")]`. Spaces between the
+/// parts are allowed, as in `1h 30m`.
+fn components(text: &str) -> Vec<(&str, &str)> {
+    let mut parts = Vec::new();
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let (number, after) = rest.split_at(number_end);
+        let unit_end = after
+            .find(|c: char| c.is_ascii_digit() || c == '.' || c.is_whitespace())
+            .unwrap_or(after.len());
+        let (unit, after) = after.split_at(unit_end);
+        parts.push((number, unit));
+        rest = after.trim_start();
+    }
+    parts
+}
+
+/// Reads a duration such as `750ms`, `30s`, `2h` or `1h30m`. Every number
+/// needs a unit, one of ns, us, ms, s, m, h and d.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let parts = components(text);
+    if parts.is_empty() {
+        return Err(String::from("expected a duration like 750ms, 30s or 2h"));
+    }
+    let mut total = Duration::ZERO;
+    for (number, unit) in parts {
+        let Ok(value) = number.parse::<f64>() else {
+            return Err(format!("{:?} is not a number in {:?}", number, text.trim()));
+        };
+        if unit.is_empty() {
+            return Err(format!(
+                "{:?} has no unit, e.g. {}s or {}ms",
+                text.trim(),
+                number,
+                number
+            ));
+        }
+        let Some((_, scale)) = DURATION_UNITS
+            .iter()
+            .find(|(names, _)| names.contains(&unit.to_lowercase().as_str()))
+        else {
+            return Err(format!(
+                "unknown unit {:?} in {:?}, expected ns, us, ms, s, m, h or d",
+                unit,
+                text.trim()
+            ));
+        };
+        match Duration::try_from_secs_f64(scale.as_secs_f64() * value)
+            .ok()
+            .and_then(|part| total.checked_add(part))
+        {
+            Some(sum) => total = sum,
+            None => return Err(format!("{:?} is too long", text.trim())),
+        }
+    }
+    Ok(total)
+}
+
+/// Reads a size in bytes such as `512`, `4kb` or `1.5mb`.
+pub fn parse_size(text: &str) -> Result<usize, String> {
+    let parts = components(text);
+    let [(number, unit)] = parts.as_slice() else {
+        return Err(format!(
+            "expected a size like 512, 4kb or 1mb, not {:?}",
+            text.trim()
+        ));
+    };
+    let Ok(value) = number.parse::<f64>() else {
+        return Err(format!("{:?} is not a number in {:?}", number, text.trim()));
+    };
+    let Some((_, scale)) = SIZE_UNITS
+        .iter()
+        .find(|(names, _)| names.contains(&unit.to_lowercase().as_str()))
+    else {
+        return Err(format!(
+            "unknown unit {:?} in {:?}, expected b, kb, mb or gb",
+            unit,
+            text.trim()
+        ));
+    };
+    let bytes = value * *scale as f64;
+    if bytes < usize::MAX as f64 {
+        Ok(bytes.round() as usize)
+    } else {
+        Err(format!("{:?} is too large", text.trim()))
+    }
+}
+
+/// Writes a duration the way `parse_duration` reads it, e.g. `1h30m` or
+/// `750ms`.
+pub fn format_duration(duration: Duration) -> String {
+    if duration.is_zero() {
+        return String::from("0s");
+    }
+    let mut text = String::new();
+    let mut nanos = duration.as_nanos();
+    for (names, scale) in DURATION_UNITS.iter().rev() {
+        let scale = scale.as_nanos();
+        if nanos >= scale {
+            text.push_str(&format!("{}{}", nanos / scale, names[0]));
+            nanos %= scale;
+        }
+    }
+    text
+}
+
+/// For `#[serde(deserialize_with = "units::deserialize_duration")]` on a
+/// `Duration` read from a config file.
+pub fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Duration, D::Error> {
+    parse_duration(&String::deserialize(deserializer)?).map_err(de::Error::custom)
+}
+
+/// For `#[serde(with = "units::optional_duration")]` on an
+/// `Option<Duration>`.
+pub mod optional_duration {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_some(&format_duration(*duration)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(text) => parse_duration(&text).map(Some).map_err(de::Error::custom),
+            None => Ok(None),
+        }
+    }
+}
+
+/// For `#[serde(deserialize_with = "units::deserialize_size")]` on a byte
+/// count, which config files can give as a plain number or a string like
+/// `"64kb"`.
+pub fn deserialize_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(usize),
+        Text(String),
+    }
+    match Size::deserialize(deserializer)? {
+        Size::Bytes(bytes) => Ok(bytes),
+        Size::Text(text) => parse_size(&text).map_err(de::Error::custom),
+    }
+}
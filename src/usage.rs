@@ -0,0 +1,81 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+static SOCKETS_OPENED: AtomicU64 = AtomicU64::new(0);
+static CONNECTIONS_IN_FLIGHT: AtomicU64 = AtomicU64::new(0);
+static PEAK_CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+static DNS_QUERIES: AtomicU64 = AtomicU64::new(0);
+static BYTES_SENT: AtomicU64 = AtomicU64::new(0);
+static BYTES_RECEIVED: AtomicU64 = AtomicU64::new(0);
+
+/// Counts a scan connection for as long as it's held, so the peak number
+/// open at once can be reported.
+pub struct InFlight;
+
+impl InFlight {
+    pub fn start() -> InFlight {
+        socket_opened();
+        let in_flight = CONNECTIONS_IN_FLIGHT.fetch_add(1, Ordering::Relaxed) + 1;
+        PEAK_CONNECTIONS.fetch_max(in_flight, Ordering::Relaxed);
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        CONNECTIONS_IN_FLIGHT.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub fn socket_opened() {
+    SOCKETS_OPENED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn dns_query() {
+    DNS_QUERIES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn sent(bytes: usize) {
+    BYTES_SENT.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+pub fn received(bytes: usize) {
+    BYTES_RECEIVED.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+/// User plus system CPU time used by the whole process so far.
+#[cfg(unix)]
+fn cpu_time() -> Option<Duration> {
+    // getrusage only fills in the struct it's handed
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return None;
+    }
+    let to_duration = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+    };
+    Some(to_duration(usage.ru_utime) + to_duration(usage.ru_stime))
+}
+
+#[cfg(not(unix))]
+fn cpu_time() -> Option<Duration> {
+    None
+}
+
+/// One line describing what the run cost, to help size the machines that
+/// run scheduled scans. Bytes are payload only, not TCP or TLS overhead.
+pub fn summary() -> String {
+    let cpu_time = match cpu_time() {
+        Some(cpu_time) => format!("{:.2}s", cpu_time.as_secs_f64()),
+        None => String::from("unknown"),
+    };
+    format!(
+        "Resources: {} sockets opened, {} connections at peak, {} DNS queries, {} bytes sent, {} bytes received, {} CPU time",
+        SOCKETS_OPENED.load(Ordering::Relaxed),
+        PEAK_CONNECTIONS.load(Ordering::Relaxed),
+        DNS_QUERIES.load(Ordering::Relaxed),
+        BYTES_SENT.load(Ordering::Relaxed),
+        BYTES_RECEIVED.load(Ordering::Relaxed),
+        cpu_time
+    )
+}
@@ -0,0 +1,173 @@
+use crate::cli::{HostMatrixArgs, MatrixFormat};
+use crate::report::escape_html;
+use crate::{
+    ErrorCodes, VerbosityLevel, build_scan_targets, error_handler, http, print_to_terminal, tls,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, timeout};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends one request per Host header to every endpoint and reports the
+/// status codes as a grid, to check that a reverse proxy routes each virtual
+/// host where it should on every node behind it.
+pub async fn run_host_matrix(mut host_args: HostMatrixArgs) {
+    let mut set: JoinSet<(SocketAddr, String, String)> = JoinSet::new();
+    let mut cells: HashMap<(SocketAddr, String), String> = HashMap::new();
+
+    let endpoints: Vec<SocketAddr> = build_scan_targets(&mut host_args.scan).await;
+
+    for endpoint in &endpoints {
+        for host in &host_args.hosts {
+            let (endpoint, host, path) = (*endpoint, host.clone(), host_args.path.clone());
+            let use_tls = host_args.tls;
+            set.spawn(async move {
+                let cell = request_cell(endpoint, &host, &path, use_tls).await;
+                (endpoint, host, cell)
+            });
+        }
+    }
+
+    print_to_terminal(String::from("Waiting for responses"), VerbosityLevel::DEBUG);
+
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok((endpoint, host, cell)) => {
+                print_to_terminal(
+                    format!("{} Host: {} - {}", endpoint, host, cell),
+                    VerbosityLevel::DEBUG,
+                );
+                cells.insert((endpoint, host), cell);
+            }
+            Err(e) => {
+                print_to_terminal(
+                    format!("An error has occured: {}", e),
+                    VerbosityLevel::ERROR,
+                );
+            }
+        }
+    }
+
+    let rendered = match host_args.format {
+        MatrixFormat::Csv => render_csv(&endpoints, &host_args.hosts, &cells),
+        MatrixFormat::Html => render_html(&endpoints, &host_args.hosts, &cells),
+    };
+
+    match &host_args.out {
+        Some(path) => {
+            if fs::write(path, rendered).is_err() {
+                error_handler(
+                    ErrorCodes::OUTPUT_WRITE_FAILURE,
+                    line!(),
+                    Some(&path.display().to_string()),
+                );
+            }
+            print_to_terminal(
+                format!("Matrix written to {}", path.display()),
+                VerbosityLevel::INFO,
+            );
+        }
+        None => print!("{}", rendered),
+    }
+}
+
+/// The status code the endpoint answered with, or a word for why it didn't.
+async fn request_cell(endpoint: SocketAddr, host: &str, path: &str, use_tls: bool) -> String {
+    let response = timeout(REQUEST_TIMEOUT, async {
+        if use_tls {
+            let server_name = host.rsplit_once(":").map_or(host, |(name, _)| name);
+            http::get(
+                tls::handshake(endpoint, Some(server_name), None).await?,
+                host,
+                path,
+            )
+            .await
+        } else {
+            http::get(TcpStream::connect(endpoint).await?, host, path).await
+        }
+    })
+    .await;
+
+    match response {
+        Ok(Ok(response)) => response.status.to_string(),
+        Ok(Err(e)) => match e.kind() {
+            io::ErrorKind::ConnectionRefused => String::from("refused"),
+            io::ErrorKind::InvalidData => String::from("not http"),
+            _ => String::from("error"),
+        },
+        Err(_) => String::from("timeout"),
+    }
+}
+
+fn cell_label(
+    cells: &HashMap<(SocketAddr, String), String>,
+    endpoint: &SocketAddr,
+    host: &str,
+) -> String {
+    match cells.get(&(*endpoint, host.to_string())) {
+        Some(cell) => cell.clone(),
+        None => String::from("-"),
+    }
+}
+
+fn render_csv(
+    endpoints: &[SocketAddr],
+    hosts: &[String],
+    cells: &HashMap<(SocketAddr, String), String>,
+) -> String {
+    let mut csv = String::from("endpoint");
+    for host in hosts {
+        csv.push_str(&format!(",{}", host));
+    }
+    csv.push('\n');
+
+    for endpoint in endpoints {
+        csv.push_str(&endpoint.to_string());
+        for host in hosts {
+            csv.push_str(&format!(",{}", cell_label(cells, endpoint, host)));
+        }
+        csv.push('\n');
+    }
+    csv
+}
+
+fn render_html(
+    endpoints: &[SocketAddr],
+    hosts: &[String],
+    cells: &HashMap<(SocketAddr, String), String>,
+) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Virtual host matrix</title>\n<style>\n\
+         table { border-collapse: collapse; font-family: monospace; }\n\
+         th, td { border: 1px solid #999; padding: 4px 8px; }\n\
+         .success { background: #b7e1b5; }\n\
+         .redirect { background: #f5e3a3; }\n\
+         .failure { background: #f0b2b2; }\n\
+         </style>\n</head>\n<body>\n<table>\n<tr><th>endpoint</th>",
+    );
+    for host in hosts {
+        html.push_str(&format!("<th>{}</th>", escape_html(host)));
+    }
+    html.push_str("</tr>\n");
+
+    for endpoint in endpoints {
+        html.push_str(&format!("<tr><th>{}</th>", endpoint));
+        for host in hosts {
+            let label = cell_label(cells, endpoint, host);
+            let class = match label.parse::<u16>() {
+                Ok(200..=299) => "success",
+                Ok(300..=399) => "redirect",
+                _ => "failure",
+            };
+            html.push_str(&format!("<td class=\"{}\">{}</td>", class, label));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
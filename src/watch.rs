@@ -0,0 +1,454 @@
+use crate::acks::Acknowledgments;
+use crate::cli::ScanArgs;
+use crate::clock::RunClock;
+use crate::diff::ChangeTracker;
+use crate::heartbeat::{DEFAULT_HEARTBEAT_INTERVAL, Heartbeat};
+use crate::labels::LabelSet;
+use crate::metrics::{self, Metrics};
+use crate::output::ResultStream;
+use crate::sources;
+use crate::units::format_duration;
+use crate::{
+    ConnectionStatus, Endpoints, ErrorCodes, ScanResult, ScanSettings, VerbosityLevel,
+    check_target, dns_cache, error_handler, ports, print_scan_result, print_to_terminal, targets,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::signal;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::watch;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::{Duration, Instant, interval_at, sleep, sleep_until};
+
+const DEFAULT_RERESOLVE_INTERVAL: Duration = Duration::from_secs(300);
+/// The most endpoints that can be watched at once. Each keeps its own probe
+/// task for as long as the watch runs, so unlike a scan, memory grows with
+/// every endpoint.
+const MAX_WATCHED_ENDPOINTS: usize = 65536;
+
+/// A probed endpoint, along with the hostname it was resolved from. The same
+/// address reached through two hostnames is watched once for each, though
+/// the two share their probes through `SharedProbes`.
+type Endpoint = (Option<String>, SocketAddr);
+
+/// What a probe found, handed to every endpoint that shares it.
+#[derive(Clone, Copy)]
+struct Outcome {
+    status: ConnectionStatus,
+    latency: Duration,
+    timestamp: SystemTime,
+    monotonic: Duration,
+}
+
+/// When a probe started, and its outcome once it has one.
+type LatestProbe = (Instant, watch::Receiver<Option<Outcome>>);
+
+/// The latest probe of each address. Endpoints that reach one address
+/// through different hostnames, or as a plain target as well, come due at
+/// different points of the interval; rather than each sending its own
+/// probe, those that come due while a probe of the address is running or
+/// within half an interval of its start take its result, so every address
+/// gets probed about once per interval and every endpoint of it reports
+/// the same state.
+struct SharedProbes {
+    window: Duration,
+    latest: Mutex<HashMap<SocketAddr, LatestProbe>>,
+}
+
+impl SharedProbes {
+    fn new(interval: Duration) -> SharedProbes {
+        SharedProbes {
+            window: interval / 2,
+            latest: Mutex::default(),
+        }
+    }
+
+    async fn probe(&self, target: SocketAddr, settings: &Arc<ScanSettings>) -> ScanResult {
+        let shared = {
+            let latest = self.latest.lock().unwrap();
+            latest
+                .get(&target)
+                .filter(|(started, _)| started.elapsed() < self.window)
+                .map(|(_, outcome)| outcome.clone())
+        };
+        // Falls through to probing when the probe being waited on was
+        // stopped before it finished
+        if let Some(mut outcome) = shared
+            && let Ok(outcome) = outcome.wait_for(Option::is_some).await
+            && let Some(outcome) = *outcome
+        {
+            print_to_terminal(
+                format!("Sharing the latest probe of {}", target),
+                VerbosityLevel::DEBUG,
+            );
+            let mut scan_result = ScanResult::new(target, outcome.status, outcome.latency);
+            scan_result.timestamp = outcome.timestamp;
+            scan_result.monotonic = outcome.monotonic;
+            return scan_result;
+        }
+
+        let (sender, receiver) = watch::channel(None);
+        self.latest
+            .lock()
+            .unwrap()
+            .insert(target, (Instant::now(), receiver));
+        let scan_result = check_target(target, None, settings.clone()).await;
+        sender.send_replace(Some(Outcome {
+            status: scan_result.status,
+            latency: scan_result.latency,
+            timestamp: scan_result.timestamp,
+            monotonic: scan_result.monotonic,
+        }));
+        scan_result
+    }
+}
+
+/// Probes every endpoint once per `interval` until interrupted.
+///
+/// Endpoints are staggered evenly across the interval instead of all firing
+/// at once, and each one waits a full interval from the start of its last
+/// probe, so a late wakeup never causes two probes to land close together.
+///
+/// Hostnames are looked up again every `--reresolve` interval. Addresses
+/// that appear start being probed straight away and addresses that disappear
+/// stop being probed, so a round-robin record or a failover never silently
+/// changes what is being watched. Each address keeps its own state, and a
+/// per-hostname line is printed whenever the number of open addresses
+/// behind a hostname and port changes.
+pub async fn run_watch(
+    scan: &ScanArgs,
+    interval: Duration,
+    settings: Arc<ScanSettings>,
+    label_set: &LabelSet,
+    acknowledgments: &Acknowledgments,
+    mut result_stream: Option<ResultStream>,
+    mut clock: RunClock,
+) {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<ScanResult>();
+    let port_set = ports::PortSet::from_args(scan);
+    let (other_targets, hostnames) =
+        targets::split_hostnames(&sources::gather_targets(&scan.targets).await);
+
+    let (addresses, _) = targets::resolve_named_targets(&other_targets, false).await;
+    let endpoints = Endpoints::new(addresses, scan);
+
+    let reresolve_interval = scan.reresolve.unwrap_or(DEFAULT_RERESOLVE_INTERVAL);
+    let mut resolved: BTreeMap<String, Vec<IpAddr>> = BTreeMap::new();
+    for (hostname, addresses) in resolve_all(&hostnames).await {
+        if addresses.is_empty() {
+            print_to_terminal(
+                format!(
+                    "Could not resolve {}, trying again in {}",
+                    targets::display_hostname(&hostname),
+                    format_duration(reresolve_interval)
+                ),
+                VerbosityLevel::WARN,
+            );
+        }
+        let addresses = allowed_addresses(addresses, scan, label_set);
+        resolved.insert(hostname, addresses);
+    }
+
+    let endpoint_count =
+        endpoints.len() + resolved.values().map(Vec::len).sum::<usize>() * port_set.ports().len();
+    if endpoint_count > MAX_WATCHED_ENDPOINTS {
+        error_handler(
+            ErrorCodes::TOO_MANY_TO_WATCH,
+            line!(),
+            Some(&endpoint_count.to_string()),
+        );
+    }
+    print_to_terminal(
+        format!(
+            "Watching {} endpoints every {}",
+            endpoint_count,
+            format_duration(interval)
+        ),
+        VerbosityLevel::INFO,
+    );
+
+    // Each endpoint keeps its own probe task, which is why there's a limit
+    let plain_endpoints = endpoints.iter().map(|target| (None, target));
+    let hostname_endpoints = resolved.iter().flat_map(|(hostname, addresses)| {
+        endpoints
+            .endpoints_of(addresses.iter().copied())
+            .map(move |target| (Some(hostname.clone()), target))
+    });
+    let endpoint_count = endpoint_count.max(1) as u32;
+    let shared = Arc::new(SharedProbes::new(interval));
+    let mut probes: HashMap<Endpoint, JoinHandle<()>> = HashMap::new();
+    for (index, endpoint) in plain_endpoints.chain(hostname_endpoints).enumerate() {
+        let offset = interval / endpoint_count * index as u32;
+        let probe = spawn_probe(&endpoint, offset, interval, &settings, &shared, &sender);
+        probes.insert(endpoint, probe);
+    }
+
+    let mut reresolve_timer = interval_at(Instant::now() + reresolve_interval, reresolve_interval);
+    let mut hostname_states: HashMap<(String, u16), BTreeMap<IpAddr, ConnectionStatus>> =
+        HashMap::new();
+    let mut hostname_summaries: HashMap<(String, u16), String> = HashMap::new();
+    let metrics = match scan.metrics_listen {
+        Some(address) => {
+            let metrics = Arc::new(Metrics::default());
+            metrics::serve(address, metrics.clone()).await;
+            print_to_terminal(
+                format!("Serving metrics on http://{}/metrics", address),
+                VerbosityLevel::INFO,
+            );
+            Some(metrics)
+        }
+        None => None,
+    };
+    let mut changes = ChangeTracker::default();
+    // Beats come from this loop, so they stop if it ever stops turning
+    let heartbeat = Heartbeat::from_args(scan);
+    let mut heartbeat_timer = interval_at(
+        Instant::now(),
+        scan.heartbeat_interval
+            .unwrap_or(DEFAULT_HEARTBEAT_INTERVAL),
+    );
+
+    let stop_at = scan
+        .max_runtime
+        .map(|max_runtime| Instant::now() + max_runtime);
+    loop {
+        tokio::select! {
+            received = receiver.recv() => match received {
+                Some(mut scan_result) => {
+                    clock.check();
+                    // Probes of addresses dropped by the last lookup may still be queued
+                    if let Some(hostname) = &scan_result.hostname
+                        && !resolved
+                            .get(hostname)
+                            .is_some_and(|addresses| addresses.contains(&scan_result.ip.ip()))
+                    {
+                        continue;
+                    }
+                    scan_result.labels = label_set.labels_for(scan_result.ip.ip());
+                    scan_result.annotation = settings.annotations.annotation_for(scan_result.ip.ip());
+                    scan_result.acknowledgment = acknowledgments.note_for(scan_result.ip);
+                    print_scan_result(&scan_result);
+                    changes.observe(
+                        describe_endpoint(&scan_result.hostname, scan_result.ip),
+                        scan_result.status.to_string().to_lowercase(),
+                    );
+                    if let Some(metrics) = &metrics {
+                        metrics.record(&scan_result);
+                    }
+                    if let Some(stream) = &mut result_stream {
+                        stream.write(&scan_result);
+                    }
+                    if let Some(hostname) = scan_result.hostname.clone() {
+                        let key = (hostname, scan_result.ip.port());
+                        hostname_states
+                            .entry(key.clone())
+                            .or_default()
+                            .insert(scan_result.ip.ip(), scan_result.status);
+                        print_hostname_summary(&key, &resolved, &hostname_states, &mut hostname_summaries);
+                    }
+                }
+                None => break,
+            },
+            _ = reresolve_timer.tick(), if !hostnames.is_empty() => {
+                for (hostname, addresses) in resolve_all(&hostnames).await {
+                    let previous = resolved.get(&hostname).cloned().unwrap_or_default();
+                    if addresses.is_empty() && !previous.is_empty() {
+                        print_to_terminal(
+                            format!(
+                                "Could not resolve {}, still watching its last {} addresses",
+                                targets::display_hostname(&hostname),
+                                previous.len()
+                            ),
+                            VerbosityLevel::WARN,
+                        );
+                        continue;
+                    }
+                    let addresses = allowed_addresses(addresses, scan, label_set);
+
+                    for ip in previous.iter().filter(|ip| !addresses.contains(ip)) {
+                        print_to_terminal(
+                            format!("{} no longer resolves to {}, stopped watching it", targets::display_hostname(&hostname), ip),
+                            VerbosityLevel::WARN,
+                        );
+                        for port in port_set.ports() {
+                            if let Some(probe) = probes.remove(&(Some(hostname.clone()), SocketAddr::new(*ip, *port))) {
+                                probe.abort();
+                            }
+                            if let Some(metrics) = &metrics {
+                                metrics.forget(Some(hostname.clone()), SocketAddr::new(*ip, *port));
+                            }
+                            changes.forget(&describe_endpoint(&Some(hostname.clone()), SocketAddr::new(*ip, *port)));
+                            if let Some(states) = hostname_states.get_mut(&(hostname.clone(), *port)) {
+                                states.remove(ip);
+                            }
+                        }
+                    }
+                    let added: Vec<IpAddr> = addresses
+                        .iter()
+                        .filter(|ip| !previous.contains(ip))
+                        .copied()
+                        .collect();
+                    for ip in &added {
+                        print_to_terminal(
+                            format!("{} now resolves to {}, watching it", targets::display_hostname(&hostname), ip),
+                            VerbosityLevel::INFO,
+                        );
+                    }
+                    for target in endpoints.endpoints_of(added.iter().copied()) {
+                        let endpoint = (Some(hostname.clone()), target);
+                        let probe = spawn_probe(&endpoint, Duration::ZERO, interval, &settings, &shared, &sender);
+                        probes.insert(endpoint, probe);
+                    }
+
+                    let changed = previous != addresses;
+                    resolved.insert(hostname.clone(), addresses);
+                    if changed {
+                        for port in port_set.ports() {
+                            print_hostname_summary(
+                                &(hostname.clone(), *port),
+                                &resolved,
+                                &hostname_states,
+                                &mut hostname_summaries,
+                            );
+                        }
+                    }
+                }
+            },
+            _ = heartbeat_timer.tick(), if heartbeat.is_some() => {
+                if let Some(heartbeat) = &heartbeat {
+                    heartbeat.beat(probes.len());
+                }
+            },
+            _ = sleep_until(stop_at.unwrap_or_else(Instant::now)), if stop_at.is_some() => {
+                print_to_terminal(
+                    format!(
+                        "Stopped at the --max-runtime of {}",
+                        format_duration(scan.max_runtime.unwrap_or_default())
+                    ),
+                    VerbosityLevel::INFO,
+                );
+                break;
+            },
+            _ = signal::ctrl_c() => break,
+        }
+    }
+
+    print_to_terminal(String::from("Watch has stopped"), VerbosityLevel::INFO);
+}
+
+/// How an endpoint is named in diff lines.
+fn describe_endpoint(hostname: &Option<String>, endpoint: SocketAddr) -> String {
+    match hostname {
+        Some(hostname) => format!("{} ({})", targets::display_hostname(hostname), endpoint),
+        None => endpoint.to_string(),
+    }
+}
+
+fn spawn_probe(
+    endpoint: &Endpoint,
+    offset: Duration,
+    interval: Duration,
+    settings: &Arc<ScanSettings>,
+    shared: &Arc<SharedProbes>,
+    sender: &UnboundedSender<ScanResult>,
+) -> JoinHandle<()> {
+    let (hostname, target) = endpoint.clone();
+    let settings = settings.clone();
+    let shared = shared.clone();
+    let sender = sender.clone();
+    tokio::spawn(async move {
+        sleep(offset).await;
+        loop {
+            let probe_started = Instant::now();
+            let mut scan_result = shared.probe(target, &settings).await;
+            scan_result.hostname = hostname.clone();
+            if sender.send(scan_result).is_err() {
+                break;
+            }
+            sleep_until(probe_started + interval).await;
+        }
+    })
+}
+
+/// Looks every hostname up at once, bypassing the DNS cache so changes show
+/// up as soon as the records' TTLs allow. A fresh resolver is built each time
+/// so edits to the system's DNS configuration and hosts file are picked up too.
+async fn resolve_all(hostnames: &[String]) -> Vec<(String, Vec<IpAddr>)> {
+    let mut lookups: JoinSet<(String, Vec<IpAddr>)> = JoinSet::new();
+    let resolver = dns_cache::build_resolver();
+    for hostname in hostnames {
+        let resolver = resolver.clone();
+        let hostname = hostname.clone();
+        lookups.spawn(async move {
+            let (addresses, _) = dns_cache::resolve(resolver, &hostname).await;
+            (hostname, addresses)
+        });
+    }
+
+    let mut resolved: Vec<(String, Vec<IpAddr>)> = Vec::new();
+    while let Some(res) = lookups.join_next().await {
+        match res {
+            Ok((hostname, mut addresses)) => {
+                addresses.sort();
+                addresses.dedup();
+                resolved.push((hostname, addresses));
+            }
+            Err(e) => print_to_terminal(
+                format!("An error has occured: {}", e),
+                VerbosityLevel::ERROR,
+            ),
+        }
+    }
+    resolved
+}
+
+fn allowed_addresses(addresses: Vec<IpAddr>, scan: &ScanArgs, label_set: &LabelSet) -> Vec<IpAddr> {
+    addresses
+        .into_iter()
+        .filter(|ip| label_set.allows(*ip, &scan.only_labels, &scan.skip_labels))
+        .collect()
+}
+
+/// Prints how many of a hostname's current addresses are open on a port,
+/// but only when that has changed since it was last printed.
+fn print_hostname_summary(
+    key: &(String, u16),
+    resolved: &BTreeMap<String, Vec<IpAddr>>,
+    hostname_states: &HashMap<(String, u16), BTreeMap<IpAddr, ConnectionStatus>>,
+    hostname_summaries: &mut HashMap<(String, u16), String>,
+) {
+    let (hostname, port) = key;
+    let addresses = resolved.get(hostname).map(Vec::len).unwrap_or_default();
+    let open = hostname_states
+        .get(key)
+        .map(|states| {
+            states
+                .values()
+                .filter(|status| **status == ConnectionStatus::Open)
+                .count()
+        })
+        .unwrap_or_default();
+
+    let summary = format!(
+        "{}:{} - {}/{} addresses open",
+        targets::display_hostname(hostname),
+        port,
+        open,
+        addresses
+    );
+    if hostname_summaries.get(key) == Some(&summary) {
+        return;
+    }
+
+    let level = if addresses > 0 && open == addresses {
+        VerbosityLevel::INFO
+    } else if open > 0 {
+        VerbosityLevel::WARN
+    } else {
+        VerbosityLevel::ERROR
+    };
+    print_to_terminal(summary.clone(), level);
+    hostname_summaries.insert(key.clone(), summary);
+}
@@ -0,0 +1,56 @@
+use crate::output::{ResultRecord, ScanMetadata};
+use crate::{ConnectionStatus, ScanResult, VerbosityLevel, http, print_to_terminal};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tokio::time::{Duration, timeout};
+
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a webhook is sent: the scan, how many endpoints ended up in each
+/// state, and every result.
+#[derive(Serialize)]
+struct Payload<'a> {
+    scan: &'a ScanMetadata,
+    summary: BTreeMap<String, usize>,
+    results: Vec<ResultRecord>,
+}
+
+/// POSTs the finished scan's results to `url` as JSON. The results have
+/// already been printed and saved, so a failed delivery only gets a warning.
+pub async fn notify(url: &http::Url, metadata: &ScanMetadata, results: &[ScanResult]) {
+    let summary = ConnectionStatus::ALL
+        .iter()
+        .map(|status| {
+            let count = results
+                .iter()
+                .filter(|scan_result| scan_result.status == *status)
+                .count();
+            (status.to_string().to_lowercase(), count)
+        })
+        .collect();
+    let payload = Payload {
+        scan: metadata,
+        summary,
+        results: results.iter().map(ResultRecord::from_scan_result).collect(),
+    };
+    let body = serde_json::to_vec(&payload).unwrap_or_default();
+
+    match timeout(DELIVERY_TIMEOUT, url.post("application/json", &body)).await {
+        Ok(Ok(response)) if (200..300).contains(&response.status) => print_to_terminal(
+            format!("Results sent to the webhook at {}", url.host),
+            VerbosityLevel::INFO,
+        ),
+        Ok(Ok(response)) => print_to_terminal(
+            format!("Webhook at {} got status {}", url.host, response.status),
+            VerbosityLevel::WARN,
+        ),
+        Ok(Err(e)) => print_to_terminal(
+            format!("Webhook at {} failed: {}", url.host, e),
+            VerbosityLevel::WARN,
+        ),
+        Err(_) => print_to_terminal(
+            format!("Webhook at {} timed out", url.host),
+            VerbosityLevel::WARN,
+        ),
+    }
+}
@@ -0,0 +1,289 @@
+use crate::http_probe::http_port;
+use crate::{
+    ConnectionStatus, ScanResult, ScanSettings, VerbosityLevel, http, print_to_terminal, sandbox,
+    tls, usage,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, timeout};
+
+/// How long the server gets to answer the upgrade.
+const UPGRADE_WAIT: Duration = Duration::from_secs(5);
+/// What the server appends to the client's key before hashing it, fixed
+/// by RFC 6455.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// How a web server answered a WebSocket upgrade for `--websocket-probe`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WebSocketSummary {
+    /// The path the upgrade was asked for
+    pub path: String,
+    pub status: u16,
+    /// Whether the server switched to WebSocket with the right
+    /// `Sec-WebSocket-Accept`
+    pub upgraded: bool,
+    /// Why it didn't, e.g. a redirect or a proxy stripping the upgrade
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub problem: Option<String>,
+}
+
+impl WebSocketSummary {
+    /// The summary for a result line, e.g. `WebSocket on /ws` or `no
+    /// WebSocket on /ws: HTTP 400`.
+    pub fn describe(&self) -> String {
+        if self.upgraded {
+            format!("WebSocket on {}", self.path)
+        } else {
+            format!(
+                "no WebSocket on {}: {}",
+                self.path,
+                self.problem.as_deref().unwrap_or("not upgraded")
+            )
+        }
+    }
+}
+
+/// Bytes for the handshake key, which only has to differ between
+/// handshakes.
+fn random_bytes(count: usize) -> Vec<u8> {
+    let random = RandomState::new();
+    let mut bytes = Vec::with_capacity(count);
+    while bytes.len() < count {
+        let mut hasher = random.build_hasher();
+        hasher.write_usize(bytes.len());
+        bytes.extend_from_slice(&hasher.finish().to_be_bytes());
+    }
+    bytes.truncate(count);
+    bytes
+}
+
+pub fn base64(data: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (index, byte)| {
+            bits | (*byte as u32) << (16 - 8 * index)
+        });
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(BASE64[(bits >> (18 - 6 * index) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// SHA-1, which the handshake uses to prove the server read the key.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    // Padded so the bit length ends the last block
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut words = [0u32; 80];
+        for (index, word) in block.chunks_exact(4).enumerate() {
+            words[index] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for index in 16..80 {
+            words[index] =
+                (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16])
+                    .rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (index, word) in words.iter().enumerate() {
+            let (f, k) = match index {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, added) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(added);
+        }
+    }
+    let mut digest = [0u8; 20];
+    for (bytes, value) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+/// The `Sec-WebSocket-Accept` a server has to answer `key` with.
+fn expected_accept(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, ACCEPT_GUID).as_bytes()))
+}
+
+async fn upgrade<S>(stream: &mut S, host: &str, path: &str) -> io::Result<WebSocketSummary>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let key = base64(&random_bytes(16));
+    let headers = format!(
+        "Upgrade: websocket\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n",
+        key
+    );
+    let response = http::request_upgrade(stream, host, path, &headers).await?;
+    let upgrade_header = response
+        .header("upgrade")
+        .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+    let accepted = response.header("sec-websocket-accept") == Some(expected_accept(&key).as_str());
+    let problem = match response.status {
+        101 if !upgrade_header => Some(String::from("switched to something other than WebSocket")),
+        101 if !accepted => Some(String::from("wrong Sec-WebSocket-Accept")),
+        101 => None,
+        status @ 300..=399 => Some(format!(
+            "HTTP {} -> {}",
+            status,
+            response.header("location").unwrap_or("nowhere")
+        )),
+        status => Some(format!("HTTP {}", status)),
+    };
+    let upgraded = problem.is_none();
+    if upgraded {
+        // A masked close frame with no body, as a client has to send
+        let mut close = vec![0x88, 0x80];
+        close.extend_from_slice(&random_bytes(4));
+        stream.write_all(&close).await?;
+        usage::sent(close.len());
+    }
+    Ok(WebSocketSummary {
+        path: path.to_string(),
+        status: response.status,
+        upgraded,
+        problem,
+    })
+}
+
+/// Asks for a WebSocket upgrade of `path`, over TLS with `server_name` as
+/// SNI if `use_tls`.
+async fn probe(
+    target: SocketAddr,
+    host: String,
+    server_name: Option<String>,
+    path: String,
+    use_tls: bool,
+) -> io::Result<WebSocketSummary> {
+    if use_tls {
+        let mut stream = tls::handshake(target, server_name.as_deref(), None).await?;
+        upgrade(&mut stream, &host, &path).await
+    } else {
+        usage::socket_opened();
+        let mut stream = TcpStream::connect(target).await?;
+        upgrade(&mut stream, &host, &path).await
+    }
+}
+
+/// Asks every open port in `results` that looks like it serves HTTP to
+/// upgrade `path` to a WebSocket, and records whether it switched, which
+/// shows whether proxies in front of the application pass upgrades
+/// through. At most `max_enrichment_probes` endpoints are asked at once.
+pub async fn probe_websockets(
+    settings: &Arc<ScanSettings>,
+    results: &mut [ScanResult],
+    path: &str,
+) {
+    let limits = settings.config.limits;
+    let slots = Arc::new(Semaphore::new(limits.max_enrichment_probes.max(1)));
+    let mut set: JoinSet<(SocketAddr, Result<io::Result<WebSocketSummary>, String>)> =
+        JoinSet::new();
+    for scan_result in results
+        .iter()
+        .filter(|scan_result| scan_result.status == ConnectionStatus::Open)
+    {
+        let Some(use_tls) = http_port(settings, scan_result) else {
+            continue;
+        };
+        let target = scan_result.ip;
+        let host = match &scan_result.hostname {
+            Some(hostname) => hostname.clone(),
+            None if target.is_ipv6() => format!("[{}]", target.ip()),
+            None => target.ip().to_string(),
+        };
+        let server_name = scan_result.hostname.clone();
+        let path = path.to_string();
+        let slots = slots.clone();
+        set.spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            let summary = sandbox::run_isolated(limits.max_probe_time, async move {
+                match timeout(
+                    UPGRADE_WAIT,
+                    probe(target, host, server_name, path, use_tls),
+                )
+                .await
+                {
+                    Ok(summary) => summary,
+                    Err(_) => Err(io::Error::from(io::ErrorKind::TimedOut)),
+                }
+            })
+            .await;
+            (target, summary)
+        });
+    }
+    if set.is_empty() {
+        return;
+    }
+    print_to_terminal(
+        format!("Asking web servers to upgrade {} to WebSocket", path),
+        VerbosityLevel::INFO,
+    );
+
+    let mut summaries: HashMap<SocketAddr, WebSocketSummary> = HashMap::new();
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok((target, Ok(Ok(summary)))) => {
+                print_to_terminal(
+                    format!("{} - {}", target, summary.describe()),
+                    VerbosityLevel::INFO,
+                );
+                summaries.insert(target, summary);
+            }
+            // Ports only guessed to be web servers often aren't
+            Ok((target, Ok(Err(e)))) => print_to_terminal(
+                format!("{} - no HTTP response: {}", target, e),
+                VerbosityLevel::DEBUG,
+            ),
+            Ok((target, Err(problem))) => {
+                print_to_terminal(format!("{} - {}", target, problem), VerbosityLevel::WARN)
+            }
+            Err(e) => {
+                print_to_terminal(
+                    format!("An error has occured: {}", e),
+                    VerbosityLevel::ERROR,
+                );
+            }
+        }
+    }
+    for scan_result in results.iter_mut() {
+        if let Some(summary) = summaries.remove(&scan_result.ip) {
+            scan_result.websocket = Some(summary);
+        }
+    }
+}